@@ -15,17 +15,21 @@
 //! ```bash
 //! sacp-conductor agent target/release/examples/toolcap_proxy -- npx -y '@zed-industries/claude-code-acp'
 //! ```
+//!
+//! In CI, pass `--headless` so that requests no rule decides are denied
+//! rather than forwarded, and get a summary of the denials at exit.
 
 use clap::Parser;
-use sacp::role::{Agent, Client, ProxyToConductor};
-use sacp::schema::RequestPermissionRequest;
-use sacp::util::MatchMessageFrom;
-use sacp::{ByteStreams, Handled, JrConnectionCx, JrMessageHandler, JrRequestCx, MessageCx};
-use std::sync::Arc;
+use sacp::ByteStreams;
+use sacp::role::ProxyToConductor;
+use std::path::PathBuf;
+use std::time::Duration;
 use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
-use toolcap::acp::{PermissionDecision, ToolcapProxy};
-use toolcap::{Matcher, Outcome, Rule, Ruleset};
-use tracing::{debug, info};
+use toolcap::acp::{DefaultDecision, LearnedPolicy, ProxyEvent, ToolcapComponent, ToolcapProxy};
+use toolcap::audit::JsonlSink;
+use toolcap::matchers::Interpreters;
+use toolcap::{Matcher, OperationKind, Outcome, Rule, RuleSuggester, Ruleset};
+use tracing::{debug, info, warn};
 
 #[derive(Parser, Debug)]
 #[command(name = "toolcap-proxy")]
@@ -38,58 +42,33 @@ struct Args {
     /// Use "Always" variants for permission responses (remember decisions)
     #[arg(long)]
     remember: bool,
-}
 
-/// Our custom message handler for the Toolcap proxy.
-/// This intercepts RequestPermissionRequest messages and evaluates them against the ruleset.
-/// All other messages are passed through to ProxyToConductor's default handler.
-struct ToolcapProxyHandler {
-    proxy: Arc<ToolcapProxy>,
-}
+    /// Policy file that keeps "always allow" answers across sessions
+    /// (e.g. `.toolcap/learned.json` in your project)
+    #[arg(long)]
+    learned: Option<PathBuf>,
 
-impl JrMessageHandler for ToolcapProxyHandler {
-    type Role = ProxyToConductor;
+    /// Write a JSON Lines audit log of every permission decision
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
 
-    fn describe_chain(&self) -> impl std::fmt::Debug {
-        "ToolcapProxyHandler"
-    }
+    /// Deny requests no rule decides instead of asking, for running without
+    /// a user (e.g. in CI)
+    #[arg(long)]
+    headless: bool,
 
-    async fn handle_message(
-        &mut self,
-        message: MessageCx,
-        cx: JrConnectionCx<Self::Role>,
-    ) -> Result<Handled<MessageCx>, sacp::Error> {
-        let proxy = self.proxy.clone();
+    /// Deny forwarded requests the user hasn't answered after this many
+    /// seconds
+    #[arg(long, value_name = "SECS")]
+    forward_timeout: Option<u64>,
 
-        // Use MatchMessageFrom to handle specific request types
-        MatchMessageFrom::new(message, &cx)
-            // Handle permission requests from the agent (going to client for approval)
-            .if_request_from(
-                Agent,
-                async move |req: RequestPermissionRequest, request_cx: JrRequestCx<_>| {
-                    let command_info = extract_command_info(&req);
-                    debug!("Evaluating permission request: {}", command_info);
+    /// Explain denials in the conversation, not just in the response
+    #[arg(long)]
+    denial_messages: bool,
 
-                    match proxy.handle_permission_request(&req) {
-                        PermissionDecision::Respond(response) => {
-                            info!(
-                                "Auto-responding to '{}': {:?}",
-                                command_info, response.outcome
-                            );
-                            request_cx.respond(response)
-                        }
-                        PermissionDecision::Forward => {
-                            info!("Forwarding request to client: {}", command_info);
-                            // Forward to client for user decision
-                            cx.send_request_to(Client, req)
-                                .forward_to_request_cx(request_cx)
-                        }
-                    }
-                },
-            )
-            .await
-            .done()
-    }
+    /// Tell the user what was already allowed when asking about a command
+    #[arg(long)]
+    annotate_forwards: bool,
 }
 
 #[tokio::main]
@@ -111,122 +90,175 @@ async fn main() -> Result<(), sacp::Error> {
 
     // Create a sample ruleset for demonstration
     let ruleset = create_default_ruleset();
-    let proxy = Arc::new(ToolcapProxy::new(ruleset).with_remembered_decisions(args.remember));
+    let mut proxy = ToolcapProxy::new(ruleset)
+        .with_remembered_decisions(args.remember)
+        .with_forward_annotations(args.annotate_forwards);
 
     info!("Loaded ruleset");
 
-    // Create our custom handler
-    let handler = ToolcapProxyHandler { proxy };
+    // Apply rules learned in earlier sessions
+    if let Some(path) = &args.learned {
+        match LearnedPolicy::open(path) {
+            Ok(policy) => {
+                info!(
+                    "Loaded {} learned rules from {}",
+                    policy.rules().len(),
+                    path.display()
+                );
+                proxy = proxy.with_learned_policy(policy);
+            }
+            Err(err) => warn!("Not using learned rules: {}", err),
+        }
+    }
+
+    // Record every decision, keeping up to 5 rotated logs of 10 MiB
+    if let Some(path) = &args.audit_log {
+        match JsonlSink::open(path) {
+            Ok(sink) => {
+                info!("Writing audit log to {}", path.display());
+                proxy = proxy.with_observer(sink.with_rotation(10 * 1024 * 1024, 5));
+            }
+            Err(err) => warn!("Not writing audit log: {}", err),
+        }
+    }
+
+    if args.headless {
+        info!("Running headless: requests no rule decides are denied");
+        proxy = proxy.with_headless(
+            DefaultDecision::deny().with_reason("no user is available to approve this"),
+        );
+    }
+    if let Some(secs) = args.forward_timeout {
+        proxy = proxy.with_forward_timeout(
+            Duration::from_secs(secs),
+            DefaultDecision::deny().with_reason("the user didn't answer in time"),
+        );
+    }
 
-    // Use ProxyToConductor role with our custom handler.
-    // ProxyToConductor's default_message_handler will:
-    // - Handle _proxy/initialize by forwarding to agent
-    // - Handle SuccessorMessage from agent by forwarding to client
-    // - Forward all other client messages to agent
-    // - Forward all other agent notifications to client
-    //
-    // Our custom handler intercepts RequestPermissionRequest before the default handler.
-    ProxyToConductor::builder()
-        .name("toolcap-proxy")
-        .with_handler(handler)
+    // Serve the proxy over stdio, logging what it does
+    let component = ToolcapComponent::new(proxy.clone())
+        .with_event_hook(log_event)
+        .with_denial_messages(args.denial_messages)
+        // The demo ruleset lives in code, so a reload just rebuilds it
+        .with_ruleset_loader(|| Ok(create_default_ruleset()));
+    let handler = component.handler();
+    let result = ProxyToConductor::builder()
+        .name(component.name().to_string())
+        .with_handler(handler.clone())
         .serve(ByteStreams::new(
             tokio::io::stdout().compat_write(),
             tokio::io::stdin().compat(),
         ))
-        .await?;
+        .await;
 
-    Ok(())
+    // Report what was denied without asking, before the sessions and their
+    // denials are forgotten
+    let summary = proxy.denial_summary();
+    if !summary.is_empty() {
+        info!("{}", summary.to_string().trim_end());
+    }
+    handler.forget_sessions();
+    result
 }
 
-/// Extracts a human-readable command description from a permission request.
-fn extract_command_info(request: &RequestPermissionRequest) -> String {
-    let kind = request
-        .tool_call
-        .fields
-        .kind
-        .map(|k| format!("{:?}", k))
-        .unwrap_or_else(|| "Unknown".to_string());
-
-    let raw_input = request
-        .tool_call
-        .fields
-        .raw_input
-        .as_ref()
-        .and_then(|v| {
-            // Try to extract command from common field names
-            v.get("command")
-                .or_else(|| v.get("cmd"))
-                .or_else(|| v.get("script"))
-                .and_then(|c| c.as_str())
-                .map(|s| s.to_string())
-        })
-        .unwrap_or_else(|| "?".to_string());
-
-    format!("{}:{}", kind, raw_input)
+/// Logs an event of the proxy component.
+fn log_event(event: &ProxyEvent<'_>) {
+    match event {
+        ProxyEvent::SessionStarted { .. } | ProxyEvent::SessionLoaded { .. } => {
+            debug!("{}", event)
+        }
+        ProxyEvent::Responded { decision, .. } => {
+            info!("{}", event);
+            debug!("Decision:\n{}", decision);
+        }
+        ProxyEvent::Forwarded { decision, .. } => {
+            info!("{}", event);
+            debug!("Decision:\n{}", decision);
+            for suggestion in RuleSuggester::new().suggest(decision) {
+                info!("  Could be allowed with a rule for {}", suggestion);
+            }
+        }
+        ProxyEvent::Defaulted { decision, .. } => {
+            info!("{}", event);
+            debug!("Decision:\n{}", decision);
+        }
+        ProxyEvent::ClientCall { decision, .. } => {
+            if decision.outcome() == Outcome::Deny {
+                warn!("{}", event);
+            } else {
+                debug!("{}", event);
+            }
+        }
+        ProxyEvent::LearnFailed { .. }
+        | ProxyEvent::TimedOut { .. }
+        | ProxyEvent::ReloadFailed { .. }
+        | ProxyEvent::PolicyFailed { .. } => warn!("{}", event),
+        _ => info!("{}", event),
+    }
 }
 
 /// Creates a default ruleset for demonstration purposes.
 fn create_default_ruleset() -> Ruleset {
     Ruleset::new(vec![
-        // ===== ALLOW: Read-only git commands =====
+        // ===== PLAN MODE: Read-only =====
         Rule::new(
-            Matcher::command("git").with_subcommands([
-                "status",
-                "log",
-                "diff",
-                "show",
-                "blame",
-                "branch",
-                "tag",
-                "remote",
-                "describe",
-                "rev-parse",
-                "ls-files",
-                "ls-tree",
-                "cat-file",
-                "shortlog",
-                "annotate",
+            Matcher::or(vec![
+                Matcher::kind(OperationKind::Edit),
+                Matcher::kind(OperationKind::Delete),
+                Matcher::kind(OperationKind::Move),
+                Matcher::and(vec![
+                    Matcher::any_execute(),
+                    Matcher::not(Matcher::or(vec![git_read_only(), read_only_tools()])),
+                ]),
             ]),
-            Outcome::Allow,
-        ),
+            Outcome::Deny,
+        )
+        .with_modes(["plan"])
+        .with_id("plan-read-only")
+        .with_description("Plan mode is read-only")
+        .with_message("only read-only commands run in plan mode")
+        .with_alternative("finish the plan and ask the user to leave plan mode"),
+        // ===== MODES: Leave bypassing permissions to the user =====
+        Rule::new(Matcher::switch_mode("bypassPermissions"), Outcome::Deny)
+            .with_id("deny-bypass-mode")
+            .with_description("Switching to bypass permissions mode")
+            .with_message("only the user can turn off permission checks"),
+        // ===== ALLOW: Read-only git commands =====
+        Rule::new(git_read_only(), Outcome::Allow)
+            .with_id("allow-git-read-only")
+            .with_description("Read-only git commands"),
         // ===== ALLOW: Safe cargo commands =====
         Rule::new(
             Matcher::command("cargo").with_subcommands([
                 "build", "check", "test", "clippy", "fmt", "doc", "tree", "metadata",
             ]),
             Outcome::Allow,
-        ),
+        )
+        .with_id("allow-cargo")
+        .with_description("Safe cargo commands"),
         // ===== ALLOW: Safe npm commands =====
         Rule::new(
             Matcher::command("npm")
                 .with_subcommands(["list", "view", "search", "audit", "outdated", "ls"]),
             Outcome::Allow,
-        ),
+        )
+        .with_id("allow-npm-read-only")
+        .with_description("Read-only npm commands"),
         // ===== ALLOW: Common read-only tools =====
-        Rule::new(
-            Matcher::or(vec![
-                Matcher::command("ls"),
-                Matcher::command("cat"),
-                Matcher::command("head"),
-                Matcher::command("tail"),
-                Matcher::command("grep"),
-                Matcher::command("rg"),
-                Matcher::command("find"),
-                Matcher::command("wc"),
-                Matcher::command("pwd"),
-                Matcher::command("which"),
-                Matcher::command("echo"),
-                Matcher::command("printf"),
-            ]),
-            Outcome::Allow,
-        ),
+        Rule::new(read_only_tools(), Outcome::Allow)
+            .with_id("allow-read-only-tools")
+            .with_description("Common read-only tools"),
         // ===== ALLOW: Go read-only commands =====
         Rule::new(
             Matcher::command("go").with_subcommands(["build", "test", "vet", "fmt", "mod"]),
             Outcome::Allow,
-        ),
+        )
+        .with_id("allow-go")
+        .with_description("Go build and test commands"),
         // ===== ALLOW: Make =====
-        Rule::new(Matcher::command("make"), Outcome::Allow),
+        Rule::new(Matcher::command("make"), Outcome::Allow)
+            .with_id("allow-make")
+            .with_description("Make"),
         // ===== ALLOW: TypeScript/JavaScript =====
         Rule::new(
             Matcher::or(vec![
@@ -235,12 +267,18 @@ fn create_default_ruleset() -> Ruleset {
                 Matcher::command("npx"),
             ]),
             Outcome::Allow,
-        ),
+        )
+        .with_id("allow-js")
+        .with_description("TypeScript/JavaScript tooling"),
         // ===== DENY: Destructive git commands =====
         Rule::new(
             Matcher::command("git").with_subcommands(["push", "reset", "rebase", "force-push"]),
             Outcome::Deny,
-        ),
+        )
+        .with_id("deny-git-destructive")
+        .with_description("Destructive git commands")
+        .with_message("history-rewriting and publishing git commands must be run by a human")
+        .with_alternative("commit locally and ask the user to push"),
         // ===== DENY: Dangerous system commands =====
         Rule::new(
             Matcher::or(vec![
@@ -254,7 +292,10 @@ fn create_default_ruleset() -> Ruleset {
                 Matcher::command("dd"),
             ]),
             Outcome::Deny,
-        ),
+        )
+        .with_id("deny-system")
+        .with_description("Dangerous system commands")
+        .with_message("privileged and destructive system commands are blocked"),
         // ===== DENY: Network commands that could exfiltrate data =====
         Rule::new(
             Matcher::or(vec![
@@ -264,6 +305,57 @@ fn create_default_ruleset() -> Ruleset {
                 Matcher::command("netcat"),
             ]),
             Outcome::Deny,
+        )
+        .with_id("deny-network")
+        .with_description("Network commands that could exfiltrate data")
+        .with_message(
+            "network access from the shell is blocked; ask the user to fetch the resource",
         ),
+        // ===== DENY: Piping into a shell or interpreter =====
+        Rule::new(Matcher::pipes_into(Interpreters::matcher()), Outcome::Deny)
+            .with_id("deny-pipe-to-interpreter")
+            .with_description("Pipelines that feed a shell or interpreter")
+            .with_message(
+                "piping into an interpreter runs unreviewed code; save and run a script instead",
+            ),
+    ])
+}
+
+/// Matches read-only git commands.
+fn git_read_only() -> Matcher {
+    Matcher::command("git").with_subcommands([
+        "status",
+        "log",
+        "diff",
+        "show",
+        "blame",
+        "branch",
+        "tag",
+        "remote",
+        "describe",
+        "rev-parse",
+        "ls-files",
+        "ls-tree",
+        "cat-file",
+        "shortlog",
+        "annotate",
+    ])
+}
+
+/// Matches common read-only tools.
+fn read_only_tools() -> Matcher {
+    Matcher::or(vec![
+        Matcher::command("ls"),
+        Matcher::command("cat"),
+        Matcher::command("head"),
+        Matcher::command("tail"),
+        Matcher::command("grep"),
+        Matcher::command("rg"),
+        Matcher::command("find"),
+        Matcher::command("wc"),
+        Matcher::command("pwd"),
+        Matcher::command("which"),
+        Matcher::command("echo"),
+        Matcher::command("printf"),
     ])
 }
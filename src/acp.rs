@@ -44,14 +44,59 @@
 //! }
 //! ```
 
+mod adapters;
+mod client_calls;
+mod component;
+mod extensions;
+mod feedback;
+mod grants;
+mod headless;
+mod learned;
+mod pending;
+mod resolver;
+mod session;
+#[cfg(feature = "test-support")]
+pub mod testing;
+
+pub use adapters::{AdapterRegistry, AgentAdapter, ClaudeCodeAdapter, CodexAdapter, GeminiAdapter};
+pub use client_calls::{CLIENT_CALL_DENIED, ClientCall};
+pub use component::{ProxyEvent, ToolcapComponent, ToolcapHandler};
+pub use extensions::{
+    DecidedPart, EvaluateRequest, EvaluateResponse, ExplainRequest, ExplainResponse, GrantInfo,
+    ListGrantsRequest, ListGrantsResponse, ReloadRulesetRequest, ReloadRulesetResponse,
+    RevokeGrantRequest, RevokeGrantResponse,
+};
+pub use grants::{Grant, GrantScope};
+pub use headless::{DefaultDecision, Denial, DenialCause, DenialSummary};
+pub use learned::{LearnedPolicy, LearnedRule};
+pub use resolver::{PolicyError, PolicyResolver, PolicySource};
+pub use session::SessionContext;
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+
 use sacp::schema::{
+    Implementation, InitializeResponse, LoadSessionRequest, NewSessionRequest, NewSessionResponse,
     PermissionOption, PermissionOptionId, PermissionOptionKind, RequestPermissionOutcome,
-    RequestPermissionRequest, RequestPermissionResponse, ToolKind,
+    RequestPermissionRequest, RequestPermissionResponse, SessionId, SessionModeId,
+    SessionNotification, SessionUpdate, ToolCallContent, ToolCallId, ToolCallStatus,
+    ToolCallUpdate, ToolCallUpdateFields, ToolKind,
 };
 
+use self::grants::{Grants, grant_matchers};
+use self::headless::Denials;
+use self::session::{Histories, Sessions};
+use crate::audit::{
+    DecisionLayer, DecisionObserver, DecisionRecord, Disposition, ShadowDisagreement, UserChoice,
+};
+use crate::decision::{Decision, MatchedRule, describe_operation};
+use crate::history::{History, OperationStatus};
 use crate::operation::{ExecuteOperation, Operation};
 use crate::outcome::Outcome;
 use crate::ruleset::Ruleset;
+use crate::sync::{read_unpoisoned, write_unpoisoned};
 
 impl Operation {
     /// Constructs an `Operation` from an ACP `RequestPermissionRequest`.
@@ -59,66 +104,89 @@ impl Operation {
     /// This extracts the tool kind and relevant data from the request to create
     /// the appropriate `Operation` variant.
     ///
-    /// When `kind` is not specified in the request, this function attempts to
-    /// infer it from the `raw_input` fields (e.g., presence of "command" suggests
-    /// an Execute operation).
+    /// The tool call is interpreted by the built-in [`AgentAdapter`]s, which
+    /// recognize the tools of common agents by their input. Tool calls that
+    /// no adapter recognizes fall back to generic inference from the tool
+    /// kind and common `raw_input` field names. File operations also cover
+    /// the paths in the tool call's `locations` and diff content, so an edit
+    /// of several files becomes an [`Operation::Batch`]. When the agent is known,
+    /// [`AdapterRegistry::operation`] interprets its tool calls more
+    /// precisely.
+    ///
+    /// The session's working directory isn't known here; see
+    /// [`ToolcapProxy::operation`].
     pub fn from_request(req: &RequestPermissionRequest) -> Self {
-        let fields = &req.tool_call.fields;
+        AdapterRegistry::builtin().operation(None, &req.tool_call.fields)
+    }
+}
 
-        // Try to get explicit kind, or infer from raw_input
-        let kind = fields
-            .kind
-            .or_else(|| infer_kind_from_input(fields.raw_input.as_ref()))
-            .unwrap_or_default();
+/// Builds an operation from a tool call without agent-specific knowledge.
+///
+/// When `kind` is not specified in the tool call, this function attempts to
+/// infer it from the `raw_input` fields (e.g., presence of "command" suggests
+/// an Execute operation).
+///
+/// A per-call working directory in `raw_input` (`cwd`, `workdir` or
+/// `working_directory`) is attached to execute operations.
+fn generic_operation(fields: &ToolCallUpdateFields) -> Operation {
+    // Try to get explicit kind, or infer from raw_input
+    let kind = fields
+        .kind
+        .or_else(|| infer_kind_from_input(fields.raw_input.as_ref()))
+        .unwrap_or_default();
 
-        match kind {
-            ToolKind::Execute => {
-                // For execute operations, extract the command from raw_input
-                let command = extract_command_from_input(fields.raw_input.as_ref());
-                Operation::Execute(ExecuteOperation::new(command))
-            }
-            ToolKind::Read => {
-                let path = extract_path_from_input(fields.raw_input.as_ref());
-                Operation::Read { path: path.into() }
-            }
-            ToolKind::Edit => {
-                let path = extract_path_from_input(fields.raw_input.as_ref());
-                Operation::Edit { path: path.into() }
-            }
-            ToolKind::Delete => {
-                let path = extract_path_from_input(fields.raw_input.as_ref());
-                Operation::Delete { path: path.into() }
-            }
-            ToolKind::Move => {
-                // Move operations typically have "from" and "to" fields
-                let (from, to) = extract_move_paths_from_input(fields.raw_input.as_ref());
-                Operation::Move {
-                    from: from.into(),
-                    to: to.into(),
-                }
+    match kind {
+        ToolKind::Execute => {
+            // For execute operations, extract the command from raw_input
+            let command = extract_command_from_input(fields.raw_input.as_ref());
+            let mut exec_op = ExecuteOperation::new(command);
+            if let Some(cwd) = extract_working_dir_from_input(fields.raw_input.as_ref()) {
+                exec_op = exec_op.with_working_dir(cwd);
             }
-            ToolKind::Search => {
-                let query = extract_string_field(fields.raw_input.as_ref(), "query")
-                    .unwrap_or_default();
-                Operation::Search { query }
-            }
-            ToolKind::Fetch => {
-                let url =
-                    extract_string_field(fields.raw_input.as_ref(), "url").unwrap_or_default();
-                Operation::Fetch { url }
-            }
-            ToolKind::Think => Operation::Think,
-            ToolKind::SwitchMode => {
-                let mode = extract_string_field(fields.raw_input.as_ref(), "mode")
-                    .unwrap_or_default();
-                Operation::SwitchMode { mode }
+            Operation::Execute(exec_op)
+        }
+        ToolKind::Read => {
+            let path = extract_path_from_input(fields.raw_input.as_ref());
+            Operation::Read { path: path.into() }
+        }
+        ToolKind::Edit => {
+            let path = extract_path_from_input(fields.raw_input.as_ref());
+            Operation::Edit { path: path.into() }
+        }
+        ToolKind::Delete => {
+            let path = extract_path_from_input(fields.raw_input.as_ref());
+            Operation::Delete { path: path.into() }
+        }
+        ToolKind::Move => {
+            // Move operations typically have "from" and "to" fields
+            let (from, to) = extract_move_paths_from_input(fields.raw_input.as_ref());
+            Operation::Move {
+                from: from.into(),
+                to: to.into(),
             }
-            ToolKind::Other => {
-                let name = fields.title.clone().unwrap_or_else(|| "unknown".to_string());
-                Operation::Other {
-                    name,
-                    description: None,
-                }
+        }
+        ToolKind::Search => {
+            let query =
+                extract_string_field(fields.raw_input.as_ref(), "query").unwrap_or_default();
+            Operation::Search { query }
+        }
+        ToolKind::Fetch => {
+            let url = extract_string_field(fields.raw_input.as_ref(), "url").unwrap_or_default();
+            Operation::Fetch { url }
+        }
+        ToolKind::Think => Operation::Think,
+        ToolKind::SwitchMode => {
+            let mode = extract_string_field(fields.raw_input.as_ref(), "mode").unwrap_or_default();
+            Operation::SwitchMode { mode }
+        }
+        ToolKind::Other => {
+            let name = fields
+                .title
+                .clone()
+                .unwrap_or_else(|| "unknown".to_string());
+            Operation::Other {
+                name,
+                description: None,
             }
         }
     }
@@ -191,6 +259,15 @@ impl Ruleset {
         let operation = Operation::from_request(req);
         self.evaluate(&operation)
     }
+
+    /// Explains how this ruleset decides an ACP permission request.
+    ///
+    /// This is the [`explain`](Ruleset::explain) counterpart of
+    /// [`evaluate_request`](Ruleset::evaluate_request).
+    pub fn explain_request(&self, req: &RequestPermissionRequest) -> Decision {
+        let operation = Operation::from_request(req);
+        self.explain(&operation)
+    }
 }
 
 /// Extracts a command string from the raw input JSON.
@@ -218,6 +295,13 @@ fn extract_command_from_input(input: Option<&serde_json::Value>) -> String {
     String::new()
 }
 
+/// Extracts a per-call working directory from the raw input JSON.
+fn extract_working_dir_from_input(input: Option<&serde_json::Value>) -> Option<String> {
+    ["cwd", "workdir", "working_directory"]
+        .into_iter()
+        .find_map(|field| extract_string_field(input, field))
+}
+
 /// Extracts a path from the raw input JSON.
 fn extract_path_from_input(input: Option<&serde_json::Value>) -> String {
     let Some(input) = input else {
@@ -267,6 +351,69 @@ fn extract_string_field(input: Option<&serde_json::Value>, field: &str) -> Optio
         .map(|s: &str| s.to_string())
 }
 
+/// Returns the paths a tool call reports touching, in its locations and diff
+/// content, without duplicates.
+fn touched_paths(fields: &ToolCallUpdateFields) -> Vec<&Path> {
+    let locations = fields
+        .locations
+        .iter()
+        .flatten()
+        .map(|location| location.path.as_path());
+    let diffs = fields
+        .content
+        .iter()
+        .flatten()
+        .filter_map(|content| match content {
+            ToolCallContent::Diff { diff } => Some(diff.path.as_path()),
+            _ => None,
+        });
+
+    let mut paths = Vec::new();
+    for path in locations.chain(diffs) {
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// Extends a file operation to cover every path the tool call reports
+/// touching.
+///
+/// Touched paths missing from the operation are added as operations of the
+/// same kind (edits, for a batch), and operations whose path couldn't be
+/// found in `raw_input` are replaced. Other operations are returned unchanged.
+fn cover_touched_paths(operation: Operation, fields: &ToolCallUpdateFields) -> Operation {
+    let touched = touched_paths(fields);
+    if touched.is_empty() {
+        return operation;
+    }
+
+    let make: fn(PathBuf) -> Operation = match &operation {
+        Operation::Read { .. } => |path| Operation::Read { path },
+        Operation::Edit { .. } | Operation::Batch(_) => |path| Operation::Edit { path },
+        Operation::Delete { .. } => |path| Operation::Delete { path },
+        _ => return operation,
+    };
+    let mut operations = match operation {
+        Operation::Batch(operations) => operations,
+        operation => vec![operation],
+    };
+
+    operations.retain(|op| !op.paths().iter().any(|path| path.as_os_str().is_empty()));
+    for path in touched {
+        if !operations.iter().any(|op| op.paths().contains(&path)) {
+            operations.push(make(path.to_path_buf()));
+        }
+    }
+
+    if operations.len() == 1 {
+        operations.remove(0)
+    } else {
+        Operation::Batch(operations)
+    }
+}
+
 // =============================================================================
 // Outcome to PermissionOptionKind conversion
 // =============================================================================
@@ -326,6 +473,34 @@ pub enum PermissionDecision {
 /// - If the ruleset produces `Unknown`, the proxy signals that the request
 ///   should be forwarded to the upstream client for user decision.
 ///
+/// The proxy also tracks each session's working directory, so that
+/// directory-scoped rules apply to requests. Feed it `session/new` and
+/// `session/load` traffic with [`observe_new_session`](Self::observe_new_session)
+/// and [`observe_load_session`](Self::observe_load_session). Clones of a
+/// proxy share this session state.
+///
+/// Tool calls are turned into operations by an [`AdapterRegistry`]. Once the
+/// agent's `initialize` response has been seen with
+/// [`observe_initialize`](Self::observe_initialize), the adapter for that
+/// agent interprets its tool calls.
+///
+/// When the user answers a forwarded request with an "always" option, pass
+/// their response to [`observe_permission_response`](Self::observe_permission_response).
+/// The decision is remembered as a [`Grant`] for the rest of the session, and
+/// grants are consulted before the ruleset, except that they never override
+/// what the ruleset denies.
+///
+/// Approvals can also outlive the session: give the proxy a
+/// [`LearnedPolicy`] with [`with_learned_policy`](Self::with_learned_policy)
+/// to apply rules learned in earlier sessions, with lower priority than the
+/// ruleset.
+///
+/// When there is no user to forward to, as in CI, the proxy can run
+/// [headless](Self::with_headless) and answer undecided requests with a
+/// [`DefaultDecision`] instead. Forwarded requests can also be given a
+/// [timeout](Self::with_forward_timeout). Every request the proxy denies
+/// itself is kept for a [`DenialSummary`], to report at the end of a run.
+///
 /// # Example
 ///
 /// ```ignore
@@ -351,9 +526,39 @@ pub enum PermissionDecision {
 /// ```
 #[derive(Debug, Clone)]
 pub struct ToolcapProxy {
-    ruleset: Ruleset,
+    /// The active ruleset, shared by clones so that replacing it applies to
+    /// all of them.
+    ruleset: Arc<RwLock<Arc<Ruleset>>>,
+    /// A ruleset evaluated alongside `ruleset` that never decides.
+    shadow: Option<Ruleset>,
     /// Whether to use "Always" variants when responding.
     remember_decisions: bool,
+    /// Context for each session, keyed by session id.
+    sessions: Sessions,
+    /// Adapters for interpreting tool calls.
+    adapters: AdapterRegistry,
+    /// The agent, once known from its `initialize` response.
+    agent: Arc<OnceLock<Implementation>>,
+    /// Decisions the user asked to remember, keyed by session id.
+    grants: Grants,
+    /// How broadly grants apply.
+    grant_scope: GrantScope,
+    /// Rules learned in earlier sessions.
+    learned: Option<LearnedPolicy>,
+    /// Observers told about every request the proxy handles.
+    observers: Vec<Arc<dyn DecisionObserver>>,
+    /// The answer to requests that would be forwarded, in headless mode.
+    headless: Option<DefaultDecision>,
+    /// How long to wait for the user, and the answer if they don't.
+    forward_timeout: Option<(Duration, DefaultDecision)>,
+    /// Requests the proxy denied itself.
+    denials: Denials,
+    /// Whether to annotate forwarded requests with the proxy's analysis.
+    forward_annotations: bool,
+    /// Picks rulesets for sessions in place of `ruleset`.
+    resolver: Option<PolicyResolver>,
+    /// The operations performed in each session, keyed by session id.
+    histories: Histories,
 }
 
 impl ToolcapProxy {
@@ -362,8 +567,22 @@ impl ToolcapProxy {
     /// By default, decisions are not remembered (uses `AllowOnce`/`RejectOnce`).
     pub fn new(ruleset: Ruleset) -> Self {
         Self {
-            ruleset,
+            ruleset: Arc::new(RwLock::new(Arc::new(ruleset))),
+            shadow: None,
             remember_decisions: false,
+            sessions: Sessions::default(),
+            adapters: AdapterRegistry::default(),
+            agent: Arc::default(),
+            grants: Grants::default(),
+            grant_scope: GrantScope::default(),
+            learned: None,
+            observers: Vec::new(),
+            headless: None,
+            forward_timeout: None,
+            denials: Denials::default(),
+            forward_annotations: false,
+            resolver: None,
+            histories: Histories::default(),
         }
     }
 
@@ -376,218 +595,1429 @@ impl ToolcapProxy {
         self
     }
 
-    /// Returns a reference to the underlying ruleset.
-    pub fn ruleset(&self) -> &Ruleset {
-        &self.ruleset
+    /// Sets the adapters used to interpret tool calls.
+    ///
+    /// Defaults to [`AdapterRegistry::default`], which has adapters for
+    /// common agents.
+    pub fn with_adapters(mut self, adapters: AdapterRegistry) -> Self {
+        self.adapters = adapters;
+        self
     }
 
-    /// Handles a permission request, returning either a response or a forward decision.
+    /// Sets how broadly remembered decisions apply.
     ///
-    /// This evaluates the request against the ruleset and:
-    /// - For `Allow`/`Deny`: finds the matching option from the request and
-    ///   creates a response selecting that option.
-    /// - For `Unknown`: returns `PermissionDecision::Forward`.
+    /// Defaults to [`GrantScope::ExactCommand`].
+    pub fn with_grant_scope(mut self, scope: GrantScope) -> Self {
+        self.grant_scope = scope;
+        self
+    }
+
+    /// Applies the rules of a learned policy.
     ///
-    /// If the expected option kind is not available in the request's options,
-    /// falls back to `Forward`.
-    pub fn handle_permission_request(
-        &self,
-        request: &RequestPermissionRequest,
-    ) -> PermissionDecision {
-        let outcome = self.ruleset.evaluate_request(request);
+    /// Learned rules are only consulted for operations the ruleset doesn't
+    /// match (see [`Ruleset::with_fallback_rules`]), so they never override
+    /// it.
+    pub fn with_learned_policy(mut self, policy: LearnedPolicy) -> Self {
+        self.learned = Some(policy);
+        self
+    }
 
-        // Determine which option kind we're looking for
-        let target_kind = if self.remember_decisions {
-            outcome.to_option_kind_remembered()
-        } else {
-            outcome.to_option_kind()
-        };
+    /// Sets a shadow ruleset, for trialling a new policy in real sessions.
+    ///
+    /// The shadow ruleset is evaluated on every request passed to
+    /// [`handle_permission_request`](Self::handle_permission_request), with
+    /// the same grants and learned rules as the active ruleset, but it never
+    /// decides. Whenever its outcome differs from the active ruleset's,
+    /// observers are told with a [`ShadowDisagreement`] (see
+    /// [`DecisionObserver::observe_shadow`]).
+    pub fn with_shadow_ruleset(mut self, shadow: Ruleset) -> Self {
+        self.shadow = Some(shadow);
+        self
+    }
 
-        // If outcome is Unknown, we should forward
-        let Some(target_kind) = target_kind else {
-            return PermissionDecision::Forward;
-        };
+    /// Adds an observer that is told how every permission request is handled.
+    ///
+    /// Observers get a record when the proxy answers a request itself or
+    /// forwards it (see [`handle_permission_request`](Self::handle_permission_request)),
+    /// and another once the user answers a forwarded request (see
+    /// [`observe_permission_response`](Self::observe_permission_response)).
+    /// Records carry the request's session and tool call ids.
+    pub fn with_observer(mut self, observer: impl DecisionObserver + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
 
-        // Find a matching option in the request
-        if let Some(option) = find_option_by_kind(&request.options, target_kind) {
-            PermissionDecision::Respond(RequestPermissionResponse {
-                outcome: RequestPermissionOutcome::Selected {
-                    option_id: option.id.clone(),
-                },
-                meta: None,
-            })
-        } else if self.remember_decisions {
-            // Fall back to non-remembered variant if remembered not available
-            if let Some(fallback_kind) = outcome.to_option_kind() {
-                if let Some(option) = find_option_by_kind(&request.options, fallback_kind) {
-                    return PermissionDecision::Respond(RequestPermissionResponse {
-                        outcome: RequestPermissionOutcome::Selected {
-                            option_id: option.id.clone(),
-                        },
-                        meta: None,
-                    });
-                }
-            }
-            // No suitable option found, must forward
-            PermissionDecision::Forward
-        } else {
-            // No suitable option found, must forward
-            PermissionDecision::Forward
-        }
+    /// Runs the proxy headless, for when no user can answer requests.
+    ///
+    /// Requests no rule decides are answered with `default` instead of being
+    /// forwarded: the one-time option for its outcome is selected, or the
+    /// request is cancelled if there is no such option. The response's
+    /// `_meta` records the default under `toolcap.default`, with its reason.
+    /// The default never applies to a request the rules decided.
+    pub fn with_headless(mut self, default: DefaultDecision) -> Self {
+        self.headless = Some(default);
+        self
     }
 
-    /// Evaluates a request and returns the outcome without creating a response.
+    /// Sets how long a forwarded request may wait for the user.
     ///
-    /// This is useful when you need the outcome for logging or other purposes
-    /// before deciding how to handle the request.
-    pub fn evaluate(&self, request: &RequestPermissionRequest) -> Outcome {
-        self.ruleset.evaluate_request(request)
+    /// If the user hasn't answered within `timeout`, the request is answered
+    /// with `default`, as in [`handle_forward_timeout`](Self::handle_forward_timeout).
+    /// The timeout is enforced by [`ToolcapComponent`], with a Tokio timer,
+    /// so the component must run on a Tokio runtime with the time driver
+    /// enabled; other hosts should enforce it themselves.
+    pub fn with_forward_timeout(mut self, timeout: Duration, default: DefaultDecision) -> Self {
+        self.forward_timeout = Some((timeout, default));
+        self
     }
-}
 
-/// Finds an option with the specified kind in the options list.
-fn find_option_by_kind(
-    options: &[PermissionOption],
-    kind: PermissionOptionKind,
-) -> Option<&PermissionOption> {
-    options.iter().find(|opt| opt.kind == kind)
-}
+    /// Sets whether to annotate forwarded requests with what the proxy made
+    /// of them.
+    ///
+    /// When enabled, [`forwarded_request`](Self::forwarded_request) tells the
+    /// user which sub-commands the rules already allowed or denied, and why
+    /// the rest couldn't be decided, so they only have to judge the part
+    /// that is actually uncertain.
+    pub fn with_forward_annotations(mut self, enabled: bool) -> Self {
+        self.forward_annotations = enabled;
+        self
+    }
 
-/// Creates a permission option with the given ID and kind.
-///
-/// This is a helper for creating options in tests.
-pub fn make_permission_option(id: impl Into<String>, kind: PermissionOptionKind) -> PermissionOption {
-    PermissionOption {
-        id: PermissionOptionId::from(id.into()),
-        kind,
-        name: String::new(),
-        meta: None,
+    /// Picks each session's ruleset with `resolver`, from its working
+    /// directory and the agent.
+    ///
+    /// Sessions the resolver has no ruleset for use the proxy's ruleset.
+    /// Sessions are resolved the first time they're needed, or when
+    /// [`resolve_session_policy`](Self::resolve_session_policy) is called.
+    pub fn with_policy_resolver(mut self, resolver: PolicyResolver) -> Self {
+        self.resolver = Some(resolver);
+        self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::matcher::Matcher;
-    use crate::rule::Rule;
-    use sacp::schema::{ToolCallId, ToolCallUpdate, ToolCallUpdateFields};
-    use serde_json::json;
+    /// Keeps at most `limit` operations in each session's
+    /// [history](Self::history), forgetting the oldest first.
+    ///
+    /// Defaults to [`History::DEFAULT_LIMIT`].
+    pub fn with_history_limit(mut self, limit: usize) -> Self {
+        self.histories = self.histories.with_limit(limit);
+        self
+    }
 
-    fn make_request(kind: ToolKind, raw_input: Option<serde_json::Value>) -> RequestPermissionRequest {
-        RequestPermissionRequest {
-            session_id: "test-session".to_string().into(),
-            tool_call: ToolCallUpdate {
-                id: ToolCallId::from("test-call"),
-                fields: ToolCallUpdateFields {
-                    kind: Some(kind),
-                    raw_input,
-                    ..Default::default()
-                },
-                meta: None,
-            },
-            options: vec![],
-            meta: None,
-        }
+    /// Returns the ruleset requests are currently decided with.
+    ///
+    /// The returned ruleset is a snapshot: it doesn't change when the
+    /// proxy's is [replaced](Self::replace_ruleset).
+    pub fn ruleset(&self) -> Arc<Ruleset> {
+        read_unpoisoned(&self.ruleset).clone()
     }
 
-    #[test]
-    fn test_execute_operation_from_request() {
-        let req = make_request(ToolKind::Execute, Some(json!({"command": "git status"})));
-        let op = Operation::from_request(&req);
+    /// Replaces the ruleset, for this proxy and all of its clones.
+    ///
+    /// Sessions, grants and learned rules are kept, as are the rulesets the
+    /// [policy resolver](Self::with_policy_resolver) picked for sessions.
+    /// If the new ruleset has no [observers](Ruleset::with_observer) of its
+    /// own, it keeps those of the old one, so a ruleset loaded from a file
+    /// still reports to the audit log. Requests already being decided finish
+    /// with the old ruleset.
+    pub fn replace_ruleset(&self, ruleset: Ruleset) {
+        let mut current = write_unpoisoned(&self.ruleset);
+        *current = Arc::new(ruleset.inherit_observers(&current));
+    }
 
-        match op {
-            Operation::Execute(exec) => {
-                assert_eq!(exec.raw(), "git status");
-            }
-            _ => panic!("Expected Execute operation"),
-        }
+    /// Returns the policy resolver, if the proxy has one.
+    pub fn policy_resolver(&self) -> Option<&PolicyResolver> {
+        self.resolver.as_ref()
     }
 
-    #[test]
-    fn test_read_operation_from_request() {
-        let req = make_request(ToolKind::Read, Some(json!({"path": "/etc/passwd"})));
-        let op = Operation::from_request(&req);
+    /// Returns the shadow ruleset, if the proxy has one.
+    pub fn shadow_ruleset(&self) -> Option<&Ruleset> {
+        self.shadow.as_ref()
+    }
 
-        match op {
-            Operation::Read { path } => {
-                assert_eq!(path.to_str().unwrap(), "/etc/passwd");
-            }
-            _ => panic!("Expected Read operation"),
-        }
+    /// Returns the default answer in headless mode, if the proxy is headless.
+    pub fn headless(&self) -> Option<&DefaultDecision> {
+        self.headless.as_ref()
     }
 
-    #[test]
-    fn test_evaluate_request() {
-        let ruleset = Ruleset::new(vec![
-            Rule::new(
-                Matcher::command("git").with_subcommand("status"),
-                Outcome::Allow,
-            ),
-            Rule::new(Matcher::command("sudo"), Outcome::Deny),
-        ]);
+    /// Returns how long forwarded requests may wait for the user, and the
+    /// answer if they don't.
+    pub fn forward_timeout(&self) -> Option<(Duration, &DefaultDecision)> {
+        self.forward_timeout
+            .as_ref()
+            .map(|(timeout, default)| (*timeout, default))
+    }
 
-        // Allowed command
-        let req = make_request(ToolKind::Execute, Some(json!({"command": "git status"})));
-        assert_eq!(ruleset.evaluate_request(&req), Outcome::Allow);
+    /// Returns whether forwarded requests are annotated.
+    pub fn forward_annotations(&self) -> bool {
+        self.forward_annotations
+    }
 
-        // Denied command
-        let req = make_request(ToolKind::Execute, Some(json!({"command": "sudo rm -rf /"})));
-        assert_eq!(ruleset.evaluate_request(&req), Outcome::Deny);
+    /// Returns the requests the proxy has denied without asking the user,
+    /// whether by rules, in headless mode or after a timeout.
+    ///
+    /// The latest [`DenialSummary::MAX_DENIALS`] denials are kept, and the
+    /// denials of a session are dropped when it's
+    /// [forgotten](Self::forget_session). Clones of a proxy share their
+    /// denials.
+    pub fn denial_summary(&self) -> DenialSummary {
+        self.denials.summary()
+    }
 
-        // Unknown command
-        let req = make_request(ToolKind::Execute, Some(json!({"command": "npm install"})));
-        assert_eq!(ruleset.evaluate_request(&req), Outcome::Unknown);
+    /// Returns the adapters used to interpret tool calls.
+    pub fn adapters(&self) -> &AdapterRegistry {
+        &self.adapters
     }
 
-    #[test]
-    fn test_think_always_matches_any() {
-        let req = make_request(ToolKind::Think, None);
-        let op = Operation::from_request(&req);
-        assert_eq!(op, Operation::Think);
+    /// Returns the learned policy, if the proxy has one.
+    ///
+    /// Grants returned by [`observe_permission_response`](Self::observe_permission_response)
+    /// can be passed to [`LearnedPolicy::learn`] to keep them for later
+    /// sessions.
+    pub fn learned_policy(&self) -> Option<&LearnedPolicy> {
+        self.learned.as_ref()
     }
 
-    mod outcome_conversion {
-        use super::*;
+    /// Records the agent's identity from its `initialize` response.
+    ///
+    /// Agents that don't report `agentInfo` stay unknown. Only the first
+    /// identity is recorded.
+    pub fn observe_initialize(&self, response: &InitializeResponse) {
+        if let Some(agent) = &response.agent_info {
+            let _ = self.agent.set(agent.clone());
+        }
+    }
 
-        #[test]
-        fn test_allow_to_option_kind() {
-            assert_eq!(
-                Outcome::Allow.to_option_kind(),
-                Some(PermissionOptionKind::AllowOnce)
-            );
-            assert_eq!(
-                Outcome::Allow.to_option_kind_remembered(),
-                Some(PermissionOptionKind::AllowAlways)
-            );
+    /// Returns the agent, if it identified itself in `initialize`.
+    pub fn agent(&self) -> Option<&Implementation> {
+        self.agent.get()
+    }
+
+    /// Records a session created by `session/new`, with its initial mode if
+    /// the agent supports modes.
+    ///
+    /// Call this once the agent has responded, since the session id is only
+    /// known from the response.
+    pub fn observe_new_session(&self, request: &NewSessionRequest, response: &NewSessionResponse) {
+        let mut context = SessionContext::new(&request.cwd);
+        if let Some(modes) = &response.modes {
+            context = context.with_mode(modes.current_mode_id.to_string());
         }
+        self.sessions.insert(response.session_id.clone(), context);
+        self.release_session_policy(&response.session_id);
+    }
 
-        #[test]
-        fn test_deny_to_option_kind() {
-            assert_eq!(
-                Outcome::Deny.to_option_kind(),
-                Some(PermissionOptionKind::RejectOnce)
-            );
-            assert_eq!(
-                Outcome::Deny.to_option_kind_remembered(),
-                Some(PermissionOptionKind::RejectAlways)
-            );
+    /// Records a session resumed by `session/load`.
+    pub fn observe_load_session(&self, request: &LoadSessionRequest) {
+        self.sessions.insert(
+            request.session_id.clone(),
+            SessionContext::new(&request.cwd),
+        );
+        self.release_session_policy(&request.session_id);
+    }
+
+    /// Picks the ruleset for a known session with the
+    /// [policy resolver](Self::with_policy_resolver), replacing the one
+    /// picked before.
+    ///
+    /// Call this once a session is recorded, to find out where its ruleset
+    /// comes from and whether its project file failed to load. Otherwise
+    /// the session is resolved when its first request is decided, and load
+    /// errors go unreported.
+    ///
+    /// Returns the ruleset's source, or `None` if the session uses the
+    /// proxy's ruleset, along with the error of a project file that failed
+    /// to load. Without a resolver, or for unknown sessions, returns
+    /// `(None, None)`.
+    pub fn resolve_session_policy(
+        &self,
+        session_id: &SessionId,
+    ) -> (Option<PolicySource>, Option<PolicyError>) {
+        match (&self.resolver, self.sessions.get(session_id)) {
+            (Some(resolver), Some(session)) => {
+                resolver.resolve_session(session_id, self.agent(), session.cwd())
+            }
+            _ => (None, None),
         }
+    }
 
-        #[test]
-        fn test_unknown_to_option_kind() {
-            assert_eq!(Outcome::Unknown.to_option_kind(), None);
-            assert_eq!(Outcome::Unknown.to_option_kind_remembered(), None);
+    /// Returns where a session's ruleset comes from, if it has been resolved
+    /// to one of the [policy resolver](Self::with_policy_resolver)'s
+    /// rulesets.
+    pub fn session_policy(&self, session_id: &SessionId) -> Option<PolicySource> {
+        let resolver = self.resolver.as_ref()?;
+        resolver.cached(session_id)?.map(|resolved| resolved.source)
+    }
+
+    /// Drops the ruleset resolved for a session, if any.
+    fn release_session_policy(&self, session_id: &SessionId) {
+        if let Some(resolver) = &self.resolver {
+            resolver.release(session_id);
         }
     }
 
-    mod toolcap_proxy {
-        use super::*;
+    /// Records that a session switched to `mode`.
+    ///
+    /// Call this when the agent reports the session's mode: in the response
+    /// to `session/load`, once it accepts a `session/set_mode` request, or in
+    /// a `current_mode_update` (see [`observe_session_update`](Self::observe_session_update)).
+    /// Requests in the session are then decided with the rules for that mode
+    /// (see [`Rule::with_modes`](crate::Rule::with_modes)). Until the mode is
+    /// known, rules restricted to a mode are skipped.
+    ///
+    /// Returns `false`, and records nothing, if the session isn't known.
+    pub fn observe_session_mode(&self, session_id: &SessionId, mode: &SessionModeId) -> bool {
+        self.sessions.set_mode(session_id, &mode.0)
+    }
 
-        fn make_request_with_options(
-            kind: ToolKind,
-            raw_input: Option<serde_json::Value>,
-            options: Vec<PermissionOption>,
-        ) -> RequestPermissionRequest {
+    /// Records what a `session/update` notification says about its session.
+    ///
+    /// Mode changes are recorded, and so are tool calls: a new tool call is
+    /// added to the session's [history](Self::history), and updates to its
+    /// status are recorded there as it runs and completes or fails. Returns
+    /// the new mode, if the notification changed the mode of a known
+    /// session.
+    pub fn observe_session_update<'a>(
+        &self,
+        notification: &'a SessionNotification,
+    ) -> Option<&'a SessionModeId> {
+        let session_id = &notification.session_id;
+        match &notification.update {
+            SessionUpdate::CurrentModeUpdate(update) => {
+                return self
+                    .observe_session_mode(session_id, &update.current_mode_id)
+                    .then_some(&update.current_mode_id);
+            }
+            SessionUpdate::ToolCall(tool_call) => {
+                let update = ToolCallUpdate::from(tool_call.clone());
+                let operation = self.tool_call_operation(session_id, &update.fields);
+                let status = operation_status(tool_call.status);
+                self.histories
+                    .record(session_id, operation, Some(&update.id), status);
+            }
+            SessionUpdate::ToolCallUpdate(update) => {
+                if let Some(status) = update.fields.status {
+                    self.histories
+                        .set_status(session_id, &update.id, operation_status(status));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Returns the operations performed so far in a session, for rules that
+    /// depend on them (see [`Matcher::after`](crate::Matcher::after)).
+    ///
+    /// The history has the tool calls the agent reported in `session/update`
+    /// notifications, with their latest status, and the requests and client
+    /// calls the proxy or the user allowed, which are marked permitted (see
+    /// [`History`]). It keeps the latest operations of each session, up to
+    /// the [limit](Self::with_history_limit), and is empty for unknown
+    /// sessions.
+    pub fn history(&self, session_id: &SessionId) -> History {
+        self.histories.get(session_id)
+    }
+
+    /// Forgets everything recorded about a session, including its grants,
+    /// its history, its denials and the ruleset resolved for it.
+    ///
+    /// ACP has no message that ends a session, so [`ToolcapComponent`]
+    /// forgets its sessions when its connection closes (see
+    /// [`ToolcapHandler::forget_sessions`]).
+    pub fn forget_session(&self, session_id: &SessionId) {
+        self.sessions.remove(session_id);
+        self.grants.clear(session_id);
+        self.histories.remove(session_id);
+        self.denials.forget_session(session_id);
+        self.release_session_policy(session_id);
+    }
+
+    /// Returns what the proxy knows about a session.
+    pub fn session(&self, session_id: &SessionId) -> Option<SessionContext> {
+        self.sessions.get(session_id)
+    }
+
+    /// Builds the operation for a request.
+    ///
+    /// The tool call is interpreted by the adapter for the agent (see
+    /// [`AdapterRegistry::operation`]), and the result is resolved against
+    /// the working directory of the request's session if it is known (see
+    /// [`Operation::in_directory`]).
+    pub fn operation(&self, request: &RequestPermissionRequest) -> Operation {
+        self.tool_call_operation(&request.session_id, &request.tool_call.fields)
+    }
+
+    /// Builds the operation for a tool call in a session.
+    fn tool_call_operation(
+        &self,
+        session_id: &SessionId,
+        fields: &ToolCallUpdateFields,
+    ) -> Operation {
+        let operation = self.adapters.operation(self.agent(), fields);
+        self.in_session(session_id, operation)
+    }
+
+    /// Records in the session's history that a request was allowed, if
+    /// `response` allows it.
+    fn observe_allowed(
+        &self,
+        request: &RequestPermissionRequest,
+        operation: Operation,
+        response: &RequestPermissionResponse,
+    ) {
+        if matches!(
+            user_choice(request, response),
+            UserChoice::AllowOnce | UserChoice::AllowAlways
+        ) {
+            self.histories.record_permitted(
+                &request.session_id,
+                operation,
+                Some(&request.tool_call.id),
+            );
+        }
+    }
+
+    /// Resolves an operation against the working directory of a session, if
+    /// it is known.
+    fn in_session(&self, session_id: &SessionId, operation: Operation) -> Operation {
+        match self.sessions.get(session_id) {
+            Some(session) => operation.in_directory(session.cwd()),
+            None => operation,
+        }
+    }
+
+    /// Records the user's answer to a forwarded request.
+    ///
+    /// The answer is passed on to the proxy's observers. If the user chose
+    /// `AllowAlways` or `RejectAlways`, the commands that no rule decided are
+    /// remembered as grants for the request's session (see [`GrantScope`]),
+    /// and the new grants are returned. Other answers are not remembered.
+    pub fn observe_permission_response(
+        &self,
+        request: &RequestPermissionRequest,
+        response: &RequestPermissionResponse,
+    ) -> Vec<Grant> {
+        let (operation, decision, _) = self.decide(request, false);
+        self.observe_decided_response(request, &operation, &decision, response)
+    }
+
+    /// Like [`observe_permission_response`](Self::observe_permission_response),
+    /// for a request already decided as `decision` when it was forwarded.
+    pub(crate) fn observe_decided_response(
+        &self,
+        request: &RequestPermissionRequest,
+        operation: &Operation,
+        decision: &Decision,
+        response: &RequestPermissionResponse,
+    ) -> Vec<Grant> {
+        self.observe_allowed(request, operation.clone(), response);
+        let choice = user_choice(request, response);
+        let outcome = match choice {
+            UserChoice::AllowAlways => Some(Outcome::Allow),
+            UserChoice::RejectAlways => Some(Outcome::Deny),
+            _ => None,
+        };
+        if !self.observers.is_empty() {
+            self.notify(
+                request,
+                operation,
+                decision,
+                self.layers(request, decision),
+                Disposition::UserChoice(choice),
+            );
+        }
+        let Some(outcome) = outcome else {
+            return Vec::new();
+        };
+        grant_matchers(decision, outcome, self.grant_scope)
+            .into_iter()
+            .filter_map(|(matcher, command)| {
+                let description = format!("Remembered from `{}`", command);
+                self.grants
+                    .add(&request.session_id, matcher, outcome, description)
+            })
+            .collect()
+    }
+
+    /// Returns the grants remembered for a session.
+    pub fn grants(&self, session_id: &SessionId) -> Vec<Grant> {
+        self.grants.list(session_id)
+    }
+
+    /// Revokes a grant, returning it if it existed.
+    pub fn revoke_grant(&self, session_id: &SessionId, grant_id: &str) -> Option<Grant> {
+        self.grants.revoke(session_id, grant_id)
+    }
+
+    /// Revokes every grant of a session.
+    pub fn clear_grants(&self, session_id: &SessionId) {
+        self.grants.clear(session_id);
+    }
+
+    /// Handles a permission request, returning either a response or a forward decision.
+    ///
+    /// This evaluates the request against the ruleset and:
+    /// - For `Allow`/`Deny`: finds the matching option from the request and
+    ///   creates a response selecting that option.
+    /// - For `Unknown`: returns `PermissionDecision::Forward`.
+    ///
+    /// If the request has no option for an `Allow`, it is forwarded; if it
+    /// has none for a `Deny`, it is cancelled, so the user is never asked to
+    /// approve what the rules denied.
+    ///
+    /// Responses carry the outcome and the deciding rules' ids, descriptions
+    /// and messages in their `_meta` field under the `toolcap` key.
+    ///
+    /// In [headless](Self::with_headless) mode, requests are never forwarded:
+    /// undecided requests get the headless default, and allowed requests
+    /// with no allow option are cancelled.
+    pub fn handle_permission_request(
+        &self,
+        request: &RequestPermissionRequest,
+    ) -> PermissionDecision {
+        self.handle_permission_request_explained(request).2
+    }
+
+    /// Like [`handle_permission_request`](Self::handle_permission_request),
+    /// but also returns the operation, how it was decided and what was done
+    /// with it.
+    pub(crate) fn handle_permission_request_explained(
+        &self,
+        request: &RequestPermissionRequest,
+    ) -> (Operation, Decision, PermissionDecision, Disposition) {
+        let (operation, decision, layers) = self.decide(request, true);
+        self.compare_shadow(request, &operation, &decision);
+        let permission_decision = self.respond(request, &decision);
+        let (permission_decision, disposition) = match (permission_decision, &self.headless) {
+            (PermissionDecision::Forward, Some(default))
+                if decision.outcome() == Outcome::Unknown =>
+            {
+                if default.outcome() == Outcome::Deny {
+                    self.record_denial(
+                        request,
+                        &operation,
+                        DenialCause::Headless,
+                        default.reason(),
+                    );
+                }
+                let response = default_response(request, &decision, default, "headless");
+                (
+                    PermissionDecision::Respond(response),
+                    Disposition::Headless(default.outcome()),
+                )
+            }
+            (PermissionDecision::Forward, Some(_)) => {
+                // Allowed, but with no option to say so and no one to ask.
+                let response = RequestPermissionResponse {
+                    outcome: RequestPermissionOutcome::Cancelled,
+                    meta: Some(decision_meta(&decision)),
+                };
+                (
+                    PermissionDecision::Respond(response),
+                    Disposition::AutoDecided,
+                )
+            }
+            (PermissionDecision::Forward, None) => {
+                (PermissionDecision::Forward, Disposition::Forwarded)
+            }
+            (respond, _) => {
+                if decision.outcome() == Outcome::Deny {
+                    let rules = decision.deciding_rules().cloned().collect();
+                    self.record_denial(request, &operation, DenialCause::Rules(rules), None);
+                }
+                (respond, Disposition::AutoDecided)
+            }
+        };
+        if let PermissionDecision::Respond(response) = &permission_decision {
+            self.observe_allowed(request, operation.clone(), response);
+        }
+        self.notify(request, &operation, &decision, layers, disposition.clone());
+        (operation, decision, permission_decision, disposition)
+    }
+
+    /// Returns the request to forward to the client, for a request decided
+    /// as `decision` (see [`explain`](Self::explain)).
+    ///
+    /// Without [forward annotations](Self::with_forward_annotations), this
+    /// is the request unchanged. With them, a note like "toolcap: allowed
+    /// `cargo build`; undecided `npm publish` (no matching rule)" is
+    /// appended to the tool call's title, if the request sets one, and the
+    /// analysis is added to the request's `_meta` under the `toolcap` key:
+    ///
+    /// ```json
+    /// { "toolcap": { "outcome": "unknown", "allowed": [{ "command": "cargo build", "rule": {...} }], "denied": [], "unknown": [{ "command": "npm publish", "reason": "no matching rule" }], "suggestions": [{ "command": "npm publish", "generality": "subcommand", "rule": "`npm publish` with any arguments except dangerous flags" }, ...], "note": "..." } }
+    /// ```
+    ///
+    /// The suggestions are the rules [`RuleSuggester`](crate::RuleSuggester) proposes for the
+    /// undecided commands, for the client to offer next to the prompt.
+    ///
+    /// A `_meta` that isn't a JSON object is left alone.
+    pub fn forwarded_request(
+        &self,
+        request: &RequestPermissionRequest,
+        decision: &Decision,
+    ) -> RequestPermissionRequest {
+        let mut request = request.clone();
+        if !self.forward_annotations {
+            return request;
+        }
+        if let Some(title) = &mut request.tool_call.fields.title {
+            title.push_str(&format!(" [{}]", feedback::forward_note(decision)));
+        }
+        let meta = request
+            .meta
+            .get_or_insert_with(|| serde_json::Value::Object(Default::default()));
+        if let Some(meta) = meta.as_object_mut() {
+            meta.insert("toolcap".into(), feedback::forward_analysis_json(decision));
+        }
+        request
+    }
+
+    /// Answers a forwarded request the user didn't answer in time.
+    ///
+    /// The response selects the one-time option for the outcome of the
+    /// [forward timeout](Self::with_forward_timeout)'s default, or cancels
+    /// the request if there is no such option. Without a forward timeout,
+    /// the request is denied. A request the rules allowed, forwarded only
+    /// because it had no allow option, is cancelled instead.
+    pub fn handle_forward_timeout(
+        &self,
+        request: &RequestPermissionRequest,
+    ) -> RequestPermissionResponse {
+        let (operation, decision, _) = self.decide(request, false);
+        self.decided_forward_timeout(request, &operation, &decision)
+    }
+
+    /// Like [`handle_forward_timeout`](Self::handle_forward_timeout), for a
+    /// request already decided as `decision` when it was forwarded.
+    pub(crate) fn decided_forward_timeout(
+        &self,
+        request: &RequestPermissionRequest,
+        operation: &Operation,
+        decision: &Decision,
+    ) -> RequestPermissionResponse {
+        let (timeout, default) = self
+            .forward_timeout
+            .clone()
+            .unwrap_or_else(|| (Duration::ZERO, DefaultDecision::deny()));
+        let (response, outcome) = if decision.outcome() == Outcome::Unknown {
+            if default.outcome() == Outcome::Deny {
+                let cause = DenialCause::Timeout(timeout);
+                self.record_denial(request, operation, cause, default.reason());
+            }
+            let response = default_response(request, decision, &default, "timeout");
+            (response, default.outcome())
+        } else {
+            let response = RequestPermissionResponse {
+                outcome: RequestPermissionOutcome::Cancelled,
+                meta: Some(decision_meta(decision)),
+            };
+            (response, Outcome::Unknown)
+        };
+        self.observe_allowed(request, operation.clone(), &response);
+        if !self.observers.is_empty() {
+            self.notify(
+                request,
+                operation,
+                decision,
+                self.layers(request, decision),
+                Disposition::TimedOut(outcome),
+            );
+        }
+        response
+    }
+
+    /// Checks a call the agent made straight to a client method, such as
+    /// `fs/write_text_file`.
+    ///
+    /// The call is turned into an operation (see [`Operation::from_client_call`]),
+    /// resolved against the working directory of its session, and decided
+    /// like a permission request, with the session's grants and learned
+    /// rules. A denied call gets an error to reject it with, with the code
+    /// [`CLIENT_CALL_DENIED`], the deciding rules' messages, and the same
+    /// payload as the `_meta` of responses as its data. In
+    /// [headless](Self::with_headless) mode, calls no rule decides get the
+    /// headless default. Calls that aren't denied should be passed on to the
+    /// client.
+    pub fn check_client_call(&self, call: ClientCall<'_>) -> Result<(), sacp::Error> {
+        self.check_client_call_explained(call).2
+    }
+
+    /// Like [`check_client_call`](Self::check_client_call), but also returns
+    /// the operation and how it was decided.
+    pub(crate) fn check_client_call_explained(
+        &self,
+        call: ClientCall<'_>,
+    ) -> (Operation, Decision, Result<(), sacp::Error>) {
+        let session_id = call.session_id();
+        let operation = self.in_session(session_id, Operation::from_client_call(call));
+        let ruleset = self.session_ruleset(session_id, None);
+        let decision = ruleset.explain(&operation);
+        if !self.observers.is_empty() {
+            let disposition = Disposition::ClientCall(call.method().into());
+            let layers = ruleset.layers(&decision);
+            self.publish(
+                DecisionRecord::new(operation.clone(), decision.clone(), layers, disposition)
+                    .with_session(session_id.to_string()),
+            );
+        }
+        let default = self
+            .headless
+            .as_ref()
+            .filter(|_| decision.outcome() == Outcome::Unknown);
+        let outcome = default.map_or(decision.outcome(), DefaultDecision::outcome);
+        if outcome != Outcome::Deny {
+            self.histories
+                .record_permitted(session_id, operation.clone(), None);
+            return (operation, decision, Ok(()));
+        }
+        let (cause, reason) = match default {
+            Some(default) => (DenialCause::Headless, default.reason()),
+            None => {
+                let rules = decision.deciding_rules().cloned().collect();
+                (DenialCause::Rules(rules), None)
+            }
+        };
+        self.denials.record(Denial::new(
+            session_id.clone(),
+            None,
+            operation.clone(),
+            cause,
+            reason.map(String::from),
+        ));
+        let error = client_call_error(&operation, &decision, default);
+        (operation, decision, Err(error))
+    }
+
+    /// Records a request the proxy denied itself.
+    fn record_denial(
+        &self,
+        request: &RequestPermissionRequest,
+        operation: &Operation,
+        cause: DenialCause,
+        reason: Option<&str>,
+    ) {
+        self.denials.record(Denial::new(
+            request.session_id.clone(),
+            Some(request.tool_call.id.clone()),
+            operation.clone(),
+            cause,
+            reason.map(String::from),
+        ));
+    }
+
+    /// Chooses how to answer a request that was decided as `decision`.
+    fn respond(
+        &self,
+        request: &RequestPermissionRequest,
+        decision: &Decision,
+    ) -> PermissionDecision {
+        let outcome = decision.outcome();
+
+        // Determine which option kind we're looking for
+        let target_kind = if self.remember_decisions {
+            outcome.to_option_kind_remembered()
+        } else {
+            outcome.to_option_kind()
+        };
+
+        // If outcome is Unknown, we should forward
+        let Some(target_kind) = target_kind else {
+            return PermissionDecision::Forward;
+        };
+
+        // Find a matching option in the request
+        if let Some(option) = find_option_by_kind(&request.options, target_kind) {
+            PermissionDecision::Respond(RequestPermissionResponse {
+                outcome: RequestPermissionOutcome::Selected {
+                    option_id: option.id.clone(),
+                },
+                meta: Some(decision_meta(decision)),
+            })
+        } else if self.remember_decisions {
+            // Fall back to non-remembered variant if remembered not available
+            if let Some(fallback_kind) = outcome.to_option_kind()
+                && let Some(option) = find_option_by_kind(&request.options, fallback_kind)
+            {
+                return PermissionDecision::Respond(RequestPermissionResponse {
+                    outcome: RequestPermissionOutcome::Selected {
+                        option_id: option.id.clone(),
+                    },
+                    meta: Some(decision_meta(decision)),
+                });
+            }
+            no_option(decision)
+        } else {
+            no_option(decision)
+        }
+    }
+
+    /// Evaluates a request and returns the outcome without creating a response.
+    ///
+    /// This is useful when you need the outcome for logging or other purposes
+    /// before deciding how to handle the request.
+    pub fn evaluate(&self, request: &RequestPermissionRequest) -> Outcome {
+        self.decide(request, false).1.outcome()
+    }
+
+    /// Explains how an operation would be decided in a session, without
+    /// telling the observers.
+    ///
+    /// The operation is resolved against the session's working directory and
+    /// decided with its grants, the learned rules and the session's mode, as
+    /// a permission request in the session would be.
+    pub fn explain_in_session(&self, session_id: &SessionId, operation: Operation) -> Decision {
+        let operation = self.in_session(session_id, operation);
+        self.session_ruleset(session_id, None)
+            .explain_unobserved(&operation)
+    }
+
+    /// Explains how the ruleset decides a request.
+    ///
+    /// This is useful for telling the user which part of a compound command
+    /// was denied or couldn't be decided, and which rule was responsible.
+    pub fn explain(&self, request: &RequestPermissionRequest) -> Decision {
+        self.decide(request, false).1
+    }
+
+    /// Decides a request, returning its operation, the decision, and the
+    /// layer of each deciding rule.
+    ///
+    /// Only the decision that handles the request is `observed`, i.e. passed
+    /// to the ruleset's observers. Queries, and requests decided again
+    /// when they're answered or time out, aren't recorded twice.
+    fn decide(
+        &self,
+        request: &RequestPermissionRequest,
+        observed: bool,
+    ) -> (Operation, Decision, Vec<DecisionLayer>) {
+        let operation = self.operation(request);
+        let ruleset = self.session_ruleset(&request.session_id, Some(&request.tool_call.id));
+        let decision = if observed {
+            ruleset.explain(&operation)
+        } else {
+            ruleset.explain_unobserved(&operation)
+        };
+        let layers = ruleset.layers(&decision);
+        (operation, decision, layers)
+    }
+
+    /// Returns the layer of each rule deciding a request as `decision`.
+    fn layers(
+        &self,
+        request: &RequestPermissionRequest,
+        decision: &Decision,
+    ) -> Vec<DecisionLayer> {
+        self.session_ruleset(&request.session_id, Some(&request.tool_call.id))
+            .layers(decision)
+    }
+
+    /// Evaluates a request against the shadow ruleset, telling the observers
+    /// if it disagrees with the active ruleset's `decision`.
+    fn compare_shadow(
+        &self,
+        request: &RequestPermissionRequest,
+        operation: &Operation,
+        decision: &Decision,
+    ) {
+        let Some(shadow) = &self.shadow else {
+            return;
+        };
+        let shadow_decision = self
+            .layered_ruleset(shadow, &request.session_id, Some(&request.tool_call.id))
+            .explain_unobserved(operation);
+        if shadow_decision.outcome() == decision.outcome() || self.observers.is_empty() {
+            return;
+        }
+        let disagreement = ShadowDisagreement::new(
+            request.session_id.to_string(),
+            request.tool_call.id.to_string(),
+            operation.clone(),
+            decision.clone(),
+            shadow_decision,
+        );
+        for observer in &self.observers {
+            observer.observe_shadow(&disagreement);
+        }
+    }
+
+    /// Tells the observers how a request was handled.
+    fn notify(
+        &self,
+        request: &RequestPermissionRequest,
+        operation: &Operation,
+        decision: &Decision,
+        layers: Vec<DecisionLayer>,
+        disposition: Disposition,
+    ) {
+        if self.observers.is_empty() {
+            return;
+        }
+        self.publish(
+            DecisionRecord::new(operation.clone(), decision.clone(), layers, disposition)
+                .with_tool_call(
+                    request.session_id.to_string(),
+                    request.tool_call.id.to_string(),
+                ),
+        );
+    }
+
+    /// Passes a record to the observers.
+    fn publish(&self, record: DecisionRecord) {
+        for observer in &self.observers {
+            observer.observe(&record);
+        }
+    }
+
+    /// Returns the ruleset for a session: its grants, then the ruleset the
+    /// policy resolver picked for it or the proxy's, then the learned rules,
+    /// with the session's history before the tool call being decided.
+    fn session_ruleset(
+        &self,
+        session_id: &SessionId,
+        tool_call_id: Option<&ToolCallId>,
+    ) -> Arc<Ruleset> {
+        let ruleset = self
+            .resolved_ruleset(session_id)
+            .unwrap_or_else(|| self.ruleset());
+        let layered = match self.layered_ruleset(&ruleset, session_id, tool_call_id) {
+            Cow::Owned(layered) => Some(layered),
+            Cow::Borrowed(_) => None,
+        };
+        match layered {
+            Some(layered) => Arc::new(layered),
+            None => ruleset,
+        }
+    }
+
+    /// Returns the ruleset the policy resolver picked for a session,
+    /// resolving it if needed.
+    fn resolved_ruleset(&self, session_id: &SessionId) -> Option<Arc<Ruleset>> {
+        let resolver = self.resolver.as_ref()?;
+        if resolver.cached(session_id).is_none() {
+            self.resolve_session_policy(session_id);
+        }
+        resolver
+            .cached(session_id)?
+            .map(|resolved| resolved.ruleset)
+    }
+
+    /// Layers a session's grants and the learned rules over `ruleset`, in
+    /// the session's current mode and after the operations in its history
+    /// other than the tool call `tool_call_id`.
+    fn layered_ruleset<'a>(
+        &self,
+        ruleset: &'a Ruleset,
+        session_id: &SessionId,
+        tool_call_id: Option<&ToolCallId>,
+    ) -> Cow<'a, Ruleset> {
+        let grants = self.grants.list(session_id);
+        let learned = self
+            .learned
+            .as_ref()
+            .map(LearnedPolicy::rules)
+            .unwrap_or_default();
+        let mode = self
+            .sessions
+            .get(session_id)
+            .and_then(|session| session.mode().map(String::from));
+        let mut history = self.histories.get(session_id);
+        if let Some(id) = tool_call_id {
+            history = history.without_tool_call(&id.0);
+        }
+        if grants.is_empty() && learned.is_empty() && mode.is_none() && history.is_empty() {
+            return Cow::Borrowed(ruleset);
+        }
+        let grants = grants.into_iter().map(|grant| grant.rule().clone());
+        let ruleset = ruleset
+            .clone()
+            .with_fallback_rules(learned)
+            .with_leading_rules(grants)
+            .with_history(history);
+        Cow::Owned(match mode {
+            Some(mode) => ruleset.with_mode(mode),
+            None => ruleset,
+        })
+    }
+}
+
+/// Converts the status of a tool call to that of its operation.
+fn operation_status(status: ToolCallStatus) -> OperationStatus {
+    match status {
+        ToolCallStatus::Pending => OperationStatus::Pending,
+        ToolCallStatus::InProgress => OperationStatus::Running,
+        ToolCallStatus::Completed => OperationStatus::Completed,
+        ToolCallStatus::Failed => OperationStatus::Failed,
+    }
+}
+
+/// Determines which option the user chose in response to a request.
+fn user_choice(
+    request: &RequestPermissionRequest,
+    response: &RequestPermissionResponse,
+) -> UserChoice {
+    let RequestPermissionOutcome::Selected { option_id } = &response.outcome else {
+        return UserChoice::Cancelled;
+    };
+    match request.options.iter().find(|opt| &opt.id == option_id) {
+        Some(option) => match option.kind {
+            PermissionOptionKind::AllowOnce => UserChoice::AllowOnce,
+            PermissionOptionKind::AllowAlways => UserChoice::AllowAlways,
+            PermissionOptionKind::RejectOnce => UserChoice::RejectOnce,
+            PermissionOptionKind::RejectAlways => UserChoice::RejectAlways,
+        },
+        None => UserChoice::Other(option_id.to_string()),
+    }
+}
+
+/// Builds the `_meta` payload attached to responses made by the proxy.
+///
+/// The payload records the outcome and the rules responsible for it, so the
+/// agent (and anything logging the response) can see why it was decided:
+///
+/// ```json
+/// { "toolcap": { "outcome": "deny", "rules": [{ "index": 3, "id": "no-force-push", "message": "..." }] } }
+/// ```
+///
+/// Denials also carry each denied sub-command with the rule that denied it,
+/// and a `reason` explaining the denial to the agent:
+///
+/// ```json
+/// { "toolcap": { "outcome": "deny", "rules": [...], "denied": [{ "command": "git push --force", "rule": {...} }], "reason": "..." } }
+/// ```
+fn decision_meta(decision: &Decision) -> serde_json::Value {
+    let rules: Vec<_> = decision.deciding_rules().map(matched_rule_json).collect();
+    let mut meta = serde_json::json!({
+        "toolcap": {
+            "outcome": decision.outcome().to_string(),
+            "rules": rules,
+        }
+    });
+    if let Some(reason) = feedback::denial_reason(decision) {
+        meta["toolcap"]["denied"] = feedback::denied_parts_json(decision).into();
+        meta["toolcap"]["reason"] = reason.into();
+    }
+    meta
+}
+
+/// Answers a decided request that offers no option for its outcome.
+///
+/// A denied request is cancelled, since forwarding it would let the user
+/// (or a headless default) allow what the rules denied; an allowed request
+/// is forwarded for the user to answer.
+fn no_option(decision: &Decision) -> PermissionDecision {
+    if decision.outcome() == Outcome::Deny {
+        PermissionDecision::Respond(RequestPermissionResponse {
+            outcome: RequestPermissionOutcome::Cancelled,
+            meta: Some(decision_meta(decision)),
+        })
+    } else {
+        PermissionDecision::Forward
+    }
+}
+
+/// Builds the response for a request answered with a default outcome.
+///
+/// The `_meta` payload is that of [`decision_meta`], with the default added,
+/// and for a default denial a `reason` explaining it to the agent:
+///
+/// ```json
+/// { "toolcap": { "outcome": "unknown", "rules": [], "default": { "outcome": "deny", "cause": "headless", "reason": "..." }, "reason": "..." } }
+/// ```
+fn default_response(
+    request: &RequestPermissionRequest,
+    decision: &Decision,
+    default: &DefaultDecision,
+    cause: &str,
+) -> RequestPermissionResponse {
+    let option = default
+        .outcome()
+        .to_option_kind()
+        .and_then(|kind| find_option_by_kind(&request.options, kind));
+    let outcome = match option {
+        Some(option) => RequestPermissionOutcome::Selected {
+            option_id: option.id.clone(),
+        },
+        None => RequestPermissionOutcome::Cancelled,
+    };
+    let mut meta = decision_meta(decision);
+    meta["toolcap"]["default"] = default_json(default, cause);
+    if default.outcome() == Outcome::Deny {
+        let reason = feedback::default_denial_reason(decision, cause, default.reason());
+        meta["toolcap"]["reason"] = reason.into();
+    }
+    RequestPermissionResponse {
+        outcome,
+        meta: Some(meta),
+    }
+}
+
+/// Converts a default decision into JSON, with what caused it to be used.
+fn default_json(default: &DefaultDecision, cause: &str) -> serde_json::Value {
+    let mut json = serde_json::json!({
+        "outcome": default.outcome().to_string(),
+        "cause": cause,
+    });
+    if let Some(reason) = default.reason() {
+        json["reason"] = reason.into();
+    }
+    json
+}
+
+/// Builds the error that rejects a denied client call.
+///
+/// The message names the operation and gives the deciding rules' messages,
+/// e.g. "toolcap denied `git push`: publishing must be done by a human", or
+/// the reason of the headless `default` that denied an undecided call. The
+/// data records the default as [`default_response`] does.
+fn client_call_error(
+    operation: &Operation,
+    decision: &Decision,
+    default: Option<&DefaultDecision>,
+) -> sacp::Error {
+    let mut message = format!("toolcap denied `{}`", describe_operation(operation));
+    let mut messages: Vec<_> = decision
+        .deciding_rules()
+        .filter_map(MatchedRule::message)
+        .collect();
+    messages.extend(default.and_then(DefaultDecision::reason));
+    if !messages.is_empty() {
+        message.push_str(": ");
+        message.push_str(&messages.join("; "));
+    }
+    let mut data = decision_meta(decision);
+    if let Some(default) = default {
+        data["toolcap"]["default"] = default_json(default, "headless");
+    }
+    sacp::Error::new((CLIENT_CALL_DENIED, message)).with_data(data)
+}
+
+/// Converts a matched rule into JSON, omitting unset metadata.
+fn matched_rule_json(rule: &MatchedRule) -> serde_json::Value {
+    let mut obj = serde_json::Map::new();
+    obj.insert("index".into(), rule.index().into());
+    if let Some(id) = rule.id() {
+        obj.insert("id".into(), id.into());
+    }
+    if let Some(description) = rule.description() {
+        obj.insert("description".into(), description.into());
+    }
+    if let Some(message) = rule.message() {
+        obj.insert("message".into(), message.into());
+    }
+    if let Some(alternative) = rule.alternative() {
+        obj.insert("alternative".into(), alternative.into());
+    }
+    serde_json::Value::Object(obj)
+}
+
+/// Finds an option with the specified kind in the options list.
+fn find_option_by_kind(
+    options: &[PermissionOption],
+    kind: PermissionOptionKind,
+) -> Option<&PermissionOption> {
+    options.iter().find(|opt| opt.kind == kind)
+}
+
+/// Creates a permission option with the given ID and kind.
+///
+/// This is a helper for creating options in tests.
+pub fn make_permission_option(
+    id: impl Into<String>,
+    kind: PermissionOptionKind,
+) -> PermissionOption {
+    PermissionOption {
+        id: PermissionOptionId::from(id.into()),
+        kind,
+        name: String::new(),
+        meta: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+    use crate::rule::Rule;
+    use sacp::schema::{ToolCallId, ToolCallUpdate, ToolCallUpdateFields};
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    fn make_request(
+        kind: ToolKind,
+        raw_input: Option<serde_json::Value>,
+    ) -> RequestPermissionRequest {
+        RequestPermissionRequest {
+            session_id: "test-session".to_string().into(),
+            tool_call: ToolCallUpdate {
+                id: ToolCallId::from("test-call"),
+                fields: ToolCallUpdateFields {
+                    kind: Some(kind),
+                    raw_input,
+                    ..Default::default()
+                },
+                meta: None,
+            },
+            options: vec![],
+            meta: None,
+        }
+    }
+
+    #[test]
+    fn test_execute_operation_from_request() {
+        let req = make_request(ToolKind::Execute, Some(json!({"command": "git status"})));
+        let op = Operation::from_request(&req);
+
+        match op {
+            Operation::Execute(exec) => {
+                assert_eq!(exec.raw(), "git status");
+            }
+            _ => panic!("Expected Execute operation"),
+        }
+    }
+
+    #[test]
+    fn test_execute_operation_working_dir_from_request() {
+        let req = make_request(
+            ToolKind::Execute,
+            Some(json!({"command": "ls", "cwd": "/home/user/project"})),
+        );
+        let Operation::Execute(exec) = Operation::from_request(&req) else {
+            panic!("Expected Execute operation");
+        };
+        assert_eq!(
+            exec.working_dir(),
+            Some(&PathBuf::from("/home/user/project"))
+        );
+
+        let req = make_request(
+            ToolKind::Execute,
+            Some(json!({"command": "ls", "workdir": "src"})),
+        );
+        let Operation::Execute(exec) = Operation::from_request(&req) else {
+            panic!("Expected Execute operation");
+        };
+        assert_eq!(exec.working_dir(), Some(&PathBuf::from("src")));
+
+        let req = make_request(ToolKind::Execute, Some(json!({"command": "ls"})));
+        let Operation::Execute(exec) = Operation::from_request(&req) else {
+            panic!("Expected Execute operation");
+        };
+        assert_eq!(exec.working_dir(), None);
+    }
+
+    #[test]
+    fn test_agent_tool_from_request() {
+        // Claude Code's MultiEdit has no `content`, but is still an edit.
+        let mut req = make_request(
+            ToolKind::Edit,
+            Some(json!({"file_path": "/p/a.rs", "edits": [{"old_string": "a"}]})),
+        );
+        req.tool_call.fields.kind = None;
+        assert_eq!(
+            Operation::from_request(&req),
+            Operation::Edit {
+                path: "/p/a.rs".into()
+            }
+        );
+    }
+
+    mod touched_paths {
+        use super::*;
+        use sacp::schema::{Diff, ToolCallLocation};
+
+        fn location(path: &str) -> ToolCallLocation {
+            ToolCallLocation {
+                path: path.into(),
+                line: None,
+                meta: None,
+            }
+        }
+
+        fn diff(path: &str) -> ToolCallContent {
+            ToolCallContent::Diff {
+                diff: Diff {
+                    path: path.into(),
+                    old_text: None,
+                    new_text: String::new(),
+                    meta: None,
+                },
+            }
+        }
+
+        #[test]
+        fn test_path_from_locations() {
+            let mut req = make_request(ToolKind::Edit, None);
+            req.tool_call.fields.locations = Some(vec![location("/p/a.rs")]);
+            assert_eq!(
+                Operation::from_request(&req),
+                Operation::Edit {
+                    path: "/p/a.rs".into()
+                }
+            );
+
+            let mut req = make_request(ToolKind::Read, Some(json!({"offset": 10})));
+            req.tool_call.fields.locations = Some(vec![location("/p/a.rs")]);
+            assert_eq!(
+                Operation::from_request(&req),
+                Operation::Read {
+                    path: "/p/a.rs".into()
+                }
+            );
+        }
+
+        #[test]
+        fn test_paths_from_diffs() {
+            let mut req = make_request(ToolKind::Edit, None);
+            req.tool_call.fields.content = Some(vec![diff("/p/a.rs"), diff("/p/b.rs")]);
+            assert_eq!(
+                Operation::from_request(&req),
+                Operation::Batch(vec![
+                    Operation::Edit {
+                        path: "/p/a.rs".into()
+                    },
+                    Operation::Edit {
+                        path: "/p/b.rs".into()
+                    },
+                ])
+            );
+        }
+
+        #[test]
+        fn test_cross_checked_with_raw_input() {
+            let mut req = make_request(ToolKind::Edit, Some(json!({"path": "/p/a.rs"})));
+            req.tool_call.fields.locations = Some(vec![location("/p/a.rs")]);
+            req.tool_call.fields.content = Some(vec![diff("/p/a.rs")]);
+            assert_eq!(
+                Operation::from_request(&req),
+                Operation::Edit {
+                    path: "/p/a.rs".into()
+                }
+            );
+
+            req.tool_call.fields.content = Some(vec![diff("/p/a.rs"), diff("/etc/passwd")]);
+            assert_eq!(
+                Operation::from_request(&req),
+                Operation::Batch(vec![
+                    Operation::Edit {
+                        path: "/p/a.rs".into()
+                    },
+                    Operation::Edit {
+                        path: "/etc/passwd".into()
+                    },
+                ])
+            );
+        }
+
+        #[test]
+        fn test_non_file_operations_unchanged() {
+            let mut req = make_request(ToolKind::Execute, Some(json!({"command": "ls"})));
+            req.tool_call.fields.locations = Some(vec![location("/p")]);
+            assert_eq!(Operation::from_request(&req), Operation::execute("ls"));
+        }
+    }
+
+    #[test]
+    fn test_read_operation_from_request() {
+        let req = make_request(ToolKind::Read, Some(json!({"path": "/etc/passwd"})));
+        let op = Operation::from_request(&req);
+
+        match op {
+            Operation::Read { path } => {
+                assert_eq!(path.to_str().unwrap(), "/etc/passwd");
+            }
+            _ => panic!("Expected Read operation"),
+        }
+    }
+
+    #[test]
+    fn test_evaluate_request() {
+        let ruleset = Ruleset::new(vec![
+            Rule::new(
+                Matcher::command("git").with_subcommand("status"),
+                Outcome::Allow,
+            ),
+            Rule::new(Matcher::command("sudo"), Outcome::Deny),
+        ]);
+
+        // Allowed command
+        let req = make_request(ToolKind::Execute, Some(json!({"command": "git status"})));
+        assert_eq!(ruleset.evaluate_request(&req), Outcome::Allow);
+
+        // Denied command
+        let req = make_request(ToolKind::Execute, Some(json!({"command": "sudo rm -rf /"})));
+        assert_eq!(ruleset.evaluate_request(&req), Outcome::Deny);
+
+        // Unknown command
+        let req = make_request(ToolKind::Execute, Some(json!({"command": "npm install"})));
+        assert_eq!(ruleset.evaluate_request(&req), Outcome::Unknown);
+    }
+
+    #[test]
+    fn test_explain_request() {
+        let ruleset = Ruleset::new(vec![
+            Rule::new(Matcher::command("find"), Outcome::Allow),
+            Rule::new(Matcher::command("xargs"), Outcome::Deny),
+        ]);
+
+        let req = make_request(
+            ToolKind::Execute,
+            Some(json!({"command": "find . | xargs rm"})),
+        );
+        let decision = ruleset.explain_request(&req);
+        assert_eq!(decision.outcome(), Outcome::Deny);
+        assert_eq!(decision.leaves().count(), 2);
+    }
+
+    #[test]
+    fn test_think_always_matches_any() {
+        let req = make_request(ToolKind::Think, None);
+        let op = Operation::from_request(&req);
+        assert_eq!(op, Operation::Think);
+    }
+
+    mod outcome_conversion {
+        use super::*;
+
+        #[test]
+        fn test_allow_to_option_kind() {
+            assert_eq!(
+                Outcome::Allow.to_option_kind(),
+                Some(PermissionOptionKind::AllowOnce)
+            );
+            assert_eq!(
+                Outcome::Allow.to_option_kind_remembered(),
+                Some(PermissionOptionKind::AllowAlways)
+            );
+        }
+
+        #[test]
+        fn test_deny_to_option_kind() {
+            assert_eq!(
+                Outcome::Deny.to_option_kind(),
+                Some(PermissionOptionKind::RejectOnce)
+            );
+            assert_eq!(
+                Outcome::Deny.to_option_kind_remembered(),
+                Some(PermissionOptionKind::RejectAlways)
+            );
+        }
+
+        #[test]
+        fn test_unknown_to_option_kind() {
+            assert_eq!(Outcome::Unknown.to_option_kind(), None);
+            assert_eq!(Outcome::Unknown.to_option_kind_remembered(), None);
+        }
+    }
+
+    mod toolcap_proxy {
+        use super::*;
+
+        fn make_request_with_options(
+            kind: ToolKind,
+            raw_input: Option<serde_json::Value>,
+            options: Vec<PermissionOption>,
+        ) -> RequestPermissionRequest {
             RequestPermissionRequest {
                 session_id: "test-session".to_string().into(),
                 tool_call: ToolCallUpdate {
@@ -604,221 +2034,1464 @@ mod tests {
             }
         }
 
-        fn standard_options() -> Vec<PermissionOption> {
-            vec![
-                make_permission_option("allow-once", PermissionOptionKind::AllowOnce),
-                make_permission_option("allow-always", PermissionOptionKind::AllowAlways),
-                make_permission_option("reject-once", PermissionOptionKind::RejectOnce),
-                make_permission_option("reject-always", PermissionOptionKind::RejectAlways),
-            ]
+        fn standard_options() -> Vec<PermissionOption> {
+            vec![
+                make_permission_option("allow-once", PermissionOptionKind::AllowOnce),
+                make_permission_option("allow-always", PermissionOptionKind::AllowAlways),
+                make_permission_option("reject-once", PermissionOptionKind::RejectOnce),
+                make_permission_option("reject-always", PermissionOptionKind::RejectAlways),
+            ]
+        }
+
+        #[test]
+        fn test_proxy_allows_matching_command() {
+            let ruleset = Ruleset::new(vec![Rule::new(
+                Matcher::command("git").with_subcommand("status"),
+                Outcome::Allow,
+            )]);
+            let proxy = ToolcapProxy::new(ruleset);
+
+            let req = make_request_with_options(
+                ToolKind::Execute,
+                Some(json!({"command": "git status"})),
+                standard_options(),
+            );
+
+            match proxy.handle_permission_request(&req) {
+                PermissionDecision::Respond(response) => {
+                    assert_eq!(
+                        response.outcome,
+                        RequestPermissionOutcome::Selected {
+                            option_id: PermissionOptionId::from("allow-once".to_string())
+                        }
+                    );
+                }
+                PermissionDecision::Forward => panic!("Expected Respond, got Forward"),
+            }
+        }
+
+        #[test]
+        fn test_proxy_denies_matching_command() {
+            let ruleset = Ruleset::new(vec![Rule::new(Matcher::command("sudo"), Outcome::Deny)]);
+            let proxy = ToolcapProxy::new(ruleset);
+
+            let req = make_request_with_options(
+                ToolKind::Execute,
+                Some(json!({"command": "sudo rm -rf /"})),
+                standard_options(),
+            );
+
+            match proxy.handle_permission_request(&req) {
+                PermissionDecision::Respond(response) => {
+                    assert_eq!(
+                        response.outcome,
+                        RequestPermissionOutcome::Selected {
+                            option_id: PermissionOptionId::from("reject-once".to_string())
+                        }
+                    );
+                }
+                PermissionDecision::Forward => panic!("Expected Respond, got Forward"),
+            }
+        }
+
+        #[test]
+        fn test_proxy_response_carries_rule_metadata() {
+            let ruleset = Ruleset::new(vec![
+                Rule::new(
+                    Matcher::command("git")
+                        .with_subcommand("push")
+                        .with_flag("--force"),
+                    Outcome::Deny,
+                )
+                .with_id("no-force-push")
+                .with_description("Block force-pushes")
+                .with_message("force-push is blocked by team policy; open a PR instead"),
+            ]);
+            let proxy = ToolcapProxy::new(ruleset);
+
+            let req = make_request_with_options(
+                ToolKind::Execute,
+                Some(json!({"command": "git push --force origin main"})),
+                standard_options(),
+            );
+
+            match proxy.handle_permission_request(&req) {
+                PermissionDecision::Respond(response) => {
+                    let rule = json!({
+                        "index": 0,
+                        "id": "no-force-push",
+                        "description": "Block force-pushes",
+                        "message": "force-push is blocked by team policy; open a PR instead",
+                    });
+                    let meta = response.meta.unwrap();
+                    assert_eq!(meta["toolcap"]["outcome"], "deny");
+                    assert_eq!(meta["toolcap"]["rules"], json!([rule]));
+                    assert_eq!(
+                        meta["toolcap"]["denied"],
+                        json!([{ "command": "git push --force origin main", "rule": rule }])
+                    );
+                    assert!(
+                        meta["toolcap"]["reason"]
+                            .as_str()
+                            .unwrap()
+                            .contains("open a PR instead")
+                    );
+                }
+                PermissionDecision::Forward => panic!("Expected Respond, got Forward"),
+            }
+        }
+
+        #[test]
+        fn test_proxy_forwards_unknown_command() {
+            let ruleset = Ruleset::new(vec![Rule::new(
+                Matcher::command("git").with_subcommand("status"),
+                Outcome::Allow,
+            )]);
+            let proxy = ToolcapProxy::new(ruleset);
+
+            let req = make_request_with_options(
+                ToolKind::Execute,
+                Some(json!({"command": "npm install"})),
+                standard_options(),
+            );
+
+            assert_eq!(
+                proxy.handle_permission_request(&req),
+                PermissionDecision::Forward
+            );
+        }
+
+        #[test]
+        fn test_proxy_with_remembered_decisions() {
+            let ruleset = Ruleset::new(vec![Rule::new(
+                Matcher::command("git").with_subcommand("status"),
+                Outcome::Allow,
+            )]);
+            let proxy = ToolcapProxy::new(ruleset).with_remembered_decisions(true);
+
+            let req = make_request_with_options(
+                ToolKind::Execute,
+                Some(json!({"command": "git status"})),
+                standard_options(),
+            );
+
+            match proxy.handle_permission_request(&req) {
+                PermissionDecision::Respond(response) => {
+                    assert_eq!(
+                        response.outcome,
+                        RequestPermissionOutcome::Selected {
+                            option_id: PermissionOptionId::from("allow-always".to_string())
+                        }
+                    );
+                }
+                PermissionDecision::Forward => panic!("Expected Respond, got Forward"),
+            }
+        }
+
+        #[test]
+        fn test_proxy_falls_back_to_once_if_always_not_available() {
+            let ruleset = Ruleset::new(vec![Rule::new(
+                Matcher::command("git").with_subcommand("status"),
+                Outcome::Allow,
+            )]);
+            let proxy = ToolcapProxy::new(ruleset).with_remembered_decisions(true);
+
+            // Only provide "once" options
+            let options = vec![
+                make_permission_option("allow-once", PermissionOptionKind::AllowOnce),
+                make_permission_option("reject-once", PermissionOptionKind::RejectOnce),
+            ];
+
+            let req = make_request_with_options(
+                ToolKind::Execute,
+                Some(json!({"command": "git status"})),
+                options,
+            );
+
+            match proxy.handle_permission_request(&req) {
+                PermissionDecision::Respond(response) => {
+                    assert_eq!(
+                        response.outcome,
+                        RequestPermissionOutcome::Selected {
+                            option_id: PermissionOptionId::from("allow-once".to_string())
+                        }
+                    );
+                }
+                PermissionDecision::Forward => panic!("Expected Respond, got Forward"),
+            }
+        }
+
+        #[test]
+        fn test_proxy_forwards_if_no_suitable_option() {
+            let ruleset = Ruleset::new(vec![Rule::new(
+                Matcher::command("git").with_subcommand("status"),
+                Outcome::Allow,
+            )]);
+            let proxy = ToolcapProxy::new(ruleset);
+
+            // Only provide reject options (no allow options)
+            let options = vec![
+                make_permission_option("reject-once", PermissionOptionKind::RejectOnce),
+                make_permission_option("reject-always", PermissionOptionKind::RejectAlways),
+            ];
+
+            let req = make_request_with_options(
+                ToolKind::Execute,
+                Some(json!({"command": "git status"})),
+                options,
+            );
+
+            // Should forward because there's no AllowOnce option to select
+            assert_eq!(
+                proxy.handle_permission_request(&req),
+                PermissionDecision::Forward
+            );
+        }
+
+        #[test]
+        fn test_proxy_evaluate_returns_outcome() {
+            let ruleset = Ruleset::new(vec![
+                Rule::new(
+                    Matcher::command("git").with_subcommand("status"),
+                    Outcome::Allow,
+                ),
+                Rule::new(Matcher::command("sudo"), Outcome::Deny),
+            ]);
+            let proxy = ToolcapProxy::new(ruleset);
+
+            let req = make_request_with_options(
+                ToolKind::Execute,
+                Some(json!({"command": "git status"})),
+                vec![],
+            );
+            assert_eq!(proxy.evaluate(&req), Outcome::Allow);
+
+            let req = make_request_with_options(
+                ToolKind::Execute,
+                Some(json!({"command": "sudo rm -rf /"})),
+                vec![],
+            );
+            assert_eq!(proxy.evaluate(&req), Outcome::Deny);
+
+            let req = make_request_with_options(
+                ToolKind::Execute,
+                Some(json!({"command": "npm install"})),
+                vec![],
+            );
+            assert_eq!(proxy.evaluate(&req), Outcome::Unknown);
+        }
+
+        #[test]
+        fn test_proxy_ruleset_accessor() {
+            let ruleset = Ruleset::new(vec![Rule::new(Matcher::command("git"), Outcome::Allow)]);
+            let proxy = ToolcapProxy::new(ruleset.clone());
+
+            // Verify the ruleset is accessible
+            let req = make_request_with_options(
+                ToolKind::Execute,
+                Some(json!({"command": "git status"})),
+                vec![],
+            );
+            assert_eq!(proxy.ruleset().evaluate_request(&req), Outcome::Allow);
+        }
+
+        #[test]
+        fn test_proxy_annotates_forwarded_request() {
+            let ruleset = Ruleset::new(vec![Rule::new(Matcher::command("cargo"), Outcome::Allow)]);
+            let mut req = make_request_with_options(
+                ToolKind::Execute,
+                Some(json!({"command": "cargo build && npm publish"})),
+                standard_options(),
+            );
+            req.tool_call.fields.title = Some("`cargo build && npm publish`".into());
+            req.meta = Some(json!({"agent": "data"}));
+
+            let proxy = ToolcapProxy::new(ruleset.clone());
+            let decision = proxy.explain(&req);
+            assert!(!proxy.forward_annotations());
+            assert_eq!(proxy.forwarded_request(&req, &decision), req);
+
+            let proxy = ToolcapProxy::new(ruleset).with_forward_annotations(true);
+            let forwarded = proxy.forwarded_request(&req, &decision);
+            assert_eq!(
+                forwarded.tool_call.fields.title.as_deref(),
+                Some(
+                    "`cargo build && npm publish` [toolcap: allowed `cargo build`; \
+                     undecided `npm publish` (no matching rule)]"
+                )
+            );
+            let meta = forwarded.meta.unwrap();
+            assert_eq!(meta["agent"], "data");
+            assert_eq!(meta["toolcap"]["outcome"], "unknown");
+            assert_eq!(meta["toolcap"]["allowed"][0]["command"], "cargo build");
+            assert_eq!(meta["toolcap"]["unknown"][0]["reason"], "no matching rule");
+            assert_eq!(
+                meta["toolcap"]["suggestions"][1]["rule"],
+                "`npm publish` with any arguments except dangerous flags"
+            );
+            assert_eq!(forwarded.options, req.options);
+        }
+    }
+
+    mod agents {
+        use super::*;
+
+        fn initialize(agent_name: &str) -> InitializeResponse {
+            serde_json::from_value(json!({
+                "protocolVersion": 1,
+                "agentInfo": {"name": agent_name, "version": "1.0.0"},
+            }))
+            .unwrap()
+        }
+
+        #[test]
+        fn test_observe_initialize() {
+            let proxy = ToolcapProxy::new(Ruleset::new(vec![]));
+            assert_eq!(proxy.agent(), None);
+
+            proxy.observe_initialize(&initialize("codex-acp"));
+            assert_eq!(
+                proxy.agent().map(|agent| agent.name.as_str()),
+                Some("codex-acp")
+            );
+            assert_eq!(
+                proxy.clone().agent().map(|agent| agent.name.as_str()),
+                Some("codex-acp")
+            );
+        }
+
+        #[test]
+        fn test_agent_adapter_used_for_requests() {
+            let ruleset = Ruleset::new(vec![Rule::new(
+                Matcher::command("cargo").with_subcommand("test"),
+                Outcome::Allow,
+            )]);
+            let proxy = ToolcapProxy::new(ruleset);
+            proxy.observe_initialize(&initialize("codex-acp"));
+
+            let req = make_request(
+                ToolKind::Execute,
+                Some(json!({"command": ["bash", "-lc", "cargo test"], "cwd": "/p"})),
+            );
+            assert_eq!(
+                proxy.operation(&req),
+                Operation::execute_in("cargo test", "/p")
+            );
+            assert_eq!(proxy.evaluate(&req), Outcome::Allow);
+        }
+
+        #[test]
+        fn test_without_adapters() {
+            let proxy =
+                ToolcapProxy::new(Ruleset::new(vec![])).with_adapters(AdapterRegistry::empty());
+            proxy.observe_initialize(&initialize("claude-code-acp"));
+
+            let req = make_request(ToolKind::Read, Some(json!({"file_path": "/p/a.rs"})));
+            assert_eq!(
+                proxy.operation(&req),
+                Operation::Read {
+                    path: "/p/a.rs".into()
+                }
+            );
+
+            // Without the Claude Code adapter, `Task` is an unnamed tool call.
+            let req = make_request(
+                ToolKind::Other,
+                Some(json!({"description": "Find tests", "subagent_type": "general"})),
+            );
+            assert!(matches!(
+                proxy.operation(&req),
+                Operation::Other {
+                    description: None,
+                    ..
+                }
+            ));
+        }
+    }
+
+    mod sessions {
+        use super::*;
+        use crate::operation::OperationKind;
+
+        // Directory matching canonicalizes paths, so these tests use real
+        // directories: the crate root and its `src` directory.
+        fn project_dir() -> PathBuf {
+            std::env::current_dir().unwrap()
+        }
+
+        fn new_session(cwd: PathBuf, session_id: &str) -> (NewSessionRequest, NewSessionResponse) {
+            let request = NewSessionRequest {
+                cwd,
+                mcp_servers: vec![],
+                meta: None,
+            };
+            let response = serde_json::from_value(json!({"sessionId": session_id})).unwrap();
+            (request, response)
+        }
+
+        fn scoped_proxy() -> ToolcapProxy {
+            ToolcapProxy::new(Ruleset::new(vec![Rule::new(
+                Matcher::and(vec![
+                    Matcher::within_directory(project_dir().join("src")),
+                    Matcher::command("rm"),
+                ]),
+                Outcome::Allow,
+            )]))
+        }
+
+        fn rm_request(raw_input: serde_json::Value) -> RequestPermissionRequest {
+            make_request(ToolKind::Execute, Some(raw_input))
+        }
+
+        #[test]
+        fn test_new_session_sets_working_dir() {
+            let proxy = scoped_proxy();
+            let req = rm_request(json!({"command": "rm lib.rs"}));
+            assert_eq!(proxy.evaluate(&req), Outcome::Unknown);
+
+            let (new_req, new_resp) = new_session(project_dir().join("src"), "test-session");
+            proxy.observe_new_session(&new_req, &new_resp);
+            assert_eq!(
+                proxy.session(&"test-session".into()),
+                Some(SessionContext::new(project_dir().join("src")))
+            );
+            assert_eq!(proxy.evaluate(&req), Outcome::Allow);
+
+            let (other_req, other_resp) = new_session(project_dir(), "test-session");
+            proxy.observe_new_session(&other_req, &other_resp);
+            assert_eq!(proxy.evaluate(&req), Outcome::Unknown);
+        }
+
+        #[test]
+        fn test_load_session_sets_working_dir() {
+            let proxy = scoped_proxy();
+            proxy.observe_load_session(&LoadSessionRequest {
+                mcp_servers: vec![],
+                cwd: project_dir().join("src"),
+                session_id: "test-session".into(),
+                meta: None,
+            });
+
+            let req = rm_request(json!({"command": "rm lib.rs"}));
+            assert_eq!(proxy.evaluate(&req), Outcome::Allow);
+
+            proxy.forget_session(&"test-session".into());
+            assert_eq!(proxy.session(&"test-session".into()), None);
+            assert_eq!(proxy.evaluate(&req), Outcome::Unknown);
+        }
+
+        #[test]
+        fn test_session_mode_selects_rules() {
+            let proxy = ToolcapProxy::new(Ruleset::new(vec![
+                Rule::new(Matcher::command("ls"), Outcome::Allow),
+                Rule::new(Matcher::any_execute(), Outcome::Deny).with_modes(["plan"]),
+                Rule::new(Matcher::command("cargo"), Outcome::Allow),
+            ]));
+            let req = rm_request(json!({"command": "cargo build"}));
+            let session_id = req.session_id.clone();
+
+            let (new_req, _) = new_session(project_dir(), "test-session");
+            let new_resp = serde_json::from_value(json!({
+                "sessionId": "test-session",
+                "modes": {
+                    "currentModeId": "plan",
+                    "availableModes": [
+                        { "id": "plan", "name": "Plan" },
+                        { "id": "code", "name": "Code" },
+                    ],
+                },
+            }))
+            .unwrap();
+            proxy.observe_new_session(&new_req, &new_resp);
+            assert_eq!(proxy.session(&session_id).unwrap().mode(), Some("plan"));
+            assert_eq!(proxy.evaluate(&req), Outcome::Deny);
+
+            let update: SessionNotification = serde_json::from_value(json!({
+                "sessionId": "test-session",
+                "update": { "sessionUpdate": "current_mode_update", "currentModeId": "code" },
+            }))
+            .unwrap();
+            assert_eq!(
+                proxy
+                    .observe_session_update(&update)
+                    .map(ToString::to_string),
+                Some("code".to_string())
+            );
+            assert_eq!(proxy.evaluate(&req), Outcome::Allow);
+
+            assert!(proxy.observe_session_mode(&session_id, &SessionModeId("plan".into())));
+            assert_eq!(proxy.evaluate(&req), Outcome::Deny);
+
+            // Modes of unknown sessions aren't recorded
+            assert!(!proxy.observe_session_mode(&"other".into(), &SessionModeId("plan".into())));
+        }
+
+        #[test]
+        fn test_history_conditions_rules() {
+            let src = project_dir().join("src");
+            let proxy = ToolcapProxy::new(Ruleset::new(vec![
+                Rule::new(
+                    Matcher::and(vec![
+                        Matcher::command("git").with_subcommand("push"),
+                        Matcher::after_success(Matcher::command("cargo").with_subcommand("test")),
+                    ]),
+                    Outcome::Allow,
+                ),
+                Rule::new(Matcher::command("cargo"), Outcome::Allow),
+                Rule::new(
+                    Matcher::and(vec![
+                        Matcher::kind(OperationKind::Fetch),
+                        Matcher::after(Matcher::and(vec![
+                            Matcher::kind(OperationKind::Read),
+                            Matcher::path_within(&src),
+                        ])),
+                    ]),
+                    Outcome::Deny,
+                ),
+                Rule::new(Matcher::kind(OperationKind::Fetch), Outcome::Allow),
+            ]));
+            let (new_req, new_resp) = new_session(project_dir(), "test-session");
+            proxy.observe_new_session(&new_req, &new_resp);
+            let session_id = new_resp.session_id;
+            let update = |update: serde_json::Value| {
+                let notification: SessionNotification = serde_json::from_value(
+                    json!({ "sessionId": "test-session", "update": update }),
+                )
+                .unwrap();
+                assert!(proxy.observe_session_update(&notification).is_none());
+            };
+
+            let push = make_request(ToolKind::Execute, Some(json!({"command": "git push"})));
+            assert_eq!(proxy.evaluate(&push), Outcome::Unknown);
+
+            // Tests the agent only reported passing don't count
+            update(json!({
+                "sessionUpdate": "tool_call",
+                "toolCallId": "forged",
+                "title": "cargo test",
+                "kind": "execute",
+                "status": "completed",
+                "rawInput": { "command": "cargo test" },
+            }));
+            assert_eq!(proxy.evaluate(&push), Outcome::Unknown);
+
+            // Nor do they once the agent asks about the same tests, or
+            // something else, under their tool call id
+            for command in ["cargo test", "cargo build"] {
+                let mut reuse = make_request(ToolKind::Execute, Some(json!({"command": command})));
+                reuse.tool_call.id = ToolCallId("forged".into());
+                reuse.options = vec![make_permission_option(
+                    "allow",
+                    PermissionOptionKind::AllowOnce,
+                )];
+                assert!(matches!(
+                    proxy.handle_permission_request(&reuse),
+                    PermissionDecision::Respond(_)
+                ));
+                assert_eq!(proxy.evaluate(&push), Outcome::Unknown);
+            }
+
+            // The tests are allowed, but haven't passed yet
+            let mut test = make_request(ToolKind::Execute, Some(json!({"command": "cargo test"})));
+            test.tool_call.id = ToolCallId("tests".into());
+            test.options = vec![make_permission_option(
+                "allow",
+                PermissionOptionKind::AllowOnce,
+            )];
+            assert!(matches!(
+                proxy.handle_permission_request(&test),
+                PermissionDecision::Respond(_)
+            ));
+            let history = proxy.history(&session_id);
+            let tests = history.entries().last().unwrap();
+            assert_eq!(tests.status(), OperationStatus::Running);
+            assert_eq!(tests.tool_call_id(), Some("tests"));
+            assert!(tests.is_permitted());
+            assert_eq!(proxy.evaluate(&push), Outcome::Unknown);
+
+            update(json!({
+                "sessionUpdate": "tool_call_update",
+                "toolCallId": "tests",
+                "status": "completed",
+            }));
+            assert_eq!(proxy.evaluate(&push), Outcome::Allow);
+
+            // A read the agent reported without asking arms the fetch guard
+            let fetch = make_request(ToolKind::Fetch, Some(json!({"url": "https://example.com"})));
+            assert_eq!(proxy.evaluate(&fetch), Outcome::Allow);
+            update(json!({
+                "sessionUpdate": "tool_call",
+                "toolCallId": "read",
+                "title": "Read lib.rs",
+                "kind": "read",
+                "status": "in_progress",
+                "rawInput": { "file_path": src.join("lib.rs") },
+            }));
+            assert_eq!(proxy.evaluate(&fetch), Outcome::Deny);
+
+            // Another session's history is its own
+            let mut elsewhere = fetch.clone();
+            elsewhere.session_id = "other".into();
+            assert_eq!(proxy.evaluate(&elsewhere), Outcome::Allow);
+
+            proxy.forget_session(&session_id);
+            assert!(proxy.history(&session_id).is_empty());
+        }
+
+        #[test]
+        fn test_per_call_cwd_resolved_against_session() {
+            let proxy = scoped_proxy();
+            let (new_req, new_resp) = new_session(project_dir(), "test-session");
+            proxy.observe_new_session(&new_req, &new_resp);
+
+            let relative = rm_request(json!({"command": "rm lib.rs", "cwd": "src"}));
+            assert_eq!(proxy.evaluate(&relative), Outcome::Allow);
+
+            let absolute = rm_request(json!({
+                "command": "rm lib.rs",
+                "cwd": project_dir().join("src"),
+            }));
+            assert_eq!(proxy.evaluate(&absolute), Outcome::Allow);
+
+            let outside = rm_request(json!({"command": "rm lib.rs", "cwd": "examples"}));
+            assert_eq!(proxy.evaluate(&outside), Outcome::Unknown);
+        }
+
+        #[test]
+        fn test_sessions_shared_between_clones() {
+            let proxy = scoped_proxy();
+            let clone = proxy.clone();
+            let (new_req, new_resp) = new_session(project_dir().join("src"), "test-session");
+            proxy.observe_new_session(&new_req, &new_resp);
+
+            let req = rm_request(json!({"command": "rm lib.rs"}));
+            assert_eq!(clone.evaluate(&req), Outcome::Allow);
+        }
+
+        #[test]
+        fn test_cd_tracked_from_session_dir() {
+            let mut rules = scoped_proxy().ruleset().rules().to_vec();
+            rules.push(Rule::new(Matcher::command("cd"), Outcome::Allow));
+            let proxy = ToolcapProxy::new(Ruleset::new(rules));
+            let (new_req, new_resp) = new_session(project_dir(), "test-session");
+            proxy.observe_new_session(&new_req, &new_resp);
+
+            let into = rm_request(json!({"command": "cd src && rm lib.rs"}));
+            assert_eq!(proxy.evaluate(&into), Outcome::Allow);
+
+            let out = rm_request(json!({"command": "cd src/.. && rm lib.rs"}));
+            assert_eq!(proxy.evaluate(&out), Outcome::Unknown);
+        }
+
+        #[test]
+        fn test_policy_resolver_picks_session_ruleset() {
+            let src = project_dir().join("src");
+            let resolver = PolicyResolver::new().with_directory_ruleset(
+                &src,
+                Ruleset::new(vec![Rule::new(Matcher::command("cargo"), Outcome::Allow)]),
+            );
+            let proxy = ToolcapProxy::new(Ruleset::new(vec![Rule::new(
+                Matcher::command("cargo"),
+                Outcome::Deny,
+            )]))
+            .with_policy_resolver(resolver);
+            let req = rm_request(json!({"command": "cargo build"}));
+            let session_id = req.session_id.clone();
+
+            // Unknown sessions use the proxy's ruleset.
+            assert_eq!(proxy.evaluate(&req), Outcome::Deny);
+            assert_eq!(proxy.resolve_session_policy(&session_id).0, None);
+
+            // Sessions are resolved when first needed.
+            let (new_req, new_resp) = new_session(src.clone(), "test-session");
+            proxy.observe_new_session(&new_req, &new_resp);
+            assert_eq!(proxy.session_policy(&session_id), None);
+            assert_eq!(proxy.evaluate(&req), Outcome::Allow);
+            assert_eq!(
+                proxy.session_policy(&session_id),
+                Some(PolicySource::Directory(src.clone()))
+            );
+
+            // Loading the session elsewhere resolves it again.
+            proxy.observe_load_session(&LoadSessionRequest {
+                session_id: session_id.clone(),
+                cwd: project_dir(),
+                mcp_servers: vec![],
+                meta: None,
+            });
+            assert_eq!(proxy.resolve_session_policy(&session_id).0, None);
+            assert_eq!(proxy.evaluate(&req), Outcome::Deny);
+
+            // The resolved ruleset is released with the session.
+            let (new_req, new_resp) = new_session(src, "test-session");
+            proxy.observe_new_session(&new_req, &new_resp);
+            assert!(proxy.resolve_session_policy(&session_id).0.is_some());
+            proxy.forget_session(&session_id);
+            assert_eq!(proxy.session_policy(&session_id), None);
+        }
+    }
+
+    mod grants {
+        use super::*;
+
+        pub(super) fn proxy() -> ToolcapProxy {
+            ToolcapProxy::new(Ruleset::new(vec![
+                Rule::new(Matcher::command("ls"), Outcome::Allow),
+                Rule::new(
+                    Matcher::command("git").with_subcommand("push"),
+                    Outcome::Deny,
+                ),
+            ]))
+        }
+
+        pub(super) fn request(command: &str) -> RequestPermissionRequest {
+            let mut req = make_request(ToolKind::Execute, Some(json!({"command": command})));
+            req.options = vec![
+                make_permission_option("allow-once", PermissionOptionKind::AllowOnce),
+                make_permission_option("allow-always", PermissionOptionKind::AllowAlways),
+                make_permission_option("reject-once", PermissionOptionKind::RejectOnce),
+                make_permission_option("reject-always", PermissionOptionKind::RejectAlways),
+            ];
+            req
+        }
+
+        pub(super) fn select(option_id: &str) -> RequestPermissionResponse {
+            RequestPermissionResponse {
+                outcome: RequestPermissionOutcome::Selected {
+                    option_id: PermissionOptionId::from(option_id.to_string()),
+                },
+                meta: None,
+            }
+        }
+
+        #[test]
+        fn test_allow_always_remembered() {
+            let proxy = proxy();
+            let req = request("cargo test --lib");
+            assert!(matches!(
+                proxy.handle_permission_request(&req),
+                PermissionDecision::Forward
+            ));
+
+            let grants = proxy.observe_permission_response(&req, &select("allow-always"));
+            assert_eq!(grants.len(), 1);
+            assert_eq!(grants[0].outcome(), Outcome::Allow);
+            assert_eq!(
+                grants[0].rule().description(),
+                Some("Remembered from `cargo test --lib`")
+            );
+
+            assert_eq!(proxy.evaluate(&req), Outcome::Allow);
+            assert_eq!(proxy.evaluate(&request("cargo test")), Outcome::Unknown);
+
+            // Responses name the grant that decided them.
+            let PermissionDecision::Respond(response) = proxy.handle_permission_request(&req)
+            else {
+                panic!("Expected a response");
+            };
+            assert_eq!(
+                response.meta.unwrap()["toolcap"]["rules"][0]["id"],
+                grants[0].id()
+            );
+        }
+
+        #[test]
+        fn test_one_time_answers_not_remembered() {
+            let proxy = proxy();
+            let req = request("cargo test");
+            assert!(
+                proxy
+                    .observe_permission_response(&req, &select("allow-once"))
+                    .is_empty()
+            );
+            let cancelled = RequestPermissionResponse {
+                outcome: RequestPermissionOutcome::Cancelled,
+                meta: None,
+            };
+            assert!(
+                proxy
+                    .observe_permission_response(&req, &cancelled)
+                    .is_empty()
+            );
+            assert!(
+                proxy
+                    .observe_permission_response(&req, &select("no-such-option"))
+                    .is_empty()
+            );
+            assert!(proxy.grants(&req.session_id).is_empty());
+        }
+
+        #[test]
+        fn test_reject_always_remembered() {
+            let proxy = proxy();
+            let req = request("ls && curl example.com");
+            proxy.observe_permission_response(&req, &select("reject-always"));
+            assert_eq!(proxy.evaluate(&req), Outcome::Deny);
+            assert_eq!(proxy.evaluate(&request("curl example.com")), Outcome::Deny);
+        }
+
+        #[test]
+        fn test_subcommand_scope() {
+            let proxy = proxy().with_grant_scope(GrantScope::Subcommand);
+            let req = request("cargo test --lib");
+            proxy.observe_permission_response(&req, &select("allow-always"));
+            assert_eq!(proxy.evaluate(&request("cargo test")), Outcome::Allow);
+            assert_eq!(proxy.evaluate(&request("cargo build")), Outcome::Unknown);
+
+            // Grants don't override the ruleset's denies
+            let proxy = ToolcapProxy::new(Ruleset::new(vec![Rule::new(
+                Matcher::command("git")
+                    .with_subcommand("push")
+                    .with_flag("--mirror"),
+                Outcome::Deny,
+            )]))
+            .with_grant_scope(GrantScope::Subcommand);
+            let req = request("git push origin main");
+            proxy.observe_permission_response(&req, &select("allow-always"));
+            assert_eq!(
+                proxy.evaluate(&request("git push origin dev")),
+                Outcome::Allow
+            );
+            assert_eq!(proxy.evaluate(&request("git push --mirror")), Outcome::Deny);
         }
 
         #[test]
-        fn test_proxy_allows_matching_command() {
-            let ruleset = Ruleset::new(vec![Rule::new(
-                Matcher::command("git").with_subcommand("status"),
-                Outcome::Allow,
-            )]);
-            let proxy = ToolcapProxy::new(ruleset);
+        fn test_grants_are_per_session() {
+            let proxy = proxy();
+            let req = request("cargo test");
+            proxy.observe_permission_response(&req, &select("allow-always"));
+
+            let mut other = request("cargo test");
+            other.session_id = "other-session".into();
+            assert_eq!(proxy.evaluate(&other), Outcome::Unknown);
+            assert!(proxy.grants(&other.session_id).is_empty());
+        }
 
-            let req = make_request_with_options(
-                ToolKind::Execute,
-                Some(json!({"command": "git status"})),
-                standard_options(),
+        #[test]
+        fn test_revoke_grant() {
+            let proxy = proxy();
+            let req = request("cargo test && make");
+            let grants = proxy.observe_permission_response(&req, &select("allow-always"));
+            assert_eq!(grants.len(), 2);
+            assert_eq!(proxy.evaluate(&req), Outcome::Allow);
+
+            let revoked = proxy.revoke_grant(&req.session_id, grants[1].id()).unwrap();
+            assert_eq!(revoked.id(), grants[1].id());
+            assert!(
+                proxy
+                    .revoke_grant(&req.session_id, grants[1].id())
+                    .is_none()
             );
+            assert_eq!(proxy.evaluate(&req), Outcome::Unknown);
+            assert_eq!(proxy.evaluate(&request("cargo test")), Outcome::Allow);
 
-            match proxy.handle_permission_request(&req) {
-                PermissionDecision::Respond(response) => {
-                    assert_eq!(
-                        response.outcome,
-                        RequestPermissionOutcome::Selected {
-                            option_id: PermissionOptionId::from("allow-once".to_string())
-                        }
-                    );
-                }
-                PermissionDecision::Forward => panic!("Expected Respond, got Forward"),
-            }
+            proxy.clear_grants(&req.session_id);
+            assert!(proxy.grants(&req.session_id).is_empty());
+            assert_eq!(proxy.evaluate(&request("cargo test")), Outcome::Unknown);
         }
 
         #[test]
-        fn test_proxy_denies_matching_command() {
-            let ruleset = Ruleset::new(vec![Rule::new(Matcher::command("sudo"), Outcome::Deny)]);
-            let proxy = ToolcapProxy::new(ruleset);
+        fn test_learned_policy() {
+            let path = std::env::temp_dir()
+                .join(format!("toolcap-proxy-learned-{}", std::process::id()))
+                .join(LearnedPolicy::DEFAULT_PATH);
+            let _ = std::fs::remove_file(&path);
+
+            let first = proxy().with_learned_policy(LearnedPolicy::open(&path).unwrap());
+            let req = request("cargo test");
+            let grants = first.observe_permission_response(&req, &select("allow-always"));
+            let learned = first
+                .learned_policy()
+                .unwrap()
+                .learn(&req.session_id, &grants)
+                .unwrap();
+            assert_eq!(learned.len(), 1);
+
+            // A new proxy picks up the learned rule in any session, but it
+            // doesn't override the ruleset.
+            let proxy = proxy().with_learned_policy(LearnedPolicy::open(&path).unwrap());
+            let mut other = request("cargo test");
+            other.session_id = "other-session".into();
+            assert_eq!(proxy.evaluate(&other), Outcome::Allow);
+            assert_eq!(proxy.evaluate(&request("git push")), Outcome::Deny);
+
+            std::fs::remove_file(&path).unwrap();
+        }
 
-            let req = make_request_with_options(
-                ToolKind::Execute,
-                Some(json!({"command": "sudo rm -rf /"})),
-                standard_options(),
-            );
+        #[test]
+        fn test_forget_session_clears_grants() {
+            let proxy = proxy();
+            let req = request("cargo test");
+            proxy.observe_permission_response(&req, &select("allow-always"));
+            assert_eq!(proxy.grants(&req.session_id).len(), 1);
+
+            proxy.forget_session(&req.session_id);
+            assert!(proxy.grants(&req.session_id).is_empty());
+        }
+    }
 
-            match proxy.handle_permission_request(&req) {
-                PermissionDecision::Respond(response) => {
-                    assert_eq!(
-                        response.outcome,
-                        RequestPermissionOutcome::Selected {
-                            option_id: PermissionOptionId::from("reject-once".to_string())
-                        }
-                    );
-                }
-                PermissionDecision::Forward => panic!("Expected Respond, got Forward"),
+    mod observers {
+        use std::sync::Mutex;
+
+        use super::grants::{proxy, request, select};
+        use super::*;
+
+        /// Collects the records and disagreements it observes.
+        #[derive(Debug, Default)]
+        struct Recorder {
+            records: Mutex<Vec<DecisionRecord>>,
+            disagreements: Mutex<Vec<ShadowDisagreement>>,
+        }
+
+        impl DecisionObserver for Recorder {
+            fn observe(&self, record: &DecisionRecord) {
+                self.records.lock().unwrap().push(record.clone());
+            }
+
+            fn observe_shadow(&self, disagreement: &ShadowDisagreement) {
+                self.disagreements
+                    .lock()
+                    .unwrap()
+                    .push(disagreement.clone());
+            }
+        }
+
+        impl Recorder {
+            fn take(&self) -> Vec<DecisionRecord> {
+                std::mem::take(&mut *self.records.lock().unwrap())
+            }
+
+            fn take_disagreements(&self) -> Vec<ShadowDisagreement> {
+                std::mem::take(&mut *self.disagreements.lock().unwrap())
             }
         }
 
+        fn layers(record: &DecisionRecord) -> Vec<DecisionLayer> {
+            record.deciding_rules().map(|(_, layer)| layer).collect()
+        }
+
         #[test]
-        fn test_proxy_forwards_unknown_command() {
-            let ruleset = Ruleset::new(vec![Rule::new(
-                Matcher::command("git").with_subcommand("status"),
-                Outcome::Allow,
-            )]);
-            let proxy = ToolcapProxy::new(ruleset);
+        fn test_auto_decided_and_forwarded() {
+            let recorder = Arc::new(Recorder::default());
+            let proxy = proxy().with_observer(recorder.clone());
+
+            proxy.handle_permission_request(&request("git push origin"));
+            proxy.handle_permission_request(&request("cargo test"));
+            let records = recorder.take();
+            assert_eq!(records.len(), 2);
+
+            assert_eq!(records[0].session_id(), Some("test-session"));
+            assert_eq!(records[0].tool_call_id(), Some("test-call"));
+            assert_eq!(records[0].outcome(), Outcome::Deny);
+            assert_eq!(records[0].disposition(), &Disposition::AutoDecided);
+            assert_eq!(layers(&records[0]), [DecisionLayer::Ruleset]);
+
+            assert_eq!(records[1].outcome(), Outcome::Unknown);
+            assert_eq!(records[1].disposition(), &Disposition::Forwarded);
+            assert!(layers(&records[1]).is_empty());
+        }
 
-            let req = make_request_with_options(
-                ToolKind::Execute,
-                Some(json!({"command": "npm install"})),
-                standard_options(),
+        #[test]
+        fn test_user_choice() {
+            let recorder = Arc::new(Recorder::default());
+            let proxy = proxy().with_observer(recorder.clone());
+            let req = request("cargo test");
+
+            proxy.observe_permission_response(&req, &select("allow-once"));
+            proxy.observe_permission_response(&req, &select("no-such-option"));
+            proxy.observe_permission_response(
+                &req,
+                &RequestPermissionResponse {
+                    outcome: RequestPermissionOutcome::Cancelled,
+                    meta: None,
+                },
+            );
+            let choices: Vec<_> = recorder
+                .take()
+                .iter()
+                .map(|record| record.disposition().clone())
+                .collect();
+            assert_eq!(
+                choices,
+                [
+                    Disposition::UserChoice(UserChoice::AllowOnce),
+                    Disposition::UserChoice(UserChoice::Other("no-such-option".into())),
+                    Disposition::UserChoice(UserChoice::Cancelled),
+                ]
             );
+        }
 
+        #[test]
+        fn test_layers() {
+            let recorder = Arc::new(Recorder::default());
+            let learned = std::env::temp_dir()
+                .join(format!("toolcap-proxy-audit-{}", std::process::id()))
+                .join(LearnedPolicy::DEFAULT_PATH);
+            let _ = std::fs::remove_file(&learned);
+            let policy = LearnedPolicy::open(&learned).unwrap();
+
+            let proxy = proxy()
+                .with_learned_policy(policy.clone())
+                .with_observer(recorder.clone());
+            let req = request("cargo test");
+            let grants = proxy.observe_permission_response(&req, &select("allow-always"));
+            policy.learn(&req.session_id, &grants).unwrap();
+            recorder.take();
+
+            // The grant decides `cargo test` in its session, the learned rule
+            // decides it elsewhere.
+            proxy.handle_permission_request(&request("ls && cargo test"));
+            let mut other = request("cargo test");
+            other.session_id = "other-session".into();
+            proxy.handle_permission_request(&other);
+
+            let records = recorder.take();
             assert_eq!(
-                proxy.handle_permission_request(&req),
+                layers(&records[0]),
+                [DecisionLayer::Ruleset, DecisionLayer::Leading]
+            );
+            assert_eq!(layers(&records[1]), [DecisionLayer::Fallback]);
+
+            std::fs::remove_file(&learned).unwrap();
+        }
+
+        #[test]
+        fn test_shadow_ruleset() {
+            let recorder = Arc::new(Recorder::default());
+            let proxy = proxy()
+                .with_shadow_ruleset(Ruleset::new(vec![
+                    Rule::new(Matcher::command("ls").with_flag("-R"), Outcome::Deny),
+                    Rule::new(Matcher::command("ls"), Outcome::Allow),
+                    Rule::new(Matcher::command("cargo"), Outcome::Allow),
+                ]))
+                .with_observer(recorder.clone());
+
+            // The active ruleset still decides.
+            assert!(matches!(
+                proxy.handle_permission_request(&request("ls -R")),
+                PermissionDecision::Respond(_)
+            ));
+            assert!(matches!(
+                proxy.handle_permission_request(&request("cargo test")),
                 PermissionDecision::Forward
+            ));
+            proxy.handle_permission_request(&request("ls"));
+            assert_eq!(recorder.take().len(), 3);
+
+            let disagreements = recorder.take_disagreements();
+            let outcomes: Vec<_> = disagreements
+                .iter()
+                .map(|d| (d.active().outcome(), d.shadow().outcome()))
+                .collect();
+            assert_eq!(
+                outcomes,
+                [
+                    (Outcome::Allow, Outcome::Deny),
+                    (Outcome::Unknown, Outcome::Allow),
+                ]
+            );
+            assert_eq!(disagreements[0].session_id(), "test-session");
+            assert_eq!(disagreements[0].tool_call_id(), "test-call");
+            assert_eq!(
+                disagreements[0].to_string(),
+                "active ruleset: allow, shadow ruleset: deny\n\
+                 active:\n\
+                 ls -R: allow (rule #0)\n\
+                 shadow:\n\
+                 ls -R: deny (rule #0)\n"
             );
         }
 
         #[test]
-        fn test_proxy_with_remembered_decisions() {
-            let ruleset = Ruleset::new(vec![Rule::new(
-                Matcher::command("git").with_subcommand("status"),
-                Outcome::Allow,
-            )]);
-            let proxy = ToolcapProxy::new(ruleset).with_remembered_decisions(true);
+        fn test_shadow_sees_grants() {
+            let recorder = Arc::new(Recorder::default());
+            let proxy = proxy()
+                .with_shadow_ruleset(Ruleset::empty())
+                .with_observer(recorder.clone());
+            let req = request("cargo test");
+            proxy.observe_permission_response(&req, &select("allow-always"));
+
+            // Both rulesets allow the granted command.
+            proxy.handle_permission_request(&req);
+            assert!(recorder.take_disagreements().is_empty());
+        }
 
-            let req = make_request_with_options(
-                ToolKind::Execute,
-                Some(json!({"command": "git status"})),
-                standard_options(),
+        #[test]
+        fn test_ruleset_observers() {
+            let recorder = Arc::new(Recorder::default());
+            let proxy = ToolcapProxy::new(
+                Ruleset::new(vec![Rule::new(Matcher::command("ls"), Outcome::Allow)])
+                    .with_observer(recorder.clone()),
             );
+            proxy.handle_permission_request(&request("ls"));
 
-            match proxy.handle_permission_request(&req) {
-                PermissionDecision::Respond(response) => {
-                    assert_eq!(
-                        response.outcome,
-                        RequestPermissionOutcome::Selected {
-                            option_id: PermissionOptionId::from("allow-always".to_string())
-                        }
-                    );
-                }
-                PermissionDecision::Forward => panic!("Expected Respond, got Forward"),
-            }
+            let records = recorder.take();
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].disposition(), &Disposition::Evaluated);
+            assert_eq!(records[0].session_id(), None);
         }
 
         #[test]
-        fn test_proxy_falls_back_to_once_if_always_not_available() {
-            let ruleset = Ruleset::new(vec![Rule::new(
-                Matcher::command("git").with_subcommand("status"),
+        fn test_requests_are_recorded_once() {
+            let recorder = Arc::new(Recorder::default());
+            let rules = vec![Rule::new(Matcher::command("ls"), Outcome::Allow)];
+            let proxy = ToolcapProxy::new(Ruleset::new(rules).with_observer(recorder.clone()))
+                .with_shadow_ruleset(Ruleset::empty().with_observer(recorder.clone()))
+                .with_observer(recorder.clone());
+            let (ls, make) = (request("ls"), request("make"));
+            proxy.handle_permission_request(&ls);
+            proxy.handle_permission_request(&make);
+            let records = recorder.take();
+            let evaluated = |records: &[DecisionRecord]| {
+                records
+                    .iter()
+                    .filter(|record| record.disposition() == &Disposition::Evaluated)
+                    .count()
+            };
+            assert_eq!(evaluated(&records), 2);
+
+            // Queries, answers and timeouts don't decide the requests again
+            proxy.evaluate(&ls);
+            proxy.explain(&make);
+            proxy.explain_in_session(&make.session_id, Operation::execute("make"));
+            proxy.handle_forward_timeout(&make);
+            proxy.observe_permission_response(&make, &select("allow-once"));
+            assert_eq!(evaluated(&recorder.take()), 0);
+        }
+
+        #[test]
+        fn test_replaced_ruleset_keeps_observers() {
+            let recorder = Arc::new(Recorder::default());
+            let proxy = ToolcapProxy::new(Ruleset::empty().with_observer(recorder.clone()));
+            let snapshot = proxy.ruleset();
+
+            proxy.replace_ruleset(Ruleset::new(vec![Rule::new(
+                Matcher::command("ls"),
                 Outcome::Allow,
-            )]);
-            let proxy = ToolcapProxy::new(ruleset).with_remembered_decisions(true);
+            )]));
+            assert!(matches!(
+                proxy.handle_permission_request(&request("ls")),
+                PermissionDecision::Respond(_)
+            ));
+            assert_eq!(recorder.take().len(), 1);
+            assert!(snapshot.rules().is_empty());
+
+            // A ruleset with observers of its own replaces them.
+            let other = Arc::new(Recorder::default());
+            proxy.replace_ruleset(Ruleset::empty().with_observer(other.clone()));
+            proxy.handle_permission_request(&request("ls"));
+            assert!(recorder.take().is_empty());
+            assert_eq!(other.take().len(), 1);
+        }
+    }
 
-            // Only provide "once" options
-            let options = vec![
-                make_permission_option("allow-once", PermissionOptionKind::AllowOnce),
-                make_permission_option("reject-once", PermissionOptionKind::RejectOnce),
-            ];
+    mod headless {
+        use super::grants::{proxy, request};
+        use super::*;
 
-            let req = make_request_with_options(
-                ToolKind::Execute,
-                Some(json!({"command": "git status"})),
-                options,
+        fn selected(decision: PermissionDecision) -> (String, serde_json::Value) {
+            let PermissionDecision::Respond(response) = decision else {
+                panic!("Expected a response");
+            };
+            let RequestPermissionOutcome::Selected { option_id } = response.outcome else {
+                panic!("Expected a selected option");
+            };
+            (option_id.to_string(), response.meta.unwrap())
+        }
+
+        #[test]
+        fn test_headless_default() {
+            let default = DefaultDecision::deny().with_reason("running in CI");
+            let proxy = proxy().with_headless(default);
+            let (option_id, meta) =
+                selected(proxy.handle_permission_request(&request("cargo test")));
+            assert_eq!(option_id, "reject-once");
+            assert_eq!(meta["toolcap"]["outcome"], "unknown");
+            assert_eq!(
+                meta["toolcap"]["default"],
+                json!({ "outcome": "deny", "cause": "headless", "reason": "running in CI" })
             );
+            let reason = meta["toolcap"]["reason"].as_str().unwrap();
+            assert!(reason.contains("- `cargo test`: not decided by any rule"));
+            assert!(reason.contains("denied by default: running in CI"));
+
+            // Requests the rules decide are answered as usual.
+            let (option_id, meta) = selected(proxy.handle_permission_request(&request("ls")));
+            assert_eq!(option_id, "allow-once");
+            assert!(meta["toolcap"].get("default").is_none());
+
+            let proxy = proxy.with_headless(DefaultDecision::allow());
+            let (option_id, _) = selected(proxy.handle_permission_request(&request("cargo test")));
+            assert_eq!(option_id, "allow-once");
+        }
 
-            match proxy.handle_permission_request(&req) {
-                PermissionDecision::Respond(response) => {
-                    assert_eq!(
-                        response.outcome,
-                        RequestPermissionOutcome::Selected {
-                            option_id: PermissionOptionId::from("allow-once".to_string())
-                        }
-                    );
-                }
-                PermissionDecision::Forward => panic!("Expected Respond, got Forward"),
+        #[test]
+        fn test_headless_without_option() {
+            let proxy = proxy().with_headless(DefaultDecision::deny());
+            let mut req = request("cargo test");
+            req.options
+                .retain(|opt| opt.kind == PermissionOptionKind::AllowOnce);
+            let PermissionDecision::Respond(response) = proxy.handle_permission_request(&req)
+            else {
+                panic!("Expected a response");
+            };
+            assert_eq!(response.outcome, RequestPermissionOutcome::Cancelled);
+        }
+
+        #[test]
+        fn test_default_never_overrides_rules() {
+            let only_allow = |command| {
+                let mut req = request(command);
+                req.options
+                    .retain(|opt| opt.kind == PermissionOptionKind::AllowOnce);
+                req
+            };
+            let only_reject = |command| {
+                let mut req = request(command);
+                req.options
+                    .retain(|opt| opt.kind == PermissionOptionKind::RejectOnce);
+                req
+            };
+
+            // A denied request with no reject option is cancelled, headless or not.
+            for proxy in [proxy(), proxy().with_headless(DefaultDecision::allow())] {
+                let PermissionDecision::Respond(response) =
+                    proxy.handle_permission_request(&only_allow("git push origin"))
+                else {
+                    panic!("Expected a response");
+                };
+                assert_eq!(response.outcome, RequestPermissionOutcome::Cancelled);
+                assert_eq!(response.meta.unwrap()["toolcap"]["outcome"], "deny");
             }
+
+            // An allowed request with no allow option isn't denied by the default.
+            let proxy = proxy().with_headless(DefaultDecision::deny());
+            let PermissionDecision::Respond(response) =
+                proxy.handle_permission_request(&only_reject("ls"))
+            else {
+                panic!("Expected a response");
+            };
+            assert_eq!(response.outcome, RequestPermissionOutcome::Cancelled);
+            assert!(response.meta.unwrap()["toolcap"].get("default").is_none());
+            assert!(proxy.denial_summary().is_empty());
+
+            let proxy =
+                proxy.with_forward_timeout(Duration::from_secs(30), DefaultDecision::deny());
+            let response = proxy.handle_forward_timeout(&only_reject("ls"));
+            assert_eq!(response.outcome, RequestPermissionOutcome::Cancelled);
+            assert!(proxy.denial_summary().is_empty());
         }
 
         #[test]
-        fn test_proxy_forwards_if_no_suitable_option() {
-            let ruleset = Ruleset::new(vec![Rule::new(
-                Matcher::command("git").with_subcommand("status"),
-                Outcome::Allow,
-            )]);
-            let proxy = ToolcapProxy::new(ruleset);
+        fn test_forward_timeout() {
+            let proxy = proxy().with_forward_timeout(
+                Duration::from_secs(30),
+                DefaultDecision::deny().with_reason("nobody answered"),
+            );
+            assert!(matches!(
+                proxy.handle_permission_request(&request("cargo test")),
+                PermissionDecision::Forward
+            ));
+            let response = proxy.handle_forward_timeout(&request("cargo test"));
+            assert_eq!(
+                response.outcome,
+                RequestPermissionOutcome::Selected {
+                    option_id: PermissionOptionId::from("reject-once".to_string()),
+                }
+            );
+            let meta = response.meta.unwrap();
+            assert_eq!(meta["toolcap"]["default"]["cause"], "timeout");
+            assert!(meta["toolcap"]["reason"].as_str().unwrap().contains(
+                "The user didn't answer in time, so it was denied by default: nobody answered"
+            ));
+        }
 
-            // Only provide reject options (no allow options)
-            let options = vec![
-                make_permission_option("reject-once", PermissionOptionKind::RejectOnce),
-                make_permission_option("reject-always", PermissionOptionKind::RejectAlways),
-            ];
+        #[test]
+        fn test_denial_summary() {
+            let proxy = proxy()
+                .with_headless(DefaultDecision::deny().with_reason("running in CI"))
+                .with_forward_timeout(Duration::from_secs(30), DefaultDecision::deny());
+            proxy.handle_permission_request(&request("ls"));
+            proxy.handle_permission_request(&request("git push origin"));
+            proxy
+                .clone()
+                .handle_permission_request(&request("cargo test"));
+            proxy.handle_forward_timeout(&request("npm publish"));
+
+            let summary = proxy.denial_summary();
+            let causes: Vec<_> = summary.denials().iter().map(Denial::cause).collect();
+            assert_eq!(
+                causes,
+                [
+                    &DenialCause::Rules(vec![MatchedRule::new(1, &proxy.ruleset().rules()[1])]),
+                    &DenialCause::Headless,
+                    &DenialCause::Timeout(Duration::from_secs(30)),
+                ]
+            );
+            assert_eq!(summary.denials()[1].reason(), Some("running in CI"));
+        }
+    }
 
-            let req = make_request_with_options(
-                ToolKind::Execute,
-                Some(json!({"command": "git status"})),
-                options,
+    mod client_calls {
+        use std::sync::Mutex;
+
+        use super::*;
+        use sacp::schema::CreateTerminalRequest;
+
+        fn proxy() -> ToolcapProxy {
+            ToolcapProxy::new(Ruleset::new(vec![
+                Rule::new(Matcher::command("ls"), Outcome::Allow),
+                Rule::new(
+                    Matcher::command("git").with_subcommand("push"),
+                    Outcome::Deny,
+                )
+                .with_id("no-push")
+                .with_message("publishing must be done by a human"),
+            ]))
+        }
+
+        fn terminal(command: &str, args: &[&str]) -> CreateTerminalRequest {
+            CreateTerminalRequest {
+                session_id: "s1".to_string().into(),
+                command: command.into(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+                env: Vec::new(),
+                cwd: None,
+                output_byte_limit: None,
+                meta: None,
+            }
+        }
+
+        #[test]
+        fn test_denied_calls_are_rejected() {
+            let proxy = proxy();
+            assert!(
+                proxy
+                    .check_client_call(ClientCall::CreateTerminal(&terminal("ls", &["-la"])))
+                    .is_ok()
+            );
+            assert!(
+                proxy
+                    .check_client_call(ClientCall::CreateTerminal(&terminal("cargo", &["test"])))
+                    .is_ok()
             );
 
-            // Should forward because there's no AllowOnce option to select
+            let push = terminal("git", &["push", "origin"]);
+            let error = proxy
+                .check_client_call(ClientCall::CreateTerminal(&push))
+                .unwrap_err();
+            assert_eq!(error.code, CLIENT_CALL_DENIED);
             assert_eq!(
-                proxy.handle_permission_request(&req),
-                PermissionDecision::Forward
+                error.message,
+                "toolcap denied `git push origin`: publishing must be done by a human"
             );
+            let data = error.data.unwrap();
+            assert_eq!(data["toolcap"]["outcome"], "deny");
+            assert_eq!(data["toolcap"]["rules"][0]["id"], "no-push");
+
+            let denials = proxy.denial_summary();
+            assert_eq!(denials.denials().len(), 1);
+            assert_eq!(denials.denials()[0].tool_call_id(), None);
         }
 
         #[test]
-        fn test_proxy_evaluate_returns_outcome() {
-            let ruleset = Ruleset::new(vec![
-                Rule::new(
-                    Matcher::command("git").with_subcommand("status"),
-                    Outcome::Allow,
-                ),
-                Rule::new(Matcher::command("sudo"), Outcome::Deny),
-            ]);
-            let proxy = ToolcapProxy::new(ruleset);
+        fn test_headless_calls() {
+            let proxy = proxy().with_headless(DefaultDecision::deny().with_reason("running in CI"));
+            assert!(
+                proxy
+                    .check_client_call(ClientCall::CreateTerminal(&terminal("ls", &[])))
+                    .is_ok()
+            );
 
-            let req = make_request_with_options(
-                ToolKind::Execute,
-                Some(json!({"command": "git status"})),
-                vec![],
+            let error = proxy
+                .check_client_call(ClientCall::CreateTerminal(&terminal("cargo", &["test"])))
+                .unwrap_err();
+            assert_eq!(error.message, "toolcap denied `cargo test`: running in CI");
+            assert_eq!(
+                error.data.unwrap()["toolcap"]["default"],
+                json!({ "outcome": "deny", "cause": "headless", "reason": "running in CI" })
             );
-            assert_eq!(proxy.evaluate(&req), Outcome::Allow);
+            let denials = proxy.denial_summary();
+            assert_eq!(denials.denials().len(), 1);
+            assert_eq!(denials.denials()[0].reason(), Some("running in CI"));
+
+            // The default never overrides the rules.
+            let proxy = proxy.with_headless(DefaultDecision::allow());
+            assert!(
+                proxy
+                    .check_client_call(ClientCall::CreateTerminal(&terminal("cargo", &["test"])))
+                    .is_ok()
+            );
+            assert!(
+                proxy
+                    .check_client_call(ClientCall::CreateTerminal(&terminal("git", &["push"])))
+                    .is_err()
+            );
+        }
 
-            let req = make_request_with_options(
-                ToolKind::Execute,
-                Some(json!({"command": "sudo rm -rf /"})),
-                vec![],
+        #[test]
+        fn test_calls_use_session_state() {
+            let src = std::env::current_dir().unwrap().join("src");
+            let proxy = ToolcapProxy::new(Ruleset::new(vec![Rule::new(
+                Matcher::and(vec![
+                    Matcher::within_directory(&src),
+                    Matcher::command("rm"),
+                ]),
+                Outcome::Deny,
+            )]));
+
+            // Terminals without a working directory run in the session's.
+            let rm = terminal("rm", &["lib.rs"]);
+            assert!(
+                proxy
+                    .check_client_call(ClientCall::CreateTerminal(&rm))
+                    .is_ok()
+            );
+            proxy.observe_load_session(&LoadSessionRequest {
+                mcp_servers: vec![],
+                cwd: src.clone(),
+                session_id: "s1".to_string().into(),
+                meta: None,
+            });
+            assert!(
+                proxy
+                    .check_client_call(ClientCall::CreateTerminal(&rm))
+                    .is_err()
             );
-            assert_eq!(proxy.evaluate(&req), Outcome::Deny);
 
-            let req = make_request_with_options(
-                ToolKind::Execute,
-                Some(json!({"command": "npm install"})),
-                vec![],
+            let mut rm = rm;
+            rm.cwd = Some(src.parent().unwrap().to_path_buf());
+            assert!(
+                proxy
+                    .check_client_call(ClientCall::CreateTerminal(&rm))
+                    .is_ok()
             );
-            assert_eq!(proxy.evaluate(&req), Outcome::Unknown);
         }
 
         #[test]
-        fn test_proxy_ruleset_accessor() {
-            let ruleset = Ruleset::new(vec![Rule::new(
-                Matcher::command("git"),
-                Outcome::Allow,
-            )]);
-            let proxy = ToolcapProxy::new(ruleset.clone());
+        fn test_calls_are_observed() {
+            #[derive(Debug, Default)]
+            struct Recorder(Mutex<Vec<DecisionRecord>>);
 
-            // Verify the ruleset is accessible
-            let req = make_request_with_options(
-                ToolKind::Execute,
-                Some(json!({"command": "git status"})),
-                vec![],
+            impl DecisionObserver for Recorder {
+                fn observe(&self, record: &DecisionRecord) {
+                    self.0.lock().unwrap().push(record.clone());
+                }
+            }
+
+            let recorder = Arc::new(Recorder::default());
+            let proxy = proxy().with_observer(recorder.clone());
+            let _ =
+                proxy.check_client_call(ClientCall::CreateTerminal(&terminal("git", &["push"])));
+
+            let records = recorder.0.lock().unwrap();
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].session_id(), Some("s1"));
+            assert_eq!(records[0].tool_call_id(), None);
+            assert_eq!(
+                records[0].disposition(),
+                &Disposition::ClientCall("terminal/create".into())
             );
-            assert_eq!(proxy.ruleset().evaluate_request(&req), Outcome::Allow);
         }
     }
 }
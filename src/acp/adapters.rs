@@ -0,0 +1,767 @@
+//! Agent-specific interpretation of tool calls.
+//!
+//! ACP leaves the shape of a tool call's `raw_input` up to the agent, so each
+//! agent describes the same kind of operation differently. An [`AgentAdapter`]
+//! knows one agent's tools and turns its tool calls into precise
+//! [`Operation`]s. An [`AdapterRegistry`] picks the adapter for the agent on
+//! the other end of the connection, as identified by its `initialize`
+//! response.
+
+use std::fmt;
+use std::sync::{Arc, LazyLock};
+
+use sacp::schema::{Implementation, ToolCallUpdateFields, ToolKind};
+use serde_json::Value;
+
+use super::{cover_touched_paths, extract_string_field, generic_operation};
+use crate::operation::{ExecuteOperation, Operation};
+use crate::shell;
+
+/// Converts one agent's tool calls into operations.
+///
+/// # Example
+///
+/// ```
+/// use sacp::schema::{Implementation, ToolCallUpdateFields};
+/// use toolcap::Operation;
+/// use toolcap::acp::{AdapterRegistry, AgentAdapter};
+///
+/// /// An agent whose only tool is `{"shell": "..."}`.
+/// #[derive(Debug)]
+/// struct MyAgentAdapter;
+///
+/// impl AgentAdapter for MyAgentAdapter {
+///     fn name(&self) -> &str {
+///         "my-agent"
+///     }
+///
+///     fn supports_agent(&self, agent: &Implementation) -> bool {
+///         agent.name == "my-agent"
+///     }
+///
+///     fn to_operation(&self, tool_call: &ToolCallUpdateFields) -> Option<Operation> {
+///         let shell = tool_call.raw_input.as_ref()?.get("shell")?.as_str()?;
+///         Some(Operation::execute(shell))
+///     }
+/// }
+///
+/// let registry = AdapterRegistry::default().with_adapter(MyAgentAdapter);
+/// ```
+pub trait AgentAdapter: fmt::Debug + Send + Sync {
+    /// Returns a short name for the adapter, for logging.
+    fn name(&self) -> &str;
+
+    /// Returns whether this adapter is meant for `agent`.
+    fn supports_agent(&self, agent: &Implementation) -> bool;
+
+    /// Converts a tool call into an operation.
+    ///
+    /// Returns `None` for tool calls the adapter doesn't recognize, so that
+    /// the registry can fall back to generic inference.
+    fn to_operation(&self, tool_call: &ToolCallUpdateFields) -> Option<Operation>;
+}
+
+/// A set of adapters, selected by agent identity.
+///
+/// The default registry contains the built-in adapters: [`ClaudeCodeAdapter`],
+/// [`CodexAdapter`] and [`GeminiAdapter`]. Clones share the same adapters.
+#[derive(Debug, Clone)]
+pub struct AdapterRegistry {
+    adapters: Vec<Arc<dyn AgentAdapter>>,
+}
+
+impl AdapterRegistry {
+    /// Creates a registry without any adapters.
+    ///
+    /// Every tool call is then interpreted by generic inference from the
+    /// tool kind and common `raw_input` field names.
+    pub fn empty() -> Self {
+        Self {
+            adapters: Vec::new(),
+        }
+    }
+
+    /// Adds an adapter.
+    ///
+    /// Adapters added later take precedence, so an adapter can replace a
+    /// built-in one for the same agent.
+    pub fn with_adapter(mut self, adapter: impl AgentAdapter + 'static) -> Self {
+        self.adapters.insert(0, Arc::new(adapter));
+        self
+    }
+
+    /// Returns the adapters in order of precedence.
+    pub fn adapters(&self) -> impl Iterator<Item = &dyn AgentAdapter> {
+        self.adapters.iter().map(|adapter| adapter.as_ref())
+    }
+
+    /// Returns the adapter for `agent`, if any.
+    pub fn adapter_for(&self, agent: &Implementation) -> Option<&dyn AgentAdapter> {
+        self.adapters()
+            .find(|adapter| adapter.supports_agent(agent))
+    }
+
+    /// Converts a tool call into an operation.
+    ///
+    /// When the agent is known, only its adapter is consulted. Otherwise each
+    /// adapter gets a chance to recognize the tool call by its title or the
+    /// shape of its input, as long as it agrees with the tool call's declared
+    /// kind. Tool calls that no adapter recognizes fall back to generic
+    /// inference.
+    ///
+    /// File operations are then cross-checked against the paths in the tool
+    /// call's `locations` and diff content, and extended to cover any that
+    /// the input didn't mention. A tool call touching several files becomes
+    /// an [`Operation::Batch`].
+    pub fn operation(
+        &self,
+        agent: Option<&Implementation>,
+        tool_call: &ToolCallUpdateFields,
+    ) -> Operation {
+        let recognized = match agent {
+            Some(agent) => self
+                .adapter_for(agent)
+                .and_then(|adapter| adapter.to_operation(tool_call)),
+            None => self.adapters().find_map(|adapter| {
+                adapter
+                    .to_operation(tool_call)
+                    .filter(|op| tool_call.kind.is_none_or(|kind| kind == tool_kind(op)))
+            }),
+        };
+        let operation = recognized.unwrap_or_else(|| generic_operation(tool_call));
+        cover_touched_paths(operation, tool_call)
+    }
+
+    /// Returns the shared default registry.
+    pub(crate) fn builtin() -> &'static AdapterRegistry {
+        static BUILTIN: LazyLock<AdapterRegistry> = LazyLock::new(AdapterRegistry::default);
+        &BUILTIN
+    }
+}
+
+impl Default for AdapterRegistry {
+    fn default() -> Self {
+        AdapterRegistry::empty()
+            .with_adapter(GeminiAdapter)
+            .with_adapter(CodexAdapter)
+            .with_adapter(ClaudeCodeAdapter)
+    }
+}
+
+/// Adapter for Claude Code, via `claude-code-acp`.
+///
+/// `raw_input` is the input of Claude Code's built-in tools: `Bash`, `Read`,
+/// `Edit`, `MultiEdit`, `Write`, `NotebookEdit`, `Glob`, `Grep`, `WebFetch`,
+/// `WebSearch` and `Task`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClaudeCodeAdapter;
+
+impl AgentAdapter for ClaudeCodeAdapter {
+    fn name(&self) -> &str {
+        "claude-code"
+    }
+
+    fn supports_agent(&self, agent: &Implementation) -> bool {
+        agent_name_contains(agent, "claude")
+    }
+
+    fn to_operation(&self, tool_call: &ToolCallUpdateFields) -> Option<Operation> {
+        let input = tool_call.raw_input.as_ref()?.as_object()?;
+        let field = |name: &str| input.get(name).and_then(Value::as_str);
+
+        // Bash. Inputs with other fields, such as a working directory, are
+        // left to generic inference.
+        if let Some(command) = field("command") {
+            let is_bash = input.keys().all(|key| BASH_FIELDS.contains(&key.as_str()));
+            return is_bash.then(|| Operation::execute(command));
+        }
+
+        // NotebookEdit
+        if let Some(path) = field("notebook_path") {
+            return Some(Operation::Edit { path: path.into() });
+        }
+
+        if let Some(path) = field("file_path") {
+            let is_edit = ["old_string", "edits", "content"]
+                .iter()
+                .any(|key| input.contains_key(*key));
+            return Some(if is_edit {
+                // Edit, MultiEdit and Write
+                Operation::Edit { path: path.into() }
+            } else {
+                // Read
+                Operation::Read { path: path.into() }
+            });
+        }
+
+        // Glob and Grep
+        if let Some(pattern) = field("pattern") {
+            return Some(Operation::Search {
+                query: pattern.to_string(),
+            });
+        }
+
+        // WebFetch
+        if let Some(url) = field("url") {
+            return Some(Operation::Fetch {
+                url: url.to_string(),
+            });
+        }
+
+        // WebSearch
+        if let Some(query) = field("query") {
+            return Some(Operation::Search {
+                query: query.to_string(),
+            });
+        }
+
+        // Task
+        if input.contains_key("subagent_type") {
+            return Some(Operation::Other {
+                name: "Task".to_string(),
+                description: field("description").map(String::from),
+            });
+        }
+
+        None
+    }
+}
+
+/// The parameters of Claude Code's `Bash` tool.
+const BASH_FIELDS: &[&str] = &[
+    "command",
+    "description",
+    "timeout",
+    "run_in_background",
+    "dangerouslyDisableSandbox",
+];
+
+/// Adapter for Codex, via `codex-acp`.
+///
+/// Commands arrive as an argument vector with a `cwd`, and edits as a
+/// `changes` map from paths to added, deleted or updated files.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CodexAdapter;
+
+impl AgentAdapter for CodexAdapter {
+    fn name(&self) -> &str {
+        "codex"
+    }
+
+    fn supports_agent(&self, agent: &Implementation) -> bool {
+        agent_name_contains(agent, "codex")
+    }
+
+    fn to_operation(&self, tool_call: &ToolCallUpdateFields) -> Option<Operation> {
+        let input = tool_call.raw_input.as_ref()?.as_object()?;
+
+        if let Some(argv) = input.get("command").and_then(Value::as_array) {
+            let argv: Vec<&str> = argv.iter().map(Value::as_str).collect::<Option<_>>()?;
+            let mut exec_op = ExecuteOperation::new(command_line(&argv));
+            if let Some(cwd) = input.get("cwd").and_then(Value::as_str) {
+                exec_op = exec_op.with_working_dir(cwd);
+            }
+            return Some(Operation::Execute(exec_op));
+        }
+
+        let changes = input.get("changes")?.as_object()?;
+        let mut operations = changes
+            .iter()
+            .map(|(path, change)| codex_file_change(path, change))
+            .collect::<Option<Vec<_>>>()?;
+        match operations.len() {
+            0 => None,
+            1 => operations.pop(),
+            _ => Some(Operation::Batch(operations)),
+        }
+    }
+}
+
+/// Converts one file of a Codex patch into an operation.
+fn codex_file_change(path: &str, change: &Value) -> Option<Operation> {
+    let change = change.as_object()?;
+    if change.contains_key("delete") {
+        return Some(Operation::Delete { path: path.into() });
+    }
+    let move_path = change
+        .get("update")
+        .and_then(|update| update.get("move_path"))
+        .and_then(Value::as_str);
+    Some(match move_path {
+        Some(to) => Operation::Move {
+            from: path.into(),
+            to: to.into(),
+        },
+        None => Operation::Edit { path: path.into() },
+    })
+}
+
+/// Turns an argument vector into the command line it runs.
+///
+/// `bash -lc <script>` and similar wrappers run `<script>` itself; other
+/// vectors are quoted into a single command.
+fn command_line(argv: &[&str]) -> String {
+    match argv {
+        [shell, flag, script]
+            if ["bash", "sh", "zsh"].contains(&shell.rsplit('/').next().unwrap_or(shell))
+                && ["-c", "-lc"].contains(flag) =>
+        {
+            script.to_string()
+        }
+        _ => argv
+            .iter()
+            .map(|arg| shell::quote(arg))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+/// Adapter for Gemini CLI.
+///
+/// Gemini CLI doesn't send `raw_input` with permission requests. Shell
+/// commands are recovered from the tool call title, which has the form
+/// `<command> [in <directory>] (<description>)`, and file operations from
+/// the tool call's locations.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GeminiAdapter;
+
+impl AgentAdapter for GeminiAdapter {
+    fn name(&self) -> &str {
+        "gemini"
+    }
+
+    fn supports_agent(&self, agent: &Implementation) -> bool {
+        agent_name_contains(agent, "gemini")
+    }
+
+    fn to_operation(&self, tool_call: &ToolCallUpdateFields) -> Option<Operation> {
+        if let Some(input) = &tool_call.raw_input {
+            return gemini_operation_from_input(input);
+        }
+
+        let path = || {
+            let location = tool_call.locations.as_ref()?.first()?;
+            Some(location.path.clone())
+        };
+        match tool_call.kind? {
+            ToolKind::Execute => {
+                let (command, directory) = parse_gemini_shell_title(tool_call.title.as_ref()?);
+                let mut exec_op = ExecuteOperation::new(command);
+                if let Some(directory) = directory {
+                    exec_op = exec_op.with_working_dir(directory);
+                }
+                Some(Operation::Execute(exec_op))
+            }
+            ToolKind::Read => Some(Operation::Read { path: path()? }),
+            ToolKind::Edit => Some(Operation::Edit { path: path()? }),
+            ToolKind::Delete => Some(Operation::Delete { path: path()? }),
+            _ => None,
+        }
+    }
+}
+
+/// The parameters of Gemini CLI's `run_shell_command` tool.
+const SHELL_FIELDS: &[&str] = &["command", "description", "directory"];
+
+/// Interprets the parameters of Gemini CLI's tools.
+fn gemini_operation_from_input(input: &Value) -> Option<Operation> {
+    let field = |name: &str| extract_string_field(Some(input), name);
+
+    // run_shell_command. Inputs with other fields are left to generic
+    // inference.
+    if let Some(command) = field("command") {
+        let mut fields = input.as_object()?.keys();
+        if !fields.all(|key| SHELL_FIELDS.contains(&key.as_str())) {
+            return None;
+        }
+        let mut exec_op = ExecuteOperation::new(command);
+        if let Some(directory) = field("directory") {
+            exec_op = exec_op.with_working_dir(directory);
+        }
+        return Some(Operation::Execute(exec_op));
+    }
+
+    // read_file
+    if let Some(path) = field("absolute_path") {
+        return Some(Operation::Read { path: path.into() });
+    }
+
+    // write_file and replace
+    if let Some(path) = field("file_path") {
+        return Some(Operation::Edit { path: path.into() });
+    }
+
+    // glob and search_file_content
+    if let Some(pattern) = field("pattern") {
+        return Some(Operation::Search { query: pattern });
+    }
+
+    None
+}
+
+/// Splits a Gemini CLI shell title into the command and its directory.
+fn parse_gemini_shell_title(title: &str) -> (String, Option<String>) {
+    let mut command = title;
+
+    if let Some(start) = trailing_description(command) {
+        command = &command[..start];
+    }
+
+    if command.ends_with(']')
+        && let Some(start) = command.rfind(" [in ")
+    {
+        let directory = &command[start + " [in ".len()..command.len() - 1];
+        return (command[..start].to_string(), Some(directory.to_string()));
+    }
+
+    (command.to_string(), None)
+}
+
+/// Finds the ` (<description>)` suffix of a Gemini CLI shell title.
+///
+/// A trailing parenthesized group is a subshell rather than a description if
+/// it follows an operator, as in `make && (cd docs && make)`; after a plain
+/// word it would be a syntax error.
+fn trailing_description(title: &str) -> Option<usize> {
+    if !title.ends_with(')') {
+        return None;
+    }
+
+    // Find the parenthesis that opens the trailing group.
+    let mut depth = 0;
+    let open = title.char_indices().rev().find_map(|(i, c)| {
+        match c {
+            ')' => depth += 1,
+            '(' => depth -= 1,
+            _ => {}
+        }
+        (depth == 0).then_some(i)
+    })?;
+
+    let before = title[..open].strip_suffix(' ')?.trim_end();
+    let follows_operator = before.is_empty() || before.ends_with(['&', '|', ';', '(']);
+    (!follows_operator).then_some(before.len())
+}
+
+/// Returns the ACP tool kind of an operation.
+fn tool_kind(operation: &Operation) -> ToolKind {
+    match operation {
+        Operation::Read { .. } => ToolKind::Read,
+        Operation::Edit { .. } => ToolKind::Edit,
+        Operation::Delete { .. } => ToolKind::Delete,
+        Operation::Move { .. } => ToolKind::Move,
+        Operation::Search { .. } => ToolKind::Search,
+        Operation::Execute(_) => ToolKind::Execute,
+        Operation::Fetch { .. } => ToolKind::Fetch,
+        Operation::Think => ToolKind::Think,
+        Operation::SwitchMode { .. } => ToolKind::SwitchMode,
+        Operation::Other { .. } => ToolKind::Other,
+        // A batch of operations of one kind has that kind; a mixed batch is
+        // treated as an edit.
+        Operation::Batch(operations) => {
+            let mut kinds = operations.iter().map(tool_kind);
+            match kinds.next() {
+                Some(first) if kinds.all(|kind| kind == first) => first,
+                _ => ToolKind::Edit,
+            }
+        }
+    }
+}
+
+/// Returns whether the agent's name contains `needle`, ignoring case.
+fn agent_name_contains(agent: &Implementation, needle: &str) -> bool {
+    agent.name.to_lowercase().contains(needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn agent(name: &str) -> Implementation {
+        Implementation {
+            name: name.to_string(),
+            title: None,
+            version: "1.0.0".to_string(),
+        }
+    }
+
+    fn tool_call(kind: Option<ToolKind>, raw_input: Value) -> ToolCallUpdateFields {
+        ToolCallUpdateFields {
+            kind,
+            raw_input: Some(raw_input),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_adapter_for_agent() {
+        let registry = AdapterRegistry::default();
+        let name = |agent_name: &str| {
+            registry
+                .adapter_for(&agent(agent_name))
+                .map(|adapter| adapter.name().to_string())
+        };
+        assert_eq!(name("claude-code-acp").as_deref(), Some("claude-code"));
+        assert_eq!(name("codex-acp").as_deref(), Some("codex"));
+        assert_eq!(name("gemini-cli").as_deref(), Some("gemini"));
+        assert_eq!(name("goose"), None);
+    }
+
+    #[test]
+    fn test_later_adapters_take_precedence() {
+        #[derive(Debug)]
+        struct EverythingIsThinking;
+
+        impl AgentAdapter for EverythingIsThinking {
+            fn name(&self) -> &str {
+                "thinking"
+            }
+
+            fn supports_agent(&self, _agent: &Implementation) -> bool {
+                true
+            }
+
+            fn to_operation(&self, _tool_call: &ToolCallUpdateFields) -> Option<Operation> {
+                Some(Operation::Think)
+            }
+        }
+
+        let registry = AdapterRegistry::default().with_adapter(EverythingIsThinking);
+        let call = tool_call(None, json!({"command": "rm -rf /"}));
+        assert_eq!(
+            registry.operation(Some(&agent("claude-code-acp")), &call),
+            Operation::Think
+        );
+    }
+
+    #[test]
+    fn test_claude_code_tools() {
+        let adapter = ClaudeCodeAdapter;
+        let op = |input| adapter.to_operation(&tool_call(None, input));
+
+        assert_eq!(
+            op(json!({"command": "git status", "description": "Show status"})),
+            Some(Operation::execute("git status"))
+        );
+        assert_eq!(
+            op(json!({"file_path": "/p/a.rs", "offset": 10, "limit": 20})),
+            Some(Operation::Read {
+                path: "/p/a.rs".into()
+            })
+        );
+        assert_eq!(
+            op(json!({"file_path": "/p/a.rs", "old_string": "a", "new_string": "b"})),
+            Some(Operation::Edit {
+                path: "/p/a.rs".into()
+            })
+        );
+        assert_eq!(
+            op(json!({"file_path": "/p/a.rs", "edits": []})),
+            Some(Operation::Edit {
+                path: "/p/a.rs".into()
+            })
+        );
+        assert_eq!(
+            op(json!({"file_path": "/p/new.rs", "content": ""})),
+            Some(Operation::Edit {
+                path: "/p/new.rs".into()
+            })
+        );
+        assert_eq!(
+            op(json!({"pattern": "**/*.rs", "path": "/p"})),
+            Some(Operation::Search {
+                query: "**/*.rs".into()
+            })
+        );
+        assert_eq!(
+            op(json!({"url": "https://example.com", "prompt": "summarize"})),
+            Some(Operation::Fetch {
+                url: "https://example.com".into()
+            })
+        );
+        assert_eq!(
+            op(json!({"query": "rust lifetimes"})),
+            Some(Operation::Search {
+                query: "rust lifetimes".into()
+            })
+        );
+        assert_eq!(
+            op(json!({"description": "Find tests", "prompt": "...", "subagent_type": "general"})),
+            Some(Operation::Other {
+                name: "Task".into(),
+                description: Some("Find tests".into()),
+            })
+        );
+        assert_eq!(op(json!({"todos": []})), None);
+    }
+
+    #[test]
+    fn test_codex_commands() {
+        let adapter = CodexAdapter;
+        let op = |input| adapter.to_operation(&tool_call(Some(ToolKind::Execute), input));
+
+        assert_eq!(
+            op(json!({"command": ["bash", "-lc", "cargo test | tail"], "cwd": "/p"})),
+            Some(Operation::execute_in("cargo test | tail", "/p"))
+        );
+        assert_eq!(
+            op(json!({"command": ["/bin/zsh", "-c", "ls"]})),
+            Some(Operation::execute("ls"))
+        );
+        assert_eq!(
+            op(json!({"command": ["git", "commit", "-m", "fix it"]})),
+            Some(Operation::execute("git commit -m 'fix it'"))
+        );
+    }
+
+    #[test]
+    fn test_codex_patches() {
+        let adapter = CodexAdapter;
+        let op = |input| adapter.to_operation(&tool_call(Some(ToolKind::Edit), input));
+
+        assert_eq!(
+            op(
+                json!({"changes": {"/p/a.rs": {"update": {"unified_diff": "", "move_path": null}}}})
+            ),
+            Some(Operation::Edit {
+                path: "/p/a.rs".into()
+            })
+        );
+        assert_eq!(
+            op(json!({"changes": {"/p/a.rs": {"add": {"content": ""}}}})),
+            Some(Operation::Edit {
+                path: "/p/a.rs".into()
+            })
+        );
+        assert_eq!(
+            op(json!({"changes": {"/p/a.rs": {"delete": {"content": ""}}}})),
+            Some(Operation::Delete {
+                path: "/p/a.rs".into()
+            })
+        );
+        assert_eq!(
+            op(json!({"changes": {
+                "/p/a.rs": {"update": {"unified_diff": "", "move_path": null}},
+                "/p/b.rs": {"delete": {"content": ""}},
+            }})),
+            Some(Operation::Batch(vec![
+                Operation::Edit {
+                    path: "/p/a.rs".into()
+                },
+                Operation::Delete {
+                    path: "/p/b.rs".into()
+                },
+            ]))
+        );
+        assert_eq!(op(json!({"changes": {}})), None);
+        assert_eq!(
+            op(
+                json!({"changes": {"/p/a.rs": {"update": {"unified_diff": "", "move_path": "/p/b.rs"}}}})
+            ),
+            Some(Operation::Move {
+                from: "/p/a.rs".into(),
+                to: "/p/b.rs".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_gemini_shell_titles() {
+        assert_eq!(
+            parse_gemini_shell_title("ls -la"),
+            ("ls -la".to_string(), None)
+        );
+        assert_eq!(
+            parse_gemini_shell_title("ls -la (List files)"),
+            ("ls -la".to_string(), None)
+        );
+        assert_eq!(
+            parse_gemini_shell_title("cargo test [in crates/core] (Run the tests)"),
+            ("cargo test".to_string(), Some("crates/core".to_string()))
+        );
+        assert_eq!(
+            parse_gemini_shell_title("cargo test (Run the (slow) tests)"),
+            ("cargo test".to_string(), None)
+        );
+        assert_eq!(
+            parse_gemini_shell_title("make && (cd docs && make)"),
+            ("make && (cd docs && make)".to_string(), None)
+        );
+        assert_eq!(
+            parse_gemini_shell_title("make && (cd docs && make) (Build everything)"),
+            ("make && (cd docs && make)".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn test_gemini_tool_calls() {
+        let adapter = GeminiAdapter;
+
+        let call = ToolCallUpdateFields {
+            kind: Some(ToolKind::Execute),
+            title: Some("rm -rf target [in /p] (Clean up)".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            adapter.to_operation(&call),
+            Some(Operation::execute_in("rm -rf target", "/p"))
+        );
+
+        let call: ToolCallUpdateFields = serde_json::from_value(json!({
+            "kind": "edit",
+            "title": "Writing to src/lib.rs",
+            "locations": [{"path": "/p/src/lib.rs"}],
+        }))
+        .unwrap();
+        assert_eq!(
+            adapter.to_operation(&call),
+            Some(Operation::Edit {
+                path: "/p/src/lib.rs".into()
+            })
+        );
+
+        let call = tool_call(None, json!({"absolute_path": "/p/README.md"}));
+        assert_eq!(
+            adapter.to_operation(&call),
+            Some(Operation::Read {
+                path: "/p/README.md".into()
+            })
+        );
+    }
+
+    #[test]
+    fn test_unrecognized_tool_calls_fall_back() {
+        let registry = AdapterRegistry::default();
+        let call = tool_call(Some(ToolKind::Move), json!({"from": "/a", "to": "/b"}));
+        assert_eq!(
+            registry.operation(None, &call),
+            Operation::Move {
+                from: "/a".into(),
+                to: "/b".into(),
+            }
+        );
+
+        // Guesses that contradict the declared kind are ignored.
+        let call = tool_call(Some(ToolKind::Delete), json!({"file_path": "/p/a.rs"}));
+        assert_eq!(
+            registry.operation(None, &call),
+            Operation::Delete {
+                path: "/p/a.rs".into()
+            }
+        );
+
+        // The agent's own adapter is the only one consulted.
+        let call = tool_call(None, json!({"absolute_path": "/p/README.md"}));
+        assert_eq!(
+            registry.operation(Some(&agent("claude-code-acp")), &call),
+            generic_operation(&call)
+        );
+        assert_eq!(
+            registry.operation(None, &call),
+            Operation::Read {
+                path: "/p/README.md".into()
+            }
+        );
+    }
+}
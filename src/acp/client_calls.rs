@@ -0,0 +1,195 @@
+use sacp::schema::{CreateTerminalRequest, ReadTextFileRequest, SessionId, WriteTextFileRequest};
+
+use crate::operation::{ExecuteOperation, Operation};
+use crate::shell::quote;
+
+/// The JSON-RPC error code of client calls the proxy rejects.
+///
+/// The code is in the range JSON-RPC reserves for implementation-defined
+/// server errors, and is not used by ACP itself.
+pub const CLIENT_CALL_DENIED: i32 = -32010;
+
+/// A request the agent sent straight to one of the client's methods,
+/// without asking for permission first.
+///
+/// Agents can read and write files through the client, and run commands in
+/// its terminals, and some don't send `session/request_permission` before
+/// doing so. The proxy checks these calls against its policy too (see
+/// [`ToolcapProxy::check_client_call`](super::ToolcapProxy::check_client_call)).
+#[derive(Debug, Clone, Copy)]
+pub enum ClientCall<'a> {
+    /// `fs/read_text_file`
+    ReadTextFile(&'a ReadTextFileRequest),
+    /// `fs/write_text_file`
+    WriteTextFile(&'a WriteTextFileRequest),
+    /// `terminal/create`
+    CreateTerminal(&'a CreateTerminalRequest),
+}
+
+impl ClientCall<'_> {
+    /// Returns the name of the method, e.g. `fs/read_text_file`.
+    pub fn method(&self) -> &'static str {
+        match self {
+            ClientCall::ReadTextFile(_) => "fs/read_text_file",
+            ClientCall::WriteTextFile(_) => "fs/write_text_file",
+            ClientCall::CreateTerminal(_) => "terminal/create",
+        }
+    }
+
+    /// Returns the session the call was made in.
+    pub fn session_id(&self) -> &SessionId {
+        match self {
+            ClientCall::ReadTextFile(req) => &req.session_id,
+            ClientCall::WriteTextFile(req) => &req.session_id,
+            ClientCall::CreateTerminal(req) => &req.session_id,
+        }
+    }
+}
+
+impl Operation {
+    /// Creates an Operation from a call to one of the client's methods.
+    ///
+    /// Reading a file is a `Read` and writing one an `Edit`. Creating a
+    /// terminal is an `Execute` of its command line, with the arguments
+    /// quoted for the shell, in the terminal's working directory if it has
+    /// one. The terminal's environment variables are prepended as
+    /// assignments (`NAME=value git push`); a variable whose name isn't a
+    /// valid shell name becomes the command word, so no rule decides it.
+    pub fn from_client_call(call: ClientCall<'_>) -> Self {
+        match call {
+            ClientCall::ReadTextFile(req) => Operation::Read {
+                path: req.path.clone(),
+            },
+            ClientCall::WriteTextFile(req) => Operation::Edit {
+                path: req.path.clone(),
+            },
+            ClientCall::CreateTerminal(req) => {
+                let mut command = String::new();
+                for var in &req.env {
+                    command.push_str(&env_assignment(&var.name, &var.value));
+                    command.push(' ');
+                }
+                command.push_str(&req.command);
+                for arg in &req.args {
+                    command.push(' ');
+                    command.push_str(&quote(arg));
+                }
+                let exec_op = ExecuteOperation::new(command);
+                Operation::Execute(match &req.cwd {
+                    Some(cwd) => exec_op.with_working_dir(cwd),
+                    None => exec_op,
+                })
+            }
+        }
+    }
+}
+
+/// Writes an environment variable as a shell assignment.
+///
+/// A name that isn't a valid shell name is quoted together with its value,
+/// so it can't inject anything into the command line.
+fn env_assignment(name: &str, value: &str) -> String {
+    let mut chars = name.chars();
+    let is_name = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_name {
+        format!("{}={}", name, quote(value))
+    } else {
+        let word = format!("{}={}", name, value);
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+    use crate::outcome::Outcome;
+    use crate::rule::Rule;
+    use crate::ruleset::Ruleset;
+    use sacp::schema::EnvVariable;
+
+    #[test]
+    fn test_operation_from_client_call() {
+        let read = ReadTextFileRequest {
+            session_id: "s1".to_string().into(),
+            path: "/repo/src/main.rs".into(),
+            line: None,
+            limit: None,
+            meta: None,
+        };
+        let call = ClientCall::ReadTextFile(&read);
+        assert_eq!(call.method(), "fs/read_text_file");
+        assert_eq!(call.session_id().to_string(), "s1");
+        assert_eq!(
+            Operation::from_client_call(call),
+            Operation::Read {
+                path: "/repo/src/main.rs".into()
+            }
+        );
+
+        let write = WriteTextFileRequest {
+            session_id: "s1".to_string().into(),
+            path: "/repo/.env".into(),
+            content: String::new(),
+            meta: None,
+        };
+        assert_eq!(
+            Operation::from_client_call(ClientCall::WriteTextFile(&write)),
+            Operation::Edit {
+                path: "/repo/.env".into()
+            }
+        );
+
+        let terminal = CreateTerminalRequest {
+            session_id: "s1".to_string().into(),
+            command: "git".into(),
+            args: vec!["commit".into(), "-m".into(), "fix it".into()],
+            env: Vec::new(),
+            cwd: Some("/repo".into()),
+            output_byte_limit: None,
+            meta: None,
+        };
+        assert_eq!(
+            Operation::from_client_call(ClientCall::CreateTerminal(&terminal)),
+            Operation::execute_in("git commit -m 'fix it'", "/repo")
+        );
+
+        let env = |name: &str, value: &str| EnvVariable {
+            name: name.into(),
+            value: value.into(),
+            meta: None,
+        };
+        let terminal = CreateTerminalRequest {
+            env: vec![env("GIT_SSH_COMMAND", "ssh -i key"), env("DEBUG", "1")],
+            ..terminal
+        };
+        let operation = Operation::from_client_call(ClientCall::CreateTerminal(&terminal));
+        assert_eq!(
+            operation,
+            Operation::execute_in(
+                "GIT_SSH_COMMAND='ssh -i key' DEBUG=1 git commit -m 'fix it'",
+                "/repo"
+            )
+        );
+        let ruleset = Ruleset::new(vec![
+            Rule::new(Matcher::command("git"), Outcome::Allow),
+            Rule::new(Matcher::command("ls"), Outcome::Allow),
+        ]);
+        assert_eq!(ruleset.evaluate(&operation), Outcome::Allow);
+
+        // Names that aren't shell names can't smuggle in another command.
+        let terminal = CreateTerminalRequest {
+            env: vec![env("X; ls #", "1")],
+            ..terminal
+        };
+        let operation = Operation::from_client_call(ClientCall::CreateTerminal(&terminal));
+        assert_eq!(
+            operation,
+            Operation::execute_in("'X; ls #=1' git commit -m 'fix it'", "/repo")
+        );
+        assert_eq!(ruleset.evaluate(&operation), Outcome::Unknown);
+    }
+}
@@ -0,0 +1,1549 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sacp::role::{Agent, Client, ProxyToConductor};
+use sacp::schema::{
+    CancelNotification, ContentChunk, CreateTerminalRequest, Implementation,
+    InitializeProxyRequest, LoadSessionRequest, LoadSessionResponse, NewSessionRequest,
+    ReadTextFileRequest, RequestPermissionOutcome, RequestPermissionRequest,
+    RequestPermissionResponse, SessionId, SessionModeId, SessionNotification, SessionUpdate,
+    SetSessionModeRequest, WriteTextFileRequest,
+};
+use sacp::util::MatchMessageFrom;
+use sacp::{Component, Handled, JrConnectionCx, JrMessageHandler, JrRequestCx, MessageCx};
+
+use super::client_calls::ClientCall;
+use super::extensions::{
+    EvaluateRequest, EvaluateResponse, ExplainRequest, ExplainResponse, GrantInfo,
+    ListGrantsRequest, ListGrantsResponse, ReloadRulesetRequest, ReloadRulesetResponse,
+    RevokeGrantRequest, RevokeGrantResponse,
+};
+use super::grants::Grant;
+use super::headless::DefaultDecision;
+use super::learned::LearnedRule;
+use super::pending::PendingRequests;
+use super::resolver::{LoadError, PolicyError, PolicySource};
+use super::{PermissionDecision, ToolcapProxy};
+use crate::audit::Disposition;
+use crate::decision::{Decision, describe_operation};
+use crate::operation::Operation;
+use crate::outcome::Outcome;
+use crate::ruleset::Ruleset;
+use crate::sync::lock_unpoisoned;
+
+/// Something the proxy component did, as given to its event hooks.
+///
+/// The `Display` output is a one-line summary meant for logs.
+#[derive(Debug)]
+pub enum ProxyEvent<'a> {
+    /// The agent answered `initialize`.
+    Initialized {
+        /// The agent, if it identified itself.
+        agent: Option<&'a Implementation>,
+        /// The name of the adapter used for the agent's tool calls, if one
+        /// recognized it.
+        adapter: Option<&'a str>,
+    },
+
+    /// A session was created by `session/new`.
+    SessionStarted {
+        session_id: &'a SessionId,
+        cwd: &'a Path,
+    },
+
+    /// A session was resumed by `session/load`.
+    SessionLoaded {
+        session_id: &'a SessionId,
+        cwd: &'a Path,
+    },
+
+    /// A session's ruleset was picked by the proxy's
+    /// [`PolicyResolver`](super::PolicyResolver).
+    PolicySelected {
+        session_id: &'a SessionId,
+        source: &'a PolicySource,
+    },
+
+    /// A session's project file couldn't be loaded, so the session uses a
+    /// fallback ruleset.
+    PolicyFailed {
+        session_id: &'a SessionId,
+        error: &'a PolicyError,
+    },
+
+    /// A session switched modes, by `session/set_mode` or as reported by
+    /// the agent.
+    ModeChanged {
+        session_id: &'a SessionId,
+        mode: &'a SessionModeId,
+    },
+
+    /// The proxy answered a permission request itself.
+    Responded {
+        request: &'a RequestPermissionRequest,
+        operation: &'a Operation,
+        decision: &'a Decision,
+        response: &'a RequestPermissionResponse,
+    },
+
+    /// The proxy couldn't decide a permission request and, being headless,
+    /// answered it with its default.
+    Defaulted {
+        request: &'a RequestPermissionRequest,
+        operation: &'a Operation,
+        decision: &'a Decision,
+        default: &'a DefaultDecision,
+        response: &'a RequestPermissionResponse,
+    },
+
+    /// The proxy couldn't decide a permission request and forwarded it to
+    /// the client.
+    Forwarded {
+        request: &'a RequestPermissionRequest,
+        operation: &'a Operation,
+        decision: &'a Decision,
+    },
+
+    /// The user answered a forwarded permission request.
+    Answered {
+        request: &'a RequestPermissionRequest,
+        response: &'a RequestPermissionResponse,
+        /// The grants remembered from the answer.
+        grants: &'a [Grant],
+    },
+
+    /// A permission request arrived that is already waiting for the user:
+    /// the same tool call, with the same fields and options. It isn't
+    /// forwarded again, and gets the same answer.
+    Duplicate {
+        request: &'a RequestPermissionRequest,
+    },
+
+    /// The client cancelled a session while a forwarded permission request
+    /// was waiting for the user, so the proxy answered it with `Cancelled`.
+    Cancelled {
+        request: &'a RequestPermissionRequest,
+    },
+
+    /// The user didn't answer a forwarded permission request in time, so
+    /// the proxy answered it with its default.
+    TimedOut {
+        request: &'a RequestPermissionRequest,
+        response: &'a RequestPermissionResponse,
+        timeout: Duration,
+    },
+
+    /// The agent called a client method directly. Denied calls were
+    /// rejected, and the rest passed on to the client.
+    ClientCall {
+        call: ClientCall<'a>,
+        operation: &'a Operation,
+        decision: &'a Decision,
+    },
+
+    /// Grants were saved to the proxy's learned policy.
+    Learned {
+        session_id: &'a SessionId,
+        rules: &'a [LearnedRule],
+    },
+
+    /// Grants couldn't be saved to the proxy's learned policy.
+    LearnFailed { error: &'a io::Error },
+
+    /// The client revoked a grant with `_toolcap/grants/revoke`.
+    GrantRevoked {
+        session_id: &'a SessionId,
+        grant: &'a Grant,
+    },
+
+    /// The client reloaded the ruleset with `_toolcap/ruleset/reload`.
+    RulesetReloaded {
+        /// The number of rules in the new ruleset.
+        rules: usize,
+    },
+
+    /// The ruleset couldn't be reloaded, so the proxy kept the old one.
+    ReloadFailed { error: &'a LoadError },
+}
+
+impl fmt::Display for ProxyEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyEvent::Initialized {
+                agent: Some(agent),
+                adapter,
+            } => write!(
+                f,
+                "agent: {} {} (adapter: {})",
+                agent.name,
+                agent.version,
+                adapter.unwrap_or("generic")
+            ),
+            ProxyEvent::Initialized { agent: None, .. } => {
+                write!(f, "agent did not identify itself")
+            }
+            ProxyEvent::SessionStarted { session_id, cwd } => {
+                write!(f, "session {} started in {}", session_id, cwd.display())
+            }
+            ProxyEvent::SessionLoaded { session_id, cwd } => {
+                write!(f, "session {} loaded in {}", session_id, cwd.display())
+            }
+            ProxyEvent::PolicySelected { session_id, source } => {
+                write!(f, "session {} uses {}", session_id, source)
+            }
+            ProxyEvent::PolicyFailed { session_id, error } => {
+                write!(f, "session {}: {}", session_id, error)
+            }
+            ProxyEvent::ModeChanged { session_id, mode } => {
+                write!(f, "session {} switched to {} mode", session_id, mode)
+            }
+            ProxyEvent::Responded {
+                operation,
+                decision,
+                ..
+            } => {
+                write!(
+                    f,
+                    "auto-responded to `{}`: {}",
+                    describe_operation(operation),
+                    decision.outcome()
+                )?;
+                let rules: Vec<_> = decision.deciding_rules().map(|r| r.to_string()).collect();
+                if !rules.is_empty() {
+                    write!(f, " (rules: {})", rules.join(", "))?;
+                }
+                Ok(())
+            }
+            ProxyEvent::Defaulted {
+                operation, default, ..
+            } => {
+                write!(
+                    f,
+                    "no one to ask about `{}`: {} by default",
+                    describe_operation(operation),
+                    default.outcome()
+                )?;
+                if let Some(reason) = default.reason() {
+                    write!(f, " ({})", reason)?;
+                }
+                Ok(())
+            }
+            ProxyEvent::Forwarded { operation, .. } => {
+                write!(
+                    f,
+                    "forwarded `{}` to the client",
+                    describe_operation(operation)
+                )
+            }
+            ProxyEvent::Answered {
+                request,
+                response,
+                grants,
+            } => {
+                match &response.outcome {
+                    RequestPermissionOutcome::Selected { option_id } => write!(
+                        f,
+                        "user chose {} for tool call {}",
+                        option_id, request.tool_call.id
+                    )?,
+                    RequestPermissionOutcome::Cancelled => {
+                        write!(f, "tool call {} was cancelled", request.tool_call.id)?
+                    }
+                }
+                for grant in grants.iter() {
+                    write!(f, "; remembered {}", grant)?;
+                }
+                Ok(())
+            }
+            ProxyEvent::Duplicate { request } => write!(
+                f,
+                "tool call {} is already waiting for an answer",
+                request.tool_call.id
+            ),
+            ProxyEvent::Cancelled { request } => write!(
+                f,
+                "cancelled tool call {}: session {} was cancelled",
+                request.tool_call.id, request.session_id
+            ),
+            ProxyEvent::TimedOut {
+                request,
+                response,
+                timeout,
+            } => {
+                write!(
+                    f,
+                    "no answer for tool call {} within {}s, ",
+                    request.tool_call.id,
+                    timeout.as_secs_f64()
+                )?;
+                match &response.outcome {
+                    RequestPermissionOutcome::Selected { option_id } => {
+                        write!(f, "chose {}", option_id)
+                    }
+                    RequestPermissionOutcome::Cancelled => write!(f, "cancelled it"),
+                }
+            }
+            ProxyEvent::ClientCall {
+                call,
+                operation,
+                decision,
+            } => {
+                let action = match decision.outcome() {
+                    Outcome::Deny => "rejected",
+                    _ => "passed on",
+                };
+                write!(
+                    f,
+                    "{} {} for `{}`",
+                    action,
+                    call.method(),
+                    describe_operation(operation)
+                )?;
+                let rules: Vec<_> = decision.deciding_rules().map(|r| r.to_string()).collect();
+                if !rules.is_empty() {
+                    write!(f, " (rules: {})", rules.join(", "))?;
+                }
+                Ok(())
+            }
+            ProxyEvent::Learned { session_id, rules } => {
+                write!(f, "learned from session {}:", session_id)?;
+                for rule in rules.iter() {
+                    write!(f, " {}", rule.rule())?;
+                }
+                Ok(())
+            }
+            ProxyEvent::LearnFailed { error } => {
+                write!(f, "failed to save learned rules: {}", error)
+            }
+            ProxyEvent::GrantRevoked { session_id, grant } => {
+                write!(f, "revoked {} in session {}", grant, session_id)
+            }
+            ProxyEvent::RulesetReloaded { rules } => {
+                write!(f, "reloaded the ruleset ({} rules)", rules)
+            }
+            ProxyEvent::ReloadFailed { error } => {
+                write!(f, "failed to reload the ruleset: {}", error)
+            }
+        }
+    }
+}
+
+type EventHook = Arc<dyn Fn(&ProxyEvent<'_>) + Send + Sync>;
+
+type RulesetLoader = Arc<dyn Fn() -> Result<Ruleset, LoadError> + Send + Sync>;
+
+/// Calls every hook with an event.
+fn emit(hooks: &[EventHook], event: ProxyEvent<'_>) {
+    for hook in hooks {
+        hook(&event);
+    }
+}
+
+/// An ACP proxy that decides permission requests with a [`ToolcapProxy`].
+///
+/// The component sits between a client and an agent in a conductor chain.
+/// It answers the permission requests its proxy can decide and forwards the
+/// rest to the client, remembering the user's "always" answers (and saving
+/// them to the proxy's [`LearnedPolicy`](super::LearnedPolicy), if it has
+/// one). Along the way it records the agent's identity and each session's
+/// working directory. Calls the agent makes straight to the client's
+/// `fs/read_text_file`, `fs/write_text_file` and `terminal/create` methods
+/// are checked too, and rejected with an error if the policy denies them
+/// (see [`ToolcapProxy::check_client_call`]). Every other message passes
+/// through unchanged.
+///
+/// If the proxy has a [forward timeout](ToolcapProxy::with_forward_timeout),
+/// forwarded requests the user doesn't answer in time are answered with its
+/// default, and a late answer is ignored. The deadline uses a Tokio timer,
+/// so such a component must be served on a Tokio runtime with the time
+/// driver enabled.
+///
+/// # Example
+///
+/// ```ignore
+/// use sacp::{ByteStreams, Component};
+/// use toolcap::acp::{ToolcapComponent, ToolcapProxy};
+///
+/// let proxy = ToolcapProxy::new(ruleset).with_remembered_decisions(true);
+/// ToolcapComponent::new(proxy)
+///     .with_event_hook(|event| eprintln!("{}", event))
+///     .serve(ByteStreams::new(stdout, stdin))
+///     .await?;
+/// ```
+///
+/// To combine the proxy with other handlers, add its [`handler`](Self::handler)
+/// to your own `ProxyToConductor` connection instead.
+#[derive(Clone)]
+pub struct ToolcapComponent {
+    proxy: Arc<ToolcapProxy>,
+    name: String,
+    hooks: Vec<EventHook>,
+    denial_messages: bool,
+    loader: Option<RulesetLoader>,
+    pending: PendingRequests,
+    sessions: SeenSessions,
+}
+
+/// The sessions started or loaded through a connection.
+type SeenSessions = Arc<Mutex<HashSet<SessionId>>>;
+
+impl ToolcapComponent {
+    /// The connection name used unless [`with_name`](Self::with_name) is called.
+    pub const DEFAULT_NAME: &str = "toolcap-proxy";
+
+    /// Creates a component deciding requests with `proxy`.
+    pub fn new(proxy: ToolcapProxy) -> Self {
+        Self {
+            proxy: Arc::new(proxy),
+            name: Self::DEFAULT_NAME.into(),
+            hooks: Vec::new(),
+            denial_messages: false,
+            loader: None,
+            pending: PendingRequests::default(),
+            sessions: SeenSessions::default(),
+        }
+    }
+
+    /// Sets the name of the connection, as shown in sacp's logs.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Adds a hook that is called with every [`ProxyEvent`], e.g. to log it.
+    ///
+    /// Hooks are called synchronously while messages are handled, so they
+    /// should be quick.
+    pub fn with_event_hook(
+        mut self,
+        hook: impl Fn(&ProxyEvent<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Sets whether to show the user why permission requests were denied.
+    ///
+    /// The agent learns why from the denial response itself: its `_meta`
+    /// carries the explanation under `toolcap.reason`, whether the rules,
+    /// the headless default or a forward timeout denied the request. With
+    /// denial messages, the component also sends the explanation to the
+    /// client as an agent message (a `session/update` notification), so the
+    /// user sees it in the conversation. The agent never receives these
+    /// messages.
+    pub fn with_denial_messages(mut self, enabled: bool) -> Self {
+        self.denial_messages = enabled;
+        self
+    }
+
+    /// Sets how to load a fresh ruleset when the client asks for a reload
+    /// with `_toolcap/ruleset/reload`, e.g. by reading the policy file again.
+    ///
+    /// The new ruleset replaces the proxy's for every session, keeping its
+    /// observers (see [`ToolcapProxy::replace_ruleset`]). If the loader
+    /// fails, the proxy keeps its ruleset and answers the client with the
+    /// error. Without a loader, reload requests aren't supported.
+    pub fn with_ruleset_loader(
+        mut self,
+        loader: impl Fn() -> Result<Ruleset, LoadError> + Send + Sync + 'static,
+    ) -> Self {
+        self.loader = Some(Arc::new(loader));
+        self
+    }
+
+    /// Returns the proxy that decides requests.
+    pub fn proxy(&self) -> &ToolcapProxy {
+        &self.proxy
+    }
+
+    /// Returns the component's connection name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns a message handler for the component, sharing its proxy and
+    /// its forwarded requests.
+    pub fn handler(&self) -> ToolcapHandler {
+        ToolcapHandler {
+            proxy: self.proxy.clone(),
+            hooks: self.hooks.clone().into(),
+            denial_messages: self.denial_messages,
+            loader: self.loader.clone(),
+            pending: self.pending.clone(),
+            sessions: self.sessions.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for ToolcapComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ToolcapComponent")
+            .field("proxy", &self.proxy)
+            .field("name", &self.name)
+            .field("hooks", &self.hooks.len())
+            .field("denial_messages", &self.denial_messages)
+            .field("loader", &self.loader.is_some())
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
+
+impl Component for ToolcapComponent {
+    async fn serve(self, client: impl Component) -> Result<(), sacp::Error> {
+        let handler = self.handler();
+        let result = ProxyToConductor::builder()
+            .name(self.name.clone())
+            .with_handler(handler.clone())
+            .serve(client)
+            .await;
+        handler.forget_sessions();
+        result
+    }
+}
+
+/// The sacp message handler of a [`ToolcapComponent`].
+///
+/// Created by [`ToolcapComponent::handler`]. It handles `initialize`,
+/// `session/new`, `session/load`, `session/set_mode`,
+/// `session/request_permission`, `fs/read_text_file`, `fs/write_text_file`
+/// and `terminal/create` requests, and the client's `_toolcap/*` extension
+/// requests (see [`EvaluateRequest`](super::EvaluateRequest) and its
+/// siblings). It leaves every other message to the handlers after it, after
+/// noting mode changes and tool calls in `session/update` notifications
+/// (see [`ToolcapProxy::history`]) and answering the
+/// forwarded permission requests of sessions cancelled by `session/cancel`.
+///
+/// Forwarded permission requests are tracked until they're answered, so a
+/// request that is already waiting for the user (the same tool call, with
+/// the same fields and options) isn't forwarded again: it gets the same
+/// answer.
+///
+/// ACP has no message that ends a session, so the proxy keeps what it
+/// learned about the handler's sessions until the connection closes, when
+/// [`forget_sessions`](Self::forget_sessions) should be called.
+/// [`ToolcapComponent::serve`](Component::serve) does this itself.
+#[derive(Clone)]
+pub struct ToolcapHandler {
+    proxy: Arc<ToolcapProxy>,
+    hooks: Arc<[EventHook]>,
+    denial_messages: bool,
+    loader: Option<RulesetLoader>,
+    pending: PendingRequests,
+    sessions: SeenSessions,
+}
+
+impl ToolcapHandler {
+    /// Returns the proxy that decides requests.
+    pub fn proxy(&self) -> &ToolcapProxy {
+        &self.proxy
+    }
+
+    /// Forgets every session started or loaded through this handler (see
+    /// [`ToolcapProxy::forget_session`]), with its grants, history and
+    /// resolved ruleset.
+    pub fn forget_sessions(&self) {
+        let sessions = std::mem::take(&mut *self.lock_sessions());
+        for session_id in &sessions {
+            self.proxy.forget_session(session_id);
+        }
+    }
+
+    /// Notes a session started or loaded through this handler.
+    fn seen_session(&self, session_id: &SessionId) {
+        self.lock_sessions().insert(session_id.clone());
+    }
+
+    fn lock_sessions(&self) -> std::sync::MutexGuard<'_, HashSet<SessionId>> {
+        lock_unpoisoned(&self.sessions)
+    }
+}
+
+impl fmt::Debug for ToolcapHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ToolcapHandler")
+            .field("proxy", &self.proxy)
+            .field("hooks", &self.hooks.len())
+            .field("denial_messages", &self.denial_messages)
+            .field("loader", &self.loader.is_some())
+            .field("pending", &self.pending.len())
+            .field("sessions", &self.lock_sessions().len())
+            .finish()
+    }
+}
+
+impl JrMessageHandler for ToolcapHandler {
+    type Role = ProxyToConductor;
+
+    fn describe_chain(&self) -> impl fmt::Debug {
+        "ToolcapHandler"
+    }
+
+    async fn handle_message(
+        &mut self,
+        message: MessageCx,
+        cx: JrConnectionCx<Self::Role>,
+    ) -> Result<Handled<MessageCx>, sacp::Error> {
+        let (init, new, load, permission) =
+            (self.clone(), self.clone(), self.clone(), self.clone());
+        let (init_cx, new_cx, load_cx) = (cx.clone(), cx.clone(), cx.clone());
+        let (set_mode, update, set_mode_cx) = (self.clone(), self.clone(), cx.clone());
+        let cancel = self.clone();
+        let (read, write, terminal) = (self.clone(), self.clone(), self.clone());
+        let (read_cx, write_cx, terminal_cx) = (cx.clone(), cx.clone(), cx.clone());
+        let (evaluate, explain, list, revoke, reload) = (
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+            self.clone(),
+        );
+
+        MatchMessageFrom::new(message, &cx)
+            // Record which agent is at the end of the chain, so its tool calls
+            // are interpreted by the right adapter
+            .if_request_from(
+                Client,
+                async move |req: InitializeProxyRequest, request_cx: JrRequestCx<_>| {
+                    init_cx
+                        .send_request_to(Agent, req.initialize)
+                        .await_when_result_received(async move |result| {
+                            if let Ok(response) = &result {
+                                init.proxy.observe_initialize(response);
+                                let agent = init.proxy.agent();
+                                let adapter = agent
+                                    .and_then(|agent| init.proxy.adapters().adapter_for(agent))
+                                    .map(|adapter| adapter.name());
+                                emit(&init.hooks, ProxyEvent::Initialized { agent, adapter });
+                            }
+                            request_cx.respond_with_result(result)
+                        })
+                },
+            )
+            .await
+            // Record the working directory of new sessions once the agent
+            // assigns an id
+            .if_request_from(
+                Client,
+                async move |req: NewSessionRequest, request_cx: JrRequestCx<_>| {
+                    new_cx
+                        .send_request_to(Agent, req.clone())
+                        .await_when_result_received(async move |result| {
+                            if let Ok(response) = &result {
+                                new.proxy.observe_new_session(&req, response);
+                                new.seen_session(&response.session_id);
+                                emit(
+                                    &new.hooks,
+                                    ProxyEvent::SessionStarted {
+                                        session_id: &response.session_id,
+                                        cwd: &req.cwd,
+                                    },
+                                );
+                                new.resolve_policy(&response.session_id);
+                            }
+                            request_cx.respond_with_result(result)
+                        })
+                },
+            )
+            .await
+            // Record the working directory of loaded sessions, and their mode
+            // once the agent reports it
+            .if_request_from(
+                Client,
+                async move |req: LoadSessionRequest, request_cx: JrRequestCx<_>| {
+                    load.proxy.observe_load_session(&req);
+                    load.seen_session(&req.session_id);
+                    emit(
+                        &load.hooks,
+                        ProxyEvent::SessionLoaded {
+                            session_id: &req.session_id,
+                            cwd: &req.cwd,
+                        },
+                    );
+                    load.resolve_policy(&req.session_id);
+                    load_cx
+                        .send_request_to(Agent, req.clone())
+                        .await_when_result_received(async move |result| {
+                            if let Ok(LoadSessionResponse {
+                                modes: Some(modes), ..
+                            }) = &result
+                            {
+                                load.observe_mode(&req.session_id, &modes.current_mode_id);
+                            }
+                            request_cx.respond_with_result(result)
+                        })
+                },
+            )
+            .await
+            // Follow mode changes, so that rules for the session's mode apply
+            .if_request_from(
+                Client,
+                async move |req: SetSessionModeRequest, request_cx: JrRequestCx<_>| {
+                    set_mode_cx
+                        .send_request_to(Agent, req.clone())
+                        .await_when_result_received(async move |result| {
+                            if result.is_ok() {
+                                set_mode.observe_mode(&req.session_id, &req.mode_id);
+                            }
+                            request_cx.respond_with_result(result)
+                        })
+                },
+            )
+            .await
+            .if_notification_from(Agent, async move |notification: SessionNotification| {
+                if let Some(mode) = update.proxy.observe_session_update(&notification) {
+                    emit(
+                        &update.hooks,
+                        ProxyEvent::ModeChanged {
+                            session_id: &notification.session_id,
+                            mode,
+                        },
+                    );
+                }
+                Ok(Handled::No {
+                    message: notification,
+                    retry: false,
+                })
+            })
+            .await
+            // Answer the cancelled session's forwarded requests, then let the
+            // agent know
+            .if_notification_from(Client, async move |notification: CancelNotification| {
+                cancel.cancel_pending(&notification.session_id);
+                Ok(Handled::No {
+                    message: notification,
+                    retry: false,
+                })
+            })
+            .await
+            // Answer the client's questions about the policy itself
+            .if_request_from(
+                Client,
+                async move |req: EvaluateRequest, request_cx: JrRequestCx<_>| {
+                    let decision = evaluate.explain_command(&req.session_id, req.command, req.cwd);
+                    request_cx.respond(EvaluateResponse {
+                        outcome: decision.outcome().to_string(),
+                    })
+                },
+            )
+            .await
+            .if_request_from(
+                Client,
+                async move |req: ExplainRequest, request_cx: JrRequestCx<_>| {
+                    let decision = explain.explain_command(&req.session_id, req.command, req.cwd);
+                    request_cx.respond(ExplainResponse::from(&decision))
+                },
+            )
+            .await
+            .if_request_from(
+                Client,
+                async move |req: ListGrantsRequest, request_cx: JrRequestCx<_>| {
+                    let grants = list.proxy.grants(&req.session_id);
+                    request_cx.respond(ListGrantsResponse {
+                        grants: grants.iter().map(GrantInfo::from).collect(),
+                    })
+                },
+            )
+            .await
+            .if_request_from(
+                Client,
+                async move |req: RevokeGrantRequest, request_cx: JrRequestCx<_>| {
+                    let revoked = revoke.proxy.revoke_grant(&req.session_id, &req.grant_id);
+                    if let Some(grant) = &revoked {
+                        emit(
+                            &revoke.hooks,
+                            ProxyEvent::GrantRevoked {
+                                session_id: &req.session_id,
+                                grant,
+                            },
+                        );
+                    }
+                    request_cx.respond(RevokeGrantResponse {
+                        revoked: revoked.as_ref().map(GrantInfo::from),
+                    })
+                },
+            )
+            .await
+            .if_request_from(
+                Client,
+                async move |_: ReloadRulesetRequest, request_cx: JrRequestCx<_>| {
+                    request_cx.respond_with_result(reload.reload_ruleset())
+                },
+            )
+            .await
+            // Enforce the policy on calls the agent makes to the client
+            // without asking for permission
+            .if_request_from(
+                Agent,
+                async move |req: ReadTextFileRequest, request_cx: JrRequestCx<_>| match read
+                    .check_client_call(ClientCall::ReadTextFile(&req))
+                {
+                    Ok(()) => read_cx
+                        .send_request_to(Client, req)
+                        .forward_to_request_cx(request_cx),
+                    Err(error) => request_cx.respond_with_error(error),
+                },
+            )
+            .await
+            .if_request_from(
+                Agent,
+                async move |req: WriteTextFileRequest, request_cx: JrRequestCx<_>| match write
+                    .check_client_call(ClientCall::WriteTextFile(&req))
+                {
+                    Ok(()) => write_cx
+                        .send_request_to(Client, req)
+                        .forward_to_request_cx(request_cx),
+                    Err(error) => request_cx.respond_with_error(error),
+                },
+            )
+            .await
+            .if_request_from(
+                Agent,
+                async move |req: CreateTerminalRequest, request_cx: JrRequestCx<_>| match terminal
+                    .check_client_call(ClientCall::CreateTerminal(&req))
+                {
+                    Ok(()) => terminal_cx
+                        .send_request_to(Client, req)
+                        .forward_to_request_cx(request_cx),
+                    Err(error) => request_cx.respond_with_error(error),
+                },
+            )
+            .await
+            // Decide permission requests from the agent, or forward them to
+            // the client
+            .if_request_from(
+                Agent,
+                async move |req: RequestPermissionRequest, request_cx: JrRequestCx<_>| {
+                    permission.handle_permission_request(req, request_cx, &cx)
+                },
+            )
+            .await
+            .done()
+    }
+}
+
+impl ToolcapHandler {
+    /// Explains how a command would be decided in a session, for the
+    /// client's extension requests.
+    fn explain_command(
+        &self,
+        session_id: &SessionId,
+        command: String,
+        cwd: Option<PathBuf>,
+    ) -> Decision {
+        let operation = match cwd {
+            Some(cwd) => Operation::execute_in(command, cwd),
+            None => Operation::execute(command),
+        };
+        self.proxy.explain_in_session(session_id, operation)
+    }
+
+    /// Replaces the proxy's ruleset with a freshly loaded one.
+    fn reload_ruleset(&self) -> Result<ReloadRulesetResponse, sacp::Error> {
+        let Some(loader) = &self.loader else {
+            return Err(sacp::Error::method_not_found());
+        };
+        match loader() {
+            Ok(ruleset) => {
+                let rules = ruleset.rules().len();
+                self.proxy.replace_ruleset(ruleset);
+                emit(&self.hooks, ProxyEvent::RulesetReloaded { rules });
+                Ok(ReloadRulesetResponse { rules })
+            }
+            Err(error) => {
+                emit(&self.hooks, ProxyEvent::ReloadFailed { error: &error });
+                Err(sacp::Error::internal_error().with_data(error.to_string()))
+            }
+        }
+    }
+
+    /// Picks a new session's ruleset, reporting where it came from.
+    fn resolve_policy(&self, session_id: &SessionId) {
+        let (source, error) = self.proxy.resolve_session_policy(session_id);
+        if let Some(error) = &error {
+            emit(&self.hooks, ProxyEvent::PolicyFailed { session_id, error });
+        }
+        if let Some(source) = &source {
+            emit(
+                &self.hooks,
+                ProxyEvent::PolicySelected { session_id, source },
+            );
+        }
+    }
+
+    /// Records a session's new mode.
+    fn observe_mode(&self, session_id: &SessionId, mode: &SessionModeId) {
+        if self.proxy.observe_session_mode(session_id, mode) {
+            emit(&self.hooks, ProxyEvent::ModeChanged { session_id, mode });
+        }
+    }
+
+    /// Answers a permission request, or forwards it to the client.
+    fn handle_permission_request(
+        self,
+        req: RequestPermissionRequest,
+        request_cx: JrRequestCx<RequestPermissionResponse>,
+        cx: &JrConnectionCx<ProxyToConductor>,
+    ) -> Result<(), sacp::Error> {
+        let (operation, decision, permission_decision, disposition) =
+            self.proxy.handle_permission_request_explained(&req);
+        match permission_decision {
+            PermissionDecision::Respond(response) => {
+                if let (Disposition::Headless(_), Some(default)) =
+                    (&disposition, self.proxy.headless())
+                {
+                    emit(
+                        &self.hooks,
+                        ProxyEvent::Defaulted {
+                            request: &req,
+                            operation: &operation,
+                            decision: &decision,
+                            default,
+                            response: &response,
+                        },
+                    );
+                    self.send_denial_message(&req, &response, cx)?;
+                    return request_cx.respond(response);
+                }
+                emit(
+                    &self.hooks,
+                    ProxyEvent::Responded {
+                        request: &req,
+                        operation: &operation,
+                        decision: &decision,
+                        response: &response,
+                    },
+                );
+                self.send_denial_message(&req, &response, cx)?;
+                request_cx.respond(response)
+            }
+            PermissionDecision::Forward => {
+                // The user is only asked once per request; duplicates share
+                // the answer
+                if !self.pending.insert(&req, &operation, &decision, request_cx) {
+                    emit(&self.hooks, ProxyEvent::Duplicate { request: &req });
+                    return Ok(());
+                }
+                emit(
+                    &self.hooks,
+                    ProxyEvent::Forwarded {
+                        request: &req,
+                        operation: &operation,
+                        decision: &decision,
+                    },
+                );
+                let forwarded = self.proxy.forwarded_request(&req, &decision);
+                let answer = cx.send_request_to(Client, forwarded);
+                let Some((timeout, _)) = self.proxy.forward_timeout() else {
+                    return answer.await_when_result_received(async move |result| {
+                        self.answer_pending(&req, result)
+                    });
+                };
+                // Waiting for the answer would block the handler, so wait
+                // in a task of its own
+                let task_cx = cx.clone();
+                cx.spawn(async move {
+                    match tokio::time::timeout(timeout, answer.block_task()).await {
+                        Ok(result) => self.answer_pending(&req, result),
+                        Err(_) => {
+                            let Some(pending) = self.pending.take(&req) else {
+                                return Ok(());
+                            };
+                            let response = self.proxy.decided_forward_timeout(
+                                &req,
+                                &pending.operation,
+                                &pending.decision,
+                            );
+                            emit(
+                                &self.hooks,
+                                ProxyEvent::TimedOut {
+                                    request: &req,
+                                    response: &response,
+                                    timeout,
+                                },
+                            );
+                            self.send_denial_message(&req, &response, &task_cx)?;
+                            pending.respond(Ok(response))
+                        }
+                    }
+                })
+            }
+        }
+    }
+
+    /// Shows the user why the proxy denied a request, if it did and
+    /// [denial messages](ToolcapComponent::with_denial_messages) are enabled.
+    fn send_denial_message(
+        &self,
+        req: &RequestPermissionRequest,
+        response: &RequestPermissionResponse,
+        cx: &JrConnectionCx<ProxyToConductor>,
+    ) -> Result<(), sacp::Error> {
+        let reason = response
+            .meta
+            .as_ref()
+            .and_then(|meta| meta["toolcap"]["reason"].as_str());
+        match reason {
+            Some(reason) if self.denial_messages => {
+                cx.send_notification_to(Client, denial_message(&req.session_id, reason.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Answers a forwarded request, and its duplicates, with the client's
+    /// answer.
+    ///
+    /// Answers to requests that were already answered, because their session
+    /// was cancelled or they timed out, are dropped.
+    fn answer_pending(
+        &self,
+        req: &RequestPermissionRequest,
+        result: Result<RequestPermissionResponse, sacp::Error>,
+    ) -> Result<(), sacp::Error> {
+        let Some(pending) = self.pending.take(req) else {
+            return Ok(());
+        };
+        if let Ok(response) = &result {
+            self.observe_answer(req, &pending.operation, &pending.decision, response);
+        }
+        pending.respond(result)
+    }
+
+    /// Answers every forwarded request of a cancelled session with
+    /// `Cancelled`, as ACP requires.
+    fn cancel_pending(&self, session_id: &SessionId) {
+        for pending in self.pending.take_session(session_id) {
+            let response = RequestPermissionResponse {
+                outcome: RequestPermissionOutcome::Cancelled,
+                meta: None,
+            };
+            self.proxy.observe_decided_response(
+                &pending.request,
+                &pending.operation,
+                &pending.decision,
+                &response,
+            );
+            emit(
+                &self.hooks,
+                ProxyEvent::Cancelled {
+                    request: &pending.request,
+                },
+            );
+            // The agent may have gone away; the session is over either way
+            let _ = pending.respond(Ok(response));
+        }
+    }
+
+    /// Checks a call the agent made to a client method, returning the error
+    /// to reject it with if it's denied.
+    fn check_client_call(&self, call: ClientCall<'_>) -> Result<(), sacp::Error> {
+        let (operation, decision, result) = self.proxy.check_client_call_explained(call);
+        emit(
+            &self.hooks,
+            ProxyEvent::ClientCall {
+                call,
+                operation: &operation,
+                decision: &decision,
+            },
+        );
+        result
+    }
+
+    /// Remembers the user's answer to a forwarded request, saving the new
+    /// grants to the learned policy.
+    fn observe_answer(
+        &self,
+        req: &RequestPermissionRequest,
+        operation: &Operation,
+        decision: &Decision,
+        response: &RequestPermissionResponse,
+    ) {
+        let grants = self
+            .proxy
+            .observe_decided_response(req, operation, decision, response);
+        emit(
+            &self.hooks,
+            ProxyEvent::Answered {
+                request: req,
+                response,
+                grants: &grants,
+            },
+        );
+        let Some(policy) = self.proxy.learned_policy() else {
+            return;
+        };
+        match policy.learn(&req.session_id, &grants) {
+            Ok(rules) if rules.is_empty() => {}
+            Ok(rules) => emit(
+                &self.hooks,
+                ProxyEvent::Learned {
+                    session_id: &req.session_id,
+                    rules: &rules,
+                },
+            ),
+            Err(error) => emit(&self.hooks, ProxyEvent::LearnFailed { error: &error }),
+        }
+    }
+}
+
+/// Builds the agent message explaining a denial to the session.
+fn denial_message(session_id: &SessionId, reason: String) -> SessionNotification {
+    SessionNotification {
+        session_id: session_id.clone(),
+        update: SessionUpdate::AgentMessageChunk(ContentChunk {
+            content: reason.into(),
+            meta: None,
+        }),
+        meta: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::acp::make_permission_option;
+    use crate::matcher::Matcher;
+    use crate::outcome::Outcome;
+    use crate::rule::Rule;
+    use crate::ruleset::Ruleset;
+    use sacp::schema::{
+        PermissionOptionKind, ToolCallId, ToolCallUpdate, ToolCallUpdateFields, ToolKind,
+    };
+    use serde_json::json;
+
+    fn proxy() -> ToolcapProxy {
+        ToolcapProxy::new(Ruleset::new(vec![
+            Rule::new(Matcher::command("ls"), Outcome::Allow).with_id("ls"),
+        ]))
+    }
+
+    fn request(command: &str) -> RequestPermissionRequest {
+        RequestPermissionRequest {
+            session_id: "s1".to_string().into(),
+            tool_call: ToolCallUpdate {
+                id: ToolCallId::from("c1"),
+                fields: ToolCallUpdateFields {
+                    kind: Some(ToolKind::Execute),
+                    raw_input: Some(json!({ "command": command })),
+                    ..Default::default()
+                },
+                meta: None,
+            },
+            options: vec![
+                make_permission_option("allow", PermissionOptionKind::AllowOnce),
+                make_permission_option("always", PermissionOptionKind::AllowAlways),
+            ],
+            meta: None,
+        }
+    }
+
+    #[test]
+    fn test_builder() {
+        let component = ToolcapComponent::new(proxy());
+        assert_eq!(component.name(), ToolcapComponent::DEFAULT_NAME);
+
+        let component = component
+            .with_name("my-proxy")
+            .with_event_hook(|_| {})
+            .with_denial_messages(true);
+        assert_eq!(component.name(), "my-proxy");
+        assert_eq!(component.proxy().ruleset().rules().len(), 1);
+
+        // Handlers share the component's proxy.
+        let handler = component.handler();
+        assert!(std::ptr::eq(handler.proxy(), component.proxy()));
+        assert!(handler.denial_messages);
+    }
+
+    #[test]
+    fn test_forget_sessions() {
+        let component = ToolcapComponent::new(proxy());
+        let proxy = component.proxy();
+        for session_id in ["s1", "s2"] {
+            proxy.observe_load_session(&LoadSessionRequest {
+                mcp_servers: vec![],
+                cwd: "/repo".into(),
+                session_id: session_id.to_string().into(),
+                meta: None,
+            });
+        }
+        let handler = component.handler();
+        handler.seen_session(&"s1".to_string().into());
+        proxy.observe_permission_response(
+            &request("cargo test"),
+            &RequestPermissionResponse {
+                outcome: RequestPermissionOutcome::Selected {
+                    option_id: "always".to_string().into(),
+                },
+                meta: None,
+            },
+        );
+        assert_eq!(proxy.grants(&"s1".to_string().into()).len(), 1);
+
+        // Only the handler's own sessions are forgotten, by every clone.
+        handler.clone().forget_sessions();
+        assert!(proxy.session(&"s1".to_string().into()).is_none());
+        assert!(proxy.grants(&"s1".to_string().into()).is_empty());
+        assert!(proxy.session(&"s2".to_string().into()).is_some());
+        assert!(handler.lock_sessions().is_empty());
+    }
+
+    #[test]
+    fn test_answers_are_remembered_and_reported() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let handler = ToolcapComponent::new(proxy())
+            .with_event_hook(move |event| recorded.lock().unwrap().push(event.to_string()))
+            .handler();
+
+        let req = request("cargo test");
+        let response = RequestPermissionResponse {
+            outcome: RequestPermissionOutcome::Selected {
+                option_id: "always".to_string().into(),
+            },
+            meta: None,
+        };
+        let (operation, decision, ..) = handler.proxy().handle_permission_request_explained(&req);
+        handler.observe_answer(&req, &operation, &decision, &response);
+        assert_eq!(handler.proxy().grants(&req.session_id).len(), 1);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "user chose always for tool call c1; remembered [grant-1] allow exactly `cargo test` \
+             (Remembered from `cargo test`)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extension_requests() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let component = ToolcapComponent::new(proxy())
+            .with_event_hook(move |event| recorded.lock().unwrap().push(event.to_string()));
+
+        // Commands are decided in the session, relative to its directory.
+        let handler = component.handler();
+        let decision = handler.explain_command(&"s1".into(), "ls".into(), Some("/repo".into()));
+        assert_eq!(decision.outcome(), Outcome::Allow);
+        let decision = handler.explain_command(&"s1".into(), "rm -rf /".into(), None);
+        assert_eq!(decision.outcome(), Outcome::Unknown);
+
+        // Without a loader, there's nothing to reload from.
+        let error = handler.reload_ruleset().unwrap_err();
+        assert_eq!(error.code, sacp::Error::method_not_found().code);
+
+        let component = component.with_ruleset_loader(|| {
+            Ok(Ruleset::new(vec![
+                Rule::new(Matcher::command("ls"), Outcome::Allow),
+                Rule::new(Matcher::command("rm"), Outcome::Deny),
+            ]))
+        });
+        let response = component.handler().reload_ruleset().unwrap();
+        assert_eq!(response.rules, 2);
+        assert_eq!(component.proxy().ruleset().rules().len(), 2);
+
+        // A failed reload keeps the ruleset.
+        let component = component.with_ruleset_loader(|| Err("bad policy".into()));
+        assert!(component.handler().reload_ruleset().is_err());
+        assert_eq!(component.proxy().ruleset().rules().len(), 2);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "reloaded the ruleset (2 rules)",
+                "failed to reload the ruleset: bad policy"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_event_display() {
+        let proxy = proxy();
+        let req = request("ls -la");
+        let (operation, decision, PermissionDecision::Respond(response), _) =
+            proxy.handle_permission_request_explained(&req)
+        else {
+            panic!("Expected a response");
+        };
+        assert_eq!(
+            ProxyEvent::Responded {
+                request: &req,
+                operation: &operation,
+                decision: &decision,
+                response: &response,
+            }
+            .to_string(),
+            "auto-responded to `ls -la`: allow (rules: ls)"
+        );
+
+        let req = request("cargo test");
+        let (operation, decision, ..) = proxy.handle_permission_request_explained(&req);
+        assert_eq!(
+            ProxyEvent::Forwarded {
+                request: &req,
+                operation: &operation,
+                decision: &decision,
+            }
+            .to_string(),
+            "forwarded `cargo test` to the client"
+        );
+
+        assert_eq!(
+            ProxyEvent::ModeChanged {
+                session_id: &req.session_id,
+                mode: &SessionModeId("plan".into()),
+            }
+            .to_string(),
+            format!("session {} switched to plan mode", req.session_id)
+        );
+
+        let default = DefaultDecision::deny().with_reason("running in CI");
+        let proxy = proxy.with_headless(default.clone());
+        let (operation, decision, PermissionDecision::Respond(response), _) =
+            proxy.handle_permission_request_explained(&req)
+        else {
+            panic!("Expected a response");
+        };
+        assert_eq!(
+            ProxyEvent::Defaulted {
+                request: &req,
+                operation: &operation,
+                decision: &decision,
+                default: &default,
+                response: &response,
+            }
+            .to_string(),
+            "no one to ask about `cargo test`: deny by default (running in CI)"
+        );
+        assert_eq!(
+            ProxyEvent::TimedOut {
+                request: &req,
+                response: &response,
+                timeout: Duration::from_secs(30),
+            }
+            .to_string(),
+            "no answer for tool call c1 within 30s, cancelled it"
+        );
+
+        let terminal = CreateTerminalRequest {
+            session_id: "s1".to_string().into(),
+            command: "ls".into(),
+            args: vec!["-la".into()],
+            env: Vec::new(),
+            cwd: None,
+            output_byte_limit: None,
+            meta: None,
+        };
+        let call = ClientCall::CreateTerminal(&terminal);
+        let (operation, decision, _) = proxy.check_client_call_explained(call);
+        assert_eq!(
+            ProxyEvent::ClientCall {
+                call,
+                operation: &operation,
+                decision: &decision,
+            }
+            .to_string(),
+            "passed on terminal/create for `ls -la` (rules: ls)"
+        );
+
+        let agent = Implementation {
+            name: "claude-code-acp".into(),
+            title: None,
+            version: "1.0".into(),
+        };
+        assert_eq!(
+            ProxyEvent::Initialized {
+                agent: Some(&agent),
+                adapter: None,
+            }
+            .to_string(),
+            "agent: claude-code-acp 1.0 (adapter: generic)"
+        );
+        assert_eq!(
+            ProxyEvent::SessionStarted {
+                session_id: &"s1".into(),
+                cwd: Path::new("/repo"),
+            }
+            .to_string(),
+            "session s1 started in /repo"
+        );
+        let grant =
+            Grant::new(Rule::new(Matcher::command("make"), Outcome::Allow).with_id("grant-2"));
+        assert_eq!(
+            ProxyEvent::GrantRevoked {
+                session_id: &"s1".into(),
+                grant: &grant,
+            }
+            .to_string(),
+            "revoked [grant-2] allow make in session s1"
+        );
+        assert_eq!(
+            ProxyEvent::PolicySelected {
+                session_id: &"s1".into(),
+                source: &PolicySource::Agent("codex".into()),
+            }
+            .to_string(),
+            "session s1 uses the ruleset for agent codex"
+        );
+    }
+
+    /// Tests of forwarded requests, run between a scripted client and agent
+    /// whose client holds its answers.
+    #[cfg(feature = "test-support")]
+    mod pending {
+        use tokio::sync::mpsc;
+
+        use super::*;
+        use crate::acp::testing::{HarnessCx, ProxyHarness, ScriptedClient, Transcript};
+
+        /// Serves `component` with a client that holds permission requests,
+        /// and runs `test`.
+        async fn run(
+            component: ToolcapComponent,
+            test: impl AsyncFnOnce(HarnessCx) -> Result<(), sacp::Error>,
+        ) -> Transcript {
+            let harness =
+                ProxyHarness::new().with_client(ScriptedClient::new().with_held_answers());
+            let ((), transcript) =
+                tokio::time::timeout(Duration::from_secs(5), harness.run(component, test))
+                    .await
+                    .expect("test timed out")
+                    .unwrap();
+            transcript
+        }
+
+        /// Returns a component whose events are sent to the returned
+        /// receiver.
+        fn component() -> (ToolcapComponent, mpsc::UnboundedReceiver<String>) {
+            let (events_tx, events) = mpsc::unbounded_channel();
+            let component = ToolcapComponent::new(proxy()).with_event_hook(move |event| {
+                let _ = events_tx.send(event.to_string());
+            });
+            (component, events)
+        }
+
+        /// Asks for permission as the agent, in a task of its own.
+        fn ask(
+            cx: &HarnessCx,
+            req: RequestPermissionRequest,
+        ) -> tokio::task::JoinHandle<Result<RequestPermissionResponse, sacp::Error>> {
+            let cx = cx.clone();
+            tokio::spawn(async move { cx.agent_request(req).await })
+        }
+
+        fn in_session(
+            session_id: &str,
+            mut req: RequestPermissionRequest,
+        ) -> RequestPermissionRequest {
+            req.session_id = session_id.to_string().into();
+            req
+        }
+
+        fn selected(option_id: &str) -> RequestPermissionOutcome {
+            RequestPermissionOutcome::Selected {
+                option_id: option_id.to_string().into(),
+            }
+        }
+
+        #[tokio::test]
+        async fn test_cancel_answers_pending_requests() {
+            let (component, mut events) = component();
+            let handler = component.handler();
+
+            let transcript = run(component, async |cx| {
+                let answer = ask(&cx, request("cargo test"));
+                let held = cx.held_request().await?;
+                assert_eq!(held.request().tool_call.id, ToolCallId::from("c1"));
+                assert_eq!(handler.pending.len(), 1);
+
+                // The proxy answers for the client, and passes the
+                // cancellation on to the agent
+                cx.cancel(&"s1".into())?;
+                let response = answer.await.unwrap()?;
+                assert_eq!(response.outcome, RequestPermissionOutcome::Cancelled);
+                assert_eq!(handler.pending.len(), 0);
+
+                // The client's late answer is dropped
+                held.answer(selected("always"))?;
+                let response = cx.agent_request(request("ls")).await?;
+                assert_eq!(response.outcome, selected("allow"));
+                assert!(handler.proxy().grants(&"s1".into()).is_empty());
+                Ok(())
+            })
+            .await;
+
+            assert_eq!(
+                events.recv().await.unwrap(),
+                "forwarded `cargo test` to the client"
+            );
+            assert_eq!(
+                events.recv().await.unwrap(),
+                "cancelled tool call c1: session s1 was cancelled"
+            );
+            let cancelled = transcript
+                .agent_messages()
+                .iter()
+                .filter(|message| message.method == "session/cancel")
+                .count();
+            assert_eq!(cancelled, 1);
+        }
+
+        #[tokio::test]
+        async fn test_duplicates_share_the_answer() {
+            let (component, mut events) = component();
+            let handler = component.handler();
+
+            let transcript = run(component, async |cx| {
+                let first = ask(&cx, request("cargo test"));
+                let held = cx.held_request().await?;
+                assert_eq!(
+                    events.recv().await.unwrap(),
+                    "forwarded `cargo test` to the client"
+                );
+                let duplicate = ask(&cx, request("cargo test"));
+                assert_eq!(
+                    events.recv().await.unwrap(),
+                    "tool call c1 is already waiting for an answer"
+                );
+
+                // Requests in other sessions, or asking something else under
+                // the same tool call id, are forwarded on their own
+                let other = ask(&cx, in_session("s2", request("cargo test")));
+                let other_held = cx.held_request().await?;
+                assert_eq!(other_held.request().session_id, "s2".into());
+                let changed = ask(&cx, request("cargo publish"));
+                let changed_held = cx.held_request().await?;
+                assert_eq!(
+                    changed_held.request().tool_call.fields.raw_input,
+                    Some(json!({ "command": "cargo publish" }))
+                );
+                assert_eq!(handler.pending.len(), 3);
+
+                // Both requests for the tool call get the user's answer
+                held.answer(selected("allow"))?;
+                assert_eq!(first.await.unwrap()?.outcome, selected("allow"));
+                assert_eq!(duplicate.await.unwrap()?.outcome, selected("allow"));
+                assert_eq!(handler.pending.len(), 2);
+
+                changed_held.answer(RequestPermissionOutcome::Cancelled)?;
+                assert_eq!(
+                    changed.await.unwrap()?.outcome,
+                    RequestPermissionOutcome::Cancelled
+                );
+                other_held.answer(selected("allow"))?;
+                assert_eq!(other.await.unwrap()?.outcome, selected("allow"));
+                assert_eq!(handler.pending.len(), 0);
+                Ok(())
+            })
+            .await;
+
+            assert_eq!(transcript.forwarded().len(), 3);
+        }
+    }
+}
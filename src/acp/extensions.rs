@@ -0,0 +1,239 @@
+use std::path::PathBuf;
+
+use sacp::schema::SessionId;
+use sacp::{JrRequest, JrResponsePayload};
+use serde::{Deserialize, Serialize};
+
+use super::grants::Grant;
+use crate::decision::{Decision, DecisionNode, describe_operation};
+
+// The proxy answers these requests itself, so clients can query and manage
+// its policy. Extension methods start with an underscore, as ACP requires.
+
+/// `_toolcap/evaluate`: asks whether a command would be allowed in a session,
+/// without running it.
+///
+/// The command is resolved against the session's working directory (or
+/// `cwd`, relative to it) and decided with the session's grants, learned
+/// rules and mode, as a permission request would be.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JrRequest)]
+#[request(method = "_toolcap/evaluate", response = EvaluateResponse)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluateRequest {
+    pub session_id: SessionId,
+    /// The shell command to evaluate.
+    pub command: String,
+    /// The directory the command would run in, if not the session's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+}
+
+/// The response to `_toolcap/evaluate`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JrResponsePayload)]
+pub struct EvaluateResponse {
+    /// `allow`, `deny` or `unknown`.
+    pub outcome: String,
+}
+
+/// `_toolcap/explain`: asks how a command would be decided in a session,
+/// part by part.
+///
+/// The command is decided as for [`EvaluateRequest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JrRequest)]
+#[request(method = "_toolcap/explain", response = ExplainResponse)]
+#[serde(rename_all = "camelCase")]
+pub struct ExplainRequest {
+    pub session_id: SessionId,
+    /// The shell command to explain.
+    pub command: String,
+    /// The directory the command would run in, if not the session's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+}
+
+/// The response to `_toolcap/explain`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JrResponsePayload)]
+pub struct ExplainResponse {
+    /// `allow`, `deny` or `unknown`.
+    pub outcome: String,
+    /// Each sub-command and how it was decided, in order.
+    pub parts: Vec<DecidedPart>,
+    /// The whole decision tree, as text for display.
+    pub text: String,
+}
+
+/// How one sub-command was decided, in an [`ExplainResponse`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecidedPart {
+    pub command: String,
+    /// `allow`, `deny` or `unknown`.
+    pub outcome: String,
+    /// The rule that decided it, e.g. `no-sudo` or `#3`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    /// The rule's message, or why no rule decided it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// `_toolcap/grants/list`: lists the decisions remembered for a session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JrRequest)]
+#[request(method = "_toolcap/grants/list", response = ListGrantsResponse)]
+#[serde(rename_all = "camelCase")]
+pub struct ListGrantsRequest {
+    pub session_id: SessionId,
+}
+
+/// The response to `_toolcap/grants/list`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JrResponsePayload)]
+pub struct ListGrantsResponse {
+    pub grants: Vec<GrantInfo>,
+}
+
+/// A remembered decision, as reported to clients.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GrantInfo {
+    /// The grant's id, for revoking it.
+    pub id: String,
+    /// `allow` or `deny`.
+    pub outcome: String,
+    /// The grant's rule, as text for display.
+    pub rule: String,
+}
+
+/// `_toolcap/grants/revoke`: forgets a decision remembered for a session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JrRequest)]
+#[request(method = "_toolcap/grants/revoke", response = RevokeGrantResponse)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokeGrantRequest {
+    pub session_id: SessionId,
+    pub grant_id: String,
+}
+
+/// The response to `_toolcap/grants/revoke`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JrResponsePayload)]
+pub struct RevokeGrantResponse {
+    /// The revoked grant, or `None` if the session had no such grant.
+    pub revoked: Option<GrantInfo>,
+}
+
+/// `_toolcap/ruleset/reload`: reloads the proxy's ruleset from its source.
+///
+/// Only proxies given a loader (see
+/// [`ToolcapComponent::with_ruleset_loader`](super::ToolcapComponent::with_ruleset_loader))
+/// can reload; others answer with a "method not found" error.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JrRequest)]
+#[request(method = "_toolcap/ruleset/reload", response = ReloadRulesetResponse)]
+pub struct ReloadRulesetRequest {}
+
+/// The response to `_toolcap/ruleset/reload`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JrResponsePayload)]
+pub struct ReloadRulesetResponse {
+    /// The number of rules in the new ruleset.
+    pub rules: usize,
+}
+
+impl From<&Grant> for GrantInfo {
+    fn from(grant: &Grant) -> Self {
+        Self {
+            id: grant.id().into(),
+            outcome: grant.outcome().to_string(),
+            rule: grant.to_string(),
+        }
+    }
+}
+
+impl From<&Decision> for ExplainResponse {
+    fn from(decision: &Decision) -> Self {
+        let parts = decision
+            .leaves()
+            .filter_map(|leaf| match leaf {
+                DecisionNode::Leaf {
+                    operation,
+                    outcome,
+                    rule,
+                    reason,
+                } => Some(DecidedPart {
+                    command: describe_operation(operation),
+                    outcome: outcome.to_string(),
+                    rule: rule.as_ref().map(ToString::to_string),
+                    reason: match (rule, reason) {
+                        (_, Some(reason)) => Some(reason.to_string()),
+                        (Some(rule), None) => rule.message().map(String::from),
+                        (None, None) => None,
+                    },
+                }),
+                DecisionNode::Compound { .. } => None,
+            })
+            .collect();
+        Self {
+            outcome: decision.outcome().to_string(),
+            parts,
+            text: decision.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+    use crate::operation::Operation;
+    use crate::outcome::Outcome;
+    use crate::rule::Rule;
+    use crate::ruleset::Ruleset;
+    use sacp::JrMessage;
+    use serde_json::json;
+
+    #[test]
+    fn test_request_schemas() {
+        let req = EvaluateRequest {
+            session_id: "s1".to_string().into(),
+            command: "cargo test".into(),
+            cwd: None,
+        };
+        assert_eq!(req.method(), "_toolcap/evaluate");
+        let parsed = ExplainRequest::parse_message(
+            "_toolcap/explain",
+            &json!({"sessionId": "s1", "command": "ls", "cwd": "/repo"}),
+        );
+        assert_eq!(
+            parsed.unwrap().unwrap(),
+            ExplainRequest {
+                session_id: "s1".to_string().into(),
+                command: "ls".into(),
+                cwd: Some("/repo".into()),
+            }
+        );
+        let parsed = RevokeGrantRequest::parse_message(
+            "_toolcap/grants/revoke",
+            &json!({"sessionId": "s1", "grantId": "grant-1"}),
+        );
+        assert_eq!(parsed.unwrap().unwrap().grant_id, "grant-1");
+        assert!(
+            ReloadRulesetRequest::parse_message("_toolcap/ruleset/reload", &json!({})).is_some()
+        );
+    }
+
+    #[test]
+    fn test_explain_response() {
+        let ruleset = Ruleset::new(vec![
+            Rule::new(Matcher::command("cargo"), Outcome::Allow),
+            Rule::new(Matcher::command("sudo"), Outcome::Deny)
+                .with_id("no-sudo")
+                .with_message("no root"),
+        ]);
+        let decision = ruleset.explain(&Operation::execute("cargo build && sudo ls | wc"));
+        let response = ExplainResponse::from(&decision);
+        assert_eq!(response.outcome, "deny");
+        assert_eq!(response.text, decision.to_string());
+        assert_eq!(
+            serde_json::to_value(&response.parts).unwrap(),
+            json!([
+                { "command": "cargo build", "outcome": "allow", "rule": "#0" },
+                { "command": "sudo ls", "outcome": "deny", "rule": "no-sudo", "reason": "no root" },
+                { "command": "wc", "outcome": "unknown", "reason": "no matching rule" },
+            ])
+        );
+    }
+}
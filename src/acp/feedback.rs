@@ -0,0 +1,361 @@
+use serde_json::Value;
+
+use super::matched_rule_json;
+use crate::decision::{Decision, DecisionNode, MatchedRule, describe_operation};
+use crate::outcome::Outcome;
+use crate::suggest::RuleSuggester;
+
+/// A denied part of an operation, with the rule that denied it.
+struct DeniedPart<'a> {
+    /// The denied sub-command, or `None` for a compound command denied as a
+    /// whole by a pipeline-shape rule.
+    command: Option<String>,
+    rule: Option<&'a MatchedRule>,
+}
+
+/// Returns the denied parts of a denied decision, in order.
+fn denied_parts(decision: &Decision) -> Vec<DeniedPart<'_>> {
+    fn visit<'a>(node: &'a DecisionNode, out: &mut Vec<DeniedPart<'a>>) {
+        match node {
+            DecisionNode::Leaf {
+                operation,
+                outcome: Outcome::Deny,
+                rule,
+                ..
+            } => out.push(DeniedPart {
+                command: Some(describe_operation(operation)),
+                rule: rule.as_ref(),
+            }),
+            DecisionNode::Leaf { .. } => {}
+            DecisionNode::Compound {
+                outcome,
+                rule,
+                children,
+                ..
+            } => {
+                if let (Outcome::Deny, Some(rule)) = (outcome, rule) {
+                    out.push(DeniedPart {
+                        command: None,
+                        rule: Some(rule),
+                    });
+                }
+                for child in children {
+                    visit(child, out);
+                }
+            }
+        }
+    }
+
+    let mut parts = Vec::new();
+    if decision.outcome() == Outcome::Deny {
+        visit(decision.tree(), &mut parts);
+    }
+    parts
+}
+
+/// Explains a denial to the agent: which parts of the command were denied,
+/// by which rules and why, and what to do instead.
+///
+/// Returns `None` unless the decision is a denial.
+///
+/// ```text
+/// This request was denied by the toolcap permission policy.
+/// - `rm -rf build`: denied by rule no-rm-rf (Recursive deletes): deleting trees is blocked; instead, use `cargo clean`
+/// Don't retry it with small variations; take a different approach, or ask the user.
+/// ```
+pub(crate) fn denial_reason(decision: &Decision) -> Option<String> {
+    if decision.outcome() != Outcome::Deny {
+        return None;
+    }
+    let mut reason = String::from("This request was denied by the toolcap permission policy.\n");
+    for part in denied_parts(decision) {
+        match part.command {
+            Some(command) => reason.push_str(&format!("- `{}`: denied", command)),
+            None => reason.push_str("- the command as a whole: denied"),
+        }
+        if let Some(rule) = part.rule {
+            reason.push_str(&format!(" by rule {}", rule));
+            if let Some(description) = rule.description() {
+                reason.push_str(&format!(" ({})", description));
+            }
+            if let Some(message) = rule.message() {
+                reason.push_str(&format!(": {}", message));
+            }
+            if let Some(alternative) = rule.alternative() {
+                reason.push_str(&format!("; instead, {}", alternative));
+            }
+        }
+        reason.push('\n');
+    }
+    reason.push_str(
+        "Don't retry it with small variations; take a different approach, or ask the user.",
+    );
+    Some(reason)
+}
+
+/// Explains to the agent a request that no rule decided, and that was
+/// denied by default because no one could be asked (`cause` is `headless`)
+/// or the user didn't answer in time (`timeout`), with the default's reason.
+///
+/// ```text
+/// This request was denied by the toolcap permission policy.
+/// - `npm publish`: not decided by any rule (no matching rule)
+/// No one can be asked to approve it, so it was denied by default: running in CI
+/// Don't retry it with small variations; take a different approach, or ask the user.
+/// ```
+pub(crate) fn default_denial_reason(
+    decision: &Decision,
+    cause: &str,
+    reason: Option<&str>,
+) -> String {
+    let mut text = String::from("This request was denied by the toolcap permission policy.\n");
+    for leaf in decision.leaves() {
+        if let DecisionNode::Leaf {
+            operation,
+            outcome: Outcome::Unknown,
+            reason,
+            ..
+        } = leaf
+        {
+            text.push_str(&format!(
+                "- `{}`: not decided by any rule",
+                describe_operation(operation)
+            ));
+            if let Some(reason) = reason {
+                text.push_str(&format!(" ({})", reason));
+            }
+            text.push('\n');
+        }
+    }
+    text.push_str(match cause {
+        "timeout" => "The user didn't answer in time, so it was denied by default",
+        _ => "No one can be asked to approve it, so it was denied by default",
+    });
+    if let Some(reason) = reason {
+        text.push_str(&format!(": {}", reason));
+    }
+    text.push_str(
+        "\nDon't retry it with small variations; take a different approach, or ask the user.",
+    );
+    text
+}
+
+/// Returns the denied parts of a denial as JSON, for response `_meta`.
+pub(crate) fn denied_parts_json(decision: &Decision) -> Vec<Value> {
+    denied_parts(decision)
+        .into_iter()
+        .map(|part| {
+            let mut obj = serde_json::Map::new();
+            if let Some(command) = part.command {
+                obj.insert("command".into(), command.into());
+            }
+            if let Some(rule) = part.rule {
+                obj.insert("rule".into(), matched_rule_json(rule));
+            }
+            Value::Object(obj)
+        })
+        .collect()
+}
+
+/// Summarizes what toolcap made of a request it couldn't decide, for the
+/// user it's forwarded to.
+///
+/// Each sub-command is listed under its outcome, with the reason for those
+/// left undecided, e.g. "toolcap: allowed `cargo build`; undecided `npm
+/// publish` (no matching rule)".
+pub(crate) fn forward_note(decision: &Decision) -> String {
+    let mut groups = Vec::new();
+    for (outcome, label) in [
+        (Outcome::Allow, "allowed"),
+        (Outcome::Deny, "denied"),
+        (Outcome::Unknown, "undecided"),
+    ] {
+        let commands: Vec<_> = decision
+            .leaves()
+            .filter_map(|leaf| match leaf {
+                DecisionNode::Leaf {
+                    operation,
+                    outcome: leaf_outcome,
+                    reason,
+                    ..
+                } if *leaf_outcome == outcome => Some(match reason {
+                    Some(reason) => format!("`{}` ({})", describe_operation(operation), reason),
+                    None => format!("`{}`", describe_operation(operation)),
+                }),
+                _ => None,
+            })
+            .collect();
+        if !commands.is_empty() {
+            groups.push(format!("{} {}", label, commands.join(", ")));
+        }
+    }
+    format!("toolcap: {}", groups.join("; "))
+}
+
+/// Returns the analysis of a forwarded request as JSON, for its `_meta`:
+/// each sub-command under its outcome, with the rule that decided it or the
+/// reason it's undecided, and the rules that could allow the undecided ones
+/// (see [`RuleSuggester`]).
+pub(crate) fn forward_analysis_json(decision: &Decision) -> Value {
+    let mut allowed = Vec::new();
+    let mut denied = Vec::new();
+    let mut unknown = Vec::new();
+    for leaf in decision.leaves() {
+        let DecisionNode::Leaf {
+            operation,
+            outcome,
+            rule,
+            reason,
+        } = leaf
+        else {
+            continue;
+        };
+        let mut obj = serde_json::Map::new();
+        obj.insert("command".into(), describe_operation(operation).into());
+        if let Some(rule) = rule {
+            obj.insert("rule".into(), matched_rule_json(rule));
+        }
+        if let Some(reason) = reason {
+            obj.insert("reason".into(), reason.to_string().into());
+        }
+        match outcome {
+            Outcome::Allow => allowed.push(Value::Object(obj)),
+            Outcome::Deny => denied.push(Value::Object(obj)),
+            Outcome::Unknown => unknown.push(Value::Object(obj)),
+        }
+    }
+    let suggestions: Vec<_> = RuleSuggester::new()
+        .suggest(decision)
+        .iter()
+        .map(|suggestion| {
+            serde_json::json!({
+                "command": suggestion.command(),
+                "generality": suggestion.generality().to_string(),
+                "rule": suggestion.to_string(),
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "outcome": decision.outcome().to_string(),
+        "allowed": allowed,
+        "denied": denied,
+        "unknown": unknown,
+        "suggestions": suggestions,
+        "note": forward_note(decision),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+    use crate::operation::Operation;
+    use crate::rule::Rule;
+    use crate::ruleset::Ruleset;
+
+    fn ruleset() -> Ruleset {
+        Ruleset::new(vec![
+            Rule::new(Matcher::command("cargo"), Outcome::Allow),
+            Rule::new(Matcher::command("rm").with_flag("-rf"), Outcome::Deny)
+                .with_id("no-rm-rf")
+                .with_description("Recursive deletes")
+                .with_message("deleting trees is blocked")
+                .with_alternative("use `cargo clean`"),
+            Rule::new(Matcher::command("curl"), Outcome::Deny),
+        ])
+    }
+
+    #[test]
+    fn test_denial_reason() {
+        let decision = ruleset().explain(&Operation::execute("cargo build && rm -rf target"));
+        assert_eq!(
+            denial_reason(&decision).unwrap(),
+            "This request was denied by the toolcap permission policy.
+- `rm -rf target`: denied by rule no-rm-rf (Recursive deletes): deleting trees is blocked; instead, use `cargo clean`
+Don't retry it with small variations; take a different approach, or ask the user."
+        );
+        assert_eq!(
+            denied_parts_json(&decision),
+            [serde_json::json!({
+                "command": "rm -rf target",
+                "rule": {
+                    "index": 1,
+                    "id": "no-rm-rf",
+                    "description": "Recursive deletes",
+                    "message": "deleting trees is blocked",
+                    "alternative": "use `cargo clean`",
+                },
+            })]
+        );
+
+        let decision = ruleset().explain(&Operation::execute("curl x"));
+        assert_eq!(
+            denial_reason(&decision).unwrap().lines().nth(1),
+            Some("- `curl x`: denied by rule #2")
+        );
+
+        let decision = ruleset().explain(&Operation::execute("cargo test"));
+        assert_eq!(denial_reason(&decision), None);
+        assert!(denied_parts_json(&decision).is_empty());
+    }
+
+    #[test]
+    fn test_default_denial_reason() {
+        let decision = ruleset().explain(&Operation::execute("cargo build && npm publish"));
+        assert_eq!(
+            default_denial_reason(&decision, "headless", Some("running in CI")),
+            "This request was denied by the toolcap permission policy.
+- `npm publish`: not decided by any rule (no matching rule)
+No one can be asked to approve it, so it was denied by default: running in CI
+Don't retry it with small variations; take a different approach, or ask the user."
+        );
+        assert_eq!(
+            default_denial_reason(&decision, "timeout", None)
+                .lines()
+                .nth(2),
+            Some("The user didn't answer in time, so it was denied by default")
+        );
+    }
+
+    #[test]
+    fn test_forward_analysis() {
+        let decision = ruleset().explain(&Operation::execute("cargo build && npm publish"));
+        assert_eq!(
+            forward_note(&decision),
+            "toolcap: allowed `cargo build`; undecided `npm publish` (no matching rule)"
+        );
+        assert_eq!(
+            forward_analysis_json(&decision),
+            serde_json::json!({
+                "outcome": "unknown",
+                "allowed": [{ "command": "cargo build", "rule": { "index": 0 } }],
+                "denied": [],
+                "unknown": [{ "command": "npm publish", "reason": "no matching rule" }],
+                "suggestions": [
+                    {
+                        "command": "npm publish",
+                        "generality": "exact",
+                        "rule": "exactly `npm publish`",
+                    },
+                    {
+                        "command": "npm publish",
+                        "generality": "subcommand",
+                        "rule": "`npm publish` with any arguments except dangerous flags",
+                    },
+                    {
+                        "command": "npm publish",
+                        "generality": "command",
+                        "rule": "any `npm` command except dangerous flags",
+                    },
+                ],
+                "note": "toolcap: allowed `cargo build`; undecided `npm publish` (no matching rule)",
+            })
+        );
+
+        let decision = ruleset().explain(&Operation::execute("cargo build 'oops"));
+        assert!(
+            forward_note(&decision)
+                .starts_with("toolcap: undecided `cargo build 'oops` (parse failure: ")
+        );
+    }
+}
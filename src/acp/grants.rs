@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use sacp::schema::SessionId;
+
+use crate::decision::{Decision, DecisionNode, UnknownReason};
+use crate::matcher::Matcher;
+use crate::operation::{ExecuteOperation, Operation};
+use crate::outcome::Outcome;
+use crate::rule::Rule;
+use crate::suggest::RuleSuggester;
+use crate::sync::lock_unpoisoned;
+
+/// How broadly a remembered decision applies.
+///
+/// Set with [`ToolcapProxy::with_grant_scope`](super::ToolcapProxy::with_grant_scope).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum GrantScope {
+    /// Only the exact command, with the same arguments.
+    #[default]
+    ExactCommand,
+
+    /// Any invocation of the same subcommand without a dangerous flag (e.g.
+    /// `git log` for `git log --oneline`, but not `git push --force` for
+    /// `git push`), as [`RuleSuggester`] would suggest.
+    ///
+    /// Only tools known to take subcommands, such as `git` and `cargo`, are
+    /// generalized; other commands, and commands using a dangerous flag, are
+    /// remembered exactly.
+    Subcommand,
+}
+
+/// A decision the user made in the client, remembered for the rest of the
+/// session.
+///
+/// Grants are created by
+/// [`ToolcapProxy::observe_permission_response`](super::ToolcapProxy::observe_permission_response)
+/// when the user picks an "always" option.
+#[derive(Debug, Clone)]
+pub struct Grant {
+    rule: Rule,
+}
+
+impl Grant {
+    pub(crate) fn new(rule: Rule) -> Self {
+        Self { rule }
+    }
+
+    /// Returns the grant's id, unique within the proxy.
+    pub fn id(&self) -> &str {
+        self.rule.id().unwrap_or_default()
+    }
+
+    /// Returns the rule the grant adds to its session.
+    pub fn rule(&self) -> &Rule {
+        &self.rule
+    }
+
+    /// Returns whether the grant allows or denies.
+    pub fn outcome(&self) -> Outcome {
+        self.rule.outcome()
+    }
+}
+
+impl fmt::Display for Grant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.rule)
+    }
+}
+
+/// The grants of every session, keyed by session id.
+///
+/// Clones share the same grants.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Grants {
+    inner: Arc<Mutex<GrantsInner>>,
+}
+
+#[derive(Debug, Default)]
+struct GrantsInner {
+    next_id: u64,
+    sessions: HashMap<SessionId, Vec<Grant>>,
+}
+
+impl Grants {
+    /// Adds a grant for `matcher` to a session, unless an identical one
+    /// exists already.
+    pub(crate) fn add(
+        &self,
+        session_id: &SessionId,
+        matcher: Matcher,
+        outcome: Outcome,
+        description: String,
+    ) -> Option<Grant> {
+        let mut inner = self.lock();
+        let exists = inner.sessions.get(session_id).is_some_and(|grants| {
+            grants
+                .iter()
+                .any(|grant| grant.rule.matcher() == &matcher && grant.outcome() == outcome)
+        });
+        if exists {
+            return None;
+        }
+
+        inner.next_id += 1;
+        let grant = Grant::new(
+            Rule::new(matcher, outcome)
+                .with_id(format!("grant-{}", inner.next_id))
+                .with_description(description),
+        );
+        inner
+            .sessions
+            .entry(session_id.clone())
+            .or_default()
+            .push(grant.clone());
+        Some(grant)
+    }
+
+    pub(crate) fn list(&self, session_id: &SessionId) -> Vec<Grant> {
+        self.lock()
+            .sessions
+            .get(session_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn revoke(&self, session_id: &SessionId, grant_id: &str) -> Option<Grant> {
+        let mut inner = self.lock();
+        let grants = inner.sessions.get_mut(session_id)?;
+        let index = grants.iter().position(|grant| grant.id() == grant_id)?;
+        Some(grants.remove(index))
+    }
+
+    pub(crate) fn clear(&self, session_id: &SessionId) {
+        self.lock().sessions.remove(session_id);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, GrantsInner> {
+        lock_unpoisoned(&self.inner)
+    }
+}
+
+/// Derives the matchers to remember for a decision the user made about a
+/// forwarded request, each with the command it was derived from.
+///
+/// Only commands that no rule decided are remembered, since those are what
+/// the user was asked about. An allow covers each of them. A deny is only
+/// remembered when a single command was undecided, since otherwise it isn't
+/// known which one the user objected to. Operations other than commands
+/// can't be expressed as matchers yet and aren't remembered.
+pub(crate) fn grant_matchers(
+    decision: &Decision,
+    outcome: Outcome,
+    scope: GrantScope,
+) -> Vec<(Matcher, String)> {
+    let undecided: Vec<_> = decision
+        .leaves()
+        .filter(|leaf| leaf.outcome() == Outcome::Unknown)
+        .collect();
+    if outcome == Outcome::Deny && undecided.len() != 1 {
+        return Vec::new();
+    }
+
+    undecided
+        .into_iter()
+        .filter_map(|leaf| match leaf {
+            DecisionNode::Leaf {
+                operation: Operation::Execute(exec_op),
+                reason: Some(UnknownReason::NoMatchingRule),
+                ..
+            } => Some((command_matcher(exec_op, scope), exec_op.raw().to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Tools whose first argument is a subcommand, which a
+/// [`GrantScope::Subcommand`] grant can generalize to.
+const SUBCOMMAND_TOOLS: &[&str] = &[
+    "bun", "cargo", "deno", "docker", "dotnet", "gh", "git", "go", "gradle", "kubectl", "mvn",
+    "npm", "pip", "pnpm", "poetry", "rustup", "uv", "yarn",
+];
+
+/// Builds the matcher remembering a command.
+///
+/// A grant for a command run in a known directory only applies within that
+/// directory.
+fn command_matcher(exec_op: &ExecuteOperation, scope: GrantScope) -> Matcher {
+    let name = exec_op.command_name().unwrap_or_default();
+    let suggester = RuleSuggester::new();
+    let matcher = match (scope, exec_op.subcommand()) {
+        (GrantScope::Subcommand, Some(subcommand))
+            if !subcommand.starts_with('-')
+                && SUBCOMMAND_TOOLS.contains(&name)
+                && !suggester.has_dangerous_flag(exec_op) =>
+        {
+            suggester.generalize(Matcher::command(name).with_subcommand(subcommand))
+        }
+        // The words as the shell splits them, so a grant for `rm 'my file'`
+        // doesn't also allow `rm my file`
+        _ => Matcher::exact(exec_op.raw()),
+    };
+    match exec_op.working_dir() {
+        Some(dir) => Matcher::and(vec![matcher, Matcher::within_directory(dir.clone())]),
+        None => matcher,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ruleset::Ruleset;
+
+    fn ruleset() -> Ruleset {
+        Ruleset::new(vec![
+            Rule::new(Matcher::command("ls"), Outcome::Allow),
+            Rule::new(Matcher::command("sudo"), Outcome::Deny),
+        ])
+    }
+
+    fn matchers(command: &str, outcome: Outcome, scope: GrantScope) -> Vec<String> {
+        let decision = ruleset().explain(&Operation::execute(command));
+        grant_matchers(&decision, outcome, scope)
+            .iter()
+            .map(|(matcher, _)| matcher.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_grant_matchers() {
+        use GrantScope::*;
+        use Outcome::*;
+
+        assert_eq!(
+            matchers("git log --oneline", Allow, ExactCommand),
+            vec!["exactly `git log --oneline`"]
+        );
+        assert_eq!(
+            matchers("rm -rf build", Allow, Subcommand),
+            vec!["exactly `rm -rf build`"]
+        );
+
+        // Only the undecided parts of a compound command are remembered.
+        assert_eq!(
+            matchers("ls && git status | wc -l", Allow, ExactCommand),
+            vec!["exactly `git status`", "exactly `wc -l`"]
+        );
+    }
+
+    #[test]
+    fn test_subcommand_grants() {
+        let grant = |command: &str| {
+            let decision = ruleset().explain(&Operation::execute(command));
+            let matchers = grant_matchers(&decision, Outcome::Allow, GrantScope::Subcommand);
+            matchers.into_iter().next().unwrap().0
+        };
+
+        let git_push = grant("git push origin main");
+        assert!(git_push.matches(&Operation::execute("git push origin feature")));
+        assert!(!git_push.matches(&Operation::execute("git push --force origin main")));
+
+        // Only tools with subcommands are generalized, and never across
+        // dangerous flags
+        let rm = grant("rm build");
+        assert_eq!(rm.to_string(), "exactly `rm build`");
+        assert!(!rm.matches(&Operation::execute("rm build -rf /")));
+        assert_eq!(
+            grant("git push --force origin main").to_string(),
+            "exactly `git push --force origin main`"
+        );
+    }
+
+    #[test]
+    fn test_exact_grants_keep_quoted_arguments() {
+        let decision = ruleset().explain(&Operation::execute("rm 'my file'"));
+        let (matcher, _) = grant_matchers(&decision, Outcome::Allow, GrantScope::ExactCommand)
+            .into_iter()
+            .next()
+            .unwrap();
+        assert_eq!(matcher.to_string(), "exactly `rm 'my file'`");
+        assert!(matcher.matches(&Operation::execute("rm \"my file\"")));
+        assert!(!matcher.matches(&Operation::execute("rm my file")));
+        assert!(!matcher.matches(&Operation::execute("ls && rm my file")));
+    }
+
+    #[test]
+    fn test_grants_keep_the_working_directory() {
+        let root = std::env::current_dir().unwrap();
+        let operation = Operation::execute("make clean").in_directory(root.join("src"));
+        let decision = ruleset().explain(&operation);
+        let (matcher, _) = grant_matchers(&decision, Outcome::Allow, GrantScope::ExactCommand)
+            .into_iter()
+            .next()
+            .unwrap();
+        assert!(matcher.matches(&operation));
+        assert!(!matcher.matches(&Operation::execute("make clean").in_directory(&root)));
+    }
+
+    #[test]
+    fn test_deny_needs_single_undecided_command() {
+        use Outcome::*;
+
+        let scope = GrantScope::ExactCommand;
+        assert_eq!(
+            matchers("ls | curl x", Deny, scope),
+            vec!["exactly `curl x`"]
+        );
+        assert!(matchers("git status | curl x", Deny, scope).is_empty());
+    }
+
+    #[test]
+    fn test_unparseable_commands_not_remembered() {
+        let decision = ruleset().explain(&Operation::execute("echo $(whoami)"));
+        assert!(grant_matchers(&decision, Outcome::Allow, GrantScope::ExactCommand).is_empty());
+    }
+
+    #[test]
+    fn test_add_list_revoke() {
+        let grants = Grants::default();
+        let session: SessionId = "s1".into();
+        let other: SessionId = "s2".into();
+
+        let grant = grants
+            .add(
+                &session,
+                Matcher::exact("make"),
+                Outcome::Allow,
+                "make".into(),
+            )
+            .unwrap();
+        assert_eq!(grant.id(), "grant-1");
+        assert!(
+            grants
+                .add(
+                    &session,
+                    Matcher::exact("make"),
+                    Outcome::Allow,
+                    "make".into()
+                )
+                .is_none()
+        );
+        grants.add(
+            &other,
+            Matcher::exact("make"),
+            Outcome::Allow,
+            "make".into(),
+        );
+
+        assert_eq!(grants.list(&session).len(), 1);
+        assert_eq!(grants.list(&other)[0].id(), "grant-2");
+
+        assert!(grants.revoke(&session, "grant-2").is_none());
+        assert_eq!(grants.revoke(&session, "grant-1").unwrap().id(), "grant-1");
+        assert!(grants.list(&session).is_empty());
+
+        grants.clear(&other);
+        assert!(grants.list(&other).is_empty());
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sacp::schema::{SessionId, ToolCallId};
+
+use crate::decision::{MatchedRule, describe_operation};
+use crate::operation::Operation;
+use crate::outcome::Outcome;
+use crate::sync::lock_unpoisoned;
+
+/// The answer the proxy gives when no one can: to requests no rule decides
+/// in headless mode, and to forwarded requests the user doesn't answer in
+/// time.
+///
+/// See [`ToolcapProxy::with_headless`](super::ToolcapProxy::with_headless)
+/// and [`ToolcapProxy::with_forward_timeout`](super::ToolcapProxy::with_forward_timeout).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefaultDecision {
+    outcome: Outcome,
+    reason: Option<String>,
+}
+
+impl DefaultDecision {
+    /// Denies by default.
+    pub fn deny() -> Self {
+        Self {
+            outcome: Outcome::Deny,
+            reason: None,
+        }
+    }
+
+    /// Allows by default.
+    pub fn allow() -> Self {
+        Self {
+            outcome: Outcome::Allow,
+            reason: None,
+        }
+    }
+
+    /// Sets the reason given to the agent, e.g. "no one is available to
+    /// approve this in CI".
+    pub fn with_reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+
+    /// Returns the outcome, either `Allow` or `Deny`.
+    pub fn outcome(&self) -> Outcome {
+        self.outcome
+    }
+
+    /// Returns the reason given to the agent, if set.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+}
+
+/// Why the proxy denied a request itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DenialCause {
+    /// Rules denied the request.
+    Rules(Vec<MatchedRule>),
+    /// No rule decided the request, and headless mode denies by default.
+    Headless,
+    /// The request was forwarded, but the user didn't answer in time.
+    Timeout(Duration),
+}
+
+/// A request the proxy denied without asking the user.
+///
+/// Requests are either permission requests or calls to client methods.
+#[derive(Debug, Clone)]
+pub struct Denial {
+    session_id: SessionId,
+    tool_call_id: Option<ToolCallId>,
+    operation: Operation,
+    cause: DenialCause,
+    reason: Option<String>,
+}
+
+impl Denial {
+    pub(crate) fn new(
+        session_id: SessionId,
+        tool_call_id: Option<ToolCallId>,
+        operation: Operation,
+        cause: DenialCause,
+        reason: Option<String>,
+    ) -> Self {
+        Self {
+            session_id,
+            tool_call_id,
+            operation,
+            cause,
+            reason,
+        }
+    }
+
+    /// Returns the session of the request.
+    pub fn session_id(&self) -> &SessionId {
+        &self.session_id
+    }
+
+    /// Returns the tool call of the request, unless it was a call to a
+    /// client method (see [`ClientCall`](super::ClientCall)).
+    pub fn tool_call_id(&self) -> Option<&ToolCallId> {
+        self.tool_call_id.as_ref()
+    }
+
+    /// Returns the operation that was denied.
+    pub fn operation(&self) -> &Operation {
+        &self.operation
+    }
+
+    /// Returns why the request was denied.
+    pub fn cause(&self) -> &DenialCause {
+        &self.cause
+    }
+
+    /// Returns the reason of the [`DefaultDecision`] that denied the
+    /// request, if any.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` (session {}): ",
+            describe_operation(&self.operation),
+            self.session_id
+        )?;
+        match &self.cause {
+            DenialCause::Rules(rules) => {
+                let label = if rules.len() == 1 { "rule" } else { "rules" };
+                let rules: Vec<_> = rules.iter().map(MatchedRule::to_string).collect();
+                write!(f, "denied by {} {}", label, rules.join(", "))?;
+            }
+            DenialCause::Headless => write!(f, "no rule matched")?,
+            DenialCause::Timeout(timeout) => {
+                write!(f, "no answer within {}s", timeout.as_secs_f64())?
+            }
+        }
+        if let Some(reason) = &self.reason {
+            write!(f, " ({})", reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// The requests a proxy denied without asking the user, for reporting at
+/// the end of a run.
+///
+/// Created by [`ToolcapProxy::denial_summary`](super::ToolcapProxy::denial_summary).
+/// The proxy keeps the latest [`MAX_DENIALS`](Self::MAX_DENIALS) denials,
+/// and counts the older ones. The `Display` output lists the denials, one
+/// per line.
+#[derive(Debug, Clone)]
+pub struct DenialSummary {
+    denials: Vec<Denial>,
+    omitted: usize,
+}
+
+impl DenialSummary {
+    /// The number of denials a proxy keeps.
+    pub const MAX_DENIALS: usize = 1000;
+
+    /// Returns the denials kept, in the order they were made.
+    pub fn denials(&self) -> &[Denial] {
+        &self.denials
+    }
+
+    /// Returns the number of older denials that weren't kept.
+    pub fn omitted(&self) -> usize {
+        self.omitted
+    }
+
+    /// Returns whether nothing was denied.
+    pub fn is_empty(&self) -> bool {
+        self.denials.is_empty() && self.omitted == 0
+    }
+}
+
+impl fmt::Display for DenialSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.denials.len() + self.omitted {
+            0 => return writeln!(f, "No requests were denied"),
+            1 => writeln!(f, "1 request was denied:")?,
+            n => writeln!(f, "{} requests were denied:", n)?,
+        }
+        if self.omitted > 0 {
+            writeln!(f, "  ({} earlier denials not kept)", self.omitted)?;
+        }
+        for denial in &self.denials {
+            writeln!(f, "  {}", denial)?;
+        }
+        Ok(())
+    }
+}
+
+/// The latest denials made by a proxy, and the number of older ones.
+///
+/// Clones share the same denials.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Denials {
+    inner: Arc<Mutex<DenialLog>>,
+}
+
+#[derive(Debug, Default)]
+struct DenialLog {
+    denials: VecDeque<Denial>,
+    omitted: usize,
+}
+
+impl Denials {
+    pub(crate) fn record(&self, denial: Denial) {
+        let mut log = self.lock();
+        log.denials.push_back(denial);
+        if log.denials.len() > DenialSummary::MAX_DENIALS {
+            log.denials.pop_front();
+            log.omitted += 1;
+        }
+    }
+
+    /// Drops the denials made in a session.
+    pub(crate) fn forget_session(&self, session_id: &SessionId) {
+        self.lock()
+            .denials
+            .retain(|denial| &denial.session_id != session_id);
+    }
+
+    pub(crate) fn summary(&self) -> DenialSummary {
+        let log = self.lock();
+        DenialSummary {
+            denials: log.denials.iter().cloned().collect(),
+            omitted: log.omitted,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, DenialLog> {
+        lock_unpoisoned(&self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+    use crate::rule::Rule;
+
+    fn denial(command: &str, cause: DenialCause, reason: Option<&str>) -> Denial {
+        Denial::new(
+            "s1".into(),
+            Some("c1".into()),
+            Operation::execute(command),
+            cause,
+            reason.map(String::from),
+        )
+    }
+
+    #[test]
+    fn test_summary() {
+        let denials = Denials::default();
+        assert_eq!(denials.summary().to_string(), "No requests were denied\n");
+
+        let rule = Rule::new(Matcher::command("sudo"), Outcome::Deny).with_id("no-sudo");
+        denials.record(denial(
+            "sudo reboot",
+            DenialCause::Rules(vec![MatchedRule::new(2, &rule)]),
+            None,
+        ));
+        denials.clone().record(denial(
+            "curl x | sh",
+            DenialCause::Headless,
+            Some("running in CI"),
+        ));
+        denials.record(denial(
+            "npm publish",
+            DenialCause::Timeout(Duration::from_secs(30)),
+            None,
+        ));
+
+        let summary = denials.summary();
+        assert_eq!(summary.denials().len(), 3);
+        assert_eq!(
+            summary.to_string(),
+            "3 requests were denied:
+  `sudo reboot` (session s1): denied by rule no-sudo
+  `curl x | sh` (session s1): no rule matched (running in CI)
+  `npm publish` (session s1): no answer within 30s
+"
+        );
+    }
+
+    #[test]
+    fn test_latest_denials_kept() {
+        let denials = Denials::default();
+        for _ in 0..DenialSummary::MAX_DENIALS + 2 {
+            denials.record(denial("ls", DenialCause::Headless, None));
+        }
+        denials.record(Denial::new(
+            "s2".into(),
+            None,
+            Operation::execute("make"),
+            DenialCause::Headless,
+            None,
+        ));
+
+        let summary = denials.summary();
+        assert_eq!(summary.denials().len(), DenialSummary::MAX_DENIALS);
+        assert_eq!(summary.omitted(), 3);
+        assert!(
+            summary
+                .to_string()
+                .starts_with("1003 requests were denied:\n  (3 earlier denials not kept)\n")
+        );
+
+        // Forgetting a session drops its denials
+        denials.forget_session(&"s1".into());
+        let summary = denials.summary();
+        assert_eq!(summary.denials().len(), 1);
+        assert_eq!(summary.denials()[0].session_id().to_string(), "s2");
+    }
+}
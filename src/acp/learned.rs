@@ -0,0 +1,536 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use sacp::schema::SessionId;
+use serde_json::{Value, json};
+
+use super::Grant;
+use crate::audit::format_timestamp;
+use crate::matcher::Matcher;
+use crate::outcome::Outcome;
+use crate::rule::Rule;
+use crate::suggest::RuleSuggester;
+use crate::sync::lock_unpoisoned;
+
+/// Permissions the user approved in earlier sessions, stored in a
+/// project-local policy file.
+///
+/// The file is JSON, with one entry per learned rule recording when and in
+/// which session it was learned:
+///
+/// ```json
+/// {
+///   "rules": [
+///     {
+///       "exact": ["cargo", "test", "--lib"],
+///       "outcome": "allow",
+///       "learned_at": "2025-06-01T12:00:00Z",
+///       "session_id": "sess_123"
+///     },
+///     {
+///       "command": "git",
+///       "subcommand": "log",
+///       "except_flags": ["--force", "--hard"],
+///       "directory": "/home/me/project",
+///       "outcome": "allow",
+///       "learned_at": "2025-06-02T09:30:00Z",
+///       "session_id": "sess_456"
+///     }
+///   ]
+/// }
+/// ```
+///
+/// A rule with `except_flags` doesn't cover commands using any of those
+/// flags, and a rule with a `directory` only applies to commands run within
+/// it.
+///
+/// Give the policy to [`ToolcapProxy::with_learned_policy`](super::ToolcapProxy::with_learned_policy)
+/// to apply its rules, with lower priority than the proxy's ruleset. Clones
+/// share the same rules.
+#[derive(Debug, Clone)]
+pub struct LearnedPolicy {
+    path: PathBuf,
+    rules: Arc<Mutex<Vec<LearnedRule>>>,
+}
+
+/// A rule in a [`LearnedPolicy`].
+#[derive(Debug, Clone)]
+pub struct LearnedRule {
+    rule: Rule,
+    learned_at: String,
+    session_id: String,
+}
+
+impl LearnedPolicy {
+    /// Where the policy file is kept, relative to the project root.
+    pub const DEFAULT_PATH: &str = ".toolcap/learned.json";
+
+    /// Opens the policy file at `path`, loading its rules.
+    ///
+    /// A missing file is treated as empty, and is created when the first rule
+    /// is learned. A file that isn't a valid policy is an error.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let rules = read_rules(&path)?;
+        Ok(Self {
+            path,
+            rules: Arc::new(Mutex::new(rules)),
+        })
+    }
+
+    /// Opens the policy file of the project at `root`, at
+    /// [`DEFAULT_PATH`](Self::DEFAULT_PATH).
+    pub fn open_in_project(root: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open(root.as_ref().join(Self::DEFAULT_PATH))
+    }
+
+    /// Returns the path of the policy file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the learned rules, in the order they were learned.
+    pub fn learned(&self) -> Vec<LearnedRule> {
+        self.lock().clone()
+    }
+
+    /// Returns the rules to apply.
+    pub fn rules(&self) -> Vec<Rule> {
+        self.lock()
+            .iter()
+            .map(|learned| learned.rule.clone())
+            .collect()
+    }
+
+    /// Learns the allow grants of a session, returning the rules that were
+    /// new.
+    ///
+    /// Deny grants aren't learned, and neither are rules the policy already
+    /// has. The file is reloaded first, so rules learned by other processes
+    /// are kept, and then replaced atomically. Processes learning at the
+    /// same time take turns, holding an advisory lock on a `.lock` file next
+    /// to the policy file while they update it.
+    pub fn learn(&self, session_id: &SessionId, grants: &[Grant]) -> io::Result<Vec<LearnedRule>> {
+        let mut rules = self.lock();
+        let _file_lock = lock_file(&self.path)?;
+        let mut merged = read_rules(&self.path)?;
+        let learned_at = format_timestamp(SystemTime::now());
+
+        let mut added = Vec::new();
+        for grant in grants {
+            let matcher = grant.rule().matcher();
+            if grant.outcome() != Outcome::Allow
+                || matcher_json(matcher).is_none()
+                || merged
+                    .iter()
+                    .any(|learned| learned.rule.matcher() == matcher)
+            {
+                continue;
+            }
+            let learned = LearnedRule::new(
+                merged.len(),
+                matcher.clone(),
+                learned_at.clone(),
+                session_id.to_string(),
+            );
+            merged.push(learned.clone());
+            added.push(learned);
+        }
+
+        if !added.is_empty() {
+            write_rules(&self.path, &merged)?;
+        }
+        *rules = merged;
+        Ok(added)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<LearnedRule>> {
+        lock_unpoisoned(&self.rules)
+    }
+}
+
+impl LearnedRule {
+    fn new(index: usize, matcher: Matcher, learned_at: String, session_id: String) -> Self {
+        let rule = Rule::new(matcher, Outcome::Allow)
+            .with_id(format!("learned-{}", index + 1))
+            .with_description(format!(
+                "Learned in session {} at {}",
+                session_id, learned_at
+            ));
+        Self {
+            rule,
+            learned_at,
+            session_id,
+        }
+    }
+
+    /// Returns the rule.
+    pub fn rule(&self) -> &Rule {
+        &self.rule
+    }
+
+    /// Returns when the rule was learned, as an RFC 3339 timestamp.
+    pub fn learned_at(&self) -> &str {
+        &self.learned_at
+    }
+
+    /// Returns the id of the session the rule was learned in.
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+}
+
+/// Reads the rules of a policy file, or none if it doesn't exist.
+fn read_rules(path: &Path) -> io::Result<Vec<LearnedRule>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let invalid = |message: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid policy file {}: {}", path.display(), message),
+        )
+    };
+
+    let value: Value = serde_json::from_str(&contents).map_err(|err| invalid(&err.to_string()))?;
+    let entries = value["rules"]
+        .as_array()
+        .ok_or_else(|| invalid("expected a `rules` array"))?;
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let matcher = json_matcher(entry)
+                .ok_or_else(|| invalid(&format!("rule {} has no valid command", index + 1)))?;
+            if entry["outcome"] != "allow" {
+                return Err(invalid(&format!("rule {} is not an allow rule", index + 1)));
+            }
+            let field = |name: &str| entry[name].as_str().unwrap_or_default().to_string();
+            Ok(LearnedRule::new(
+                index,
+                matcher,
+                field("learned_at"),
+                field("session_id"),
+            ))
+        })
+        .collect()
+}
+
+/// Takes the advisory lock guarding updates to a policy file, creating the
+/// lock file and its directory if needed. The lock is held until the
+/// returned file is dropped.
+fn lock_file(path: &Path) -> io::Result<fs::File> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(PathBuf::from(lock_path))?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Replaces a policy file with the given rules.
+///
+/// The rules are written to a temporary file next to the policy file, named
+/// uniquely for this write, which is then renamed over it, so readers never
+/// see a partial file.
+fn write_rules(path: &Path, rules: &[LearnedRule]) -> io::Result<()> {
+    let entries: Vec<_> = rules
+        .iter()
+        .filter_map(|learned| {
+            let mut entry = matcher_json(learned.rule.matcher())?;
+            entry.insert("outcome".into(), "allow".into());
+            entry.insert("learned_at".into(), learned.learned_at.clone().into());
+            entry.insert("session_id".into(), learned.session_id.clone().into());
+            Some(Value::Object(entry))
+        })
+        .collect();
+    let mut contents = serde_json::to_string_pretty(&json!({ "rules": entries }))?;
+    contents.push('\n');
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let result = fs::File::create_new(&tmp_path).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    });
+    match result.and_then(|()| fs::rename(&tmp_path, path)) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(err)
+        }
+    }
+}
+
+/// Converts a matcher into the fields of a policy file entry.
+///
+/// Only the matchers created for grants can be stored.
+fn matcher_json(matcher: &Matcher) -> Option<serde_json::Map<String, Value>> {
+    let value = match matcher {
+        Matcher::And(parts) => {
+            let [inner, restriction] = parts.as_slice() else {
+                return None;
+            };
+            let mut map = matcher_json(inner)?;
+            match restriction {
+                Matcher::WithinDirectory { path } => {
+                    map.insert("directory".into(), path.to_str()?.into());
+                }
+                Matcher::Not(excluded)
+                    if map.contains_key("subcommand") && !map.contains_key("directory") =>
+                {
+                    map.insert("except_flags".into(), excluded_flags(excluded)?.into());
+                }
+                _ => return None,
+            }
+            return Some(map);
+        }
+        Matcher::Exact { name, args } => {
+            let words: Vec<_> = std::iter::once(name).chain(args).collect();
+            json!({ "exact": words })
+        }
+        Matcher::Command {
+            name,
+            subcommands: Some(subcommands),
+            required_flags,
+        } if subcommands.len() == 1 && required_flags.is_empty() => {
+            json!({ "command": name, "subcommand": subcommands[0] })
+        }
+        _ => return None,
+    };
+    match value {
+        Value::Object(map) => Some(map),
+        _ => None,
+    }
+}
+
+/// Returns the flags excluded by a grant that doesn't cover dangerous flags
+/// (see [`RuleSuggester`]).
+fn excluded_flags(excluded: &Matcher) -> Option<Vec<String>> {
+    let Matcher::Or(flags) = excluded else {
+        return None;
+    };
+    flags
+        .iter()
+        .map(|flag| match flag {
+            Matcher::UsesFlag { flag } => Some(flag.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Reads the matcher of a policy file entry.
+fn json_matcher(entry: &Value) -> Option<Matcher> {
+    let mut matcher = if let Some(words) = entry["exact"].as_array() {
+        let mut words = words.iter().map(|word| word.as_str().map(String::from));
+        let name = words.next()??;
+        let args = words.collect::<Option<Vec<_>>>()?;
+        Matcher::Exact { name, args }
+    } else {
+        let name = entry["command"].as_str()?;
+        let subcommand = entry["subcommand"].as_str()?;
+        let matcher = Matcher::command(name).with_subcommand(subcommand);
+        match entry.get("except_flags") {
+            Some(flags) => {
+                let flags = flags
+                    .as_array()?
+                    .iter()
+                    .map(|flag| flag.as_str())
+                    .collect::<Option<Vec<_>>>()?;
+                RuleSuggester::new()
+                    .with_dangerous_flags(flags)
+                    .generalize(matcher)
+            }
+            None => matcher,
+        }
+    };
+    if let Some(dir) = entry.get("directory") {
+        matcher = Matcher::and(vec![matcher, Matcher::within_directory(dir.as_str()?)]);
+    }
+    Some(matcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a fresh policy file path in a temporary project directory.
+    fn policy_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("toolcap-learned-{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        dir.join(LearnedPolicy::DEFAULT_PATH)
+    }
+
+    fn grant(matcher: Matcher, outcome: Outcome) -> Grant {
+        Grant::new(Rule::new(matcher, outcome).with_id("grant-1"))
+    }
+
+    #[test]
+    fn test_missing_file_is_empty() {
+        let policy = LearnedPolicy::open(policy_path("missing")).unwrap();
+        assert!(policy.rules().is_empty());
+        assert!(!policy.path().exists());
+    }
+
+    #[test]
+    fn test_learn_and_reload() {
+        let path = policy_path("reload");
+        let policy = LearnedPolicy::open(&path).unwrap();
+        let learned = policy
+            .learn(
+                &"sess-1".into(),
+                &[
+                    grant(Matcher::exact("cargo test --lib"), Outcome::Allow),
+                    grant(
+                        Matcher::command("git").with_subcommand("log"),
+                        Outcome::Allow,
+                    ),
+                    grant(Matcher::exact("rm -rf /"), Outcome::Deny),
+                ],
+            )
+            .unwrap();
+        assert_eq!(learned.len(), 2);
+        assert_eq!(learned[0].session_id(), "sess-1");
+        assert_eq!(learned[1].rule().id(), Some("learned-2"));
+
+        let reloaded = LearnedPolicy::open(&path).unwrap();
+        let rules: Vec<_> = reloaded.rules().iter().map(Rule::to_string).collect();
+        assert_eq!(
+            rules,
+            policy
+                .rules()
+                .iter()
+                .map(Rule::to_string)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(reloaded.learned()[0].learned_at(), learned[0].learned_at());
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains(r#""session_id": "sess-1""#));
+        // Only the policy and its lock file are left behind
+        let mut files: Vec<_> = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["learned.json", "learned.json.lock"]);
+    }
+
+    #[test]
+    fn test_restricted_grants_round_trip() {
+        let path = policy_path("restricted");
+        let policy = LearnedPolicy::open(&path).unwrap();
+        let subcommand = RuleSuggester::new()
+            .with_dangerous_flags(["--force"])
+            .generalize(Matcher::command("git").with_subcommand("push"));
+        let matchers = [
+            Matcher::and(vec![
+                Matcher::exact("make clean"),
+                Matcher::within_directory("/work/project"),
+            ]),
+            Matcher::and(vec![
+                subcommand.clone(),
+                Matcher::within_directory("/work/project"),
+            ]),
+            subcommand,
+        ];
+        let grants: Vec<_> = matchers
+            .iter()
+            .map(|matcher| grant(matcher.clone(), Outcome::Allow))
+            .collect();
+        assert_eq!(policy.learn(&"sess-1".into(), &grants).unwrap().len(), 3);
+
+        let reloaded = LearnedPolicy::open(&path).unwrap();
+        let reloaded: Vec<_> = reloaded
+            .rules()
+            .iter()
+            .map(|rule| rule.matcher().clone())
+            .collect();
+        assert_eq!(reloaded, matchers);
+    }
+
+    #[test]
+    fn test_learn_deduplicates() {
+        let path = policy_path("dedupe");
+        let policy = LearnedPolicy::open(&path).unwrap();
+        let other = LearnedPolicy::open(&path).unwrap();
+        let make = [grant(Matcher::exact("make"), Outcome::Allow)];
+
+        assert_eq!(policy.learn(&"sess-1".into(), &make).unwrap().len(), 1);
+        // Rules learned by another process are picked up before writing.
+        assert!(other.learn(&"sess-2".into(), &make).unwrap().is_empty());
+        assert_eq!(other.rules().len(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_learners_keep_each_others_rules() {
+        let path = policy_path("concurrent");
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                // Separate policies stand in for separate processes
+                let policy = LearnedPolicy::open(&path).unwrap();
+                std::thread::spawn(move || {
+                    let grants = [grant(
+                        Matcher::exact(&format!("make t{}", i)),
+                        Outcome::Allow,
+                    )];
+                    policy.learn(&"sess-1".into(), &grants).unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(LearnedPolicy::open(&path).unwrap().rules().len(), 8);
+    }
+
+    #[test]
+    fn test_invalid_file() {
+        let path = policy_path("invalid");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        fs::write(&path, "not json").unwrap();
+        let err = LearnedPolicy::open(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        fs::write(
+            &path,
+            r#"{"rules": [{"exact": ["ls"], "outcome": "deny"}]}"#,
+        )
+        .unwrap();
+        assert!(LearnedPolicy::open(&path).is_err());
+
+        fs::write(
+            &path,
+            r#"{"rules": [{"exact": ["ls", "-la"], "outcome": "allow"}]}"#,
+        )
+        .unwrap();
+        let policy = LearnedPolicy::open(&path).unwrap();
+        assert_eq!(policy.learned()[0].learned_at(), "");
+        assert_eq!(policy.rules()[0].matcher(), &Matcher::exact("ls -la"));
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use sacp::JrRequestCx;
+use sacp::schema::{RequestPermissionRequest, RequestPermissionResponse, SessionId, ToolCallId};
+
+use crate::decision::Decision;
+use crate::operation::Operation;
+use crate::sync::lock_unpoisoned;
+
+/// Permission requests forwarded to the client and still waiting for an
+/// answer, keyed by session and tool call.
+///
+/// A request is only a duplicate of a pending one if it asks the same
+/// thing: the same tool call, with the same fields and options. An agent
+/// that reuses a tool call id for something else gets its own answer.
+///
+/// Whoever takes a request out of the table answers it: the client's
+/// answer, a timeout or the session's cancellation, whichever comes first.
+/// Clones share the same table.
+#[derive(Clone, Default)]
+pub(crate) struct PendingRequests {
+    inner: Arc<Mutex<Sessions>>,
+}
+
+/// The pending requests of each session, by tool call.
+type Sessions = HashMap<SessionId, HashMap<ToolCallId, Vec<Pending>>>;
+
+/// A forwarded request, with everyone waiting for its answer.
+pub(crate) struct Pending {
+    pub(crate) request: RequestPermissionRequest,
+    /// The request's operation, and how it was decided when it was forwarded.
+    pub(crate) operation: Operation,
+    pub(crate) decision: Decision,
+    /// The forwarded request's own responder, then those of duplicates.
+    responders: Vec<JrRequestCx<RequestPermissionResponse>>,
+}
+
+impl Pending {
+    /// Returns whether `request` asks the same as the pending request,
+    /// ignoring their `_meta`.
+    fn asks(&self, request: &RequestPermissionRequest) -> bool {
+        let (pending, other) = (&self.request, request);
+        pending.tool_call.id == other.tool_call.id
+            && pending.tool_call.fields == other.tool_call.fields
+            && pending.options.len() == other.options.len()
+            && pending
+                .options
+                .iter()
+                .zip(&other.options)
+                .all(|(a, b)| a.id == b.id && a.name == b.name && a.kind == b.kind)
+    }
+
+    /// Answers the request and its duplicates.
+    pub(crate) fn respond(
+        self,
+        result: Result<RequestPermissionResponse, sacp::Error>,
+    ) -> Result<(), sacp::Error> {
+        for responder in self.responders {
+            responder.respond_with_result(result.clone())?;
+        }
+        Ok(())
+    }
+}
+
+impl PendingRequests {
+    /// Adds a request about to be forwarded, with its operation and how it
+    /// was decided.
+    ///
+    /// If the same request is already pending, `responder` waits for its
+    /// answer instead, and `false` is returned: the request is a duplicate
+    /// and shouldn't be forwarded.
+    pub(crate) fn insert(
+        &self,
+        request: &RequestPermissionRequest,
+        operation: &Operation,
+        decision: &Decision,
+        responder: JrRequestCx<RequestPermissionResponse>,
+    ) -> bool {
+        let mut sessions = self.lock();
+        let calls = sessions
+            .entry(request.session_id.clone())
+            .or_default()
+            .entry(request.tool_call.id.clone())
+            .or_default();
+        match calls.iter_mut().find(|pending| pending.asks(request)) {
+            Some(pending) => {
+                pending.responders.push(responder);
+                false
+            }
+            None => {
+                calls.push(Pending {
+                    request: request.clone(),
+                    operation: operation.clone(),
+                    decision: decision.clone(),
+                    responders: vec![responder],
+                });
+                true
+            }
+        }
+    }
+
+    /// Removes a pending request, or returns `None` if it was already
+    /// answered.
+    pub(crate) fn take(&self, request: &RequestPermissionRequest) -> Option<Pending> {
+        let mut sessions = self.lock();
+        let session = sessions.get_mut(&request.session_id)?;
+        let calls = session.get_mut(&request.tool_call.id)?;
+        let index = calls.iter().position(|pending| pending.asks(request))?;
+        let pending = calls.remove(index);
+        if calls.is_empty() {
+            session.remove(&request.tool_call.id);
+        }
+        if session.is_empty() {
+            sessions.remove(&request.session_id);
+        }
+        Some(pending)
+    }
+
+    /// Removes every pending request of a session.
+    pub(crate) fn take_session(&self, session_id: &SessionId) -> Vec<Pending> {
+        self.lock()
+            .remove(session_id)
+            .map(|calls| calls.into_values().flatten().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of pending requests, not counting duplicates.
+    pub(crate) fn len(&self) -> usize {
+        self.lock()
+            .values()
+            .flat_map(HashMap::values)
+            .map(Vec::len)
+            .sum()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Sessions> {
+        lock_unpoisoned(&self.inner)
+    }
+}
@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use sacp::schema::{Implementation, SessionId};
+
+use crate::ruleset::Ruleset;
+use crate::sync::lock_unpoisoned;
+use crate::workdir::normalize;
+
+/// An error from a ruleset loader.
+pub(crate) type LoadError = Box<dyn std::error::Error + Send + Sync>;
+
+type ProjectLoader = Arc<dyn Fn(&Path) -> Result<Ruleset, LoadError> + Send + Sync>;
+
+/// Picks the ruleset for each session, so that one proxy can serve
+/// sessions in different projects, or for different agents, with different
+/// policies.
+///
+/// A session's ruleset is the first of:
+///
+/// 1. the ruleset configured for the deepest directory containing the
+///    session's working directory (see [`with_directory_ruleset`](Self::with_directory_ruleset));
+/// 2. the ruleset in the nearest project file, looking in the working
+///    directory and then each of its parents (see [`with_project_files`](Self::with_project_files));
+/// 3. the ruleset configured for the agent (see [`with_agent_ruleset`](Self::with_agent_ruleset)).
+///
+/// Sessions none of these apply to use the proxy's own ruleset. Either way,
+/// the session's grants, the learned rules and its mode are applied on top.
+///
+/// A session's ruleset is resolved once and cached until the session ends
+/// (see [`ToolcapProxy::forget_session`](super::ToolcapProxy::forget_session))
+/// or is loaded again. Clones share the cache.
+#[derive(Clone, Default)]
+pub struct PolicyResolver {
+    directories: Vec<(PathBuf, Arc<Ruleset>)>,
+    agents: Vec<(String, Arc<Ruleset>)>,
+    project_files: Option<(String, ProjectLoader)>,
+    resolved: Arc<Mutex<HashMap<SessionId, Option<ResolvedPolicy>>>>,
+}
+
+/// A resolved ruleset and where it came from.
+#[derive(Debug, Clone)]
+pub(crate) struct ResolvedPolicy {
+    pub(crate) source: PolicySource,
+    pub(crate) ruleset: Arc<Ruleset>,
+}
+
+impl PolicyResolver {
+    /// Creates a resolver with no rulesets, leaving every session to the
+    /// proxy's own ruleset.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses `ruleset` for sessions whose working directory is `dir` or
+    /// inside it.
+    ///
+    /// When several configured directories contain a session's working
+    /// directory, the deepest one wins. Directories are compared after
+    /// resolving symlinks, so a session can't escape its directory's
+    /// ruleset through a link to it.
+    pub fn with_directory_ruleset(mut self, dir: impl Into<PathBuf>, ruleset: Ruleset) -> Self {
+        self.directories.push((dir.into(), Arc::new(ruleset)));
+        self
+    }
+
+    /// Uses `ruleset` for sessions of the agent named `name`, as it
+    /// identifies itself in its `initialize` response (e.g.
+    /// `claude-code-acp`).
+    pub fn with_agent_ruleset(mut self, name: impl Into<String>, ruleset: Ruleset) -> Self {
+        self.agents.push((name.into(), Arc::new(ruleset)));
+        self
+    }
+
+    /// Looks for a project file called `name` (e.g. `.toolcap.toml`) in
+    /// each session's working directory and its parents, and loads the
+    /// nearest one with `loader`.
+    ///
+    /// Toolcap doesn't define a format for project files: `loader` is given
+    /// the file's path and parses it however it likes. If it fails, the
+    /// session falls back to the agent's ruleset or the proxy's.
+    ///
+    /// Project files come from the workspace the agent works in, so only
+    /// enable them for workspaces you trust to set their own policy.
+    pub fn with_project_files(
+        mut self,
+        name: impl Into<String>,
+        loader: impl Fn(&Path) -> Result<Ruleset, LoadError> + Send + Sync + 'static,
+    ) -> Self {
+        self.project_files = Some((name.into(), Arc::new(loader)));
+        self
+    }
+
+    /// Resolves the ruleset for a session of `agent` running in `cwd`,
+    /// without caching it.
+    ///
+    /// Returns no ruleset if none of the resolver's rulesets apply. If the
+    /// nearest project file fails to load, the error is returned along
+    /// with the ruleset used instead.
+    pub fn resolve(
+        &self,
+        agent: Option<&Implementation>,
+        cwd: &Path,
+    ) -> (Option<(PolicySource, Arc<Ruleset>)>, Option<PolicyError>) {
+        let cwd = canonical(cwd);
+        let directory = self
+            .directories
+            .iter()
+            .map(|(dir, ruleset)| (dir, canonical(dir), ruleset))
+            .filter(|(_, canonical_dir, _)| cwd.starts_with(canonical_dir))
+            .max_by_key(|(_, canonical_dir, _)| canonical_dir.components().count())
+            .map(|(dir, _, ruleset)| (dir, ruleset));
+        if let Some((dir, ruleset)) = directory {
+            return (
+                Some((PolicySource::Directory(dir.clone()), ruleset.clone())),
+                None,
+            );
+        }
+
+        let mut error = None;
+        if let Some((name, loader)) = &self.project_files
+            && let Some(path) = find_project_file(&cwd, name)
+        {
+            match loader(&path) {
+                Ok(ruleset) => {
+                    return (
+                        Some((PolicySource::ProjectFile(path), Arc::new(ruleset))),
+                        None,
+                    );
+                }
+                Err(err) => error = Some(PolicyError { path, error: err }),
+            }
+        }
+
+        let agent = agent.and_then(|agent| {
+            self.agents
+                .iter()
+                .find(|(name, _)| *name == agent.name)
+                .map(|(name, ruleset)| (PolicySource::Agent(name.clone()), ruleset.clone()))
+        });
+        (agent, error)
+    }
+
+    /// Resolves and caches the ruleset for a session, replacing any cached
+    /// one.
+    pub(crate) fn resolve_session(
+        &self,
+        session_id: &SessionId,
+        agent: Option<&Implementation>,
+        cwd: &Path,
+    ) -> (Option<PolicySource>, Option<PolicyError>) {
+        let (resolved, error) = self.resolve(agent, cwd);
+        let resolved = resolved.map(|(source, ruleset)| ResolvedPolicy { source, ruleset });
+        let source = resolved.as_ref().map(|resolved| resolved.source.clone());
+        self.lock().insert(session_id.clone(), resolved);
+        (source, error)
+    }
+
+    /// Returns the cached resolution of a session: `None` if it hasn't been
+    /// resolved, `Some(None)` if it uses the proxy's ruleset.
+    pub(crate) fn cached(&self, session_id: &SessionId) -> Option<Option<ResolvedPolicy>> {
+        self.lock().get(session_id).cloned()
+    }
+
+    /// Drops the cached ruleset of a session.
+    pub(crate) fn release(&self, session_id: &SessionId) {
+        self.lock().remove(session_id);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<SessionId, Option<ResolvedPolicy>>> {
+        lock_unpoisoned(&self.resolved)
+    }
+}
+
+impl fmt::Debug for PolicyResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyResolver")
+            .field(
+                "directories",
+                &self
+                    .directories
+                    .iter()
+                    .map(|(dir, _)| dir)
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "agents",
+                &self.agents.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            )
+            .field(
+                "project_files",
+                &self.project_files.as_ref().map(|(name, _)| name),
+            )
+            .field("sessions", &self.lock().len())
+            .finish()
+    }
+}
+
+/// Finds the nearest project file called `name`, in `dir` or one of its
+/// parents.
+fn find_project_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Resolves symlinks in a path, or normalizes it lexically if it doesn't
+/// exist.
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| normalize(path))
+}
+
+/// Where a session's ruleset came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicySource {
+    /// The ruleset configured for this directory, which contains the
+    /// session's working directory.
+    Directory(PathBuf),
+    /// The project file at this path.
+    ProjectFile(PathBuf),
+    /// The ruleset configured for the agent with this name.
+    Agent(String),
+}
+
+impl fmt::Display for PolicySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicySource::Directory(dir) => write!(f, "the ruleset for {}", dir.display()),
+            PolicySource::ProjectFile(path) => write!(f, "{}", path.display()),
+            PolicySource::Agent(name) => write!(f, "the ruleset for agent {}", name),
+        }
+    }
+}
+
+/// A project file that couldn't be loaded.
+#[derive(Debug)]
+pub struct PolicyError {
+    path: PathBuf,
+    error: LoadError,
+}
+
+impl PolicyError {
+    /// Returns the path of the project file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to load {}: {}", self.path.display(), self.error)
+    }
+}
+
+impl std::error::Error for PolicyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::matcher::Matcher;
+    use crate::outcome::Outcome;
+    use crate::rule::Rule;
+
+    const PROJECT_FILE: &str = ".toolcap.toml";
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("toolcap-resolver-{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn allowing(command: &str) -> Ruleset {
+        Ruleset::new(vec![Rule::new(Matcher::command(command), Outcome::Allow)])
+    }
+
+    fn agent(name: &str) -> Implementation {
+        Implementation {
+            name: name.into(),
+            title: None,
+            version: "1.0".into(),
+        }
+    }
+
+    /// Loads a "project file" holding one command to allow.
+    fn load_project_file(path: &Path) -> Result<Ruleset, LoadError> {
+        let command = fs::read_to_string(path)?;
+        match command.trim() {
+            "" => Err("no command".into()),
+            command => Ok(allowing(command)),
+        }
+    }
+
+    #[test]
+    fn test_resolution_order() {
+        let root = scratch_dir("order");
+        let project = root.join("project");
+        fs::create_dir_all(project.join("src")).unwrap();
+        fs::write(project.join(PROJECT_FILE), "make").unwrap();
+
+        let resolver = PolicyResolver::new()
+            .with_agent_ruleset("codex", allowing("cargo"))
+            .with_project_files(PROJECT_FILE, load_project_file)
+            .with_directory_ruleset(&root, allowing("ls"))
+            .with_directory_ruleset(root.join("project/vendor"), allowing("cat"));
+        let codex = agent("codex");
+
+        // The deepest configured directory wins over project files.
+        let (resolved, _) = resolver.resolve(Some(&codex), &project.join("vendor/lib"));
+        assert_eq!(
+            resolved.unwrap().0,
+            PolicySource::Directory(root.join("project/vendor"))
+        );
+
+        // Without configured directories, the nearest project file applies.
+        let resolver = PolicyResolver {
+            directories: Vec::new(),
+            ..resolver
+        };
+        let (resolved, error) = resolver.resolve(Some(&codex), &project.join("src"));
+        let (source, ruleset) = resolved.unwrap();
+        assert_eq!(
+            source,
+            PolicySource::ProjectFile(project.join(PROJECT_FILE))
+        );
+        assert_eq!(ruleset.rules()[0].to_string(), "allow make");
+        assert!(error.is_none());
+
+        // Then the agent's ruleset, and otherwise nothing.
+        let (resolved, _) = resolver.resolve(Some(&codex), &root);
+        assert_eq!(resolved.unwrap().0, PolicySource::Agent("codex".into()));
+        let (resolved, _) = resolver.resolve(Some(&agent("gemini")), &root);
+        assert!(resolved.is_none());
+        let (resolved, _) = resolver.resolve(None, &root);
+        assert!(resolved.is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_directories_follow_symlinks() {
+        let root = scratch_dir("symlinks");
+        let project = root.join("project");
+        fs::create_dir_all(project.join("src")).unwrap();
+        std::os::unix::fs::symlink(&project, root.join("link")).unwrap();
+
+        let resolver = PolicyResolver::new().with_directory_ruleset(&project, allowing("ls"));
+        let (resolved, _) = resolver.resolve(None, &root.join("link/src"));
+        assert_eq!(
+            resolved.unwrap().0,
+            PolicySource::Directory(project.clone())
+        );
+
+        // Paths are normalized, so `..` can't leave or enter a directory.
+        let (resolved, _) = resolver.resolve(None, &project.join("../elsewhere"));
+        assert!(resolved.is_none());
+        let (resolved, _) = resolver.resolve(None, &root.join("missing/../project/src"));
+        assert!(resolved.is_some());
+    }
+
+    #[test]
+    fn test_broken_project_file() {
+        let root = scratch_dir("broken");
+        fs::write(root.join(PROJECT_FILE), "").unwrap();
+        let resolver = PolicyResolver::new()
+            .with_agent_ruleset("codex", allowing("cargo"))
+            .with_project_files(PROJECT_FILE, load_project_file);
+
+        let (resolved, error) = resolver.resolve(Some(&agent("codex")), &root);
+        assert_eq!(resolved.unwrap().0, PolicySource::Agent("codex".into()));
+        let error = error.unwrap();
+        assert_eq!(error.path(), root.join(PROJECT_FILE));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "failed to load {}: no command",
+                root.join(PROJECT_FILE).display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_sessions_are_cached_until_released() {
+        let resolver = PolicyResolver::new().with_directory_ruleset("/repo", allowing("ls"));
+        let s1 = SessionId::from("s1");
+        let s2 = SessionId::from("s2");
+        assert!(resolver.cached(&s1).is_none());
+
+        resolver.resolve_session(&s1, None, Path::new("/repo/src"));
+        resolver.resolve_session(&s2, None, Path::new("/tmp"));
+        assert!(resolver.cached(&s1).unwrap().is_some());
+        assert!(resolver.cached(&s2).unwrap().is_none());
+
+        // Clones share the cache.
+        resolver.clone().release(&s1);
+        assert!(resolver.cached(&s1).is_none());
+    }
+}
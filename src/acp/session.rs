@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use sacp::schema::{SessionId, ToolCallId};
+
+use crate::history::{History, OperationStatus};
+use crate::operation::Operation;
+use crate::sync::lock_unpoisoned;
+
+/// What the proxy knows about an ACP session.
+///
+/// Contexts are recorded from `session/new` and `session/load` (see
+/// [`ToolcapProxy::observe_new_session`](super::ToolcapProxy::observe_new_session)),
+/// and follow the session's mode as it changes (see
+/// [`ToolcapProxy::observe_session_mode`](super::ToolcapProxy::observe_session_mode)).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionContext {
+    cwd: PathBuf,
+    mode: Option<String>,
+}
+
+impl SessionContext {
+    /// Creates a context for a session running in `cwd`.
+    pub fn new(cwd: impl Into<PathBuf>) -> Self {
+        Self {
+            cwd: cwd.into(),
+            mode: None,
+        }
+    }
+
+    /// Sets the session's current mode, such as `plan` or `code`.
+    pub fn with_mode(mut self, mode: impl Into<String>) -> Self {
+        self.mode = Some(mode.into());
+        self
+    }
+
+    /// Returns the session's working directory.
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+
+    /// Returns the session's current mode, if the agent reported one.
+    pub fn mode(&self) -> Option<&str> {
+        self.mode.as_deref()
+    }
+}
+
+/// The sessions known to a proxy, keyed by session id.
+///
+/// Clones share the same sessions, so a proxy can be cloned into message
+/// handlers.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Sessions {
+    inner: Arc<Mutex<HashMap<SessionId, SessionContext>>>,
+}
+
+impl Sessions {
+    pub(crate) fn insert(&self, id: SessionId, context: SessionContext) {
+        self.lock().insert(id, context);
+    }
+
+    pub(crate) fn get(&self, id: &SessionId) -> Option<SessionContext> {
+        self.lock().get(id).cloned()
+    }
+
+    /// Sets the mode of a known session, returning `false` if the session
+    /// isn't known.
+    pub(crate) fn set_mode(&self, id: &SessionId, mode: &str) -> bool {
+        match self.lock().get_mut(id) {
+            Some(context) => {
+                context.mode = Some(mode.into());
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn remove(&self, id: &SessionId) -> Option<SessionContext> {
+        self.lock().remove(id)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<SessionId, SessionContext>> {
+        lock_unpoisoned(&self.inner)
+    }
+}
+
+/// The operations performed in each session, keyed by session id.
+///
+/// Clones share the same histories. A [`History`] shares its entries until
+/// it's changed, so handing one to a ruleset is cheap.
+#[derive(Debug, Clone)]
+pub(crate) struct Histories {
+    inner: Arc<Mutex<HashMap<SessionId, History>>>,
+    /// How many operations each history keeps.
+    limit: usize,
+}
+
+impl Default for Histories {
+    fn default() -> Self {
+        Self {
+            inner: Arc::default(),
+            limit: History::DEFAULT_LIMIT,
+        }
+    }
+}
+
+impl Histories {
+    /// Keeps at most `limit` operations per session.
+    pub(crate) fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Records an operation the agent reported.
+    pub(crate) fn record(
+        &self,
+        id: &SessionId,
+        operation: Operation,
+        tool_call_id: Option<&ToolCallId>,
+        status: OperationStatus,
+    ) {
+        self.history_mut(id, |history| {
+            history.record(operation, tool_call_id.map(|id| &*id.0), status)
+        });
+    }
+
+    /// Records an operation the proxy or the user permitted.
+    pub(crate) fn record_permitted(
+        &self,
+        id: &SessionId,
+        operation: Operation,
+        tool_call_id: Option<&ToolCallId>,
+    ) {
+        self.history_mut(id, |history| {
+            let id = tool_call_id.map(|id| &*id.0);
+            history.record_permitted(operation, id, OperationStatus::Running)
+        });
+    }
+
+    /// Changes a session's history, starting one if needed.
+    fn history_mut(&self, id: &SessionId, change: impl FnOnce(&mut History)) {
+        let mut histories = self.lock();
+        let history = histories
+            .entry(id.clone())
+            .or_insert_with(|| History::new().with_limit(self.limit));
+        change(history);
+    }
+
+    /// Updates the status of a tool call, returning `false` if it isn't in
+    /// the session's history.
+    pub(crate) fn set_status(
+        &self,
+        id: &SessionId,
+        tool_call_id: &ToolCallId,
+        status: OperationStatus,
+    ) -> bool {
+        match self.lock().get_mut(id) {
+            Some(history) => history.set_status(&tool_call_id.0, status),
+            None => false,
+        }
+    }
+
+    /// Returns a session's history, which is empty for unknown sessions.
+    pub(crate) fn get(&self, id: &SessionId) -> History {
+        self.lock().get(id).cloned().unwrap_or_default()
+    }
+
+    pub(crate) fn remove(&self, id: &SessionId) {
+        self.lock().remove(id);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<SessionId, History>> {
+        lock_unpoisoned(&self.inner)
+    }
+}
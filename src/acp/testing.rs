@@ -0,0 +1,917 @@
+//! End-to-end testing of proxies, with the `test-support` feature.
+//!
+//! A [`ProxyHarness`] serves a [`ToolcapComponent`] over in-memory byte
+//! streams, as a conductor would, with a [`ScriptedAgent`] on one side and a
+//! [`ScriptedClient`] on the other. Every message goes through the proxy's
+//! actual JSON-RPC routing, and the [`Transcript`] records what each side
+//! received, so tests can check which permission requests the proxy answered
+//! itself and which it forwarded to the client.
+//!
+//! ```
+//! use toolcap::acp::ToolcapComponent;
+//! use toolcap::acp::ToolcapProxy;
+//! use toolcap::acp::testing::{ProxyHarness, ScriptedAgent};
+//! use toolcap::{Matcher, Outcome, Rule, Ruleset};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), sacp::Error> {
+//! let proxy = ToolcapProxy::new(Ruleset::new(vec![
+//!     Rule::new(Matcher::command("ls"), Outcome::Allow),
+//! ]));
+//! let agent = ScriptedAgent::new()
+//!     .with_command("ls -la")
+//!     .with_command("rm -rf target");
+//!
+//! let ((), transcript) = ProxyHarness::new()
+//!     .with_agent(agent)
+//!     .run(ToolcapComponent::new(proxy), async |cx| {
+//!         cx.initialize().await?;
+//!         let session_id = cx.new_session("/repo").await?;
+//!         cx.prompt(&session_id, "clean up").await?;
+//!         Ok(())
+//!     })
+//!     .await?;
+//!
+//! // The proxy allowed `ls -la` itself, and asked the user about the rest.
+//! assert_eq!(transcript.answered_by_proxy().len(), 1);
+//! assert_eq!(transcript.forwarded().len(), 1);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use sacp::role::{Agent, Client, ConductorToClient};
+use sacp::schema::{
+    CancelNotification, ClientCapabilities, CreateTerminalRequest, CreateTerminalResponse,
+    Implementation, InitializeProxyRequest, InitializeRequest, InitializeResponse,
+    LoadSessionRequest, LoadSessionResponse, NewSessionRequest, NewSessionResponse,
+    PermissionOptionKind, PromptRequest, PromptResponse, ReadTextFileRequest, ReadTextFileResponse,
+    RequestPermissionOutcome, RequestPermissionRequest, RequestPermissionResponse, SessionId,
+    SetSessionModeRequest, SetSessionModeResponse, StopReason, TerminalId, ToolCallId,
+    ToolCallUpdate, ToolCallUpdateFields, ToolKind, WriteTextFileRequest, WriteTextFileResponse,
+};
+use sacp::util::{MatchMessage, MatchMessageFrom};
+use sacp::{
+    ByteStreams, Component, Handled, JrConnectionCx, JrMessageHandler, JrNotification, JrRequest,
+    JrRequestCx, MessageCx,
+};
+use serde_json::{Value, json};
+use tokio::sync::mpsc;
+use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+
+use super::{ToolcapComponent, make_permission_option};
+use crate::sync::lock_unpoisoned;
+
+/// A message the scripted client or agent received from the proxy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceivedMessage {
+    /// The JSON-RPC method, e.g. `session/request_permission`.
+    pub method: String,
+    /// The message's parameters.
+    pub params: Value,
+}
+
+/// An agent that answers the proxy from a script.
+///
+/// The agent accepts `initialize`, `session/new` (numbering its sessions
+/// `session-1`, `session-2`, ...), `session/load` and `session/set_mode`.
+/// On every `session/prompt`, it asks permission for each of its tool calls
+/// in turn, then ends the turn.
+#[derive(Debug, Clone)]
+pub struct ScriptedAgent {
+    info: Option<Implementation>,
+    tool_calls: Vec<ToolCallUpdateFields>,
+}
+
+impl Default for ScriptedAgent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptedAgent {
+    /// Creates an agent named `scripted-agent`, with no tool calls.
+    pub fn new() -> Self {
+        Self {
+            info: None,
+            tool_calls: Vec::new(),
+        }
+        .with_name("scripted-agent")
+    }
+
+    /// Sets the name the agent gives in its `initialize` response, which
+    /// picks the proxy's adapter for its tool calls (e.g. `claude-code-acp`).
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.info = Some(Implementation {
+            name: name.into(),
+            title: None,
+            version: "1.0.0".into(),
+        });
+        self
+    }
+
+    /// Adds a tool call to ask permission for on every prompt.
+    pub fn with_tool_call(mut self, fields: ToolCallUpdateFields) -> Self {
+        self.tool_calls.push(fields);
+        self
+    }
+
+    /// Adds a shell command to ask permission for on every prompt.
+    pub fn with_command(self, command: impl Into<String>) -> Self {
+        let command = command.into();
+        self.with_tool_call(ToolCallUpdateFields {
+            kind: Some(ToolKind::Execute),
+            title: Some(command.clone()),
+            raw_input: Some(json!({ "command": command })),
+            ..Default::default()
+        })
+    }
+
+    /// Returns the request the agent sends to ask about a tool call.
+    ///
+    /// The request offers one option of each kind: `allow-once`,
+    /// `allow-always`, `reject-once` and `reject-always`.
+    fn permission_request(
+        session_id: &SessionId,
+        id: ToolCallId,
+        fields: ToolCallUpdateFields,
+    ) -> RequestPermissionRequest {
+        RequestPermissionRequest {
+            session_id: session_id.clone(),
+            tool_call: ToolCallUpdate {
+                id,
+                fields,
+                meta: None,
+            },
+            options: vec![
+                make_permission_option("allow-once", PermissionOptionKind::AllowOnce),
+                make_permission_option("allow-always", PermissionOptionKind::AllowAlways),
+                make_permission_option("reject-once", PermissionOptionKind::RejectOnce),
+                make_permission_option("reject-always", PermissionOptionKind::RejectAlways),
+            ],
+            meta: None,
+        }
+    }
+}
+
+type PermissionAnswer =
+    Arc<dyn Fn(&RequestPermissionRequest) -> RequestPermissionOutcome + Send + Sync>;
+
+/// A client that answers the proxy from a script.
+///
+/// The client answers permission requests as its user would (by default,
+/// allowing once), or holds them for the test to answer, reads and writes
+/// files in an in-memory file system, and creates terminals (numbered
+/// `term-1`, `term-2`, ...) without running anything.
+#[derive(Clone)]
+pub struct ScriptedClient {
+    answer: PermissionAnswer,
+    hold: bool,
+    files: HashMap<PathBuf, String>,
+}
+
+impl Default for ScriptedClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptedClient {
+    /// Creates a client whose user allows every request once, with no
+    /// files.
+    pub fn new() -> Self {
+        Self {
+            answer: Arc::new(|_| RequestPermissionOutcome::Cancelled),
+            hold: false,
+            files: HashMap::new(),
+        }
+        .with_answer(PermissionOptionKind::AllowOnce)
+    }
+
+    /// Answers permission requests with their option of `kind`, or cancels
+    /// those without one.
+    pub fn with_answer(self, kind: PermissionOptionKind) -> Self {
+        self.with_answer_fn(move |request| {
+            match request.options.iter().find(|option| option.kind == kind) {
+                Some(option) => RequestPermissionOutcome::Selected {
+                    option_id: option.id.clone(),
+                },
+                None => RequestPermissionOutcome::Cancelled,
+            }
+        })
+    }
+
+    /// Answers permission requests with `answer`.
+    pub fn with_answer_fn(
+        mut self,
+        answer: impl Fn(&RequestPermissionRequest) -> RequestPermissionOutcome + Send + Sync + 'static,
+    ) -> Self {
+        self.answer = Arc::new(answer);
+        self.hold = false;
+        self
+    }
+
+    /// Holds permission requests instead of answering them, for the test to
+    /// answer through [`HarnessCx::held_request`], e.g. to check what the
+    /// proxy does while the user is deciding.
+    pub fn with_held_answers(mut self) -> Self {
+        self.hold = true;
+        self
+    }
+
+    /// Adds a file for `fs/read_text_file` to read.
+    pub fn with_file(mut self, path: impl Into<PathBuf>, content: impl Into<String>) -> Self {
+        self.files.insert(path.into(), content.into());
+        self
+    }
+}
+
+impl fmt::Debug for ScriptedClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScriptedClient")
+            .field("hold", &self.hold)
+            .field("files", &self.files.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A permission request the scripted agent made, and the answer it got.
+#[derive(Debug, Clone)]
+pub struct PermissionExchange {
+    request: RequestPermissionRequest,
+    response: Result<RequestPermissionResponse, sacp::Error>,
+}
+
+impl PermissionExchange {
+    /// Returns the agent's request.
+    pub fn request(&self) -> &RequestPermissionRequest {
+        &self.request
+    }
+
+    /// Returns the answer the agent got, from the proxy or the client.
+    pub fn response(&self) -> Result<&RequestPermissionResponse, &sacp::Error> {
+        self.response.as_ref()
+    }
+
+    /// Returns the id of the selected option, or `None` if the request was
+    /// cancelled or failed.
+    pub fn selected(&self) -> Option<&str> {
+        match &self.response {
+            Ok(RequestPermissionResponse {
+                outcome: RequestPermissionOutcome::Selected { option_id },
+                ..
+            }) => Some(&option_id.0),
+            _ => None,
+        }
+    }
+}
+
+/// A permission request the scripted client is holding, waiting for the
+/// test to answer it.
+#[derive(Debug)]
+pub struct HeldRequest {
+    request: RequestPermissionRequest,
+    responder: JrRequestCx<RequestPermissionResponse>,
+}
+
+impl HeldRequest {
+    /// Returns the request, as the client received it.
+    pub fn request(&self) -> &RequestPermissionRequest {
+        &self.request
+    }
+
+    /// Answers the request, as the client's user.
+    pub fn answer(self, outcome: RequestPermissionOutcome) -> Result<(), sacp::Error> {
+        self.responder.respond(RequestPermissionResponse {
+            outcome,
+            meta: None,
+        })
+    }
+}
+
+/// What the scripted client and agent received during a run.
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    client: Vec<ReceivedMessage>,
+    agent: Vec<ReceivedMessage>,
+    permissions: Vec<PermissionExchange>,
+}
+
+impl Transcript {
+    /// Returns the messages the client received, in order.
+    pub fn client_messages(&self) -> &[ReceivedMessage] {
+        &self.client
+    }
+
+    /// Returns the messages the agent received, in order.
+    pub fn agent_messages(&self) -> &[ReceivedMessage] {
+        &self.agent
+    }
+
+    /// Returns the permission requests the scripted agent made on prompts,
+    /// with their answers.
+    pub fn permissions(&self) -> &[PermissionExchange] {
+        &self.permissions
+    }
+
+    /// Returns the permission requests the proxy forwarded to the client,
+    /// as the client saw them.
+    pub fn forwarded(&self) -> Vec<RequestPermissionRequest> {
+        self.client
+            .iter()
+            .filter(|message| message.method == "session/request_permission")
+            .filter_map(|message| serde_json::from_value(message.params.clone()).ok())
+            .collect()
+    }
+
+    /// Returns the scripted agent's permission requests that the proxy
+    /// answered itself, without asking the client.
+    pub fn answered_by_proxy(&self) -> Vec<&PermissionExchange> {
+        let forwarded = self.forwarded();
+        self.permissions
+            .iter()
+            .filter(|exchange| {
+                !forwarded.iter().any(|request| {
+                    request.session_id == exchange.request.session_id
+                        && request.tool_call.id == exchange.request.tool_call.id
+                })
+            })
+            .collect()
+    }
+}
+
+/// Runs a proxy between a scripted agent and client.
+///
+/// See the [module documentation](self) for an example.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyHarness {
+    agent: ScriptedAgent,
+    client: ScriptedClient,
+}
+
+impl ProxyHarness {
+    /// Creates a harness with the default [`ScriptedAgent`] and
+    /// [`ScriptedClient`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the agent behind the proxy.
+    pub fn with_agent(mut self, agent: ScriptedAgent) -> Self {
+        self.agent = agent;
+        self
+    }
+
+    /// Sets the client in front of the proxy.
+    pub fn with_client(mut self, client: ScriptedClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Serves `component` between the agent and client, and runs `test`,
+    /// which drives the client and agent through a [`HarnessCx`].
+    ///
+    /// Returns what `test` returned, and what the client and agent received.
+    /// Must be called within a tokio runtime.
+    pub async fn run<T>(
+        self,
+        component: ToolcapComponent,
+        test: impl AsyncFnOnce(HarnessCx) -> Result<T, sacp::Error>,
+    ) -> Result<(T, Transcript), sacp::Error> {
+        let (proxy_io, peers_io) = tokio::io::duplex(64 * 1024);
+        let (proxy_read, proxy_write) = tokio::io::split(proxy_io);
+        let (peers_read, peers_write) = tokio::io::split(peers_io);
+        let proxy = tokio::spawn(component.serve(ByteStreams::new(
+            proxy_write.compat_write(),
+            proxy_read.compat(),
+        )));
+
+        let state = Arc::new(Mutex::new(PeerState {
+            files: self.client.files.clone(),
+            ..Default::default()
+        }));
+        let (held_tx, held) = mpsc::unbounded_channel();
+        let held = Arc::new(tokio::sync::Mutex::new(held));
+        let peers = ScriptedPeers {
+            agent: self.agent,
+            client: self.client,
+            state: state.clone(),
+            held: held_tx,
+        };
+        let result = ConductorToClient::builder()
+            .name("toolcap-test-peers")
+            .with_handler(peers)
+            .connect_to(ByteStreams::new(
+                peers_write.compat_write(),
+                peers_read.compat(),
+            ))?
+            .with_client(async |cx| {
+                test(HarnessCx {
+                    cx,
+                    state: state.clone(),
+                    held,
+                })
+                .await
+            })
+            .await;
+        proxy.abort();
+
+        let transcript = lock(&state).transcript.clone();
+        Ok((result?, transcript))
+    }
+}
+
+/// Drives the scripted client and agent in a [`ProxyHarness`] run.
+#[derive(Clone)]
+pub struct HarnessCx {
+    cx: JrConnectionCx<ConductorToClient>,
+    state: Arc<Mutex<PeerState>>,
+    held: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<HeldRequest>>>,
+}
+
+impl HarnessCx {
+    /// Initializes the proxy and agent, as the client, returning the
+    /// agent's response.
+    pub async fn initialize(&self) -> Result<InitializeResponse, sacp::Error> {
+        let initialize = InitializeRequest {
+            protocol_version: sacp::schema::V1,
+            client_capabilities: ClientCapabilities::default(),
+            client_info: None,
+            meta: None,
+        };
+        self.client_request(InitializeProxyRequest { initialize })
+            .await
+    }
+
+    /// Starts a session in `cwd`, as the client, returning its id.
+    pub async fn new_session(&self, cwd: impl Into<PathBuf>) -> Result<SessionId, sacp::Error> {
+        let request = NewSessionRequest {
+            cwd: cwd.into(),
+            mcp_servers: Vec::new(),
+            meta: None,
+        };
+        Ok(self.client_request(request).await?.session_id)
+    }
+
+    /// Sends a prompt, as the client, and waits for the agent to end its
+    /// turn.
+    pub async fn prompt(
+        &self,
+        session_id: &SessionId,
+        text: &str,
+    ) -> Result<PromptResponse, sacp::Error> {
+        let request = PromptRequest {
+            session_id: session_id.clone(),
+            prompt: vec![text.to_string().into()],
+            meta: None,
+        };
+        self.client_request(request).await
+    }
+
+    /// Cancels a session, as the client.
+    pub fn cancel(&self, session_id: &SessionId) -> Result<(), sacp::Error> {
+        self.client_notification(CancelNotification {
+            session_id: session_id.clone(),
+            meta: None,
+        })
+    }
+
+    /// Sends a request to the proxy as the client, and waits for the
+    /// response.
+    pub async fn client_request<R: JrRequest>(
+        &self,
+        request: R,
+    ) -> Result<R::Response, sacp::Error> {
+        self.cx.send_request_to(Client, request).block_task().await
+    }
+
+    /// Sends a notification to the proxy as the client.
+    pub fn client_notification<N: JrNotification>(
+        &self,
+        notification: N,
+    ) -> Result<(), sacp::Error> {
+        self.cx.send_notification_to(Client, notification)
+    }
+
+    /// Sends a request to the proxy as the agent, e.g. a permission request
+    /// or a file system call, and waits for the response.
+    pub async fn agent_request<R: JrRequest>(
+        &self,
+        request: R,
+    ) -> Result<R::Response, sacp::Error> {
+        self.cx.send_request_to(Agent, request).block_task().await
+    }
+
+    /// Sends a notification to the proxy as the agent.
+    pub fn agent_notification<N: JrNotification>(
+        &self,
+        notification: N,
+    ) -> Result<(), sacp::Error> {
+        self.cx.send_notification_to(Agent, notification)
+    }
+
+    /// Waits for the next permission request the client holds (see
+    /// [`ScriptedClient::with_held_answers`]).
+    pub async fn held_request(&self) -> Result<HeldRequest, sacp::Error> {
+        self.held
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| sacp::Error::internal_error().with_data("the scripted client stopped"))
+    }
+
+    /// Returns what the client and agent have received so far.
+    pub fn transcript(&self) -> Transcript {
+        lock(&self.state).transcript.clone()
+    }
+}
+
+impl fmt::Debug for HarnessCx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HarnessCx").finish_non_exhaustive()
+    }
+}
+
+/// What the scripted client and agent share during a run.
+#[derive(Default)]
+struct PeerState {
+    transcript: Transcript,
+    /// The client's file system.
+    files: HashMap<PathBuf, String>,
+    sessions: u64,
+    tool_calls: u64,
+    terminals: u64,
+}
+
+fn lock(state: &Mutex<PeerState>) -> std::sync::MutexGuard<'_, PeerState> {
+    lock_unpoisoned(state)
+}
+
+/// The scripted agent and client, on a conductor's connection to the
+/// proxy: messages to the agent come wrapped for the proxy's successor, and
+/// the rest are for the client.
+struct ScriptedPeers {
+    agent: ScriptedAgent,
+    client: ScriptedClient,
+    state: Arc<Mutex<PeerState>>,
+    /// Where the client puts the permission requests it holds.
+    held: mpsc::UnboundedSender<HeldRequest>,
+}
+
+impl JrMessageHandler for ScriptedPeers {
+    type Role = ConductorToClient;
+
+    fn describe_chain(&self) -> impl fmt::Debug {
+        "ScriptedPeers"
+    }
+
+    async fn handle_message(
+        &mut self,
+        message: MessageCx,
+        cx: JrConnectionCx<Self::Role>,
+    ) -> Result<Handled<MessageCx>, sacp::Error> {
+        let result = MatchMessageFrom::new(message, &cx)
+            .if_message_from(Agent, async |message: MessageCx| {
+                self.receive(&message, |transcript| &mut transcript.agent)?;
+                self.agent_handle(message, &cx).await
+            })
+            .await
+            .otherwise(async |message| {
+                self.receive(&message, |transcript| &mut transcript.client)?;
+                self.client_handle(message).await
+            })
+            .await;
+        result.map(|()| Handled::Yes)
+    }
+}
+
+impl ScriptedPeers {
+    /// Records a message in the transcript.
+    fn receive(
+        &self,
+        message: &MessageCx,
+        log: impl FnOnce(&mut Transcript) -> &mut Vec<ReceivedMessage>,
+    ) -> Result<(), sacp::Error> {
+        let message = message.to_untyped_message()?;
+        let mut state = lock(&self.state);
+        log(&mut state.transcript).push(ReceivedMessage {
+            method: message.method().into(),
+            params: message.params().clone(),
+        });
+        Ok(())
+    }
+
+    /// Answers a message to the agent.
+    async fn agent_handle(
+        &self,
+        message: MessageCx,
+        cx: &JrConnectionCx<ConductorToClient>,
+    ) -> Result<(), sacp::Error> {
+        MatchMessage::new(message)
+            .if_request(async |_: InitializeRequest, request_cx| {
+                request_cx.respond(InitializeResponse {
+                    protocol_version: sacp::schema::V1,
+                    agent_capabilities: Default::default(),
+                    auth_methods: Vec::new(),
+                    agent_info: self.agent.info.clone(),
+                    meta: None,
+                })
+            })
+            .await
+            .if_request(async |_: NewSessionRequest, request_cx| {
+                let session = {
+                    let mut state = lock(&self.state);
+                    state.sessions += 1;
+                    state.sessions
+                };
+                request_cx.respond(NewSessionResponse {
+                    session_id: format!("session-{}", session).into(),
+                    modes: None,
+                    meta: None,
+                })
+            })
+            .await
+            .if_request(async |_: LoadSessionRequest, request_cx| {
+                request_cx.respond(LoadSessionResponse::default())
+            })
+            .await
+            .if_request(async |_: SetSessionModeRequest, request_cx| {
+                request_cx.respond(SetSessionModeResponse::default())
+            })
+            .await
+            .if_request(async |req: PromptRequest, request_cx| {
+                // Asking for permission waits on the proxy, so do it in a
+                // task of its own
+                let requests: Vec<_> = self
+                    .agent
+                    .tool_calls
+                    .iter()
+                    .map(|fields| {
+                        let id = {
+                            let mut state = lock(&self.state);
+                            state.tool_calls += 1;
+                            format!("call-{}", state.tool_calls)
+                        };
+                        ScriptedAgent::permission_request(
+                            &req.session_id,
+                            id.into(),
+                            fields.clone(),
+                        )
+                    })
+                    .collect();
+                let (state, cx) = (self.state.clone(), cx.clone());
+                cx.clone().spawn(async move {
+                    for request in requests {
+                        let response = cx
+                            .send_request_to(Agent, request.clone())
+                            .block_task()
+                            .await;
+                        lock(&state)
+                            .transcript
+                            .permissions
+                            .push(PermissionExchange { request, response });
+                    }
+                    request_cx.respond(PromptResponse {
+                        stop_reason: StopReason::EndTurn,
+                        meta: None,
+                    })
+                })
+            })
+            .await
+            .otherwise(reject_unknown)
+            .await
+    }
+
+    /// Answers a message to the client.
+    async fn client_handle(&self, message: MessageCx) -> Result<(), sacp::Error> {
+        MatchMessage::new(message)
+            .if_request(async |req: RequestPermissionRequest, request_cx| {
+                if self.client.hold {
+                    let held = HeldRequest {
+                        request: req,
+                        responder: request_cx,
+                    };
+                    // The test may not be waiting for it, and the request
+                    // then goes unanswered
+                    let _ = self.held.send(held);
+                    return Ok(());
+                }
+                request_cx.respond(RequestPermissionResponse {
+                    outcome: (self.client.answer)(&req),
+                    meta: None,
+                })
+            })
+            .await
+            .if_request(async |req: ReadTextFileRequest, request_cx| {
+                let content = lock(&self.state).files.get(&req.path).cloned();
+                match content {
+                    Some(content) => request_cx.respond(ReadTextFileResponse {
+                        content,
+                        meta: None,
+                    }),
+                    None => request_cx.respond_with_error(
+                        sacp::Error::internal_error()
+                            .with_data(format!("no such file: {}", req.path.display())),
+                    ),
+                }
+            })
+            .await
+            .if_request(async |req: WriteTextFileRequest, request_cx| {
+                lock(&self.state).files.insert(req.path, req.content);
+                request_cx.respond(WriteTextFileResponse::default())
+            })
+            .await
+            .if_request(async |_: CreateTerminalRequest, request_cx| {
+                let terminal = {
+                    let mut state = lock(&self.state);
+                    state.terminals += 1;
+                    state.terminals
+                };
+                request_cx.respond(CreateTerminalResponse {
+                    terminal_id: TerminalId(format!("term-{}", terminal).into()),
+                    meta: None,
+                })
+            })
+            .await
+            .otherwise(reject_unknown)
+            .await
+    }
+}
+
+/// Rejects requests the scripted peers don't support, and ignores
+/// notifications.
+async fn reject_unknown(message: MessageCx) -> Result<(), sacp::Error> {
+    match message {
+        MessageCx::Request(_, request_cx) => {
+            request_cx.respond_with_error(sacp::Error::method_not_found())
+        }
+        MessageCx::Notification(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::acp::{CLIENT_CALL_DENIED, DefaultDecision, ToolcapProxy};
+    use crate::{Matcher, OperationKind, Outcome, Rule, Ruleset};
+
+    fn component() -> ToolcapComponent {
+        ToolcapComponent::new(ToolcapProxy::new(Ruleset::new(vec![
+            Rule::new(Matcher::command("ls"), Outcome::Allow),
+            Rule::new(Matcher::command("rm"), Outcome::Deny),
+            Rule::new(Matcher::kind(OperationKind::Edit), Outcome::Deny),
+        ])))
+    }
+
+    async fn run<T>(
+        harness: ProxyHarness,
+        test: impl AsyncFnOnce(HarnessCx) -> Result<T, sacp::Error>,
+    ) -> (T, Transcript) {
+        tokio::time::timeout(Duration::from_secs(5), harness.run(component(), test))
+            .await
+            .expect("test timed out")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_answered_by_proxy() {
+        let agent = ScriptedAgent::new()
+            .with_command("ls -la")
+            .with_command("rm -rf /");
+        let (response, transcript) = run(ProxyHarness::new().with_agent(agent), async |cx| {
+            cx.initialize().await?;
+            let session_id = cx.new_session("/repo").await?;
+            assert_eq!(session_id.to_string(), "session-1");
+            cx.prompt(&session_id, "look around").await
+        })
+        .await;
+
+        assert_eq!(response.stop_reason, StopReason::EndTurn);
+        assert!(transcript.forwarded().is_empty());
+        let answered = transcript.answered_by_proxy();
+        assert_eq!(answered.len(), 2);
+        assert_eq!(answered[0].selected(), Some("allow-once"));
+        assert_eq!(answered[1].selected(), Some("reject-once"));
+        let methods: Vec<_> = transcript
+            .agent_messages()
+            .iter()
+            .map(|message| message.method.as_str())
+            .collect();
+        assert_eq!(methods, ["initialize", "session/new", "session/prompt"]);
+    }
+
+    #[tokio::test]
+    async fn test_denials_explain_themselves() {
+        let agent = ScriptedAgent::new()
+            .with_command("rm -rf /")
+            .with_command("cargo build");
+        let proxy = ToolcapProxy::new(Ruleset::new(vec![Rule::new(
+            Matcher::command("rm"),
+            Outcome::Deny,
+        )]))
+        .with_headless(DefaultDecision::deny().with_reason("running in CI"));
+        let component = ToolcapComponent::new(proxy).with_denial_messages(true);
+        let harness = ProxyHarness::new().with_agent(agent);
+        let ((), transcript) = tokio::time::timeout(
+            Duration::from_secs(5),
+            harness.run(component, async |cx| {
+                cx.initialize().await?;
+                let session_id = cx.new_session("/repo").await?;
+                cx.prompt(&session_id, "clean up").await?;
+                Ok(())
+            }),
+        )
+        .await
+        .expect("test timed out")
+        .unwrap();
+
+        // The agent gets the explanation in the responses...
+        let reasons: Vec<_> = transcript
+            .permissions()
+            .iter()
+            .map(|exchange| {
+                let meta = exchange.response().unwrap().meta.clone().unwrap();
+                meta["toolcap"]["reason"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(reasons.len(), 2);
+        assert!(reasons[0].contains("- `rm -rf /`: denied by rule #0"));
+        assert!(reasons[1].contains("denied by default: running in CI"));
+
+        // ...and the user sees it in the conversation.
+        let messages: Vec<_> = transcript
+            .client_messages()
+            .iter()
+            .filter(|message| message.params["update"]["sessionUpdate"] == "agent_message_chunk")
+            .map(|message| message.params["update"]["content"]["text"].clone())
+            .collect();
+        assert_eq!(messages, [reasons[0].as_str(), reasons[1].as_str()]);
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_to_client() {
+        let agent = ScriptedAgent::new().with_command("cargo build");
+        let client = ScriptedClient::new().with_answer(PermissionOptionKind::RejectOnce);
+        let ((), transcript) = run(
+            ProxyHarness::new().with_agent(agent).with_client(client),
+            async |cx| {
+                cx.initialize().await?;
+                let session_id = cx.new_session("/repo").await?;
+                cx.prompt(&session_id, "build it").await?;
+                Ok(())
+            },
+        )
+        .await;
+
+        let forwarded = transcript.forwarded();
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(forwarded[0].tool_call.id.to_string(), "call-1");
+        assert!(transcript.answered_by_proxy().is_empty());
+        assert_eq!(transcript.permissions()[0].selected(), Some("reject-once"));
+    }
+
+    #[tokio::test]
+    async fn test_client_calls() {
+        let client = ScriptedClient::new().with_file("/repo/README.md", "# Hello");
+        let ((read, write), transcript) =
+            run(ProxyHarness::new().with_client(client), async |cx| {
+                cx.initialize().await?;
+                let session_id = cx.new_session("/repo").await?;
+                let read = cx
+                    .agent_request(ReadTextFileRequest {
+                        session_id: session_id.clone(),
+                        path: "/repo/README.md".into(),
+                        line: None,
+                        limit: None,
+                        meta: None,
+                    })
+                    .await?;
+                let write = cx
+                    .agent_request(WriteTextFileRequest {
+                        session_id,
+                        path: "/repo/README.md".into(),
+                        content: "# Goodbye".into(),
+                        meta: None,
+                    })
+                    .await;
+                Ok((read, write))
+            })
+            .await;
+
+        assert_eq!(read.content, "# Hello");
+        assert_eq!(write.unwrap_err().code, CLIENT_CALL_DENIED);
+        let methods: Vec<_> = transcript
+            .client_messages()
+            .iter()
+            .map(|message| message.method.as_str())
+            .collect();
+        assert_eq!(methods, ["fs/read_text_file"]);
+    }
+}
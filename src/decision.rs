@@ -0,0 +1,445 @@
+use std::fmt;
+
+use crate::operation::Operation;
+use crate::outcome::Outcome;
+use crate::rule::Rule;
+use crate::shell::ParseError;
+
+/// An explained evaluation result.
+///
+/// A decision pairs the overall outcome of evaluating an operation with a
+/// tree describing how that outcome was reached: every sub-command of a
+/// compound shell command, its individual outcome, the rule that matched it
+/// and, for unknown outcomes, the reason no decision could be made.
+///
+/// Decisions are produced by [`Ruleset::explain`](crate::Ruleset::explain).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    tree: DecisionNode,
+}
+
+impl Decision {
+    pub(crate) fn new(tree: DecisionNode) -> Self {
+        Self { tree }
+    }
+
+    /// Returns the overall outcome.
+    pub fn outcome(&self) -> Outcome {
+        self.tree.outcome()
+    }
+
+    /// Returns the root of the decision tree.
+    pub fn tree(&self) -> &DecisionNode {
+        &self.tree
+    }
+
+    /// Returns the rules responsible for the overall outcome.
+    ///
+    /// These are the matched rules of every node whose outcome equals the
+    /// overall outcome, e.g. the rules that denied parts of a denied command.
+    pub fn deciding_rules(&self) -> impl Iterator<Item = &MatchedRule> {
+        let outcome = self.outcome();
+        let mut nodes = Vec::new();
+        self.tree.collect_nodes(&mut nodes);
+        nodes.into_iter().filter_map(move |node| match node {
+            DecisionNode::Leaf {
+                outcome: node_outcome,
+                rule: Some(rule),
+                ..
+            }
+            | DecisionNode::Compound {
+                outcome: node_outcome,
+                rule: Some(rule),
+                ..
+            } if *node_outcome == outcome => Some(rule),
+            _ => None,
+        })
+    }
+
+    /// Returns an iterator over every leaf of the decision tree, in order.
+    pub fn leaves(&self) -> impl Iterator<Item = &DecisionNode> {
+        let mut leaves = Vec::new();
+        self.tree.collect_leaves(&mut leaves);
+        leaves.into_iter()
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.tree.fmt_indented(f, 0)
+    }
+}
+
+/// A node in a decision tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecisionNode {
+    /// A single operation evaluated directly against the rules.
+    ///
+    /// For execute operations this is one simple command of the parsed shell
+    /// command (or the whole command, if it couldn't be parsed).
+    Leaf {
+        /// The operation that was evaluated.
+        operation: Operation,
+        /// The outcome for this operation.
+        outcome: Outcome,
+        /// The rule that matched, if any.
+        rule: Option<MatchedRule>,
+        /// Why no decision could be made, if the outcome is `Unknown`.
+        reason: Option<UnknownReason>,
+    },
+
+    /// A compound shell command whose outcome is aggregated from its parts.
+    Compound {
+        /// The shell operator joining the parts.
+        kind: CompoundKind,
+        /// The aggregated outcome.
+        outcome: Outcome,
+        /// The pipeline-shape rule that matched the compound as a whole, if any.
+        rule: Option<MatchedRule>,
+        /// The decisions for each part, in order.
+        children: Vec<DecisionNode>,
+    },
+}
+
+impl DecisionNode {
+    /// Returns the outcome of this node.
+    pub fn outcome(&self) -> Outcome {
+        match self {
+            DecisionNode::Leaf { outcome, .. } | DecisionNode::Compound { outcome, .. } => *outcome,
+        }
+    }
+
+    fn collect_leaves<'a>(&'a self, out: &mut Vec<&'a DecisionNode>) {
+        match self {
+            DecisionNode::Leaf { .. } => out.push(self),
+            DecisionNode::Compound { children, .. } => {
+                for child in children {
+                    child.collect_leaves(out);
+                }
+            }
+        }
+    }
+
+    fn collect_nodes<'a>(&'a self, out: &mut Vec<&'a DecisionNode>) {
+        out.push(self);
+        if let DecisionNode::Compound { children, .. } = self {
+            for child in children {
+                child.collect_nodes(out);
+            }
+        }
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        match self {
+            DecisionNode::Leaf {
+                operation,
+                outcome,
+                rule,
+                reason,
+            } => {
+                write!(
+                    f,
+                    "{}{}: {}",
+                    indent,
+                    describe_operation(operation),
+                    outcome
+                )?;
+                if let Some(rule) = rule {
+                    write_rule(f, rule)?;
+                }
+                if let Some(reason) = reason {
+                    write!(f, " ({})", reason)?;
+                }
+                writeln!(f)
+            }
+            DecisionNode::Compound {
+                kind,
+                outcome,
+                rule,
+                children,
+            } => {
+                write!(f, "{}{}: {}", indent, kind, outcome)?;
+                if let Some(rule) = rule {
+                    write_rule(f, rule)?;
+                }
+                writeln!(f)?;
+                for child in children {
+                    child.fmt_indented(f, depth + 1)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The rule that decided an operation, along with its metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedRule {
+    index: usize,
+    id: Option<String>,
+    description: Option<String>,
+    message: Option<String>,
+    alternative: Option<String>,
+}
+
+impl MatchedRule {
+    pub(crate) fn new(index: usize, rule: &Rule) -> Self {
+        Self {
+            index,
+            id: rule.id().map(String::from),
+            description: rule.description().map(String::from),
+            message: rule.message().map(String::from),
+            alternative: rule.alternative().map(String::from),
+        }
+    }
+
+    /// Returns the index of the rule within its ruleset.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the rule's id, if set.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// Returns the rule's description, if set.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Returns the rule's message, if set.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// Returns the rule's suggested alternative, if set.
+    pub fn alternative(&self) -> Option<&str> {
+        self.alternative.as_deref()
+    }
+}
+
+/// Displays the rule's id, or its index if it has no id.
+impl fmt::Display for MatchedRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.id {
+            Some(id) => write!(f, "{}", id),
+            None => write!(f, "#{}", self.index),
+        }
+    }
+}
+
+/// How the parts of a compound operation are joined: by a shell operator, or
+/// as a batch of operations in one tool call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompoundKind {
+    /// `cmd1 | cmd2`
+    Pipeline,
+    /// `cmd1 && cmd2`
+    And,
+    /// `cmd1 || cmd2`
+    Or,
+    /// `cmd1; cmd2`
+    Sequence,
+    /// `( cmd )`
+    Subshell,
+    /// [`Operation::Batch`]
+    Batch,
+}
+
+impl fmt::Display for CompoundKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompoundKind::Pipeline => write!(f, "pipeline"),
+            CompoundKind::And => write!(f, "and"),
+            CompoundKind::Or => write!(f, "or"),
+            CompoundKind::Sequence => write!(f, "sequence"),
+            CompoundKind::Subshell => write!(f, "subshell"),
+            CompoundKind::Batch => write!(f, "batch"),
+        }
+    }
+}
+
+/// The reason an operation evaluated to `Unknown`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnknownReason {
+    /// The shell command could not be parsed.
+    ParseFailure(ParseError),
+    /// No rule matched the operation.
+    NoMatchingRule,
+    /// The command uses a shell construct that can't be analyzed statically.
+    Unsupported(String),
+    /// An earlier `cd` changed to a directory that can't be resolved
+    /// statically, so directory-scoped rules couldn't be checked.
+    UnresolvedWorkingDirectory,
+}
+
+impl fmt::Display for UnknownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnknownReason::ParseFailure(err) => write!(f, "parse failure: {}", err),
+            UnknownReason::NoMatchingRule => write!(f, "no matching rule"),
+            UnknownReason::Unsupported(construct) => write!(f, "unsupported: {}", construct),
+            UnknownReason::UnresolvedWorkingDirectory => {
+                write!(f, "working directory could not be resolved")
+            }
+        }
+    }
+}
+
+/// Writes a rule reference, with its message if it has one.
+fn write_rule(f: &mut fmt::Formatter<'_>, rule: &MatchedRule) -> fmt::Result {
+    match rule.message() {
+        Some(message) => write!(f, " (rule {}: {})", rule, message),
+        None => write!(f, " (rule {})", rule),
+    }
+}
+
+/// Returns a short human-readable description of an operation.
+pub(crate) fn describe_operation(operation: &Operation) -> String {
+    match operation {
+        Operation::Read { path } => format!("read {}", path.display()),
+        Operation::Edit { path } => format!("edit {}", path.display()),
+        Operation::Delete { path } => format!("delete {}", path.display()),
+        Operation::Move { from, to } => format!("move {} -> {}", from.display(), to.display()),
+        Operation::Search { query } => format!("search {}", query),
+        Operation::Execute(exec_op) => exec_op.raw().to_string(),
+        Operation::Fetch { url } => format!("fetch {}", url),
+        Operation::Think => "think".to_string(),
+        Operation::SwitchMode { mode } => format!("switch mode {}", mode),
+        Operation::Other { name, .. } => name.clone(),
+        Operation::Batch(operations) => operations
+            .iter()
+            .map(describe_operation)
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+
+    fn matched(index: usize) -> Option<MatchedRule> {
+        Some(MatchedRule::new(
+            index,
+            &Rule::new(Matcher::any_execute(), Outcome::Allow),
+        ))
+    }
+
+    fn leaf(command: &str, outcome: Outcome, rule: Option<MatchedRule>) -> DecisionNode {
+        DecisionNode::Leaf {
+            operation: Operation::execute(command),
+            outcome,
+            rule,
+            reason: if outcome == Outcome::Unknown {
+                Some(UnknownReason::NoMatchingRule)
+            } else {
+                None
+            },
+        }
+    }
+
+    #[test]
+    fn test_leaves_in_order() {
+        let decision = Decision::new(DecisionNode::Compound {
+            kind: CompoundKind::And,
+            outcome: Outcome::Unknown,
+            rule: None,
+            children: vec![
+                DecisionNode::Compound {
+                    kind: CompoundKind::Pipeline,
+                    outcome: Outcome::Allow,
+                    rule: None,
+                    children: vec![
+                        leaf("find .", Outcome::Allow, matched(0)),
+                        leaf("grep foo", Outcome::Allow, matched(1)),
+                    ],
+                },
+                leaf("npm install", Outcome::Unknown, None),
+            ],
+        });
+
+        let commands: Vec<_> = decision
+            .leaves()
+            .map(|leaf| match leaf {
+                DecisionNode::Leaf {
+                    operation: Operation::Execute(exec_op),
+                    ..
+                } => exec_op.raw().to_string(),
+                _ => panic!("Expected execute leaf"),
+            })
+            .collect();
+        assert_eq!(commands, vec!["find .", "grep foo", "npm install"]);
+        assert_eq!(decision.outcome(), Outcome::Unknown);
+    }
+
+    #[test]
+    fn test_display() {
+        let decision = Decision::new(DecisionNode::Compound {
+            kind: CompoundKind::Pipeline,
+            outcome: Outcome::Deny,
+            rule: None,
+            children: vec![
+                leaf("find .", Outcome::Allow, matched(0)),
+                leaf(
+                    "xargs rm",
+                    Outcome::Deny,
+                    Some(MatchedRule::new(
+                        3,
+                        &Rule::new(Matcher::command("xargs"), Outcome::Deny)
+                            .with_id("no-xargs")
+                            .with_message("use find -exec instead"),
+                    )),
+                ),
+            ],
+        });
+
+        assert_eq!(
+            decision.to_string(),
+            "pipeline: deny\n  find .: allow (rule #0)\n  xargs rm: deny (rule no-xargs: use find -exec instead)\n"
+        );
+    }
+
+    #[test]
+    fn test_deciding_rules() {
+        let decision = Decision::new(DecisionNode::Compound {
+            kind: CompoundKind::And,
+            outcome: Outcome::Deny,
+            rule: None,
+            children: vec![
+                leaf("find .", Outcome::Allow, matched(0)),
+                leaf("rm foo", Outcome::Deny, matched(2)),
+            ],
+        });
+
+        let indices: Vec<_> = decision.deciding_rules().map(|r| r.index()).collect();
+        assert_eq!(indices, vec![2]);
+    }
+
+    #[test]
+    fn test_compound_rule() {
+        let decision = Decision::new(DecisionNode::Compound {
+            kind: CompoundKind::Pipeline,
+            outcome: Outcome::Deny,
+            rule: Some(MatchedRule::new(
+                0,
+                &Rule::new(Matcher::pipes_into(Matcher::command("sh")), Outcome::Deny)
+                    .with_id("no-pipe-to-shell"),
+            )),
+            children: vec![
+                leaf("cat x", Outcome::Allow, matched(1)),
+                leaf("sh", Outcome::Allow, matched(2)),
+            ],
+        });
+
+        let ids: Vec<_> = decision.deciding_rules().map(|r| r.to_string()).collect();
+        assert_eq!(ids, vec!["no-pipe-to-shell"]);
+        assert_eq!(
+            decision.to_string(),
+            "pipeline: deny (rule no-pipe-to-shell)\n  cat x: allow (rule #1)\n  sh: allow (rule #2)\n"
+        );
+    }
+}
@@ -0,0 +1,403 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::matcher::Matcher;
+use crate::operation::{ExecuteOperation, Operation};
+use crate::shell::parse;
+
+/// How far an operation in a session's [`History`] has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperationStatus {
+    /// Announced by the agent, but not yet allowed or started.
+    Pending,
+    /// Allowed or started, but not known to have finished.
+    Running,
+    /// Finished successfully.
+    Completed,
+    /// Finished with an error.
+    Failed,
+}
+
+impl OperationStatus {
+    /// Returns `true` if the operation was allowed or started, and hasn't
+    /// failed.
+    pub fn is_performed(self) -> bool {
+        matches!(self, OperationStatus::Running | OperationStatus::Completed)
+    }
+
+    /// Returns `true` if the operation has finished, successfully or not.
+    pub fn is_finished(self) -> bool {
+        matches!(self, OperationStatus::Completed | OperationStatus::Failed)
+    }
+}
+
+impl fmt::Display for OperationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OperationStatus::Pending => write!(f, "pending"),
+            OperationStatus::Running => write!(f, "running"),
+            OperationStatus::Completed => write!(f, "completed"),
+            OperationStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// An operation in a session's [`History`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    operation: Operation,
+    tool_call_id: Option<String>,
+    status: OperationStatus,
+    permitted: bool,
+}
+
+impl HistoryEntry {
+    /// Returns the operation.
+    pub fn operation(&self) -> &Operation {
+        &self.operation
+    }
+
+    /// Returns the id of the tool call that performed the operation, if it
+    /// was performed by one.
+    pub fn tool_call_id(&self) -> Option<&str> {
+        self.tool_call_id.as_deref()
+    }
+
+    /// Returns how far the operation has got.
+    pub fn status(&self) -> OperationStatus {
+        self.status
+    }
+
+    /// Returns `true` if the operation was permitted, by the proxy or the
+    /// user, rather than only reported by the agent.
+    pub fn is_permitted(&self) -> bool {
+        self.permitted
+    }
+
+    /// Returns `true` if `matcher` matches the operation or, for compound
+    /// commands and batches, one of its parts.
+    pub(crate) fn matched_by(&self, matcher: &Matcher) -> bool {
+        operation_matched_by(&self.operation, matcher)
+    }
+
+    /// Moves the operation to `status`, unless that would take it back: a
+    /// finished operation stays finished, and a running one doesn't become
+    /// pending again.
+    fn advance(&mut self, status: OperationStatus) {
+        let backwards = self.status.is_finished()
+            || (self.status == OperationStatus::Running && status == OperationStatus::Pending);
+        if !backwards {
+            self.status = status;
+        }
+    }
+}
+
+/// Returns `true` if `matcher` matches `operation` or one of its parts.
+fn operation_matched_by(operation: &Operation, matcher: &Matcher) -> bool {
+    if matcher.matches(operation) {
+        return true;
+    }
+    match operation {
+        Operation::Execute(exec_op) => parse(exec_op.raw()).is_ok_and(|ast| {
+            ast.commands().any(|cmd| {
+                let part = ExecuteOperation::from_parsed(cmd, exec_op.working_dir());
+                matcher.matches(&Operation::Execute(part))
+            })
+        }),
+        Operation::Batch(operations) => operations
+            .iter()
+            .any(|operation| operation_matched_by(operation, matcher)),
+        _ => false,
+    }
+}
+
+/// The operations performed earlier in a session, oldest first.
+///
+/// Rules can depend on a session's history with [`Matcher::after`] and
+/// [`Matcher::after_success`], once it is given to the ruleset with
+/// [`Ruleset::with_history`](crate::Ruleset::with_history). An ACP proxy
+/// keeps the history of each of its sessions, from the requests it allows
+/// and the tool calls the agent reports.
+///
+/// The proxy can't see what a tool call did, so statuses come from the
+/// agent, and an agent could report tool calls it never made. Operations
+/// the proxy or the user permitted are marked as such (see
+/// [`HistoryEntry::is_permitted`]), and only those count for
+/// [`Matcher::after_success`]; [`Matcher::after`] also counts what the agent
+/// only reported, which suits rules that restrict what comes next.
+///
+/// A history keeps at most [`DEFAULT_LIMIT`](Self::DEFAULT_LIMIT)
+/// operations (see [`with_limit`](Self::with_limit)), forgetting the oldest
+/// first. Clones share the recorded operations until one of them records
+/// more, so handing a history to a ruleset is cheap.
+///
+/// # Example
+///
+/// ```
+/// use toolcap::{History, Operation, OperationStatus};
+///
+/// let mut history = History::new();
+/// history.record(Operation::execute("cargo test"), Some("call-1"), OperationStatus::Running);
+/// history.set_status("call-1", OperationStatus::Completed);
+///
+/// assert_eq!(history.entries().next().unwrap().status(), OperationStatus::Completed);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct History {
+    entries: Arc<Vec<HistoryEntry>>,
+    limit: usize,
+    /// A tool call whose operation is left out, see
+    /// [`without_tool_call`](Self::without_tool_call).
+    hidden: Option<String>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            entries: Arc::default(),
+            limit: Self::DEFAULT_LIMIT,
+            hidden: None,
+        }
+    }
+}
+
+impl History {
+    /// The number of operations a history keeps by default.
+    pub const DEFAULT_LIMIT: usize = 1000;
+
+    /// Creates an empty history.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps at most `limit` operations, forgetting the oldest first.
+    ///
+    /// Rules that depend on a forgotten operation no longer match, so the
+    /// limit should cover the sessions the rules are meant for.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self.truncate();
+        self
+    }
+
+    /// Adds a permitted operation to the history, returning it for
+    /// chaining.
+    pub fn with_operation(mut self, operation: Operation, status: OperationStatus) -> Self {
+        self.record_permitted(operation, None, status);
+        self
+    }
+
+    /// Records an operation the agent reported, performed by the tool call
+    /// `tool_call_id` if given.
+    ///
+    /// If the tool call is already in the history, its operation is kept
+    /// and only its status is updated (see [`set_status`](Self::set_status)).
+    pub fn record(
+        &mut self,
+        operation: Operation,
+        tool_call_id: Option<&str>,
+        status: OperationStatus,
+    ) {
+        self.insert(operation, tool_call_id, status, false);
+    }
+
+    /// Like [`record`](Self::record), for an operation the proxy or the user
+    /// permitted.
+    ///
+    /// If the tool call is in the history only as the agent reported it, or
+    /// with another operation, its entry is replaced by the permitted
+    /// operation with `status`: a tool call the agent reported as something
+    /// else, or as already completed, doesn't count as permitted.
+    pub fn record_permitted(
+        &mut self,
+        operation: Operation,
+        tool_call_id: Option<&str>,
+        status: OperationStatus,
+    ) {
+        self.insert(operation, tool_call_id, status, true);
+    }
+
+    fn insert(
+        &mut self,
+        operation: Operation,
+        tool_call_id: Option<&str>,
+        status: OperationStatus,
+        permitted: bool,
+    ) {
+        if let Some(index) = tool_call_id.and_then(|id| self.position(id)) {
+            let entry = &mut Arc::make_mut(&mut self.entries)[index];
+            if permitted && (!entry.permitted || entry.operation != operation) {
+                // What the agent reported before asking says nothing about
+                // what was permitted, or how it went
+                entry.operation = operation;
+                entry.status = status;
+                entry.permitted = true;
+            } else {
+                entry.advance(status);
+            }
+            return;
+        }
+        Arc::make_mut(&mut self.entries).push(HistoryEntry {
+            operation,
+            tool_call_id: tool_call_id.map(String::from),
+            status,
+            permitted,
+        });
+        self.truncate();
+    }
+
+    /// Forgets the oldest operations beyond the limit.
+    fn truncate(&mut self) {
+        let excess = self.entries.len().saturating_sub(self.limit);
+        if excess > 0 {
+            Arc::make_mut(&mut self.entries).drain(..excess);
+        }
+    }
+
+    /// Updates the status of a tool call's operation, returning `false` if
+    /// the tool call isn't in the history.
+    ///
+    /// Statuses only move forward: a finished operation stays finished, and
+    /// a running one doesn't become pending again.
+    pub fn set_status(&mut self, tool_call_id: &str, status: OperationStatus) -> bool {
+        // Look before writing, so that unknown tool calls don't copy
+        // operations shared with clones
+        let Some(index) = self.position(tool_call_id) else {
+            return false;
+        };
+        Arc::make_mut(&mut self.entries)[index].advance(status);
+        true
+    }
+
+    /// Returns the index of a tool call's operation.
+    fn position(&self, tool_call_id: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.tool_call_id() == Some(tool_call_id))
+    }
+
+    /// Returns the history without a tool call's operation, for deciding
+    /// that tool call on what happened before it.
+    ///
+    /// The returned history shares the recorded operations, and leaves
+    /// out only one tool call at a time.
+    pub fn without_tool_call(&self, tool_call_id: &str) -> History {
+        History {
+            hidden: Some(tool_call_id.to_string()),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the operations, oldest first.
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| self.hidden.is_none() || entry.tool_call_id() != self.hidden.as_deref())
+    }
+
+    /// Returns `true` if no operation has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.entries().next().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_only_moves_forward() {
+        let mut history = History::new();
+        let read = Operation::Read {
+            path: "README.md".into(),
+        };
+        history.record(read.clone(), Some("c1"), OperationStatus::Pending);
+        history.record(read.clone(), Some("c1"), OperationStatus::Running);
+        assert_eq!(history.entries().count(), 1);
+        let status = |history: &History| history.entries().next().unwrap().status();
+        assert_eq!(status(&history), OperationStatus::Running);
+
+        // A late announcement doesn't undo the start
+        history.record(read, Some("c1"), OperationStatus::Pending);
+        assert_eq!(status(&history), OperationStatus::Running);
+
+        assert!(history.set_status("c1", OperationStatus::Failed));
+        assert!(history.set_status("c1", OperationStatus::Completed));
+        assert_eq!(status(&history), OperationStatus::Failed);
+        assert!(!history.set_status("c2", OperationStatus::Completed));
+    }
+
+    #[test]
+    fn test_permitted_operations() {
+        let mut history = History::new();
+        let test = Operation::execute("cargo test");
+        history.record(test.clone(), Some("c1"), OperationStatus::Completed);
+        history.record(test.clone(), Some("c2"), OperationStatus::Pending);
+        history.record_permitted(test, Some("c2"), OperationStatus::Running);
+        let permitted: Vec<_> = history.entries().map(HistoryEntry::is_permitted).collect();
+        assert_eq!(permitted, [false, true]);
+
+        // Permitting a reported tool call replaces what the agent said
+        history.record_permitted(
+            Operation::execute("ls"),
+            Some("c1"),
+            OperationStatus::Running,
+        );
+        let entry = history.entries().next().unwrap();
+        assert_eq!(entry.operation(), &Operation::execute("ls"));
+        assert_eq!(entry.status(), OperationStatus::Running);
+        assert!(entry.is_permitted());
+        assert!(
+            History::new()
+                .with_operation(Operation::execute("ls"), OperationStatus::Running)
+                .entries()
+                .all(HistoryEntry::is_permitted)
+        );
+    }
+
+    #[test]
+    fn test_limit_forgets_the_oldest() {
+        let mut history = History::new().with_limit(2);
+        for command in ["ls", "pwd", "make"] {
+            history.record(
+                Operation::execute(command),
+                None,
+                OperationStatus::Completed,
+            );
+        }
+        let operations: Vec<_> = history.entries().map(HistoryEntry::operation).collect();
+        assert_eq!(
+            operations,
+            [&Operation::execute("pwd"), &Operation::execute("make")]
+        );
+        assert_eq!(history.with_limit(1).entries().count(), 1);
+    }
+
+    #[test]
+    fn test_compound_commands_match_by_part() {
+        let history = History::new().with_operation(
+            Operation::execute("cargo fmt && cargo test"),
+            OperationStatus::Completed,
+        );
+        let entry = history.entries().next().unwrap();
+        assert!(entry.matched_by(&Matcher::command("cargo").with_subcommand("test")));
+        assert!(!entry.matched_by(&Matcher::command("cargo").with_subcommand("build")));
+        assert!(entry.tool_call_id().is_none());
+    }
+
+    #[test]
+    fn test_without_tool_call() {
+        let mut history =
+            History::new().with_operation(Operation::execute("ls"), OperationStatus::Completed);
+        history.record(
+            Operation::execute("make"),
+            Some("c1"),
+            OperationStatus::Running,
+        );
+        let earlier = history.without_tool_call("c1");
+        let operations: Vec<_> = earlier.entries().map(HistoryEntry::operation).collect();
+        assert_eq!(operations, [&Operation::execute("ls")]);
+        assert!(Arc::ptr_eq(&earlier.entries, &history.entries));
+        assert!(History::new().without_tool_call("c1").is_empty());
+    }
+}
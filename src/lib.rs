@@ -30,12 +30,21 @@
 //! assert_eq!(ruleset.evaluate(&op), Outcome::Unknown);
 //! ```
 
-mod outcome;
-mod operation;
+pub mod audit;
+mod decision;
+mod history;
 mod matcher;
+mod operation;
+mod outcome;
+pub mod replay;
 mod rule;
 mod ruleset;
 pub mod shell;
+mod strategy;
+mod suggest;
+#[cfg(any(feature = "acp", feature = "audit"))]
+mod sync;
+mod workdir;
 
 #[cfg(feature = "acp")]
 pub mod acp;
@@ -43,8 +52,12 @@ pub mod acp;
 #[cfg(feature = "matchers")]
 pub mod matchers;
 
-pub use outcome::Outcome;
-pub use operation::{Operation, ExecuteOperation};
+pub use decision::{CompoundKind, Decision, DecisionNode, MatchedRule, UnknownReason};
+pub use history::{History, HistoryEntry, OperationStatus};
 pub use matcher::Matcher;
+pub use operation::{ExecuteOperation, Operation, OperationKind};
+pub use outcome::Outcome;
 pub use rule::Rule;
 pub use ruleset::Ruleset;
+pub use strategy::{CombiningStrategy, OperatorPolicy};
+pub use suggest::{Generality, RuleSuggester, Suggestion};
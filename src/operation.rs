@@ -1,4 +1,7 @@
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::shell::{ParsedCommand, quote};
 
 /// An operation represents an attempted tool use by an agent.
 ///
@@ -24,7 +27,15 @@ pub enum Operation {
     /// Switching session mode.
     SwitchMode { mode: String },
     /// Uncategorized operations.
-    Other { name: String, description: Option<String> },
+    Other {
+        name: String,
+        description: Option<String>,
+    },
+    /// Several operations performed by a single tool call, such as an edit
+    /// that touches more than one file.
+    ///
+    /// A batch is allowed only if every operation in it is allowed.
+    Batch(Vec<Operation>),
 }
 
 impl Operation {
@@ -53,6 +64,141 @@ impl Operation {
     pub fn execute_in(command: impl Into<String>, working_dir: impl Into<PathBuf>) -> Self {
         Operation::Execute(ExecuteOperation::new(command).with_working_dir(working_dir))
     }
+
+    /// Resolves this operation against the directory it is performed in.
+    ///
+    /// Execute operations without a working directory get `dir`, and a
+    /// relative working directory is resolved against it. Relative paths of
+    /// file operations are made absolute. Other operations are unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::path::Path;
+    /// use toolcap::Operation;
+    ///
+    /// let op = Operation::execute("ls").in_directory("/home/user/project");
+    /// assert!(matches!(
+    ///     op,
+    ///     Operation::Execute(ref e) if e.working_dir().unwrap() == Path::new("/home/user/project")
+    /// ));
+    ///
+    /// let op = Operation::Read { path: "src/lib.rs".into() }.in_directory("/home/user/project");
+    /// assert_eq!(op, Operation::Read { path: "/home/user/project/src/lib.rs".into() });
+    /// ```
+    pub fn in_directory(self, dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref();
+        match self {
+            Operation::Execute(exec_op) => {
+                let working_dir = match exec_op.working_dir() {
+                    Some(working_dir) => dir.join(working_dir),
+                    None => dir.to_path_buf(),
+                };
+                Operation::Execute(exec_op.with_working_dir(working_dir))
+            }
+            Operation::Read { path } => Operation::Read {
+                path: dir.join(path),
+            },
+            Operation::Edit { path } => Operation::Edit {
+                path: dir.join(path),
+            },
+            Operation::Delete { path } => Operation::Delete {
+                path: dir.join(path),
+            },
+            Operation::Move { from, to } => Operation::Move {
+                from: dir.join(from),
+                to: dir.join(to),
+            },
+            Operation::Batch(operations) => Operation::Batch(
+                operations
+                    .into_iter()
+                    .map(|operation| operation.in_directory(dir))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    /// Returns the file paths this operation touches.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::path::Path;
+    /// use toolcap::Operation;
+    ///
+    /// let op = Operation::Batch(vec![
+    ///     Operation::Edit { path: "a.rs".into() },
+    ///     Operation::Delete { path: "b.rs".into() },
+    /// ]);
+    /// assert_eq!(op.paths(), vec![Path::new("a.rs"), Path::new("b.rs")]);
+    /// assert!(Operation::execute("ls").paths().is_empty());
+    /// ```
+    pub fn paths(&self) -> Vec<&Path> {
+        match self {
+            Operation::Read { path } | Operation::Edit { path } | Operation::Delete { path } => {
+                vec![path]
+            }
+            Operation::Move { from, to } => vec![from, to],
+            Operation::Batch(operations) => operations.iter().flat_map(Operation::paths).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the kind of operation this is.
+    pub fn kind(&self) -> OperationKind {
+        match self {
+            Operation::Read { .. } => OperationKind::Read,
+            Operation::Edit { .. } => OperationKind::Edit,
+            Operation::Delete { .. } => OperationKind::Delete,
+            Operation::Move { .. } => OperationKind::Move,
+            Operation::Search { .. } => OperationKind::Search,
+            Operation::Execute(_) => OperationKind::Execute,
+            Operation::Fetch { .. } => OperationKind::Fetch,
+            Operation::Think => OperationKind::Think,
+            Operation::SwitchMode { .. } => OperationKind::SwitchMode,
+            Operation::Other { .. } => OperationKind::Other,
+            Operation::Batch(_) => OperationKind::Batch,
+        }
+    }
+}
+
+/// The kind of an [`Operation`], without its details.
+///
+/// Used to write rules about whole kinds of operations (see
+/// [`Matcher::kind`](crate::Matcher::kind)).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+    Read,
+    Edit,
+    Delete,
+    Move,
+    Search,
+    Execute,
+    Fetch,
+    Think,
+    SwitchMode,
+    Other,
+    Batch,
+}
+
+impl fmt::Display for OperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OperationKind::Read => "read",
+            OperationKind::Edit => "edit",
+            OperationKind::Delete => "delete",
+            OperationKind::Move => "move",
+            OperationKind::Search => "search",
+            OperationKind::Execute => "execute",
+            OperationKind::Fetch => "fetch",
+            OperationKind::Think => "think",
+            OperationKind::SwitchMode => "switch mode",
+            OperationKind::Other => "other",
+            OperationKind::Batch => "batch",
+        };
+        f.write_str(name)
+    }
 }
 
 /// Holds parsed command data for execute operations.
@@ -73,6 +219,24 @@ impl ExecuteOperation {
         }
     }
 
+    /// Creates an execute operation for a simple command parsed out of a
+    /// larger command line.
+    ///
+    /// The words are quoted for the shell, so the command line keeps their
+    /// boundaries: `rm 'my file'` doesn't become `rm my file`.
+    pub(crate) fn from_parsed(cmd: &ParsedCommand, working_dir: Option<&PathBuf>) -> Self {
+        let mut raw = quote(&cmd.name);
+        for arg in &cmd.args {
+            raw.push(' ');
+            raw.push_str(&quote(arg));
+        }
+
+        Self {
+            raw,
+            working_dir: working_dir.cloned(),
+        }
+    }
+
     /// Sets the working directory for this operation.
     pub fn with_working_dir(mut self, dir: impl Into<PathBuf>) -> Self {
         self.working_dir = Some(dir.into());
@@ -118,6 +282,32 @@ impl ExecuteOperation {
     pub fn has_flag(&self, flag: &str) -> bool {
         self.args().any(|arg| arg == flag)
     }
+
+    /// Checks if a flag is used in any form: alone, combined with other short
+    /// flags (`-Rf` uses `-f`), or with a value (`--force=true` uses
+    /// `--force`).
+    ///
+    /// A combined short flag like `-rf` is used if each of its letters is,
+    /// so `rm -r -f` and `rm -fr` both use it. Short flags that take a value
+    /// can't be told apart from combined ones, so this errs on the side of
+    /// finding a flag.
+    pub fn uses_flag(&self, flag: &str) -> bool {
+        if flag.starts_with("--") {
+            return self.args().any(|arg| {
+                arg.strip_prefix(flag)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('='))
+            });
+        }
+        let Some(letters) = flag.strip_prefix('-').filter(|letters| !letters.is_empty()) else {
+            return self.has_flag(flag);
+        };
+        let short_flags: String = self
+            .args()
+            .filter(|arg| !arg.starts_with("--"))
+            .filter_map(|arg| arg.strip_prefix('-'))
+            .collect();
+        letters.chars().all(|letter| short_flags.contains(letter))
+    }
 }
 
 #[cfg(test)]
@@ -144,6 +334,20 @@ mod tests {
         assert!(!op.has_flag("--all"));
     }
 
+    #[test]
+    fn test_uses_flag() {
+        let op = ExecuteOperation::new("git push -fu --force-with-lease=main origin");
+        assert!(op.uses_flag("-f"));
+        assert!(op.uses_flag("-uf"));
+        assert!(op.uses_flag("--force-with-lease"));
+        assert!(!op.uses_flag("--force"));
+        assert!(!op.uses_flag("-r"));
+
+        assert!(ExecuteOperation::new("rm -r -f /").uses_flag("-rf"));
+        assert!(ExecuteOperation::new("rm -Rf /").uses_flag("-R"));
+        assert!(!ExecuteOperation::new("rm -Rf /").uses_flag("-r"));
+    }
+
     #[test]
     fn test_args() {
         let op = ExecuteOperation::new("cargo build --release");
@@ -151,6 +355,51 @@ mod tests {
         assert_eq!(args, vec!["build", "--release"]);
     }
 
+    #[test]
+    fn test_in_directory() {
+        let op = Operation::execute("ls").in_directory("/home/user");
+        assert_eq!(op, Operation::execute_in("ls", "/home/user"));
+
+        let op = Operation::execute_in("ls", "sub").in_directory("/home/user");
+        assert_eq!(op, Operation::execute_in("ls", "/home/user/sub"));
+
+        let op = Operation::execute_in("ls", "/tmp").in_directory("/home/user");
+        assert_eq!(op, Operation::execute_in("ls", "/tmp"));
+
+        let op = Operation::Move {
+            from: "a".into(),
+            to: "/b".into(),
+        }
+        .in_directory("/home/user");
+        assert_eq!(
+            op,
+            Operation::Move {
+                from: "/home/user/a".into(),
+                to: "/b".into(),
+            }
+        );
+
+        let op = Operation::Batch(vec![
+            Operation::Edit { path: "a".into() },
+            Operation::execute("ls"),
+        ])
+        .in_directory("/home/user");
+        assert_eq!(
+            op,
+            Operation::Batch(vec![
+                Operation::Edit {
+                    path: "/home/user/a".into()
+                },
+                Operation::execute_in("ls", "/home/user"),
+            ])
+        );
+
+        let op = Operation::Search {
+            query: "foo".into(),
+        };
+        assert_eq!(op.clone().in_directory("/home/user"), op);
+    }
+
     #[test]
     fn test_working_dir() {
         let op = ExecuteOperation::new("ls").with_working_dir("/home/user");
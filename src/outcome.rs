@@ -8,3 +8,13 @@ pub enum Outcome {
     /// No rule matched; the client should escalate to the user.
     Unknown,
 }
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Allow => write!(f, "allow"),
+            Outcome::Deny => write!(f, "deny"),
+            Outcome::Unknown => write!(f, "unknown"),
+        }
+    }
+}
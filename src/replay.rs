@@ -0,0 +1,458 @@
+//! Replaying recorded operations against a candidate ruleset.
+//!
+//! Before changing a policy, it helps to know what the change does to real
+//! traffic. A [`Replay`] evaluates a corpus of past operations against the
+//! current (baseline) ruleset and a candidate, and its [`ReplayReport`] lists
+//! every decision that changes, how often each of the candidate's rules is
+//! used, and which commands the candidate still can't decide.
+//!
+//! With the `replay` feature, `read_corpus` and `open_corpus` load a corpus
+//! from a JSON Lines file, such as an audit log written by `JsonlSink`.
+//!
+//! # Example
+//!
+//! ```
+//! use toolcap::replay::Replay;
+//! use toolcap::{Matcher, Operation, Outcome, Rule, Ruleset};
+//!
+//! let baseline = Ruleset::new(vec![Rule::new(Matcher::command("git"), Outcome::Allow)]);
+//! let candidate = Ruleset::new(vec![
+//!     Rule::new(Matcher::command("git").with_subcommand("push"), Outcome::Deny),
+//!     Rule::new(Matcher::command("git"), Outcome::Allow),
+//! ]);
+//!
+//! let report = Replay::new(baseline, candidate).run([
+//!     Operation::execute("git status"),
+//!     Operation::execute("git push origin main"),
+//!     Operation::execute("cargo build"),
+//! ]);
+//!
+//! assert_eq!(report.total(), 3);
+//! assert_eq!(report.changes().len(), 1);
+//! assert_eq!(report.changes()[0].transition(), (Outcome::Allow, Outcome::Deny));
+//! assert_eq!(report.unknown_commands(), [("cargo build".to_string(), 1)]);
+//! ```
+
+#[cfg(feature = "replay")]
+mod corpus;
+
+#[cfg(feature = "replay")]
+pub use corpus::{open_corpus, read_corpus};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::decision::{Decision, DecisionNode, describe_operation};
+use crate::operation::Operation;
+use crate::outcome::Outcome;
+use crate::ruleset::Ruleset;
+
+/// Compares how two rulesets decide the same operations.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    baseline: Ruleset,
+    candidate: Ruleset,
+}
+
+impl Replay {
+    /// Creates a replay comparing a `candidate` ruleset to a `baseline`.
+    pub fn new(baseline: Ruleset, candidate: Ruleset) -> Self {
+        Self {
+            baseline,
+            candidate,
+        }
+    }
+
+    /// Returns the ruleset being compared against.
+    pub fn baseline(&self) -> &Ruleset {
+        &self.baseline
+    }
+
+    /// Returns the ruleset being tried out.
+    pub fn candidate(&self) -> &Ruleset {
+        &self.candidate
+    }
+
+    /// Evaluates every operation against both rulesets.
+    pub fn run(&self, operations: impl IntoIterator<Item = Operation>) -> ReplayReport {
+        let mut report = ReplayReport {
+            total: 0,
+            changes: Vec::new(),
+            coverage: self
+                .candidate
+                .rules()
+                .iter()
+                .enumerate()
+                .map(|(index, rule)| RuleCoverage {
+                    index,
+                    id: rule.id().map(String::from),
+                    matched: 0,
+                    decided: 0,
+                })
+                .collect(),
+            unknown: Vec::new(),
+        };
+        let mut unknown = HashMap::new();
+
+        for operation in operations {
+            report.total += 1;
+            let before = self.baseline.explain(&operation);
+            let after = self.candidate.explain(&operation);
+
+            let matched: HashSet<_> = matched_rules(&after).collect();
+            for index in matched {
+                report.coverage[index].matched += 1;
+            }
+            let decided: HashSet<_> = after.deciding_rules().map(|rule| rule.index()).collect();
+            for index in decided {
+                report.coverage[index].decided += 1;
+            }
+
+            let undecided: HashSet<_> = after
+                .leaves()
+                .filter(|leaf| leaf.outcome() == Outcome::Unknown)
+                .filter_map(|leaf| match leaf {
+                    DecisionNode::Leaf { operation, .. } => Some(describe_operation(operation)),
+                    DecisionNode::Compound { .. } => None,
+                })
+                .collect();
+            for command in undecided {
+                *unknown.entry(command).or_insert(0) += 1;
+            }
+
+            if before.outcome() != after.outcome() {
+                report.changes.push(Change {
+                    operation,
+                    before,
+                    after,
+                });
+            }
+        }
+
+        report.unknown = unknown.into_iter().collect();
+        report
+            .unknown
+            .sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+        report
+    }
+}
+
+/// Returns the index of every rule that matched part of a decision.
+fn matched_rules(decision: &Decision) -> impl Iterator<Item = usize> + '_ {
+    let mut nodes = vec![decision.tree()];
+    std::iter::from_fn(move || {
+        while let Some(node) = nodes.pop() {
+            let rule = match node {
+                DecisionNode::Leaf { rule, .. } => rule,
+                DecisionNode::Compound { rule, children, .. } => {
+                    nodes.extend(children.iter().rev());
+                    rule
+                }
+            };
+            if let Some(rule) = rule {
+                return Some(rule.index());
+            }
+        }
+        None
+    })
+}
+
+/// An operation whose outcome differs between the two rulesets.
+#[derive(Debug, Clone)]
+pub struct Change {
+    operation: Operation,
+    before: Decision,
+    after: Decision,
+}
+
+impl Change {
+    /// Returns the operation.
+    pub fn operation(&self) -> &Operation {
+        &self.operation
+    }
+
+    /// Returns how the baseline ruleset decided the operation.
+    pub fn before(&self) -> &Decision {
+        &self.before
+    }
+
+    /// Returns how the candidate ruleset decided the operation.
+    pub fn after(&self) -> &Decision {
+        &self.after
+    }
+
+    /// Returns the outcomes before and after, e.g. `(Allow, Unknown)`.
+    pub fn transition(&self) -> (Outcome, Outcome) {
+        (self.before.outcome(), self.after.outcome())
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {}: {}",
+            self.before.outcome(),
+            self.after.outcome(),
+            describe_operation(&self.operation)
+        )
+    }
+}
+
+/// How often one of the candidate's rules was used during a replay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleCoverage {
+    index: usize,
+    id: Option<String>,
+    matched: usize,
+    decided: usize,
+}
+
+impl RuleCoverage {
+    /// Returns the index of the rule within the candidate ruleset.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the rule's id, if set.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// Returns how many operations the rule matched some part of.
+    pub fn matched(&self) -> usize {
+        self.matched
+    }
+
+    /// Returns how many operations the rule was responsible for the outcome
+    /// of (see [`Decision::deciding_rules`]).
+    pub fn decided(&self) -> usize {
+        self.decided
+    }
+}
+
+/// The results of a [`Replay`].
+///
+/// The report's `Display` output is a summary meant for people: the number of
+/// changes per transition, each change, the coverage of every rule, and the
+/// most common unknown commands.
+#[derive(Debug, Clone)]
+pub struct ReplayReport {
+    total: usize,
+    changes: Vec<Change>,
+    coverage: Vec<RuleCoverage>,
+    unknown: Vec<(String, usize)>,
+}
+
+impl ReplayReport {
+    /// Returns the number of operations replayed.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Returns every operation whose outcome changed, in replay order.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Returns how many operations went through each transition, most common
+    /// first.
+    pub fn transitions(&self) -> Vec<((Outcome, Outcome), usize)> {
+        let mut counts: Vec<((Outcome, Outcome), usize)> = Vec::new();
+        for change in &self.changes {
+            let transition = change.transition();
+            match counts.iter_mut().find(|(t, _)| *t == transition) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((transition, 1)),
+            }
+        }
+        counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+        counts
+    }
+
+    /// Returns the coverage of each of the candidate's rules, in rule order.
+    ///
+    /// Rules that were never matched are candidates for removal.
+    pub fn coverage(&self) -> &[RuleCoverage] {
+        &self.coverage
+    }
+
+    /// Returns the commands the candidate couldn't decide, with how many
+    /// operations they appeared in, most common first.
+    ///
+    /// Each part of a compound command is counted separately, so
+    /// `ls && cargo publish` counts towards `cargo publish`.
+    pub fn unknown_commands(&self) -> &[(String, usize)] {
+        &self.unknown
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Replayed {} operations: {} changed",
+            self.total,
+            self.changes.len()
+        )?;
+        for ((before, after), count) in self.transitions() {
+            writeln!(f, "  {} -> {}: {}", before, after, count)?;
+        }
+
+        if !self.changes.is_empty() {
+            writeln!(f, "\nChanged decisions:")?;
+            for change in &self.changes {
+                writeln!(f, "  {}", change)?;
+            }
+        }
+
+        if !self.coverage.is_empty() {
+            writeln!(f, "\nRule coverage:")?;
+            for rule in &self.coverage {
+                write!(f, "  #{}", rule.index)?;
+                if let Some(id) = &rule.id {
+                    write!(f, " {}", id)?;
+                }
+                if rule.matched == 0 {
+                    writeln!(f, ": never matched")?;
+                } else {
+                    writeln!(f, ": matched {}, decided {}", rule.matched, rule.decided)?;
+                }
+            }
+        }
+
+        if !self.unknown.is_empty() {
+            writeln!(f, "\nMost common unknown commands:")?;
+            for (command, count) in self.unknown.iter().take(10) {
+                writeln!(f, "  {:>5}  {}", count, command)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::Matcher;
+    use crate::rule::Rule;
+
+    fn baseline() -> Ruleset {
+        Ruleset::new(vec![
+            Rule::new(Matcher::command("git"), Outcome::Allow).with_id("git"),
+            Rule::new(Matcher::command("ls"), Outcome::Allow).with_id("ls"),
+        ])
+    }
+
+    fn candidate() -> Ruleset {
+        Ruleset::new(vec![
+            Rule::new(
+                Matcher::command("git").with_subcommand("status"),
+                Outcome::Allow,
+            )
+            .with_id("git-status"),
+            Rule::new(Matcher::command("rm"), Outcome::Deny).with_id("no-rm"),
+            Rule::new(Matcher::command("ls"), Outcome::Allow).with_id("ls"),
+            Rule::new(Matcher::command("sudo"), Outcome::Deny).with_id("no-sudo"),
+        ])
+    }
+
+    fn report(commands: &[&str]) -> ReplayReport {
+        Replay::new(baseline(), candidate()).run(commands.iter().map(|c| Operation::execute(*c)))
+    }
+
+    #[test]
+    fn test_changes() {
+        let report = report(&[
+            "git status",
+            "git push",
+            "rm -rf build",
+            "ls",
+            "cargo build",
+        ]);
+        assert_eq!(report.total(), 5);
+
+        let changes: Vec<_> = report.changes().iter().map(Change::to_string).collect();
+        assert_eq!(
+            changes,
+            [
+                "allow -> unknown: git push",
+                "unknown -> deny: rm -rf build"
+            ]
+        );
+        assert_eq!(
+            report.transitions(),
+            [
+                ((Outcome::Allow, Outcome::Unknown), 1),
+                ((Outcome::Unknown, Outcome::Deny), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coverage() {
+        let report = report(&["git status", "ls && git status", "ls | rm x", "ls"]);
+        let coverage: Vec<_> = report
+            .coverage()
+            .iter()
+            .map(|rule| (rule.id().unwrap(), rule.matched(), rule.decided()))
+            .collect();
+        assert_eq!(
+            coverage,
+            [
+                ("git-status", 2, 2),
+                ("no-rm", 1, 1),
+                // `ls | rm x` is denied, so `ls` didn't decide it.
+                ("ls", 3, 2),
+                ("no-sudo", 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_commands() {
+        let report = report(&[
+            "cargo build",
+            "ls && cargo build && cargo build",
+            "npm test",
+            "git push",
+            "npm test | tee log",
+            "cargo build",
+        ]);
+        assert_eq!(
+            report.unknown_commands(),
+            [
+                ("cargo build".to_string(), 3),
+                ("npm test".to_string(), 2),
+                ("git push".to_string(), 1),
+                ("tee log".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let report = report(&["git push", "sudo reboot", "cargo build"]);
+        assert_eq!(
+            report.to_string(),
+            "Replayed 3 operations: 2 changed
+  allow -> unknown: 1
+  unknown -> deny: 1
+
+Changed decisions:
+  allow -> unknown: git push
+  unknown -> deny: sudo reboot
+
+Rule coverage:
+  #0 git-status: never matched
+  #1 no-rm: never matched
+  #2 ls: never matched
+  #3 no-sudo: matched 1, decided 1
+
+Most common unknown commands:
+      1  cargo build
+      1  git push
+"
+        );
+    }
+}
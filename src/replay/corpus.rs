@@ -0,0 +1,244 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::operation::{ExecuteOperation, Operation};
+
+/// Opens a JSON Lines corpus and reads its operations.
+///
+/// See [`read_corpus`] for the format.
+pub fn open_corpus(path: impl AsRef<Path>) -> io::Result<Vec<Operation>> {
+    read_corpus(BufReader::new(File::open(path)?))
+}
+
+/// Reads the operations of a JSON Lines corpus.
+///
+/// Each line holds one of:
+///
+/// - a command, as a JSON string: `"git status"`;
+/// - an operation, as written to audit logs:
+///   `{"type":"execute","command":"git status","working_dir":"/repo"}`;
+/// - an audit log record, as written by `JsonlSink`, whose `operation` is
+///   used. Only the record of how a request was first handled counts:
+///   records that follow up on a request recorded elsewhere in the log
+///   (the user's answer, a timeout, a shadow disagreement) and a ruleset's
+///   own `evaluated` records are skipped, so each request is replayed once;
+/// - with the `acp` feature, an ACP `session/request_permission` request,
+///   interpreted like `Operation::from_request`.
+///
+/// Blank lines are skipped. A line that can't be read is an
+/// [`InvalidData`](io::ErrorKind::InvalidData) error naming the line.
+pub fn read_corpus(reader: impl BufRead) -> io::Result<Vec<Operation>> {
+    let mut operations = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let operation = serde_json::from_str(&line)
+            .map_err(|err| err.to_string())
+            .and_then(|value| corpus_operation(&value))
+            .map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", index + 1, err),
+                )
+            })?;
+        operations.extend(operation);
+    }
+    Ok(operations)
+}
+
+/// Audit log dispositions of records that don't count as a request of their
+/// own.
+const SKIPPED_DISPOSITIONS: &[&str] = &["user_choice", "timed_out", "evaluated", "shadow"];
+
+/// Reads the operation of one line of a corpus, or `None` for an audit log
+/// record that is skipped.
+fn corpus_operation(value: &Value) -> Result<Option<Operation>, String> {
+    match value {
+        Value::String(command) => Ok(Some(Operation::execute(command.as_str()))),
+        Value::Object(obj) if obj.contains_key("operation") => {
+            let skipped = obj
+                .get("disposition")
+                .and_then(Value::as_str)
+                .is_some_and(|disposition| SKIPPED_DISPOSITIONS.contains(&disposition));
+            if skipped {
+                return Ok(None);
+            }
+            operation(&obj["operation"]).map(Some)
+        }
+        Value::Object(obj) if obj.contains_key("toolCall") => request_operation(value).map(Some),
+        Value::Object(_) => operation(value).map(Some),
+        _ => Err("expected a command, an operation or a permission request".into()),
+    }
+}
+
+#[cfg(feature = "acp")]
+fn request_operation(value: &Value) -> Result<Operation, String> {
+    let request: sacp::schema::RequestPermissionRequest =
+        serde_json::from_value(value.clone()).map_err(|err| err.to_string())?;
+    Ok(Operation::from_request(&request))
+}
+
+#[cfg(not(feature = "acp"))]
+fn request_operation(_value: &Value) -> Result<Operation, String> {
+    Err("permission requests need the `acp` feature".into())
+}
+
+/// Reads an operation in the format written by audit logs.
+fn operation(value: &Value) -> Result<Operation, String> {
+    let obj = value
+        .as_object()
+        .ok_or_else(|| "expected an operation object".to_string())?;
+    let kind = string(obj, "type")?;
+    Ok(match kind.as_str() {
+        "read" => Operation::Read {
+            path: string(obj, "path")?.into(),
+        },
+        "edit" => Operation::Edit {
+            path: string(obj, "path")?.into(),
+        },
+        "delete" => Operation::Delete {
+            path: string(obj, "path")?.into(),
+        },
+        "move" => Operation::Move {
+            from: string(obj, "from")?.into(),
+            to: string(obj, "to")?.into(),
+        },
+        "search" => Operation::Search {
+            query: string(obj, "query")?,
+        },
+        "execute" => {
+            let exec_op = ExecuteOperation::new(string(obj, "command")?);
+            Operation::Execute(match optional_string(obj, "working_dir")? {
+                Some(dir) => exec_op.with_working_dir(dir),
+                None => exec_op,
+            })
+        }
+        "fetch" => Operation::Fetch {
+            url: string(obj, "url")?,
+        },
+        "think" => Operation::Think,
+        "switch_mode" => Operation::SwitchMode {
+            mode: string(obj, "mode")?,
+        },
+        "other" => Operation::Other {
+            name: string(obj, "name")?,
+            description: optional_string(obj, "description")?,
+        },
+        "batch" => Operation::Batch(
+            obj.get("operations")
+                .and_then(Value::as_array)
+                .ok_or_else(|| "batch without `operations`".to_string())?
+                .iter()
+                .map(operation)
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => return Err(format!("unknown operation type `{}`", kind)),
+    })
+}
+
+fn string(obj: &Map<String, Value>, field: &str) -> Result<String, String> {
+    optional_string(obj, field)?.ok_or_else(|| format!("missing `{}`", field))
+}
+
+fn optional_string(obj: &Map<String, Value>, field: &str) -> Result<Option<String>, String> {
+    match obj.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(format!("`{}` should be a string", field)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(corpus: &str) -> io::Result<Vec<Operation>> {
+        read_corpus(corpus.as_bytes())
+    }
+
+    #[test]
+    fn test_read_corpus() {
+        let operations = read(
+            r#""git status"
+
+{"type":"execute","command":"cargo test","working_dir":"/repo"}
+{"timestamp":"2025-06-01T12:00:00Z","operation":{"type":"read","path":"/repo/Cargo.toml"},"outcome":"allow"}
+{"type":"batch","operations":[{"type":"edit","path":"a"},{"type":"move","from":"b","to":"c"}]}
+{"type":"other","name":"mcp","description":null}
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            operations,
+            [
+                Operation::execute("git status"),
+                Operation::execute_in("cargo test", "/repo"),
+                Operation::Read {
+                    path: "/repo/Cargo.toml".into()
+                },
+                Operation::Batch(vec![
+                    Operation::Edit { path: "a".into() },
+                    Operation::Move {
+                        from: "b".into(),
+                        to: "c".into()
+                    },
+                ]),
+                Operation::Other {
+                    name: "mcp".into(),
+                    description: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_one_record_per_request() {
+        let operations = read(
+            r#"{"tool_call_id":"c1","operation":{"type":"execute","command":"npm publish"},"disposition":"forwarded"}
+{"tool_call_id":"c1","operation":{"type":"execute","command":"npm publish"},"disposition":"user_choice","user_choice":"allow_once"}
+{"tool_call_id":"c2","operation":{"type":"execute","command":"make"},"disposition":"forwarded"}
+{"tool_call_id":"c2","operation":{"type":"execute","command":"make"},"disposition":"timed_out"}
+{"tool_call_id":"c3","operation":{"type":"execute","command":"ls"},"disposition":"shadow"}
+{"operation":{"type":"execute","command":"ls"},"disposition":"evaluated"}
+{"tool_call_id":"c3","operation":{"type":"execute","command":"ls"},"disposition":"auto"}
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            operations,
+            [
+                Operation::execute("npm publish"),
+                Operation::execute("make"),
+                Operation::execute("ls"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_lines() {
+        let err = read("\"ls\"\n{\"type\":\"execute\"}\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "line 2: missing `command`");
+
+        let err = read("{\"type\":\"launch\"}").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: unknown operation type `launch`");
+
+        assert!(read("not json").is_err());
+        assert!(read("42").is_err());
+    }
+
+    #[cfg(feature = "acp")]
+    #[test]
+    fn test_permission_requests() {
+        let operations = read(
+            r#"{"sessionId":"s1","toolCall":{"toolCallId":"c1","kind":"execute","rawInput":{"command":"npm test"}},"options":[]}"#,
+        )
+        .unwrap();
+        assert_eq!(operations, [Operation::execute("npm test")]);
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use crate::matcher::Matcher;
 use crate::operation::Operation;
 use crate::outcome::Outcome;
@@ -5,10 +7,20 @@ use crate::outcome::Outcome;
 /// A rule pairs a matcher (predicate) with an outcome (allow or deny).
 ///
 /// When a rule's matcher matches an operation, the rule's outcome is returned.
+///
+/// Rules can optionally carry metadata: a stable id for audit trails, a
+/// human-readable description, and a message explaining the decision to the
+/// user or agent (typically used for denials). This metadata is reported in
+/// evaluation results (see [`Ruleset::explain`](crate::Ruleset::explain)).
 #[derive(Debug, Clone)]
 pub struct Rule {
     matcher: Matcher,
     outcome: Outcome,
+    id: Option<String>,
+    description: Option<String>,
+    message: Option<String>,
+    alternative: Option<String>,
+    modes: Vec<String>,
 }
 
 impl Rule {
@@ -25,7 +37,98 @@ impl Rule {
     /// );
     /// ```
     pub fn new(matcher: Matcher, outcome: Outcome) -> Self {
-        Self { matcher, outcome }
+        Self {
+            matcher,
+            outcome,
+            id: None,
+            description: None,
+            message: None,
+            alternative: None,
+            modes: Vec::new(),
+        }
+    }
+
+    /// Sets a stable identifier for this rule.
+    ///
+    /// Ids are reported in evaluation results and should stay the same across
+    /// policy revisions so that audit records remain comparable.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use toolcap::{Rule, Matcher, Outcome};
+    ///
+    /// let rule = Rule::new(
+    ///     Matcher::command("git").with_subcommand("push").with_flag("--force"),
+    ///     Outcome::Deny,
+    /// )
+    /// .with_id("no-force-push")
+    /// .with_description("Block force-pushes")
+    /// .with_message("force-push is blocked by team policy; open a PR instead");
+    ///
+    /// assert_eq!(rule.id(), Some("no-force-push"));
+    /// ```
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets a human-readable description of what this rule covers.
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Sets the message shown to the user or agent when this rule decides an operation.
+    ///
+    /// This is most useful for denials, to explain why the operation was
+    /// blocked and what to do instead.
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// Sets a suggested alternative to what this rule denies, e.g. "use
+    /// `cargo clean` to remove build output".
+    ///
+    /// Proxies pass the alternative on to the agent when they deny a request,
+    /// so it can change course instead of retrying.
+    pub fn with_alternative(mut self, alternative: impl Into<String>) -> Self {
+        self.alternative = Some(alternative.into());
+        self
+    }
+
+    /// Restricts this rule to sessions in one of `modes`, such as `plan`.
+    ///
+    /// A ruleset only consults the rule while its current mode (see
+    /// [`Ruleset::with_mode`](crate::Ruleset::with_mode)) is one of these.
+    /// Rules without modes apply in every mode.
+    ///
+    /// While the mode is unknown, the rule is skipped, even if it denies:
+    /// a proxy only learns a session's mode once the agent reports it, and
+    /// agents without modes never do. Rules that must always hold, such as
+    /// denying destructive commands, shouldn't be restricted to a mode.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use toolcap::{Matcher, Operation, OperationKind, Outcome, Rule, Ruleset};
+    ///
+    /// let ruleset = Ruleset::new(vec![
+    ///     Rule::new(Matcher::kind(OperationKind::Edit), Outcome::Deny).with_modes(["plan"]),
+    /// ]);
+    /// let edit = Operation::Edit { path: "src/lib.rs".into() };
+    ///
+    /// assert_eq!(ruleset.clone().with_mode("plan").evaluate(&edit), Outcome::Deny);
+    /// assert_eq!(ruleset.with_mode("code").evaluate(&edit), Outcome::Unknown);
+    /// ```
+    pub fn with_modes<I, S>(mut self, modes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.modes = modes.into_iter().map(Into::into).collect();
+        self
     }
 
     /// Returns the matcher for this rule.
@@ -38,9 +141,42 @@ impl Rule {
         self.outcome
     }
 
+    /// Returns the rule's id, if set.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// Returns the rule's description, if set.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Returns the rule's message, if set.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// Returns the rule's suggested alternative, if set.
+    pub fn alternative(&self) -> Option<&str> {
+        self.alternative.as_deref()
+    }
+
+    /// Returns the modes this rule is restricted to, or an empty slice if it
+    /// applies in every mode.
+    pub fn modes(&self) -> &[String] {
+        &self.modes
+    }
+
+    /// Returns `true` if this rule applies in `mode`, or while the mode is
+    /// unknown if `mode` is `None`.
+    pub fn applies_in_mode(&self, mode: Option<&str>) -> bool {
+        self.modes.is_empty() || mode.is_some_and(|mode| self.modes.iter().any(|m| m == mode))
+    }
+
     /// Evaluates this rule against an operation.
     ///
-    /// Returns `Some(outcome)` if the rule matches, `None` otherwise.
+    /// Returns `Some(outcome)` if the rule matches, `None` otherwise. The
+    /// rule's modes aren't checked.
     pub fn evaluate(&self, operation: &Operation) -> Option<Outcome> {
         if self.matcher.matches(operation) {
             Some(self.outcome)
@@ -50,6 +186,25 @@ impl Rule {
     }
 }
 
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(id) = &self.id {
+            write!(f, "[{}] ", id)?;
+        }
+        write!(f, "{} {}", self.outcome, self.matcher)?;
+        if !self.modes.is_empty() {
+            write!(f, " in {} mode", self.modes.join(" or "))?;
+        }
+        if let Some(description) = &self.description {
+            write!(f, " ({})", description)?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,4 +232,53 @@ mod tests {
         let op = Operation::execute("sudo rm -rf /");
         assert_eq!(rule.evaluate(&op), Some(Outcome::Deny));
     }
+
+    #[test]
+    fn test_rule_metadata() {
+        let rule = Rule::new(Matcher::command("sudo"), Outcome::Deny)
+            .with_id("no-sudo")
+            .with_description("Block privilege escalation")
+            .with_message("sudo is not available to agents");
+
+        assert_eq!(rule.id(), Some("no-sudo"));
+        assert_eq!(rule.description(), Some("Block privilege escalation"));
+        assert_eq!(rule.message(), Some("sudo is not available to agents"));
+    }
+
+    #[test]
+    fn test_rule_modes() {
+        let rule = Rule::new(Matcher::command("sudo"), Outcome::Deny);
+        assert!(rule.modes().is_empty());
+        assert!(rule.applies_in_mode(None));
+        assert!(rule.applies_in_mode(Some("code")));
+
+        let rule = rule.with_modes(["plan", "ask"]);
+        assert!(rule.applies_in_mode(Some("ask")));
+        assert!(!rule.applies_in_mode(Some("code")));
+        assert!(!rule.applies_in_mode(None));
+        assert_eq!(rule.to_string(), "deny sudo in plan or ask mode");
+    }
+
+    #[test]
+    fn test_rule_display() {
+        let rule = Rule::new(
+            Matcher::command("git").with_subcommand("status"),
+            Outcome::Allow,
+        );
+        assert_eq!(rule.to_string(), "allow git status");
+
+        let rule = Rule::new(
+            Matcher::command("git")
+                .with_subcommand("push")
+                .with_flag("--force"),
+            Outcome::Deny,
+        )
+        .with_id("no-force-push")
+        .with_description("Block force-pushes")
+        .with_message("open a PR instead");
+        assert_eq!(
+            rule.to_string(),
+            "[no-force-push] deny git push --force (Block force-pushes): open a PR instead"
+        );
+    }
 }
@@ -1,18 +1,38 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::audit::{DecisionLayer, DecisionObserver, DecisionRecord, Disposition};
+use crate::decision::{CompoundKind, Decision, DecisionNode, MatchedRule, UnknownReason};
+use crate::history::History;
 use crate::operation::{ExecuteOperation, Operation};
 use crate::outcome::Outcome;
 use crate::rule::Rule;
-use crate::shell::{parse, ParsedCommand, ShellAst};
+use crate::shell::{ParsedCommand, ShellAst, parse};
+use crate::strategy::{CombiningStrategy, OperatorPolicy, combine_compound};
+use crate::workdir::{ShellState, WorkingDir};
 
 /// A ruleset is an ordered list of rules.
 ///
-/// When evaluating an operation, the ruleset checks each rule in order until
-/// one matches. The first matching rule determines the outcome.
+/// When evaluating an operation, the ruleset's [`CombiningStrategy`] picks
+/// which of the matching rules determines the outcome. By default the first
+/// matching rule wins.
 ///
 /// If no rule matches, the ruleset returns `Outcome::Unknown`, signaling that
 /// the operation should be escalated to the user.
 #[derive(Debug, Clone)]
 pub struct Ruleset {
     rules: Vec<Rule>,
+    /// The number of leading rules.
+    leading: usize,
+    /// The index of the first fallback rule.
+    fallback_start: usize,
+    strategy: CombiningStrategy,
+    operator_policies: HashMap<CompoundKind, OperatorPolicy>,
+    observers: Vec<Arc<dyn DecisionObserver>>,
+    /// The mode of the session being evaluated, if known.
+    mode: Option<String>,
+    /// The operations performed earlier in the session being evaluated.
+    history: History,
 }
 
 impl Ruleset {
@@ -42,14 +62,158 @@ impl Ruleset {
     /// ]);
     /// ```
     pub fn new(rules: Vec<Rule>) -> Self {
-        Self { rules }
+        Self {
+            leading: 0,
+            fallback_start: rules.len(),
+            rules,
+            strategy: CombiningStrategy::default(),
+            operator_policies: HashMap::new(),
+            observers: Vec::new(),
+            mode: None,
+            history: History::default(),
+        }
     }
 
     /// Creates an empty ruleset.
     ///
     /// An empty ruleset returns `Outcome::Unknown` for all operations.
     pub fn empty() -> Self {
-        Self { rules: Vec::new() }
+        Self::new(Vec::new())
+    }
+
+    /// Sets the strategy used to combine the outcomes of matching rules.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use toolcap::{CombiningStrategy, Matcher, Operation, Outcome, Rule, Ruleset};
+    ///
+    /// // With most-specific matching, rule order doesn't matter.
+    /// let ruleset = Ruleset::new(vec![
+    ///     Rule::new(Matcher::command("git").with_subcommand("push"), Outcome::Allow),
+    ///     Rule::new(
+    ///         Matcher::command("git")
+    ///             .with_subcommand("push")
+    ///             .with_flag("--force"),
+    ///         Outcome::Deny,
+    ///     ),
+    /// ])
+    /// .with_strategy(CombiningStrategy::MostSpecific);
+    ///
+    /// assert_eq!(ruleset.evaluate(&Operation::execute("git push --force")), Outcome::Deny);
+    /// ```
+    pub fn with_strategy(mut self, strategy: CombiningStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets how the parts of compound commands joined by `kind` are combined.
+    ///
+    /// Operators without a policy use [`OperatorPolicy::AllParts`].
+    pub fn with_operator_policy(mut self, kind: CompoundKind, policy: OperatorPolicy) -> Self {
+        self.operator_policies.insert(kind, policy);
+        self
+    }
+
+    /// Adds rules that are consulted before the existing ones.
+    ///
+    /// This is useful for layering narrower, temporary rules (such as
+    /// decisions remembered for a session) over a base ruleset. Leading rules
+    /// take precedence over the existing rules, except that they never
+    /// override an operation the existing rules deny. Rule indices in
+    /// decisions refer to the combined list.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use toolcap::{Matcher, Operation, Outcome, Rule, Ruleset};
+    ///
+    /// let base = Ruleset::new(vec![Rule::new(
+    ///     Matcher::command("npm").with_subcommand("publish"),
+    ///     Outcome::Deny,
+    /// )]);
+    /// let layered = base.with_leading_rules([Rule::new(Matcher::command("npm"), Outcome::Allow)]);
+    ///
+    /// assert_eq!(layered.evaluate(&Operation::execute("npm test")), Outcome::Allow);
+    /// assert_eq!(layered.evaluate(&Operation::execute("npm publish")), Outcome::Deny);
+    /// ```
+    pub fn with_leading_rules(mut self, rules: impl IntoIterator<Item = Rule>) -> Self {
+        let len = self.rules.len();
+        self.rules.splice(0..0, rules);
+        self.leading += self.rules.len() - len;
+        self.fallback_start += self.rules.len() - len;
+        self
+    }
+
+    /// Adds rules that are only consulted for operations the existing rules
+    /// don't match.
+    ///
+    /// Unlike rules appended to the list, fallback rules never override the
+    /// existing ones, whatever the combining strategy. This is useful for
+    /// layering lower-priority rules (such as permissions learned in earlier
+    /// sessions) under a base ruleset. Rule indices in decisions refer to the
+    /// combined list.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use toolcap::{CombiningStrategy, Matcher, Operation, Outcome, Rule, Ruleset};
+    ///
+    /// let base = Ruleset::new(vec![Rule::new(
+    ///     Matcher::command("npm").with_subcommand("publish"),
+    ///     Outcome::Deny,
+    /// )])
+    /// .with_strategy(CombiningStrategy::MostSpecific);
+    /// let layered = base.with_fallback_rules([
+    ///     Rule::new(Matcher::exact("npm publish"), Outcome::Allow),
+    ///     Rule::new(Matcher::exact("npm test"), Outcome::Allow),
+    /// ]);
+    ///
+    /// assert_eq!(layered.evaluate(&Operation::execute("npm publish")), Outcome::Deny);
+    /// assert_eq!(layered.evaluate(&Operation::execute("npm test")), Outcome::Allow);
+    /// ```
+    pub fn with_fallback_rules(mut self, rules: impl IntoIterator<Item = Rule>) -> Self {
+        self.rules.extend(rules);
+        self
+    }
+
+    /// Sets the mode of the session being evaluated, such as `plan` or `code`.
+    ///
+    /// Rules restricted to other modes (see [`Rule::with_modes`]) are skipped.
+    /// Without a mode, only rules that apply in every mode are consulted, so
+    /// a mode-restricted deny doesn't apply until the mode is known.
+    pub fn with_mode(mut self, mode: impl Into<String>) -> Self {
+        self.mode = Some(mode.into());
+        self
+    }
+
+    /// Sets the operations performed earlier in the session being evaluated,
+    /// for rules that depend on them (see [`Matcher::after`](crate::Matcher::after)).
+    ///
+    /// Without a history, such rules never match.
+    pub fn with_history(mut self, history: History) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Adds an observer that is told about every decision the ruleset makes.
+    ///
+    /// Observers see every call to [`evaluate`](Self::evaluate) and
+    /// [`explain`](Self::explain), including those made on behalf of an ACP
+    /// proxy. Clones of the ruleset share its observers.
+    pub fn with_observer(mut self, observer: impl DecisionObserver + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// Gives this ruleset the observers of `other`, unless it has observers
+    /// of its own.
+    #[cfg(feature = "acp")]
+    pub(crate) fn inherit_observers(mut self, other: &Ruleset) -> Self {
+        if self.observers.is_empty() {
+            self.observers = other.observers.clone();
+        }
+        self
     }
 
     /// Returns the rules in this ruleset.
@@ -57,12 +221,39 @@ impl Ruleset {
         &self.rules
     }
 
+    /// Returns the mode of the session being evaluated, if set.
+    pub fn mode(&self) -> Option<&str> {
+        self.mode.as_deref()
+    }
+
+    /// Returns the operations performed earlier in the session being
+    /// evaluated.
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Returns the strategy used to combine the outcomes of matching rules.
+    pub fn strategy(&self) -> CombiningStrategy {
+        self.strategy
+    }
+
+    /// Returns the policy for compound commands joined by `kind`.
+    pub fn operator_policy(&self, kind: CompoundKind) -> OperatorPolicy {
+        self.operator_policies
+            .get(&kind)
+            .copied()
+            .unwrap_or_default()
+    }
+
     /// Evaluates an operation against this ruleset.
     ///
     /// For execute operations, this parses the command and evaluates compound
     /// commands (pipelines, logical operators) recursively. A compound command
     /// is allowed only if all parts are allowed, denied if any part is denied,
-    /// and unknown if any part is unknown and none are denied.
+    /// and unknown if any part is unknown and none are denied, whatever the
+    /// [`CombiningStrategy`]. Pipelines are additionally checked against
+    /// pipeline-shape rules (see [`Matcher::pipes_into`](crate::Matcher::pipes_into)),
+    /// and each operator's [`OperatorPolicy`] decides which parts count.
     ///
     /// # Example
     ///
@@ -77,105 +268,342 @@ impl Ruleset {
     /// assert_eq!(ruleset.evaluate(&Operation::execute("rm -rf /")), Outcome::Unknown);
     /// ```
     pub fn evaluate(&self, operation: &Operation) -> Outcome {
+        self.explain(operation).outcome()
+    }
+
+    /// Evaluates an operation and explains how the outcome was reached.
+    ///
+    /// The returned [`Decision`] carries the overall outcome along with a tree
+    /// containing each sub-command of a compound shell command, its outcome,
+    /// the index of the rule that matched it, and for `Unknown` outcomes, the
+    /// reason no rule could decide.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use toolcap::{Ruleset, Rule, Matcher, Operation, Outcome, DecisionNode};
+    ///
+    /// let ruleset = Ruleset::new(vec![
+    ///     Rule::new(Matcher::command("find"), Outcome::Allow),
+    ///     Rule::new(Matcher::command("xargs"), Outcome::Deny),
+    /// ]);
+    ///
+    /// let decision = ruleset.explain(&Operation::execute("find . | xargs rm"));
+    /// assert_eq!(decision.outcome(), Outcome::Deny);
+    ///
+    /// let denied: Vec<_> = decision
+    ///     .leaves()
+    ///     .filter(|leaf| leaf.outcome() == Outcome::Deny)
+    ///     .collect();
+    /// assert!(matches!(denied[0], DecisionNode::Leaf { rule: Some(rule), .. } if rule.index() == 1));
+    /// ```
+    pub fn explain(&self, operation: &Operation) -> Decision {
+        let decision = self.explain_unobserved(operation);
+        if !self.observers.is_empty() {
+            let layers = self.layers(&decision);
+            let record = DecisionRecord::new(
+                operation.clone(),
+                decision.clone(),
+                layers,
+                Disposition::Evaluated,
+            );
+            for observer in &self.observers {
+                observer.observe(&record);
+            }
+        }
+        decision
+    }
+
+    /// Like [`explain`](Self::explain), without telling the observers, for
+    /// queries and for decisions that were already recorded.
+    pub(crate) fn explain_unobserved(&self, operation: &Operation) -> Decision {
+        Decision::new(self.explain_operation(operation))
+    }
+
+    /// Returns the layer of each of a decision's deciding rules.
+    pub(crate) fn layers(&self, decision: &Decision) -> Vec<DecisionLayer> {
+        decision
+            .deciding_rules()
+            .map(|rule| match rule.index() {
+                index if index < self.leading => DecisionLayer::Leading,
+                index if index < self.fallback_start => DecisionLayer::Ruleset,
+                _ => DecisionLayer::Fallback,
+            })
+            .collect()
+    }
+
+    /// Explains any operation, returning its decision tree.
+    fn explain_operation(&self, operation: &Operation) -> DecisionNode {
         match operation {
-            Operation::Execute(exec_op) => self.evaluate_execute(exec_op),
-            _ => self.evaluate_simple(operation),
+            Operation::Execute(exec_op) => self.explain_execute(exec_op),
+            // An empty batch has nothing to allow, so don't let it through.
+            Operation::Batch(operations) if operations.is_empty() => DecisionNode::Leaf {
+                operation: operation.clone(),
+                outcome: Outcome::Unknown,
+                rule: None,
+                reason: Some(UnknownReason::NoMatchingRule),
+            },
+            Operation::Batch(operations) => {
+                let children = operations
+                    .iter()
+                    .map(|operation| self.explain_operation(operation))
+                    .collect();
+                self.compound_node(CompoundKind::Batch, children, None)
+            }
+            _ => self.explain_simple(operation),
         }
     }
 
-    /// Evaluates a non-execute operation using simple rule matching.
-    fn evaluate_simple(&self, operation: &Operation) -> Outcome {
-        for rule in &self.rules {
-            if let Some(outcome) = rule.evaluate(operation) {
-                return outcome;
+    /// Explains a non-execute operation using simple rule matching.
+    fn explain_simple(&self, operation: &Operation) -> DecisionNode {
+        match self.select_rule(operation, |_| true) {
+            Some(index) => {
+                let rule = &self.rules[index];
+                DecisionNode::Leaf {
+                    operation: operation.clone(),
+                    outcome: rule.outcome(),
+                    rule: Some(MatchedRule::new(index, rule)),
+                    reason: None,
+                }
             }
+            None => DecisionNode::Leaf {
+                operation: operation.clone(),
+                outcome: Outcome::Unknown,
+                rule: None,
+                reason: Some(UnknownReason::NoMatchingRule),
+            },
         }
-        Outcome::Unknown
     }
 
-    /// Evaluates an execute operation, handling compound commands.
-    fn evaluate_execute(&self, exec_op: &ExecuteOperation) -> Outcome {
+    /// Explains an execute operation, handling compound commands.
+    fn explain_execute(&self, exec_op: &ExecuteOperation) -> DecisionNode {
         // Try to parse the command
         match parse(exec_op.raw()) {
-            Ok(ast) => self.evaluate_ast(&ast, exec_op.working_dir()),
-            Err(_) => {
+            Ok(ast) => {
+                let mut state = ShellState::new(exec_op.working_dir());
+                self.explain_ast(&ast, &mut state)
+            }
+            Err(err) => {
                 // If parsing fails (e.g., unsupported shell features),
                 // return Unknown to escalate to the user
-                Outcome::Unknown
+                DecisionNode::Leaf {
+                    operation: Operation::Execute(exec_op.clone()),
+                    outcome: Outcome::Unknown,
+                    rule: None,
+                    reason: Some(UnknownReason::ParseFailure(err)),
+                }
             }
         }
     }
 
-    /// Recursively evaluates a shell AST node.
+    /// Recursively explains a shell AST node.
     ///
-    /// The evaluation semantics are:
-    /// - Compound allowed only if all parts allowed
-    /// - Compound denied if any part denied
-    /// - Compound unknown if any part unknown and none denied
-    fn evaluate_ast(&self, ast: &ShellAst, working_dir: Option<&std::path::PathBuf>) -> Outcome {
+    /// `state` tracks the working directory as `cd`, `pushd` and `popd` run.
+    /// `&&` and `;` pass it from each command to the next (assuming each
+    /// command succeeds), `||` merges the directories its branches may leave
+    /// behind, and pipeline stages and subshells don't affect it.
+    fn explain_ast(&self, ast: &ShellAst, state: &mut ShellState) -> DecisionNode {
         match ast {
-            ShellAst::Simple(cmd) => self.evaluate_simple_command(cmd, working_dir),
+            ShellAst::Simple(cmd) => {
+                let node = self.explain_simple_command(cmd, state.working_dir());
+                state.apply(cmd);
+                node
+            }
 
-            ShellAst::Pipeline(cmds)
-            | ShellAst::And(cmds)
-            | ShellAst::Or(cmds)
-            | ShellAst::Sequence(cmds) => self.evaluate_compound(cmds, working_dir),
+            ShellAst::Pipeline(cmds) => {
+                // Each stage runs in its own subshell
+                let children = cmds
+                    .iter()
+                    .map(|cmd| self.explain_ast(cmd, &mut state.clone()))
+                    .collect();
+                let rule = self.select_pipeline_rule(ast, state.working_dir());
+                self.compound_node(CompoundKind::Pipeline, children, rule)
+            }
+
+            ShellAst::And(cmds) => {
+                let children = cmds
+                    .iter()
+                    .map(|cmd| self.explain_ast(cmd, state))
+                    .collect();
+                self.compound_node(CompoundKind::And, children, None)
+            }
 
-            ShellAst::Unsupported(_) => {
+            ShellAst::Sequence(cmds) => {
+                let children = cmds
+                    .iter()
+                    .map(|cmd| self.explain_ast(cmd, state))
+                    .collect();
+                self.compound_node(CompoundKind::Sequence, children, None)
+            }
+
+            ShellAst::Or(cmds) => {
+                // Each part runs only if the previous one failed. A failed
+                // simple command changes nothing, but a compound one may have
+                // changed directory before failing, so the next part may start
+                // in either directory.
+                let mut children = Vec::new();
+                let mut finished: Option<ShellState> = None;
+                for cmd in cmds {
+                    let before = state.clone();
+                    children.push(self.explain_ast(cmd, state));
+                    finished = Some(match finished {
+                        Some(other) => other.merge(state.clone()),
+                        None => state.clone(),
+                    });
+                    *state = match cmd {
+                        ShellAst::Simple(_) => before,
+                        _ => before.merge(state.clone()),
+                    };
+                }
+                if let Some(finished) = finished {
+                    *state = finished;
+                }
+                self.compound_node(CompoundKind::Or, children, None)
+            }
+
+            ShellAst::Subshell(inner) => {
+                let child = self.explain_ast(inner, &mut state.clone());
+                self.compound_node(CompoundKind::Subshell, vec![child], None)
+            }
+
+            ShellAst::Unsupported(raw) => {
                 // Unsupported constructs should escalate to the user
-                Outcome::Unknown
+                let mut exec_op = ExecuteOperation::new(raw.clone());
+                if let Some(dir) = state.working_dir().path() {
+                    exec_op = exec_op.with_working_dir(dir.clone());
+                }
+                DecisionNode::Leaf {
+                    operation: Operation::Execute(exec_op),
+                    outcome: Outcome::Unknown,
+                    rule: None,
+                    reason: Some(UnknownReason::Unsupported(raw.clone())),
+                }
             }
         }
     }
 
-    /// Evaluates a compound command (pipeline, &&, ||, ;).
+    /// Builds the node for a compound command (pipeline, &&, ||, ;, subshell).
     ///
-    /// Semantics:
+    /// Semantics, whatever the strategy:
     /// - If any component is Deny, the compound is Deny
     /// - If all components are Allow, the compound is Allow
     /// - Otherwise (any Unknown, none Deny), the compound is Unknown
-    fn evaluate_compound(
+    ///
+    /// The operator's policy picks which components count, and a matching
+    /// pipeline-shape rule counts as one more component.
+    fn compound_node(
         &self,
-        cmds: &[ShellAst],
-        working_dir: Option<&std::path::PathBuf>,
-    ) -> Outcome {
-        let mut has_unknown = false;
-
-        for cmd in cmds {
-            match self.evaluate_ast(cmd, working_dir) {
-                Outcome::Deny => return Outcome::Deny,
-                Outcome::Unknown => has_unknown = true,
-                Outcome::Allow => {}
-            }
+        kind: CompoundKind,
+        children: Vec<DecisionNode>,
+        rule: Option<MatchedRule>,
+    ) -> DecisionNode {
+        let outcomes = self
+            .operator_policy(kind)
+            .relevant_outcomes(children.iter().map(DecisionNode::outcome))
+            .chain(rule.iter().map(|r| self.rules[r.index()].outcome()));
+        let outcome = combine_compound(outcomes);
+
+        DecisionNode::Compound {
+            kind,
+            outcome,
+            rule,
+            children,
         }
+    }
 
-        if has_unknown {
-            Outcome::Unknown
-        } else {
-            Outcome::Allow
+    /// Selects the pipeline-shape rule, if any, that matches a whole pipeline.
+    fn select_pipeline_rule(
+        &self,
+        pipeline: &ShellAst,
+        working_dir: &WorkingDir,
+    ) -> Option<MatchedRule> {
+        let mut exec_op = ExecuteOperation::new(pipeline.to_string());
+        if let Some(dir) = working_dir.path() {
+            exec_op = exec_op.with_working_dir(dir.clone());
         }
+
+        self.select_rule(&Operation::Execute(exec_op), |rule| {
+            rule.matcher().is_pipeline_shape()
+        })
+        .map(|index| MatchedRule::new(index, &self.rules[index]))
+    }
+
+    /// Selects the rule that decides `operation` among the rules accepted by
+    /// `filter` that apply in the current mode, consulting fallback rules
+    /// only if no other rule matches.
+    fn select_rule(&self, operation: &Operation, filter: impl Fn(&Rule) -> bool) -> Option<usize> {
+        let mode = self.mode.as_deref();
+        let filter = |rule: &Rule| rule.applies_in_mode(mode) && filter(rule);
+        let (rules, fallback_rules) = self.rules.split_at(self.fallback_start);
+        let rules = rules.iter().enumerate();
+        let fallback_rules = fallback_rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| (self.fallback_start + index, rule));
+
+        // Leading rules can't override what the ruleset's own rules deny
+        if self.leading > 0
+            && let Some(index) = self.strategy.select(
+                rules
+                    .clone()
+                    .skip(self.leading)
+                    .filter(|(_, rule)| filter(rule)),
+                operation,
+                &self.history,
+            )
+            && self.rules[index].outcome() == Outcome::Deny
+        {
+            return Some(index);
+        }
+
+        self.strategy
+            .select(
+                rules.filter(|(_, rule)| filter(rule)),
+                operation,
+                &self.history,
+            )
+            .or_else(|| {
+                self.strategy.select(
+                    fallback_rules.filter(|(_, rule)| filter(rule)),
+                    operation,
+                    &self.history,
+                )
+            })
     }
 
-    /// Evaluates a simple (non-compound) command.
-    fn evaluate_simple_command(
+    /// Explains a simple (non-compound) command.
+    ///
+    /// If an earlier `cd` left the working directory unresolved, directory
+    /// scoped rules can't be checked. The command is then only decided if it's
+    /// denied; otherwise a directory-scoped deny rule might have applied, so
+    /// it's escalated as unknown.
+    fn explain_simple_command(
         &self,
         cmd: &ParsedCommand,
-        working_dir: Option<&std::path::PathBuf>,
-    ) -> Outcome {
-        // Create an ExecuteOperation from the parsed command
-        let raw = if cmd.args.is_empty() {
-            cmd.name.clone()
-        } else {
-            format!("{} {}", cmd.name, cmd.args.join(" "))
-        };
-
-        let mut exec_op = ExecuteOperation::new(raw);
-        if let Some(dir) = working_dir {
-            exec_op = exec_op.with_working_dir(dir.clone());
+        working_dir: &WorkingDir,
+    ) -> DecisionNode {
+        let operation = Operation::Execute(ExecuteOperation::from_parsed(cmd, working_dir.path()));
+        let node = self.explain_simple(&operation);
+
+        if *working_dir == WorkingDir::Unresolved
+            && node.outcome() != Outcome::Deny
+            && self.has_directory_scoped_deny()
+        {
+            return DecisionNode::Leaf {
+                operation,
+                outcome: Outcome::Unknown,
+                rule: None,
+                reason: Some(UnknownReason::UnresolvedWorkingDirectory),
+            };
         }
+        node
+    }
 
-        let operation = Operation::Execute(exec_op);
-        self.evaluate_simple(&operation)
+    /// Returns `true` if any deny rule is scoped to a directory.
+    fn has_directory_scoped_deny(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.outcome() == Outcome::Deny && rule.matcher().is_directory_scoped())
     }
 }
 
@@ -189,6 +617,7 @@ impl Default for Ruleset {
 mod tests {
     use super::*;
     use crate::matcher::Matcher;
+    use std::path::PathBuf;
 
     #[test]
     fn test_empty_ruleset() {
@@ -235,7 +664,10 @@ mod tests {
                     .with_flag("--force"),
                 Outcome::Deny,
             ),
-            Rule::new(Matcher::command("git").with_subcommand("push"), Outcome::Allow),
+            Rule::new(
+                Matcher::command("git").with_subcommand("push"),
+                Outcome::Allow,
+            ),
         ]);
 
         // Force push is denied
@@ -379,8 +811,14 @@ mod tests {
                 Rule::new(Matcher::command("cat"), Outcome::Allow),
                 Rule::new(Matcher::command("echo"), Outcome::Allow),
                 Rule::new(Matcher::command("make"), Outcome::Allow),
-                Rule::new(Matcher::command("cargo").with_subcommand("build"), Outcome::Allow),
-                Rule::new(Matcher::command("cargo").with_subcommand("test"), Outcome::Allow),
+                Rule::new(
+                    Matcher::command("cargo").with_subcommand("build"),
+                    Outcome::Allow,
+                ),
+                Rule::new(
+                    Matcher::command("cargo").with_subcommand("test"),
+                    Outcome::Allow,
+                ),
                 Rule::new(Matcher::command("rm").with_flag("-rf"), Outcome::Deny),
                 Rule::new(Matcher::command("sudo"), Outcome::Deny),
             ])
@@ -403,7 +841,9 @@ mod tests {
             let ruleset = test_ruleset();
             // Long pipeline with all allowed commands
             assert_eq!(
-                ruleset.evaluate(&Operation::execute("cat file | grep pattern | sort | uniq | head -10")),
+                ruleset.evaluate(&Operation::execute(
+                    "cat file | grep pattern | sort | uniq | head -10"
+                )),
                 Outcome::Allow
             );
         }
@@ -455,7 +895,9 @@ mod tests {
             let ruleset = test_ruleset();
             // Multiple chained commands all allowed
             assert_eq!(
-                ruleset.evaluate(&Operation::execute("cargo build && cargo test && echo done")),
+                ruleset.evaluate(&Operation::execute(
+                    "cargo build && cargo test && echo done"
+                )),
                 Outcome::Allow
             );
         }
@@ -559,7 +1001,9 @@ mod tests {
             let ruleset = test_ruleset();
             // Complex with one denied component
             assert_eq!(
-                ruleset.evaluate(&Operation::execute("find . | grep foo && sudo cat /etc/shadow")),
+                ruleset.evaluate(&Operation::execute(
+                    "find . | grep foo && sudo cat /etc/shadow"
+                )),
                 Outcome::Deny
             );
         }
@@ -569,7 +1013,9 @@ mod tests {
             let ruleset = test_ruleset();
             // Pipeline with OR
             assert_eq!(
-                ruleset.evaluate(&Operation::execute("cat file | grep pattern || echo not found")),
+                ruleset.evaluate(&Operation::execute(
+                    "cat file | grep pattern || echo not found"
+                )),
                 Outcome::Allow
             );
         }
@@ -635,9 +1081,684 @@ mod tests {
             ]);
 
             assert_eq!(
-                ruleset.evaluate(&Operation::execute("find . -name '*.ts' | xargs grep interface")),
+                ruleset.evaluate(&Operation::execute(
+                    "find . -name '*.ts' | xargs grep interface"
+                )),
+                Outcome::Allow
+            );
+        }
+    }
+
+    mod strategies {
+        use super::*;
+
+        fn git_rules() -> Vec<Rule> {
+            vec![
+                Rule::new(Matcher::command("git"), Outcome::Allow),
+                Rule::new(
+                    Matcher::command("git").with_subcommand("push"),
+                    Outcome::Deny,
+                ),
+                Rule::new(
+                    Matcher::command("git")
+                        .with_subcommand("push")
+                        .with_flag("--dry-run"),
+                    Outcome::Allow,
+                ),
+            ]
+        }
+
+        fn evaluate(strategy: CombiningStrategy, command: &str) -> Outcome {
+            Ruleset::new(git_rules())
+                .with_strategy(strategy)
+                .evaluate(&Operation::execute(command))
+        }
+
+        #[test]
+        fn test_default_is_first_match() {
+            assert_eq!(Ruleset::empty().strategy(), CombiningStrategy::FirstMatch);
+            assert_eq!(
+                evaluate(CombiningStrategy::FirstMatch, "git push"),
+                Outcome::Allow
+            );
+        }
+
+        #[test]
+        fn test_deny_overrides() {
+            let strategy = CombiningStrategy::DenyOverrides;
+            assert_eq!(evaluate(strategy, "git status"), Outcome::Allow);
+            assert_eq!(evaluate(strategy, "git push"), Outcome::Deny);
+            assert_eq!(evaluate(strategy, "git push --dry-run"), Outcome::Deny);
+        }
+
+        #[test]
+        fn test_allow_overrides() {
+            let strategy = CombiningStrategy::AllowOverrides;
+            assert_eq!(evaluate(strategy, "git push"), Outcome::Allow);
+
+            let ruleset = Ruleset::new(vec![
+                Rule::new(Matcher::command("sudo"), Outcome::Deny),
+                Rule::new(Matcher::command("ls"), Outcome::Allow),
+            ])
+            .with_strategy(strategy);
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("sudo ls")),
+                Outcome::Deny
+            );
+        }
+
+        #[test]
+        fn test_most_specific() {
+            let strategy = CombiningStrategy::MostSpecific;
+            assert_eq!(evaluate(strategy, "git status"), Outcome::Allow);
+            assert_eq!(evaluate(strategy, "git push origin"), Outcome::Deny);
+            assert_eq!(evaluate(strategy, "git push --dry-run"), Outcome::Allow);
+        }
+
+        #[test]
+        fn test_most_specific_ties_go_to_earlier_rule() {
+            let ruleset = Ruleset::new(vec![
+                Rule::new(Matcher::command("git"), Outcome::Deny),
+                Rule::new(Matcher::command("git"), Outcome::Allow),
+            ])
+            .with_strategy(CombiningStrategy::MostSpecific);
+
+            let decision = ruleset.explain(&Operation::execute("git status"));
+            assert_eq!(decision.outcome(), Outcome::Deny);
+            assert_eq!(decision.deciding_rules().next().unwrap().index(), 0);
+        }
+
+        #[test]
+        fn test_fallback_rules_never_override() {
+            for strategy in [
+                CombiningStrategy::FirstMatch,
+                CombiningStrategy::DenyOverrides,
+                CombiningStrategy::AllowOverrides,
+                CombiningStrategy::MostSpecific,
+            ] {
+                let ruleset = Ruleset::new(vec![
+                    Rule::new(
+                        Matcher::command("git").with_subcommand("push"),
+                        Outcome::Deny,
+                    ),
+                    Rule::new(
+                        Matcher::command("git").with_subcommand("status"),
+                        Outcome::Allow,
+                    ),
+                ])
+                .with_strategy(strategy)
+                .with_fallback_rules([
+                    Rule::new(Matcher::exact("git push origin"), Outcome::Allow),
+                    Rule::new(Matcher::command("cargo"), Outcome::Allow),
+                ])
+                .with_leading_rules([Rule::new(Matcher::exact("rm -r build"), Outcome::Allow)]);
+
+                assert_eq!(
+                    ruleset.evaluate(&Operation::execute("git push origin")),
+                    Outcome::Deny,
+                    "{}",
+                    strategy
+                );
+
+                let decision = ruleset.explain(&Operation::execute("git status && cargo test"));
+                assert_eq!(decision.outcome(), Outcome::Allow, "{}", strategy);
+                let indices: Vec<_> = decision.deciding_rules().map(|r| r.index()).collect();
+                assert_eq!(indices, vec![2, 4], "{}", strategy);
+            }
+        }
+
+        #[test]
+        fn test_explain_reports_selected_rule() {
+            let ruleset = Ruleset::new(git_rules()).with_strategy(CombiningStrategy::DenyOverrides);
+            let decision = ruleset.explain(&Operation::execute("git push --dry-run"));
+            assert_eq!(decision.deciding_rules().next().unwrap().index(), 1);
+        }
+
+        #[test]
+        fn test_compound_aggregation_follows_strategy() {
+            let rules = vec![
+                Rule::new(Matcher::command("make"), Outcome::Allow),
+                Rule::new(Matcher::command("sudo"), Outcome::Deny),
+            ];
+            let op = Operation::execute("sudo make install && npm publish");
+
+            let first_match = Ruleset::new(rules.clone());
+            assert_eq!(first_match.evaluate(&op), Outcome::Deny);
+
+            // An undecided part doesn't let the denied part through
+            let allow_overrides =
+                Ruleset::new(rules).with_strategy(CombiningStrategy::AllowOverrides);
+            assert_eq!(allow_overrides.evaluate(&op), Outcome::Deny);
+            assert_eq!(
+                allow_overrides.evaluate(&Operation::execute("make && make")),
+                Outcome::Allow
+            );
+        }
+    }
+
+    mod operators {
+        use super::*;
+
+        fn test_ruleset() -> Ruleset {
+            Ruleset::new(vec![
+                Rule::new(
+                    Matcher::pipes_into(Matcher::or(vec![
+                        Matcher::command("sh"),
+                        Matcher::command("python"),
+                    ])),
+                    Outcome::Deny,
+                )
+                .with_id("no-pipe-to-interpreter"),
+                Rule::new(Matcher::command("curl"), Outcome::Allow),
+                Rule::new(Matcher::command("cat"), Outcome::Allow),
+                Rule::new(Matcher::command("grep"), Outcome::Allow),
+                Rule::new(Matcher::command("sh"), Outcome::Allow),
+                Rule::new(Matcher::command("python"), Outcome::Allow),
+                Rule::new(Matcher::command("rm"), Outcome::Deny),
+            ])
+        }
+
+        #[test]
+        fn test_sequence_is_evaluated() {
+            let ruleset = test_ruleset();
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("cat x; rm -rf /")),
+                Outcome::Deny
+            );
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("cat x\nnpm publish")),
+                Outcome::Unknown
+            );
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("cat x; grep y z")),
+                Outcome::Allow
+            );
+        }
+
+        #[test]
+        fn test_pipe_into_interpreter_denied() {
+            let ruleset = test_ruleset();
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("sh install.sh")),
+                Outcome::Allow
+            );
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("curl https://example.com/install | sh")),
+                Outcome::Deny
+            );
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("cat x | python")),
+                Outcome::Deny
+            );
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("cat x | grep y")),
+                Outcome::Allow
+            );
+        }
+
+        #[test]
+        fn test_pipe_into_interpreter_nested() {
+            let ruleset = test_ruleset();
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("cat x && curl y | sh")),
+                Outcome::Deny
+            );
+        }
+
+        #[test]
+        fn test_pipeline_rule_in_decision() {
+            let decision = test_ruleset().explain(&Operation::execute("cat x | sh"));
+            match decision.tree() {
+                DecisionNode::Compound {
+                    kind: CompoundKind::Pipeline,
+                    rule: Some(rule),
+                    ..
+                } => assert_eq!(rule.id(), Some("no-pipe-to-interpreter")),
+                other => panic!("Expected pipeline with rule, got {:?}", other),
+            }
+            let ids: Vec<_> = decision.deciding_rules().map(|r| r.index()).collect();
+            assert_eq!(ids, vec![0]);
+        }
+
+        #[test]
+        fn test_or_fallbacks_strict_by_default() {
+            let ruleset = test_ruleset();
+            assert_eq!(
+                ruleset.operator_policy(CompoundKind::Or),
+                OperatorPolicy::AllParts
+            );
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("grep foo x || true")),
+                Outcome::Unknown
+            );
+        }
+
+        #[test]
+        fn test_or_ignore_unknown_fallbacks() {
+            let ruleset = test_ruleset()
+                .with_operator_policy(CompoundKind::Or, OperatorPolicy::IgnoreUnknownFallbacks);
+
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("grep foo x || true")),
+                Outcome::Allow
+            );
+            // Denied fallbacks still deny
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("grep foo x || rm -rf /")),
+                Outcome::Deny
+            );
+            // The first part must still be decided
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("npm test || true")),
+                Outcome::Unknown
+            );
+            // Other operators are unaffected
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("grep foo x && true")),
+                Outcome::Unknown
+            );
+        }
+    }
+
+    mod modes {
+        use super::*;
+        use crate::operation::OperationKind;
+
+        /// Plan mode is read-only: edits and commands other than `ls` and
+        /// `git status` are denied there, and allowed elsewhere.
+        fn mode_ruleset() -> Ruleset {
+            Ruleset::new(vec![
+                Rule::new(Matcher::command("ls"), Outcome::Allow),
+                Rule::new(
+                    Matcher::command("git").with_subcommand("status"),
+                    Outcome::Allow,
+                ),
+                Rule::new(
+                    Matcher::or(vec![
+                        Matcher::kind(OperationKind::Edit),
+                        Matcher::any_execute(),
+                    ]),
+                    Outcome::Deny,
+                )
+                .with_modes(["plan"]),
+                Rule::new(Matcher::kind(OperationKind::Edit), Outcome::Allow),
+                Rule::new(Matcher::command("cargo"), Outcome::Allow),
+            ])
+        }
+
+        #[test]
+        fn test_rules_apply_in_their_modes() {
+            let edit = Operation::Edit {
+                path: "src/lib.rs".into(),
+            };
+            let plan = mode_ruleset().with_mode("plan");
+            assert_eq!(plan.mode(), Some("plan"));
+            assert_eq!(plan.evaluate(&edit), Outcome::Deny);
+            assert_eq!(
+                plan.evaluate(&Operation::execute("cargo build")),
+                Outcome::Deny
+            );
+            assert_eq!(
+                plan.evaluate(&Operation::execute("ls && git status")),
+                Outcome::Allow
+            );
+
+            let code = mode_ruleset().with_mode("code");
+            assert_eq!(code.evaluate(&edit), Outcome::Allow);
+            assert_eq!(
+                code.evaluate(&Operation::execute("cargo build")),
+                Outcome::Allow
+            );
+
+            // Without a mode, mode-restricted rules are skipped
+            assert_eq!(mode_ruleset().mode(), None);
+            assert_eq!(mode_ruleset().evaluate(&edit), Outcome::Allow);
+        }
+
+        #[test]
+        fn test_mode_survives_layering() {
+            let ruleset = mode_ruleset()
+                .with_mode("plan")
+                .with_leading_rules([Rule::new(Matcher::exact("cargo test"), Outcome::Allow)]);
+            let decision = ruleset.explain(&Operation::execute("cargo test && cargo build"));
+            assert_eq!(decision.outcome(), Outcome::Deny);
+            let rules = decision.leaves().map(|leaf| match leaf {
+                DecisionNode::Leaf { rule, .. } => rule.as_ref().map(MatchedRule::index),
+                _ => None,
+            });
+            // The grant doesn't override plan mode's deny
+            assert_eq!(rules.collect::<Vec<_>>(), [Some(3), Some(3)]);
+
+            let ruleset = mode_ruleset()
+                .with_mode("code")
+                .with_leading_rules([Rule::new(Matcher::exact("cargo test"), Outcome::Allow)]);
+            let decision = ruleset.explain(&Operation::execute("cargo test"));
+            assert_eq!(decision.deciding_rules().next().unwrap().index(), 0);
+        }
+    }
+
+    mod working_directory {
+        use super::*;
+
+        /// Allows `rm` only inside the crate's `src` directory, and denies
+        /// `git push` from `examples`.
+        fn scoped_ruleset() -> (Ruleset, PathBuf) {
+            let root = std::env::current_dir().unwrap();
+            let ruleset = Ruleset::new(vec![
+                Rule::new(
+                    Matcher::and(vec![
+                        Matcher::command("git").with_subcommand("push"),
+                        Matcher::within_directory(root.join("examples")),
+                    ]),
+                    Outcome::Deny,
+                ),
+                Rule::new(
+                    Matcher::and(vec![
+                        Matcher::command("rm"),
+                        Matcher::within_directory(root.join("src")),
+                    ]),
+                    Outcome::Allow,
+                ),
+                Rule::new(Matcher::command("cd"), Outcome::Allow),
+                Rule::new(Matcher::command("pushd"), Outcome::Allow),
+                Rule::new(Matcher::command("popd"), Outcome::Allow),
+                Rule::new(Matcher::command("ls"), Outcome::Allow),
+                Rule::new(Matcher::command("git"), Outcome::Allow),
+            ]);
+            (ruleset, root)
+        }
+
+        fn evaluate_in(ruleset: &Ruleset, command: &str, dir: &PathBuf) -> Outcome {
+            ruleset.evaluate(&Operation::execute_in(command, dir))
+        }
+
+        #[test]
+        fn test_cd_changes_directory_for_later_commands() {
+            let (ruleset, root) = scoped_ruleset();
+            assert_eq!(evaluate_in(&ruleset, "rm foo.rs", &root), Outcome::Unknown);
+            assert_eq!(
+                evaluate_in(&ruleset, "cd src && rm foo.rs", &root),
                 Outcome::Allow
             );
+            assert_eq!(
+                evaluate_in(&ruleset, "cd src; rm foo.rs", &root),
+                Outcome::Allow
+            );
+            assert_eq!(
+                evaluate_in(&ruleset, "rm foo.rs", &root.join("src")),
+                Outcome::Allow
+            );
+            assert_eq!(
+                evaluate_in(&ruleset, "cd .. && rm foo.rs", &root.join("src")),
+                Outcome::Unknown
+            );
+        }
+
+        #[test]
+        fn test_cd_to_scoped_deny() {
+            let (ruleset, root) = scoped_ruleset();
+            assert_eq!(evaluate_in(&ruleset, "git push", &root), Outcome::Allow);
+            assert_eq!(
+                evaluate_in(&ruleset, "cd examples && git push", &root),
+                Outcome::Deny
+            );
+            assert_eq!(
+                evaluate_in(&ruleset, "cd src && cd ../examples && git push", &root),
+                Outcome::Deny
+            );
+        }
+
+        #[test]
+        fn test_pushd_popd() {
+            let (ruleset, root) = scoped_ruleset();
+            assert_eq!(
+                evaluate_in(&ruleset, "pushd src && rm foo.rs", &root),
+                Outcome::Allow
+            );
+            assert_eq!(
+                evaluate_in(&ruleset, "pushd src && popd && rm foo.rs", &root),
+                Outcome::Unknown
+            );
+        }
+
+        #[test]
+        fn test_subshell_scoping() {
+            let (ruleset, root) = scoped_ruleset();
+            assert_eq!(
+                evaluate_in(&ruleset, "(cd src && rm foo.rs)", &root),
+                Outcome::Allow
+            );
+            assert_eq!(
+                evaluate_in(&ruleset, "(cd src); rm foo.rs", &root),
+                Outcome::Unknown
+            );
+            assert_eq!(
+                evaluate_in(&ruleset, "(cd src && ls) && cd examples && git push", &root),
+                Outcome::Deny
+            );
+        }
+
+        #[test]
+        fn test_pipeline_stages_do_not_change_directory() {
+            let (ruleset, root) = scoped_ruleset();
+            assert_eq!(
+                evaluate_in(&ruleset, "cd src | ls; rm foo.rs", &root),
+                Outcome::Unknown
+            );
+        }
+
+        #[test]
+        fn test_or_with_divergent_directories_fails_closed() {
+            let (ruleset, root) = scoped_ruleset();
+            assert_eq!(
+                evaluate_in(&ruleset, "cd src || cd examples; git push", &root),
+                Outcome::Unknown
+            );
+            assert_eq!(
+                evaluate_in(&ruleset, "cd src || cd src; rm foo.rs", &root),
+                Outcome::Allow
+            );
+            // A compound left-hand side may change directory before failing
+            assert_eq!(
+                evaluate_in(&ruleset, "cd src && ls || rm foo.rs", &root),
+                Outcome::Unknown
+            );
+        }
+
+        #[test]
+        fn test_unresolvable_cd_fails_closed() {
+            let (ruleset, root) = scoped_ruleset();
+
+            // A scoped deny rule might apply, so allowed commands escalate
+            let decision = ruleset.explain(&Operation::execute_in("cd ~/other && git push", &root));
+            assert_eq!(decision.outcome(), Outcome::Unknown);
+            assert!(decision.leaves().any(|leaf| matches!(
+                leaf,
+                DecisionNode::Leaf {
+                    reason: Some(UnknownReason::UnresolvedWorkingDirectory),
+                    ..
+                }
+            )));
+
+            // Scoped allow rules don't match
+            assert_eq!(
+                evaluate_in(&ruleset, "cd - && rm foo.rs", &root),
+                Outcome::Unknown
+            );
+        }
+
+        #[test]
+        fn test_unresolvable_cd_without_scoped_denies() {
+            let ruleset = Ruleset::new(vec![
+                Rule::new(Matcher::command("cd"), Outcome::Allow),
+                Rule::new(Matcher::command("ls"), Outcome::Allow),
+            ]);
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute("cd ~ && ls")),
+                Outcome::Allow
+            );
+        }
+    }
+
+    mod explain {
+        use super::*;
+        use crate::shell::ParseError;
+
+        fn test_ruleset() -> Ruleset {
+            Ruleset::new(vec![
+                Rule::new(Matcher::command("find"), Outcome::Allow),
+                Rule::new(Matcher::command("grep"), Outcome::Allow),
+                Rule::new(Matcher::command("rm"), Outcome::Deny),
+            ])
+        }
+
+        fn leaf_summary(node: &DecisionNode) -> (String, Outcome, Option<usize>) {
+            match node {
+                DecisionNode::Leaf {
+                    operation: Operation::Execute(exec_op),
+                    outcome,
+                    rule,
+                    ..
+                } => (
+                    exec_op.raw().to_string(),
+                    *outcome,
+                    rule.as_ref().map(|r| r.index()),
+                ),
+                other => panic!("Expected execute leaf, got {:?}", other),
+            }
+        }
+
+        #[test]
+        fn test_explain_simple_command() {
+            let decision = test_ruleset().explain(&Operation::execute("grep foo"));
+            assert_eq!(decision.outcome(), Outcome::Allow);
+            assert_eq!(
+                leaf_summary(decision.tree()),
+                ("grep foo".to_string(), Outcome::Allow, Some(1))
+            );
+        }
+
+        #[test]
+        fn test_explain_pipeline_identifies_denied_part() {
+            let decision = test_ruleset().explain(&Operation::execute("find . | rm foo"));
+            assert_eq!(decision.outcome(), Outcome::Deny);
+
+            match decision.tree() {
+                DecisionNode::Compound { kind, children, .. } => {
+                    assert_eq!(*kind, CompoundKind::Pipeline);
+                    assert_eq!(children.len(), 2);
+                }
+                other => panic!("Expected compound, got {:?}", other),
+            }
+
+            let leaves: Vec<_> = decision.leaves().map(leaf_summary).collect();
+            assert_eq!(
+                leaves,
+                vec![
+                    ("find .".to_string(), Outcome::Allow, Some(0)),
+                    ("rm foo".to_string(), Outcome::Deny, Some(2)),
+                ]
+            );
+        }
+
+        #[test]
+        fn test_explain_no_matching_rule() {
+            let decision = test_ruleset().explain(&Operation::execute("npm install"));
+            assert!(matches!(
+                decision.tree(),
+                DecisionNode::Leaf {
+                    reason: Some(UnknownReason::NoMatchingRule),
+                    ..
+                }
+            ));
+        }
+
+        #[test]
+        fn test_explain_parse_failure() {
+            let decision = test_ruleset().explain(&Operation::execute("echo $HOME"));
+            assert_eq!(decision.outcome(), Outcome::Unknown);
+            assert!(matches!(
+                decision.tree(),
+                DecisionNode::Leaf {
+                    reason: Some(UnknownReason::ParseFailure(ParseError::Unsupported(_))),
+                    ..
+                }
+            ));
+        }
+
+        #[test]
+        fn test_explain_non_execute_operation() {
+            let decision = test_ruleset().explain(&Operation::Read {
+                path: "/etc/passwd".into(),
+            });
+            assert_eq!(decision.outcome(), Outcome::Unknown);
+            assert_eq!(decision.leaves().count(), 1);
+        }
+
+        #[test]
+        fn test_explain_reports_rule_metadata() {
+            let ruleset = Ruleset::new(vec![
+                Rule::new(
+                    Matcher::command("git")
+                        .with_subcommand("push")
+                        .with_flag("--force"),
+                    Outcome::Deny,
+                )
+                .with_id("no-force-push")
+                .with_description("Block force-pushes")
+                .with_message("force-push is blocked by team policy; open a PR instead"),
+            ]);
+
+            let decision = ruleset.explain(&Operation::execute("git push --force"));
+            let rule = decision.deciding_rules().next().unwrap();
+            assert_eq!(rule.id(), Some("no-force-push"));
+            assert_eq!(rule.description(), Some("Block force-pushes"));
+            assert_eq!(
+                rule.message(),
+                Some("force-push is blocked by team policy; open a PR instead")
+            );
+        }
+
+        #[test]
+        fn test_explain_batch() {
+            let ruleset = test_ruleset();
+            let batch = Operation::Batch(vec![
+                Operation::execute("find ."),
+                Operation::execute("grep foo | rm bar"),
+            ]);
+            let decision = ruleset.explain(&batch);
+            assert_eq!(decision.outcome(), Outcome::Deny);
+            let DecisionNode::Compound { kind, children, .. } = decision.tree() else {
+                panic!("Expected compound root");
+            };
+            assert_eq!(*kind, CompoundKind::Batch);
+            assert_eq!(children.len(), 2);
+
+            let allowed = Operation::Batch(vec![
+                Operation::execute("find ."),
+                Operation::execute("grep foo"),
+            ]);
+            assert_eq!(ruleset.evaluate(&allowed), Outcome::Allow);
+
+            // An empty batch is never allowed.
+            assert_eq!(
+                ruleset.evaluate(&Operation::Batch(vec![])),
+                Outcome::Unknown
+            );
+        }
+
+        #[test]
+        fn test_explain_agrees_with_evaluate() {
+            let ruleset = test_ruleset();
+            for command in [
+                "find . | grep foo",
+                "find . && rm -rf /",
+                "grep foo || npm test",
+                "echo $(whoami)",
+            ] {
+                let op = Operation::execute(command);
+                assert_eq!(ruleset.explain(&op).outcome(), ruleset.evaluate(&op));
+            }
         }
     }
 }
@@ -12,6 +12,8 @@
 //! - **Logical AND**: `make && make test`
 //! - **Logical OR**: `test -f foo || touch foo`
 //! - **Chained operators**: `a && b && c`, `a || b || c`, `a && b || c`
+//! - **Sequences**: `cd build; make`, or commands on separate lines
+//! - **Subshells**: `(cd /tmp && ls)`
 //! - **Quoted strings**: `git commit -m "hello world"`, `find . -name '*.rs'`
 //! - **Escaped characters**: `echo hello\ world`
 //! - **Glob patterns**: `ls *.rs` (preserved as literal strings)
@@ -27,7 +29,6 @@
 //! - **Parameter expansion**: `$VAR`, `${VAR}`, `${VAR:-default}`
 //! - **Arithmetic expansion**: `$((1 + 2))`
 //! - **Compound commands**: `if`/`then`/`fi`, `for`/`do`/`done`, `while`/`do`/`done`, `case`/`esac`
-//! - **Subshells**: `(cd /tmp && ls)`
 //! - **Brace groups**: `{ echo a; echo b; }`
 //! - **Function definitions**: `foo() { ... }`
 //! - **Process substitution**: `<(cmd)`, `>(cmd)`
@@ -119,6 +120,10 @@ pub enum ShellAst {
     /// Sequential execution (cmd1; cmd2).
     Sequence(Vec<ShellAst>),
 
+    /// Commands run in a subshell (`( ... )`), whose environment changes
+    /// (such as `cd`) don't affect the enclosing shell.
+    Subshell(Box<ShellAst>),
+
     /// A command that couldn't be fully parsed but has a raw representation.
     /// This is used for complex constructs like subshells, command substitution, etc.
     Unsupported(String),
@@ -159,6 +164,9 @@ impl std::error::Error for ParseError {}
 ///
 /// let ast = parse("make && make test").unwrap();
 /// assert!(matches!(ast, ShellAst::And(_)));
+///
+/// let ast = parse("make; make test").unwrap();
+/// assert!(matches!(ast, ShellAst::Sequence(_)));
 /// ```
 pub fn parse(input: &str) -> Result<ShellAst, ParseError> {
     let trimmed = input.trim();
@@ -169,11 +177,19 @@ pub fn parse(input: &str) -> Result<ShellAst, ParseError> {
     let lexer = Lexer::new(trimmed.chars());
     let mut parser = DefaultParser::new(lexer);
 
-    match parser.complete_command() {
-        Ok(Some(cmd)) => convert_top_level_command(&cmd),
-        Ok(None) => Err(ParseError::Empty),
-        Err(e) => Err(ParseError::Syntax(format!("{:?}", e))),
+    // Each complete command ends at a `;`, `&` or newline, so keep parsing
+    // until the input is exhausted; stopping early would silently drop the
+    // rest of the command line.
+    let mut commands = Vec::new();
+    loop {
+        match parser.complete_command() {
+            Ok(Some(cmd)) => commands.push(cmd),
+            Ok(None) => break,
+            Err(e) => return Err(ParseError::Syntax(format!("{:?}", e))),
+        }
     }
+
+    convert_command_list(&commands)
 }
 
 // Type aliases for conch-parser's default AST types
@@ -187,6 +203,20 @@ type DefaultWord = ast::DefaultWord;
 type DefaultComplexWord = ast::DefaultComplexWord;
 type DefaultSimpleWord = ast::DefaultSimpleWord;
 
+/// Converts a list of commands, as found at the top level or in a subshell.
+fn convert_command_list(commands: &[DefaultTopLevelCommand]) -> Result<ShellAst, ParseError> {
+    let mut converted: Vec<_> = commands
+        .iter()
+        .map(convert_top_level_command)
+        .collect::<Result<_, _>>()?;
+
+    match converted.len() {
+        0 => Err(ParseError::Empty),
+        1 => Ok(converted.remove(0)),
+        _ => Ok(ShellAst::Sequence(converted)),
+    }
+}
+
 fn convert_top_level_command(cmd: &DefaultTopLevelCommand) -> Result<ShellAst, ParseError> {
     match cmd {
         ast::TopLevelCommand(cmd) => convert_command(cmd),
@@ -243,8 +273,7 @@ fn convert_listable_command(cmd: &DefaultListableCommand) -> Result<ShellAst, Pa
     match cmd {
         ast::ListableCommand::Single(pipeable) => convert_pipeable_command(pipeable),
         ast::ListableCommand::Pipe(_, cmds) => {
-            let converted: Result<Vec<_>, _> =
-                cmds.iter().map(|c| convert_pipeable_command(c)).collect();
+            let converted: Result<Vec<_>, _> = cmds.iter().map(convert_pipeable_command).collect();
             Ok(ShellAst::Pipeline(converted?))
         }
     }
@@ -253,9 +282,15 @@ fn convert_listable_command(cmd: &DefaultListableCommand) -> Result<ShellAst, Pa
 fn convert_pipeable_command(cmd: &DefaultPipeableCommand) -> Result<ShellAst, ParseError> {
     match cmd {
         ast::PipeableCommand::Simple(simple) => convert_simple_command(simple),
-        ast::PipeableCommand::Compound(_) => {
-            Err(ParseError::Unsupported("compound commands (if/for/while/case/brace groups)".into()))
-        }
+        ast::PipeableCommand::Compound(compound) => match &compound.kind {
+            // Redirections on the subshell don't affect command matching
+            ast::CompoundCommandKind::Subshell(commands) => Ok(ShellAst::Subshell(Box::new(
+                convert_command_list(commands)?,
+            ))),
+            _ => Err(ParseError::Unsupported(
+                "compound commands (if/for/while/case/brace groups)".into(),
+            )),
+        },
         ast::PipeableCommand::FunctionDef(_, _) => {
             Err(ParseError::Unsupported("function definitions".into()))
         }
@@ -296,7 +331,7 @@ fn convert_complex_word(word: &DefaultComplexWord) -> Result<String, ParseError>
     match word {
         ast::ComplexWord::Single(w) => convert_word(w),
         ast::ComplexWord::Concat(words) => {
-            let parts: Result<Vec<_>, _> = words.iter().map(|w| convert_word(w)).collect();
+            let parts: Result<Vec<_>, _> = words.iter().map(convert_word).collect();
             Ok(parts?.join(""))
         }
     }
@@ -306,7 +341,7 @@ fn convert_word(word: &DefaultWord) -> Result<String, ParseError> {
     match word {
         ast::Word::Simple(simple) => convert_simple_word(simple),
         ast::Word::DoubleQuoted(words) => {
-            let parts: Result<Vec<_>, _> = words.iter().map(|w| convert_simple_word(w)).collect();
+            let parts: Result<Vec<_>, _> = words.iter().map(convert_simple_word).collect();
             Ok(parts?.join(""))
         }
         ast::Word::SingleQuoted(s) => Ok(s.clone()),
@@ -350,6 +385,7 @@ impl ShellAst {
                     cmd.collect_commands(out);
                 }
             }
+            ShellAst::Subshell(inner) => inner.collect_commands(out),
             ShellAst::Unsupported(_) => {}
         }
     }
@@ -368,6 +404,54 @@ impl ShellAst {
     }
 }
 
+/// Renders the AST as a shell command line.
+///
+/// Arguments are quoted where needed, so the output parses back to the same
+/// AST.
+impl std::fmt::Display for ShellAst {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShellAst::Simple(cmd) => {
+                write!(f, "{}", quote(&cmd.name))?;
+                for arg in &cmd.args {
+                    write!(f, " {}", quote(arg))?;
+                }
+                Ok(())
+            }
+            ShellAst::Pipeline(cmds) => write_separated(f, cmds, " | "),
+            ShellAst::And(cmds) => write_separated(f, cmds, " && "),
+            ShellAst::Or(cmds) => write_separated(f, cmds, " || "),
+            ShellAst::Sequence(cmds) => write_separated(f, cmds, "; "),
+            ShellAst::Subshell(inner) => write!(f, "({})", inner),
+            ShellAst::Unsupported(raw) => write!(f, "{}", raw),
+        }
+    }
+}
+
+fn write_separated(
+    f: &mut std::fmt::Formatter<'_>,
+    cmds: &[ShellAst],
+    separator: &str,
+) -> std::fmt::Result {
+    for (i, cmd) in cmds.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", separator)?;
+        }
+        write!(f, "{}", cmd)?;
+    }
+    Ok(())
+}
+
+/// Quotes a word for the shell if it contains anything but safe characters.
+pub(crate) fn quote(word: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-./=:,+@%*?[]~".contains(c);
+    if !word.is_empty() && word.chars().all(is_safe) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -509,7 +593,10 @@ mod tests {
         match ast {
             ShellAst::Pipeline(cmds) => {
                 assert_eq!(cmds.len(), 5);
-                let names: Vec<_> = cmds.iter().map(|c| c.as_simple().unwrap().name.as_str()).collect();
+                let names: Vec<_> = cmds
+                    .iter()
+                    .map(|c| c.as_simple().unwrap().name.as_str())
+                    .collect();
                 assert_eq!(names, vec!["cat", "grep", "sort", "uniq", "head"]);
             }
             _ => panic!("Expected pipeline"),
@@ -640,6 +727,40 @@ mod tests {
         assert!(cmd.has_flag("--"));
     }
 
+    #[test]
+    fn test_parse_sequence() {
+        let ast = parse("cd build; make").unwrap();
+        match ast {
+            ShellAst::Sequence(cmds) => {
+                assert_eq!(cmds.len(), 2);
+                assert_eq!(cmds[0].as_simple().unwrap().name, "cd");
+                assert_eq!(cmds[1].as_simple().unwrap().name, "make");
+            }
+            _ => panic!("Expected sequence"),
+        }
+    }
+
+    #[test]
+    fn test_parse_sequence_keeps_every_command() {
+        // Regression test: commands after the first separator used to be dropped
+        for input in [
+            "ls; rm -rf /",
+            "ls\nrm -rf /",
+            "ls & rm -rf /",
+            "ls && pwd; rm -rf /",
+        ] {
+            let ast = parse(input).unwrap();
+            let names: Vec<_> = ast.commands().map(|c| c.name.as_str()).collect();
+            assert_eq!(names.last(), Some(&"rm"), "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_trailing_separator() {
+        let ast = parse("make;").unwrap();
+        assert_eq!(ast.as_simple().unwrap().name, "make");
+    }
+
     #[test]
     fn test_if_statement_unsupported() {
         let result = parse("if true; then echo yes; fi");
@@ -654,9 +775,43 @@ mod tests {
     }
 
     #[test]
-    fn test_subshell_unsupported() {
-        let result = parse("(cd /tmp && ls)");
-        assert!(matches!(result, Err(ParseError::Unsupported(_))));
+    fn test_parse_subshell() {
+        let ast = parse("(cd /tmp && ls)").unwrap();
+        match ast {
+            ShellAst::Subshell(inner) => assert!(matches!(*inner, ShellAst::And(_))),
+            _ => panic!("Expected subshell"),
+        }
+    }
+
+    #[test]
+    fn test_parse_subshell_in_sequence() {
+        let ast = parse("(cd /tmp; ls) && pwd").unwrap();
+        let names: Vec<_> = ast.commands().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["cd", "ls", "pwd"]);
+    }
+
+    #[test]
+    fn test_parse_subshell_in_pipeline() {
+        let ast = parse("curl x | (sh)").unwrap();
+        match ast {
+            ShellAst::Pipeline(stages) => {
+                assert!(matches!(&stages[1], ShellAst::Subshell(_)));
+            }
+            _ => panic!("Expected pipeline"),
+        }
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for input in [
+            "git status",
+            "find . -name '*.rs' | xargs grep 'fn main'",
+            "cd /tmp && (make; make test) || echo failed",
+            r"git commit -m 'it'\''s done'",
+        ] {
+            let ast = parse(input).unwrap();
+            assert_eq!(parse(&ast.to_string()).unwrap(), ast, "input: {:?}", input);
+        }
     }
 
     #[test]
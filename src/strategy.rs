@@ -0,0 +1,212 @@
+use std::fmt;
+
+use crate::history::History;
+use crate::operation::Operation;
+use crate::outcome::Outcome;
+use crate::rule::Rule;
+
+/// How a ruleset combines the outcomes of the rules that match an operation.
+///
+/// The strategy decides which matching rule wins for a single operation, and
+/// how the outcomes of the parts of a compound shell command are aggregated.
+/// In every strategy a compound command is denied if any of its parts is
+/// denied, and allowed only if all of its parts are allowed.
+///
+/// # Example
+///
+/// ```
+/// use toolcap::{CombiningStrategy, Matcher, Operation, Outcome, Rule, Ruleset};
+///
+/// let ruleset = Ruleset::new(vec![
+///     Rule::new(Matcher::command("git"), Outcome::Allow),
+///     Rule::new(Matcher::command("git").with_subcommand("push"), Outcome::Deny),
+/// ])
+/// .with_strategy(CombiningStrategy::DenyOverrides);
+///
+/// assert_eq!(ruleset.evaluate(&Operation::execute("git status")), Outcome::Allow);
+/// assert_eq!(ruleset.evaluate(&Operation::execute("git push")), Outcome::Deny);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CombiningStrategy {
+    /// The first matching rule, in order, decides.
+    #[default]
+    FirstMatch,
+
+    /// Any matching deny rule wins; otherwise the first matching allow rule decides.
+    DenyOverrides,
+
+    /// Any matching allow rule wins; otherwise the first matching deny rule decides.
+    AllowOverrides,
+
+    /// The matching rule with the most specific matcher decides.
+    ///
+    /// Specificity is defined by [`Matcher::specificity`](crate::Matcher::specificity).
+    /// Ties are broken by rule order.
+    MostSpecific,
+}
+
+impl CombiningStrategy {
+    /// Selects the rule that decides `operation` from a list of candidate
+    /// rules and their indices, returning the index of the selected rule.
+    pub(crate) fn select<'a>(
+        &self,
+        rules: impl Iterator<Item = (usize, &'a Rule)>,
+        operation: &Operation,
+        history: &History,
+    ) -> Option<usize> {
+        let mut matching =
+            rules.filter(|(_, rule)| rule.matcher().matches_with_history(operation, history));
+
+        match self {
+            CombiningStrategy::FirstMatch => matching.next().map(|(index, _)| index),
+            CombiningStrategy::DenyOverrides => select_overriding(matching, Outcome::Deny),
+            CombiningStrategy::AllowOverrides => select_overriding(matching, Outcome::Allow),
+            CombiningStrategy::MostSpecific => matching
+                .max_by_key(|(index, rule)| {
+                    // Prefer higher specificity, then lower index.
+                    (rule.matcher().specificity(), std::cmp::Reverse(*index))
+                })
+                .map(|(index, _)| index),
+        }
+    }
+}
+
+impl fmt::Display for CombiningStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CombiningStrategy::FirstMatch => write!(f, "first-match"),
+            CombiningStrategy::DenyOverrides => write!(f, "deny-overrides"),
+            CombiningStrategy::AllowOverrides => write!(f, "allow-overrides"),
+            CombiningStrategy::MostSpecific => write!(f, "most-specific"),
+        }
+    }
+}
+
+/// How the parts of a compound command joined by a particular operator are
+/// combined.
+///
+/// Policies are set per operator with
+/// [`Ruleset::with_operator_policy`](crate::Ruleset::with_operator_policy).
+///
+/// # Example
+///
+/// ```
+/// use toolcap::{CompoundKind, Matcher, Operation, OperatorPolicy, Outcome, Rule, Ruleset};
+///
+/// let ruleset = Ruleset::new(vec![Rule::new(Matcher::command("grep"), Outcome::Allow)])
+///     .with_operator_policy(CompoundKind::Or, OperatorPolicy::IgnoreUnknownFallbacks);
+///
+/// // `true` has no rule, but it only runs as a fallback.
+/// assert_eq!(ruleset.evaluate(&Operation::execute("grep foo x || true")), Outcome::Allow);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum OperatorPolicy {
+    /// Every part is evaluated as if it will run.
+    #[default]
+    AllParts,
+
+    /// Parts after the first that no rule decides are ignored.
+    ///
+    /// This is meant for `||`, whose right-hand sides often only provide a
+    /// fallback (`... || true`). Denied parts still deny the whole command,
+    /// and the first part must still be decided.
+    IgnoreUnknownFallbacks,
+}
+
+impl OperatorPolicy {
+    /// Returns the outcomes of the parts that count towards the compound outcome.
+    pub(crate) fn relevant_outcomes(
+        self,
+        outcomes: impl IntoIterator<Item = Outcome>,
+    ) -> impl Iterator<Item = Outcome> {
+        outcomes
+            .into_iter()
+            .enumerate()
+            .filter_map(move |(i, outcome)| match self {
+                OperatorPolicy::IgnoreUnknownFallbacks if i > 0 && outcome == Outcome::Unknown => {
+                    None
+                }
+                _ => Some(outcome),
+            })
+    }
+}
+
+/// Aggregates the outcomes of the parts of a compound command.
+///
+/// A denied part denies the whole command whatever the strategy: a part no
+/// rule decides must not let a denied part be approved along with it.
+pub(crate) fn combine_compound(outcomes: impl IntoIterator<Item = Outcome>) -> Outcome {
+    let (mut any_deny, mut any_unknown) = (false, false);
+    for outcome in outcomes {
+        match outcome {
+            Outcome::Allow => {}
+            Outcome::Deny => any_deny = true,
+            Outcome::Unknown => any_unknown = true,
+        }
+    }
+
+    if any_deny {
+        Outcome::Deny
+    } else if any_unknown {
+        Outcome::Unknown
+    } else {
+        Outcome::Allow
+    }
+}
+/// Returns the first matching rule with the `preferred` outcome, or else the
+/// first matching rule.
+fn select_overriding<'a>(
+    matching: impl Iterator<Item = (usize, &'a Rule)>,
+    preferred: Outcome,
+) -> Option<usize> {
+    let mut first = None;
+    for (index, rule) in matching {
+        if rule.outcome() == preferred {
+            return Some(index);
+        }
+        first.get_or_insert(index);
+    }
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combine_compound() {
+        use Outcome::*;
+
+        assert_eq!(combine_compound([Allow, Allow]), Allow);
+        assert_eq!(combine_compound([Allow, Unknown]), Unknown);
+        assert_eq!(combine_compound([Allow, Deny]), Deny);
+        assert_eq!(combine_compound([Unknown, Deny]), Deny);
+    }
+
+    #[test]
+    fn test_operator_policy() {
+        use Outcome::*;
+
+        let outcomes = [Allow, Unknown, Deny];
+        let all: Vec<_> = OperatorPolicy::AllParts
+            .relevant_outcomes(outcomes)
+            .collect();
+        assert_eq!(all, vec![Allow, Unknown, Deny]);
+
+        let fallbacks: Vec<_> = OperatorPolicy::IgnoreUnknownFallbacks
+            .relevant_outcomes(outcomes)
+            .collect();
+        assert_eq!(fallbacks, vec![Allow, Deny]);
+
+        let first_unknown: Vec<_> = OperatorPolicy::IgnoreUnknownFallbacks
+            .relevant_outcomes([Unknown, Allow])
+            .collect();
+        assert_eq!(first_unknown, vec![Unknown, Allow]);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(CombiningStrategy::default().to_string(), "first-match");
+        assert_eq!(CombiningStrategy::MostSpecific.to_string(), "most-specific");
+    }
+}
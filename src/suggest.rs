@@ -0,0 +1,383 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::decision::{Decision, DecisionNode, UnknownReason};
+use crate::matcher::Matcher;
+use crate::operation::{ExecuteOperation, Operation};
+use crate::outcome::Outcome;
+use crate::rule::Rule;
+
+/// How broadly a suggested rule applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Generality {
+    /// The exact command, e.g. `cargo test -p foo --lib`.
+    Exact,
+    /// The command's subcommand with any arguments, e.g. `cargo test`.
+    Subcommand,
+    /// Any use of the command, e.g. `cargo`.
+    Command,
+}
+
+impl fmt::Display for Generality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Generality::Exact => write!(f, "exact"),
+            Generality::Subcommand => write!(f, "subcommand"),
+            Generality::Command => write!(f, "command"),
+        }
+    }
+}
+
+/// A candidate rule that would allow a command no rule decided.
+///
+/// Created by [`RuleSuggester::suggest`].
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    command: String,
+    generality: Generality,
+    rule: Rule,
+    label: String,
+}
+
+impl Suggestion {
+    /// Returns the command the rule was suggested for.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Returns how broadly the rule applies.
+    pub fn generality(&self) -> Generality {
+        self.generality
+    }
+
+    /// Returns the suggested rule.
+    pub fn rule(&self) -> &Rule {
+        &self.rule
+    }
+
+    /// Consumes the suggestion, returning the rule.
+    pub fn into_rule(self) -> Rule {
+        self.rule
+    }
+}
+
+impl fmt::Display for Suggestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
+/// Proposes rules for the commands a ruleset couldn't decide.
+///
+/// Each undecided command gets suggestions at several levels of
+/// [`Generality`], from the exact command to any use of the command, so the
+/// user can pick how much to allow. Generalized rules never cover a dangerous
+/// flag (such as `--force`): they exclude every dangerous flag, and a command
+/// that uses one is only suggested exactly.
+///
+/// # Example
+///
+/// ```
+/// use toolcap::{Matcher, Operation, Outcome, Rule, RuleSuggester, Ruleset};
+///
+/// let ruleset = Ruleset::new(vec![Rule::new(Matcher::command("ls"), Outcome::Allow)]);
+/// let decision = ruleset.explain(&Operation::execute("ls && cargo test -p foo --lib"));
+///
+/// let suggestions = RuleSuggester::new().suggest(&decision);
+/// let labels: Vec<_> = suggestions.iter().map(|s| s.to_string()).collect();
+/// assert_eq!(labels, [
+///     "exactly `cargo test -p foo --lib`",
+///     "`cargo test` with any arguments except dangerous flags",
+///     "any `cargo` command except dangerous flags",
+/// ]);
+///
+/// let rule = suggestions[1].rule();
+/// assert!(rule.matcher().matches(&Operation::execute("cargo test --release")));
+/// assert!(!rule.matcher().matches(&Operation::execute("cargo test --force")));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RuleSuggester {
+    dangerous_flags: Vec<String>,
+}
+
+impl RuleSuggester {
+    /// Flags that are treated as dangerous by default.
+    ///
+    /// Flags are found in any form (see [`ExecuteOperation::uses_flag`]), so
+    /// `-f` also covers `-Rf` and `--force` also covers `--force=true`.
+    pub const DANGEROUS_FLAGS: &[&str] = &[
+        "-f",
+        "--force",
+        "--force-with-lease",
+        "-r",
+        "-R",
+        "--recursive",
+        "--hard",
+        "--no-verify",
+        "-D",
+        "--delete",
+        "--prune",
+    ];
+
+    /// Creates a suggester with the default [`DANGEROUS_FLAGS`](Self::DANGEROUS_FLAGS).
+    pub fn new() -> Self {
+        Self {
+            dangerous_flags: Self::DANGEROUS_FLAGS.iter().map(|&f| f.into()).collect(),
+        }
+    }
+
+    /// Sets the flags that generalized rules must not cover.
+    pub fn with_dangerous_flags<I, S>(mut self, flags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.dangerous_flags = flags.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Returns the flags that generalized rules must not cover.
+    pub fn dangerous_flags(&self) -> &[String] {
+        &self.dangerous_flags
+    }
+
+    /// Suggests rules for the undecided commands in a decision.
+    ///
+    /// For a compound command, each command that no rule matched gets its own
+    /// suggestions, in the order the commands appear. Suggestions for a
+    /// command are ordered from the most specific to the most general.
+    /// Operations other than commands get no suggestions.
+    pub fn suggest(&self, decision: &Decision) -> Vec<Suggestion> {
+        let mut seen = HashSet::new();
+        decision
+            .leaves()
+            .filter_map(|leaf| match leaf {
+                DecisionNode::Leaf {
+                    operation: Operation::Execute(exec_op),
+                    reason: Some(UnknownReason::NoMatchingRule),
+                    ..
+                } => Some(exec_op),
+                _ => None,
+            })
+            .filter(|exec_op| seen.insert(exec_op.raw()))
+            .flat_map(|exec_op| self.suggest_command(exec_op))
+            .collect()
+    }
+
+    /// Suggests rules for a single command.
+    fn suggest_command(&self, exec_op: &ExecuteOperation) -> Vec<Suggestion> {
+        let Some(name) = exec_op.command_name() else {
+            return Vec::new();
+        };
+        let command = exec_op.raw();
+        let suggestion = |generality, matcher, label| Suggestion {
+            command: command.to_string(),
+            generality,
+            rule: Rule::new(matcher, Outcome::Allow)
+                .with_description(format!("Suggested for `{}`", command)),
+            label,
+        };
+
+        let mut suggestions = vec![suggestion(
+            Generality::Exact,
+            Matcher::exact(command),
+            format!("exactly `{}`", command),
+        )];
+        if self.has_dangerous_flag(exec_op) {
+            return suggestions;
+        }
+
+        let except = if self.dangerous_flags.is_empty() {
+            ""
+        } else {
+            " except dangerous flags"
+        };
+        if let Some(subcommand) = exec_op.subcommand().filter(|sub| !sub.starts_with('-')) {
+            suggestions.push(suggestion(
+                Generality::Subcommand,
+                self.generalize(Matcher::command(name).with_subcommand(subcommand)),
+                format!("`{} {}` with any arguments{}", name, subcommand, except),
+            ));
+        }
+        suggestions.push(suggestion(
+            Generality::Command,
+            self.generalize(Matcher::command(name)),
+            format!("any `{}` command{}", name, except),
+        ));
+        suggestions
+    }
+
+    /// Returns `true` if a command uses one of the dangerous flags.
+    pub(crate) fn has_dangerous_flag(&self, exec_op: &ExecuteOperation) -> bool {
+        self.dangerous_flags
+            .iter()
+            .any(|flag| exec_op.uses_flag(flag))
+    }
+
+    /// Restricts a matcher to commands without dangerous flags.
+    pub(crate) fn generalize(&self, matcher: Matcher) -> Matcher {
+        if self.dangerous_flags.is_empty() {
+            return matcher;
+        }
+        let dangerous = self
+            .dangerous_flags
+            .iter()
+            .map(|flag| Matcher::uses_flag(flag.clone()))
+            .collect();
+        Matcher::and(vec![matcher, Matcher::not(Matcher::or(dangerous))])
+    }
+}
+
+impl Default for RuleSuggester {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ruleset::Ruleset;
+
+    fn ruleset() -> Ruleset {
+        Ruleset::new(vec![
+            Rule::new(Matcher::command("ls"), Outcome::Allow),
+            Rule::new(Matcher::command("sudo"), Outcome::Deny),
+        ])
+    }
+
+    fn suggest(suggester: &RuleSuggester, command: &str) -> Vec<(Generality, String)> {
+        let decision = ruleset().explain(&Operation::execute(command));
+        suggester
+            .suggest(&decision)
+            .iter()
+            .map(|s| (s.generality(), s.rule().matcher().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_levels() {
+        let suggester = RuleSuggester::new().with_dangerous_flags(["--force"]);
+        assert_eq!(
+            suggest(&suggester, "cargo test -p foo --lib"),
+            [
+                (
+                    Generality::Exact,
+                    "exactly `cargo test -p foo --lib`".into()
+                ),
+                (
+                    Generality::Subcommand,
+                    "(cargo test and not (using --force))".into()
+                ),
+                (
+                    Generality::Command,
+                    "(cargo and not (using --force))".into()
+                ),
+            ]
+        );
+
+        // Commands without a subcommand skip that level.
+        assert_eq!(
+            suggest(&suggester, "make -j4"),
+            [
+                (Generality::Exact, "exactly `make -j4`".into()),
+                (Generality::Command, "(make and not (using --force))".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_without_dangerous_flags() {
+        let suggester = RuleSuggester::new().with_dangerous_flags(Vec::<String>::new());
+        assert_eq!(
+            suggest(&suggester, "git log --oneline"),
+            [
+                (Generality::Exact, "exactly `git log --oneline`".into()),
+                (Generality::Subcommand, "git log".into()),
+                (Generality::Command, "git".into()),
+            ]
+        );
+
+        assert_eq!(
+            suggest(&suggester, "make"),
+            [
+                (Generality::Exact, "exactly `make`".into()),
+                (Generality::Command, "make".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_never_generalizes_across_dangerous_flags() {
+        let suggester = RuleSuggester::new();
+        assert_eq!(
+            suggest(&suggester, "git push --force origin main"),
+            [(
+                Generality::Exact,
+                "exactly `git push --force origin main`".into()
+            )]
+        );
+
+        let decision = ruleset().explain(&Operation::execute("git push origin main"));
+        for suggestion in suggester.suggest(&decision) {
+            let matcher = suggestion.rule().matcher();
+            assert!(matcher.matches(&Operation::execute("git push origin main")));
+            assert!(!matcher.matches(&Operation::execute("git push --force origin main")));
+            assert!(!matcher.matches(&Operation::execute("git push -f origin main")));
+            assert!(!matcher.matches(&Operation::execute("git push -fu origin main")));
+            assert!(!matcher.matches(&Operation::execute("git push --force=true origin")));
+        }
+
+        // Dangerous flags are found however they're written
+        assert_eq!(suggest(&suggester, "rm -Rf /").len(), 1);
+        assert_eq!(suggest(&suggester, "rm -r /").len(), 1);
+        let decision = ruleset().explain(&Operation::execute("rm build"));
+        for suggestion in suggester.suggest(&decision) {
+            let matcher = suggestion.rule().matcher();
+            assert!(!matcher.matches(&Operation::execute("rm -Rf /")));
+            assert!(!matcher.matches(&Operation::execute("rm -r /")));
+        }
+    }
+
+    #[test]
+    fn test_compound_commands() {
+        let suggester = RuleSuggester::new();
+        let decision = ruleset().explain(&Operation::execute(
+            "ls | grep foo && sudo reboot; npm test && grep foo bar",
+        ));
+        let suggestions = suggester.suggest(&decision);
+        let commands: Vec<_> = suggestions
+            .iter()
+            .filter(|s| s.generality() == Generality::Exact)
+            .map(Suggestion::command)
+            .collect();
+        assert_eq!(commands, ["grep foo", "npm test", "grep foo bar"]);
+    }
+
+    #[test]
+    fn test_suggestions_decide_the_command() {
+        let suggester = RuleSuggester::new();
+        let command = "ls && cargo test -p foo --lib | tee log";
+        let decision = ruleset().explain(&Operation::execute(command));
+        assert_eq!(decision.outcome(), Outcome::Unknown);
+
+        let suggestions = suggester.suggest(&decision);
+        for generality in [
+            Generality::Exact,
+            Generality::Subcommand,
+            Generality::Command,
+        ] {
+            let rules = suggestions
+                .iter()
+                .filter(|s| s.generality() == generality)
+                .map(|s| s.rule().clone());
+            let ruleset = ruleset().with_leading_rules(rules);
+            assert_eq!(
+                ruleset.evaluate(&Operation::execute(command)),
+                Outcome::Allow,
+                "{:?}",
+                generality
+            );
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Locks that stay usable after a thread panics while holding them.
+//!
+//! The state behind toolcap's locks is only changed by short updates that
+//! can't panic halfway through, such as inserting into a map or swapping a
+//! ruleset whole, so it's consistent even when a lock is poisoned. Rather
+//! than failing every later request, the guard is taken back.
+
+use std::sync::{Mutex, MutexGuard};
+#[cfg(feature = "acp")]
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Locks `mutex`, ignoring poisoning.
+pub(crate) fn lock_unpoisoned<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Locks `lock` for reading, ignoring poisoning.
+#[cfg(feature = "acp")]
+pub(crate) fn read_unpoisoned<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Locks `lock` for writing, ignoring poisoning.
+#[cfg(feature = "acp")]
+pub(crate) fn write_unpoisoned<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,255 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::shell::ParsedCommand;
+
+/// The shell's working directory at some point in a command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum WorkingDir {
+    /// No working directory was given for the command.
+    Unknown,
+    /// The working directory is known.
+    Known(PathBuf),
+    /// The directory was changed to a target that can't be resolved
+    /// statically (e.g. `cd ~`, `cd -`, or a relative path from an unknown
+    /// directory).
+    Unresolved,
+}
+
+impl WorkingDir {
+    /// Returns the path, if known.
+    pub(crate) fn path(&self) -> Option<&PathBuf> {
+        match self {
+            WorkingDir::Known(path) => Some(path),
+            WorkingDir::Unknown | WorkingDir::Unresolved => None,
+        }
+    }
+
+    /// Combines two possible directories into one that covers both.
+    fn merge(self, other: WorkingDir) -> WorkingDir {
+        if self == other {
+            self
+        } else {
+            WorkingDir::Unresolved
+        }
+    }
+}
+
+/// Tracks the effect of `cd`, `pushd` and `popd` while walking a command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ShellState {
+    dir: WorkingDir,
+    stack: Vec<WorkingDir>,
+}
+
+impl ShellState {
+    /// Creates the state for a command line run in `working_dir`.
+    pub(crate) fn new(working_dir: Option<&PathBuf>) -> Self {
+        Self {
+            dir: match working_dir {
+                Some(path) => WorkingDir::Known(path.clone()),
+                None => WorkingDir::Unknown,
+            },
+            stack: Vec::new(),
+        }
+    }
+
+    /// Returns the current working directory.
+    pub(crate) fn working_dir(&self) -> &WorkingDir {
+        &self.dir
+    }
+
+    /// Combines two states that the shell may be in after a branch, e.g. the
+    /// two sides of `||`. Directories that differ become unresolved.
+    pub(crate) fn merge(self, other: ShellState) -> ShellState {
+        let len = self.stack.len().max(other.stack.len());
+        let mut left = self.stack.into_iter();
+        let mut right = other.stack.into_iter();
+        let stack = (0..len)
+            .map(|_| match (left.next(), right.next()) {
+                (Some(a), Some(b)) => a.merge(b),
+                _ => WorkingDir::Unresolved,
+            })
+            .collect();
+
+        ShellState {
+            dir: self.dir.merge(other.dir),
+            stack,
+        }
+    }
+
+    /// Updates the state for a command that has just run, assuming it succeeded.
+    ///
+    /// Only `cd`, `pushd` and `popd` have an effect.
+    pub(crate) fn apply(&mut self, cmd: &ParsedCommand) {
+        match cmd.name.as_str() {
+            "cd" => self.dir = self.cd_target(&cmd.args),
+            "pushd" => self.pushd(&cmd.args),
+            "popd" => self.popd(&cmd.args),
+            _ => {}
+        }
+    }
+
+    /// Resolves the directory `cd` changes to.
+    fn cd_target(&self, args: &[String]) -> WorkingDir {
+        let operands = operands(args);
+        let [target] = operands.as_slice() else {
+            // `cd` alone goes to $HOME; several operands are an error.
+            return WorkingDir::Unresolved;
+        };
+        self.resolve(target)
+    }
+
+    fn pushd(&mut self, args: &[String]) {
+        if args.iter().any(|arg| arg.starts_with(['-', '+'])) {
+            // Stack rotation and `-n` aren't modeled.
+            self.dir = WorkingDir::Unresolved;
+            self.stack.clear();
+            return;
+        }
+
+        match args {
+            [] => {
+                // Swap the top two directories.
+                if let Some(top) = self.stack.pop() {
+                    let previous = std::mem::replace(&mut self.dir, top);
+                    self.stack.push(previous);
+                }
+            }
+            [target] => {
+                let target = self.resolve(target);
+                let previous = std::mem::replace(&mut self.dir, target);
+                self.stack.push(previous);
+            }
+            _ => self.dir = WorkingDir::Unresolved,
+        }
+    }
+
+    fn popd(&mut self, args: &[String]) {
+        if !args.is_empty() {
+            // Removing other stack entries isn't modeled.
+            self.dir = WorkingDir::Unresolved;
+            self.stack.clear();
+            return;
+        }
+
+        if let Some(top) = self.stack.pop() {
+            self.dir = top;
+        }
+    }
+
+    /// Resolves a directory operand against the current directory.
+    fn resolve(&self, target: &str) -> WorkingDir {
+        if target == "-" || target.starts_with('~') {
+            return WorkingDir::Unresolved;
+        }
+
+        let target = Path::new(target);
+        if target.is_absolute() {
+            return WorkingDir::Known(normalize(target));
+        }
+        match &self.dir {
+            WorkingDir::Known(base) => WorkingDir::Known(normalize(&base.join(target))),
+            WorkingDir::Unknown | WorkingDir::Unresolved => WorkingDir::Unresolved,
+        }
+    }
+}
+
+/// Returns the operands of `cd`, skipping its options.
+fn operands(args: &[String]) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let mut options_done = false;
+    for arg in args {
+        if options_done || arg == "-" || !arg.starts_with('-') {
+            operands.push(arg.as_str());
+        } else if arg == "--" {
+            options_done = true;
+        }
+    }
+    operands
+}
+
+/// Lexically normalizes a path, resolving `.` and `..` the way the shell
+/// does for `cd` without `-P`.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::{ShellAst, parse};
+
+    fn run(start: Option<&str>, commands: &str) -> WorkingDir {
+        let start = start.map(PathBuf::from);
+        let mut state = ShellState::new(start.as_ref());
+        let ast = parse(commands).unwrap();
+        for cmd in ast.commands() {
+            state.apply(cmd);
+        }
+        assert!(!matches!(ast, ShellAst::Unsupported(_)));
+        state.dir
+    }
+
+    fn known(path: &str) -> WorkingDir {
+        WorkingDir::Known(PathBuf::from(path))
+    }
+
+    #[test]
+    fn test_cd_absolute() {
+        assert_eq!(run(Some("/home/user"), "cd /etc"), known("/etc"));
+        assert_eq!(run(None, "cd /etc"), known("/etc"));
+    }
+
+    #[test]
+    fn test_cd_relative() {
+        assert_eq!(run(Some("/home/user/a"), "cd ../b"), known("/home/user/b"));
+        assert_eq!(
+            run(Some("/home/user"), "cd ./src/."),
+            known("/home/user/src")
+        );
+        assert_eq!(run(Some("/"), "cd .."), known("/"));
+        assert_eq!(run(None, "cd src"), WorkingDir::Unresolved);
+    }
+
+    #[test]
+    fn test_cd_options() {
+        assert_eq!(run(Some("/a"), "cd -P /etc"), known("/etc"));
+        assert_eq!(run(Some("/a"), "cd -- b"), known("/a/b"));
+    }
+
+    #[test]
+    fn test_cd_unresolvable() {
+        assert_eq!(run(Some("/a"), "cd"), WorkingDir::Unresolved);
+        assert_eq!(run(Some("/a"), "cd ~/projects"), WorkingDir::Unresolved);
+        assert_eq!(run(Some("/a"), "cd -"), WorkingDir::Unresolved);
+        assert_eq!(run(Some("/a"), "cd b c"), WorkingDir::Unresolved);
+    }
+
+    #[test]
+    fn test_pushd_popd() {
+        assert_eq!(run(Some("/a"), "pushd /b"), known("/b"));
+        assert_eq!(run(Some("/a"), "pushd /b; popd"), known("/a"));
+        assert_eq!(run(Some("/a"), "pushd /b; pushd"), known("/a"));
+        assert_eq!(run(Some("/a"), "pushd /b; cd c; popd"), known("/a"));
+        assert_eq!(run(Some("/a"), "popd"), known("/a"));
+        assert_eq!(run(Some("/a"), "pushd /b; popd +1"), WorkingDir::Unresolved);
+    }
+
+    #[test]
+    fn test_merge() {
+        let a = ShellState::new(Some(&PathBuf::from("/a")));
+        let b = ShellState::new(Some(&PathBuf::from("/b")));
+        assert_eq!(a.clone().merge(a.clone()), a);
+        assert_eq!(a.merge(b).dir, WorkingDir::Unresolved);
+    }
+}